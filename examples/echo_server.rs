@@ -0,0 +1,34 @@
+use std::error::Error;
+use std::net::TcpListener;
+use std::num::NonZeroU32;
+use std::os::fd::AsRawFd;
+
+use tracing::{info, Level};
+
+use rummelplatz::ops::examples::EchoServer;
+use rummelplatz::ring;
+
+ring! {
+    echo_ring -> rummelplatz::ops::Error,
+    echo_server: rummelplatz::ops::examples::EchoServer
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt()
+        .compact()
+        .with_max_level(Level::DEBUG)
+        .init();
+
+    let listener = TcpListener::bind("127.0.0.1:7878")?;
+    info!("echo server listening on {}", listener.local_addr()?);
+
+    let echo_server = EchoServer::new(listener.as_raw_fd());
+    let mut ring = echo_ring::Ring::builder()
+        .ring_size(NonZeroU32::new(128).unwrap())
+        .echo_server(echo_server)
+        .build()?;
+
+    ring.run()?;
+
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use std::error::Error;
+use std::num::NonZeroU32;
+
+use tracing::{info, Level};
+
+use rummelplatz::ops::NopBenchOp;
+use rummelplatz::{ring, ControlFlow};
+
+ring! {
+    bench_ring -> rummelplatz::ops::Error,
+    nop_bench: rummelplatz::ops::NopBenchOp
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt()
+        .compact()
+        .with_max_level(Level::INFO)
+        .init();
+
+    let nop_bench = NopBenchOp::new(1_000_000, 64, |report| {
+        info!(
+            "{} nops in {:?}: {:.0} completions/s, {:?} per completion",
+            report.completions,
+            report.elapsed,
+            report.completions_per_sec(),
+            report.per_completion()
+        );
+        ControlFlow::Exit
+    });
+    let mut ring = bench_ring::Ring::new(
+        bench_ring::Ring::new_raw_ring(NonZeroU32::new(128).unwrap())?,
+        None,
+        nop_bench,
+    );
+
+    ring.run()?;
+
+    Ok(())
+}
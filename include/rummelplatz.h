@@ -0,0 +1,46 @@
+/*
+ * C API of rummelplatz, built with `cargo rustc --release --features capi --crate-type cdylib`.
+ * See src/capi.rs for the documentation of every function.
+ */
+
+#ifndef RUMMELPLATZ_H
+#define RUMMELPLATZ_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* return values of callbacks, or a negative errno failing the ring */
+#define RUMMELPLATZ_CONTINUE 0
+#define RUMMELPLATZ_EXIT 1
+
+struct rummelplatz_ring;
+struct rummelplatz_stop_handle;
+
+/* called for every completion of a request with the `user` pointer it was submitted with */
+typedef int (*rummelplatz_callback)(void *user, int32_t result, uint32_t flags);
+
+/* NULL on failure, with errno set */
+struct rummelplatz_ring *rummelplatz_ring_new(uint32_t entries);
+
+/* `sqe` points to a prepared `struct io_uring_sqe`, its user_data is overwritten */
+int rummelplatz_ring_submit(struct rummelplatz_ring *ring, const void *sqe,
+                            rummelplatz_callback callback, void *user);
+
+/* blocks until a callback exits the ring or it is stopped, 0 or a negative errno */
+int rummelplatz_ring_run(struct rummelplatz_ring *ring);
+
+void rummelplatz_ring_free(struct rummelplatz_ring *ring);
+
+/* thread-safe handle stopping a running ring */
+struct rummelplatz_stop_handle *rummelplatz_ring_stop_handle(const struct rummelplatz_ring *ring);
+int rummelplatz_stop(const struct rummelplatz_stop_handle *handle);
+void rummelplatz_stop_handle_free(struct rummelplatz_stop_handle *handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RUMMELPLATZ_H */
@@ -0,0 +1,394 @@
+//! Attribute macros of [rummelplatz](https://docs.rs/rummelplatz), re-exported by its `macros`
+//! feature as `rummelplatz::macros`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, quote_spanned, ToTokens};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::visit_mut::VisitMut;
+use syn::{
+    parenthesized, parse_macro_input, Attribute, Error, Expr, Fields, GenericParam, Ident,
+    ItemStruct, Path, PathArguments, PathSegment, Token, Type,
+};
+
+/// Generates a ring from a struct listing its operations, like `rummelplatz::ring!`:
+///
+/// ```ignore
+/// /// Echoes every connection accepted on the listener.
+/// #[rummelplatz::macros::ring(error = rummelplatz::ops::Error)]
+/// pub struct EchoRing {
+///     echo_server: EchoServer,
+/// }
+///
+/// let mut ring = EchoRing::new(echo_ring::Ring::new_raw_ring(size)?, None, echo_server);
+/// ring.run()?;
+/// ```
+///
+/// The fields name the operations, their types are resolved like in the surrounding module.
+/// The ring is generated into a module named after the struct in snake case (`echo_ring`), or
+/// `module = name`, with the visibility and doc comments of the struct; the struct becomes an
+/// alias of its `Ring`. Type parameters and where clauses of the struct carry over, and
+/// `error = Error` or `errors(Setup, Completion, Teardown)` declare the errors of `run`. `sqe128`
+/// generates a ring with 128 byte SQEs and 32 byte CQEs, `decorate = function` passes every SQE
+/// through `function` after its user data is set. `crate = path` names the `rummelplatz` crate
+/// for code that re-exports it under another path.
+///
+/// Fields whose type does not implement `RingOperation` are reported at the field.
+#[proc_macro_attribute]
+pub fn ring(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as Args);
+    let item = parse_macro_input!(item as ItemStruct);
+    expand(args, item)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct Args {
+    /// The path of `rummelplatz` of `crate = path`.
+    krate: Option<Path>,
+    module: Option<Ident>,
+    sqe128: bool,
+    decorate: Option<Expr>,
+    /// Either the one error of `error = ..` or the three of `errors(..)`.
+    errors: Vec<Type>,
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut args = Args::default();
+        while !input.is_empty() {
+            if input.peek(Token![crate]) {
+                input.parse::<Token![crate]>()?;
+                input.parse::<Token![=]>()?;
+                args.krate = Some(input.parse()?);
+                if !input.is_empty() {
+                    input.parse::<Token![,]>()?;
+                }
+                continue;
+            }
+            let key: Ident = input.parse()?;
+            match key.to_string().as_str() {
+                "sqe128" => args.sqe128 = true,
+                "decorate" => {
+                    input.parse::<Token![=]>()?;
+                    args.decorate = Some(input.parse()?);
+                }
+                "module" => {
+                    input.parse::<Token![=]>()?;
+                    args.module = Some(input.parse()?);
+                }
+                "error" => {
+                    input.parse::<Token![=]>()?;
+                    args.errors = vec![input.parse()?];
+                }
+                "errors" => {
+                    let content;
+                    parenthesized!(content in input);
+                    let types = Punctuated::<Type, Token![,]>::parse_terminated(&content)?;
+                    let [setup, completion, teardown]: [Type; 3] = types
+                        .into_iter()
+                        .collect::<Vec<_>>()
+                        .try_into()
+                        .map_err(|_| {
+                            Error::new(
+                                key.span(),
+                                "`errors` takes the setup, completion and teardown error",
+                            )
+                        })?;
+                    args.errors = vec![setup, completion, teardown];
+                }
+                _ => {
+                    return Err(Error::new(
+                        key.span(),
+                        "expected `module = name`, `error = Error`, `errors(Setup, Completion, Teardown)`, `sqe128`, `decorate = function` or `crate = path`",
+                    ));
+                }
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        Ok(args)
+    }
+}
+
+/// Rewrites paths relative to the surrounding module for the generated module inside it, the
+/// rest resolves through its glob import of `super`.
+struct Rebase;
+
+impl VisitMut for Rebase {
+    fn visit_path_mut(&mut self, path: &mut Path) {
+        if path.leading_colon.is_none() {
+            if let Some(first) = path.segments.first_mut() {
+                if first.ident == "self" {
+                    first.ident = format_ident!("super", span = first.ident.span());
+                } else if first.ident == "super" {
+                    let ident = format_ident!("super", span = first.ident.span());
+                    path.segments.insert(
+                        0,
+                        PathSegment {
+                            ident,
+                            arguments: PathArguments::None,
+                        },
+                    );
+                }
+            }
+        }
+        syn::visit_mut::visit_path_mut(self, path);
+    }
+}
+
+/// `EchoRing` becomes `echo_ring`, a run of capitals is one word up to the capital starting the
+/// next: `HTTPOp` becomes `http_op`.
+fn snake_case(ident: &Ident) -> Ident {
+    let chars: Vec<char> = ident.to_string().chars().collect();
+    let mut name = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_lowercase = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_lowercase)
+            {
+                name.push('_');
+            }
+        }
+        name.extend(c.to_lowercase());
+    }
+    Ident::new(&name, ident.span())
+}
+
+fn expand(args: Args, item: ItemStruct) -> syn::Result<TokenStream2> {
+    let fields = match &item.fields {
+        Fields::Named(fields) if !fields.named.is_empty() => &fields.named,
+        Fields::Named(_) | Fields::Unit => {
+            return Err(Error::new(
+                item.ident.span(),
+                "a ring needs at least one operation",
+            ));
+        }
+        Fields::Unnamed(fields) => {
+            return Err(Error::new(
+                fields.span(),
+                "operations are named, use a struct with named fields",
+            ));
+        }
+    };
+
+    let mut params = vec![];
+    let mut bounds = vec![];
+    for param in &item.generics.params {
+        match param {
+            GenericParam::Type(param) => {
+                let ident = &param.ident;
+                let param_bounds = &param.bounds;
+                params.push(ident.clone());
+                if !param_bounds.is_empty() {
+                    bounds.push(quote!(#ident: #param_bounds));
+                }
+            }
+            param => {
+                return Err(Error::new(param.span(), "rings only take type parameters"));
+            }
+        }
+    }
+    if let Some(where_clause) = &item.generics.where_clause {
+        bounds.extend(
+            where_clause
+                .predicates
+                .iter()
+                .map(ToTokens::to_token_stream),
+        );
+    }
+
+    let mut operations = vec![];
+    let mut assertions = vec![];
+    for field in fields {
+        let name = field.ident.as_ref().unwrap();
+        let Type::Path(ty) = &field.ty else {
+            return Err(Error::new(
+                field.ty.span(),
+                "operations are given by the path of their type",
+            ));
+        };
+        if ty.qself.is_some() {
+            return Err(Error::new(
+                field.ty.span(),
+                "operations are given by the path of their type",
+            ));
+        }
+        assertions.push(quote_spanned! {field.ty.span()=>
+            assert_ring_operation::<#ty>();
+        });
+        let mut path = ty.path.clone();
+        Rebase.visit_path_mut(&mut path);
+        operations.push(quote!(#name: #path));
+    }
+
+    let mut rebased_bounds = bounds.clone();
+    for bound in &mut rebased_bounds {
+        let mut predicate: syn::WherePredicate = syn::parse2(bound.clone())?;
+        Rebase.visit_where_predicate_mut(&mut predicate);
+        *bound = predicate.into_token_stream();
+    }
+
+    let header_generics = (!params.is_empty()).then(|| quote!(<#(#params),*>));
+    let header_where = (!rebased_bounds.is_empty()).then(|| quote!(where { #(#rebased_bounds),* }));
+    let mut errors = args.errors;
+    errors
+        .iter_mut()
+        .for_each(|error| Rebase.visit_type_mut(error));
+    let header_errors = match errors.as_slice() {
+        [] => None,
+        [error] => Some(quote!(-> #error)),
+        errors => Some(quote!(-> (#(#errors),*))),
+    };
+
+    let docs: Vec<&Attribute> = item
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .collect();
+    let vis = &item.vis;
+    let name = &item.ident;
+    let module = args.module.unwrap_or_else(|| snake_case(name));
+    let generics = &item.generics;
+    let where_clause = &item.generics.where_clause;
+    let krate = args
+        .krate
+        .map_or_else(|| quote!(::rummelplatz), ToTokens::into_token_stream);
+
+    let (sqe128, entries) = match args.sqe128 {
+        true => (
+            Some(quote!(sqe128,)),
+            Some(quote!(<#krate::io_uring::squeue::Entry128, #krate::io_uring::cqueue::Entry32>)),
+        ),
+        false => (None, None),
+    };
+    let decorate = args.decorate.map(|mut decorate| {
+        Rebase.visit_expr_mut(&mut decorate);
+        quote!(decorate = #decorate,)
+    });
+
+    Ok(quote! {
+        const _: () = {
+            fn assert_ring_operation<T: #krate::RingOperation #entries>() {}
+            #[allow(dead_code)]
+            fn assert_operations #generics () #where_clause {
+                #(#assertions)*
+            }
+        };
+
+        #krate::ring!(
+            @header [#(#docs)* #vis] {
+                #[allow(unused_imports)]
+                use super::*;
+            }
+            #module #header_generics #header_where #header_errors,
+            #sqe128
+            #decorate
+            #(#operations),*
+        );
+
+        #(#docs)*
+        #vis type #name #header_generics = #module::Ring #header_generics;
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::{format_ident, quote};
+    use syn::parse_quote;
+
+    use super::{expand, snake_case, Args};
+
+    #[test]
+    fn snake_case_keeps_acronyms_together() {
+        for (name, expected) in [
+            ("EchoRing", "echo_ring"),
+            ("HTTPOp", "http_op"),
+            ("IORing", "io_ring"),
+            ("TcpHTTPProxy", "tcp_http_proxy"),
+            ("Ring2Op", "ring2_op"),
+            ("ring", "ring"),
+        ] {
+            assert_eq!(snake_case(&format_ident!("{name}")), expected);
+        }
+    }
+
+    #[test]
+    fn expands_to_a_ring_and_an_alias() {
+        let args: Args = parse_quote!(error = Error);
+        let expanded = expand(
+            args,
+            parse_quote! {
+                /// Echoes.
+                pub struct HTTPRing {
+                    echo: self::EchoOp,
+                }
+            },
+        )
+        .unwrap()
+        .to_string();
+
+        let ring = quote! {
+            ::rummelplatz::ring!(
+                @header [#[doc = r" Echoes."] pub] {
+                    #[allow(unused_imports)]
+                    use super::*;
+                }
+                http_ring -> Error,
+                echo: super::EchoOp
+            );
+        };
+        assert!(expanded.contains(&ring.to_string()), "{expanded}");
+        let alias = quote!(
+            pub type HTTPRing = http_ring::Ring;
+        );
+        assert!(expanded.contains(&alias.to_string()), "{expanded}");
+    }
+
+    #[test]
+    fn expands_with_the_given_crate() {
+        let args: Args = parse_quote!(crate = ::reexported::rummelplatz, module = echo, sqe128);
+        let expanded = expand(
+            args,
+            parse_quote!(
+                struct Echo {
+                    echo: EchoOp,
+                }
+            ),
+        )
+        .unwrap()
+        .to_string();
+
+        assert_eq!(
+            expanded.matches(":: rummelplatz ::").count(),
+            expanded.matches(":: reexported :: rummelplatz ::").count(),
+            "{expanded}"
+        );
+        let ring = quote!(::reexported::rummelplatz::ring!);
+        assert!(expanded.contains(&ring.to_string()), "{expanded}");
+        let operation = quote!(::reexported::rummelplatz::RingOperation<
+            ::reexported::rummelplatz::io_uring::squeue::Entry128,
+            ::reexported::rummelplatz::io_uring::cqueue::Entry32
+        >);
+        assert!(expanded.contains(&operation.to_string()), "{expanded}");
+    }
+
+    #[test]
+    fn rejects_rings_without_operations() {
+        let error = expand(
+            Args::default(),
+            parse_quote!(
+                struct Empty;
+            ),
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "a ring needs at least one operation");
+    }
+}
@@ -0,0 +1,6 @@
+#[rummelplatz_macros::ring(error = rummelplatz::ops::Error)]
+struct TickRing<'a> {
+    tick: &'a rummelplatz::ops::TickOp,
+}
+
+fn main() {}
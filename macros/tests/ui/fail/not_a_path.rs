@@ -0,0 +1,7 @@
+#[rummelplatz_macros::ring(error = rummelplatz::ops::Error)]
+struct TickRing {
+    tick: rummelplatz::ops::TickOp,
+    ticks: [rummelplatz::ops::TickOp; 2],
+}
+
+fn main() {}
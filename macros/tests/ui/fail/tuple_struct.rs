@@ -0,0 +1,4 @@
+#[rummelplatz_macros::ring(error = rummelplatz::ops::Error)]
+struct Tuple(rummelplatz::ops::TickOp);
+
+fn main() {}
@@ -0,0 +1,6 @@
+#[rummelplatz_macros::ring(errors(rummelplatz::ops::Error, rummelplatz::ops::Error))]
+struct TickRing {
+    tick: rummelplatz::ops::TickOp,
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+#[rummelplatz_macros::ring(error = rummelplatz::ops::Error, size = 64)]
+struct TickRing {
+    tick: rummelplatz::ops::TickOp,
+}
+
+fn main() {}
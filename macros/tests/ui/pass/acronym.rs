@@ -0,0 +1,12 @@
+use rummelplatz::ops::TickOp;
+use rummelplatz_macros::ring;
+
+/// Ticks.
+#[ring(error = rummelplatz::ops::Error)]
+pub struct HTTPRing {
+    tick: TickOp,
+}
+
+fn main() {
+    let _: Option<http_ring::Ring> = None::<HTTPRing>;
+}
@@ -0,0 +1,14 @@
+mod reexport {
+    pub use rummelplatz;
+}
+
+use rummelplatz_macros::ring;
+
+#[ring(crate = crate::reexport::rummelplatz, error = rummelplatz::ops::Error)]
+struct TickRing {
+    tick: rummelplatz::ops::TickOp,
+}
+
+fn main() {
+    let _: Option<tick_ring::Ring> = None::<TickRing>;
+}
@@ -0,0 +1,15 @@
+use rummelplatz::ops::{ChannelOp, RingPayload, TickOp};
+use rummelplatz_macros::ring;
+
+#[ring(module = ticks, errors(rummelplatz::ops::Error, rummelplatz::ops::Error, rummelplatz::ops::Error))]
+struct TickRing<T>
+where
+    T: RingPayload,
+{
+    tick: TickOp,
+    values: ChannelOp<T>,
+}
+
+fn main() {
+    let _: Option<ticks::Ring<u32>> = None::<TickRing<u32>>;
+}
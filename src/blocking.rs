@@ -0,0 +1,112 @@
+//! Running a batch of requests on a temporary ring, for programs without an event loop.
+//!
+//! [`block_on_ring`] creates a ring, hands a [`BatchSubmitter`] to a closure pushing the
+//! requests, submits them and blocks until every one of them completed. The data pushed with
+//! a request comes back with its completion, so it can own the buffers the request uses:
+//!
+//! ```no_run
+//! # use std::num::NonZeroU32;
+//! # use std::os::fd::AsRawFd;
+//! # use rummelplatz::blocking::block_on_ring;
+//! # use rummelplatz::io_uring::{opcode, types};
+//! let files = ["a.txt", "b.txt"].map(|path| std::fs::File::open(path).unwrap());
+//! let results = block_on_ring(NonZeroU32::new(64).unwrap(), |mut submitter| {
+//!     for (index, file) in files.iter().enumerate() {
+//!         let mut buf = vec![0u8; 4096];
+//!         let read = opcode::Read::new(types::Fd(file.as_raw_fd()), buf.as_mut_ptr(), 4096);
+//!         submitter.push(read.build(), (index, buf))?;
+//!     }
+//!     Ok(())
+//! })?;
+//! for ((index, buf), cqe) in results {
+//!     println!("file {index}: {:?}", &buf[..cqe.result().max(0) as usize]);
+//! }
+//! # Ok::<(), rummelplatz::ops::Error>(())
+//! ```
+//!
+//! Requests beyond the size of the submission queue wait in the backlog until there is room.
+//! Multishot requests only return their data with their last completion, the ones before are
+//! dropped.
+
+use std::collections::VecDeque;
+use std::num::NonZeroU32;
+
+use io_uring::cqueue::Entry;
+use io_uring::IoUring;
+use tracing::trace;
+
+use crate::ops::Error;
+use crate::user_data::UserDataToken;
+use crate::SubmissionQueueSubmitter;
+
+/// The submitter handed to the closure of [`block_on_ring`], `T` is the data of a request.
+pub type BatchSubmitter<'a, 'b, 'c, T> =
+    SubmissionQueueSubmitter<'a, 'b, 'c, T, fn(&mut io_uring::squeue::Entry, T)>;
+
+fn wrap<T>(entry: &mut io_uring::squeue::Entry, data: T) {
+    let user_data = UserDataToken::new(Box::new(data)).into_user_data();
+    take_mut::take(entry, |entry| entry.user_data(user_data));
+}
+
+/// Creates a ring with `ring_size` entries, pushes the requests of `batch` and blocks until all
+/// of them completed. Returns the data of every request with its completion entry, in the
+/// order they completed.
+pub fn block_on_ring<T, F>(ring_size: NonZeroU32, batch: F) -> Result<Vec<(T, Entry)>, Error>
+where
+    F: FnOnce(BatchSubmitter<'_, '_, '_, T>) -> Result<(), io_uring::squeue::PushError>,
+{
+    block_on_ring_with(IoUring::new(ring_size.get())?, batch)
+}
+
+/// Like [`block_on_ring`], on a ring built by the caller (e.g. with `IoUring::builder()` for
+/// other setup flags).
+pub fn block_on_ring_with<T, F>(mut ring: IoUring, batch: F) -> Result<Vec<(T, Entry)>, Error>
+where
+    F: FnOnce(BatchSubmitter<'_, '_, '_, T>) -> Result<(), io_uring::squeue::PushError>,
+{
+    let mut backlog = VecDeque::new();
+    let (submit, mut sq, mut cq) = ring.split();
+
+    let pushed = batch(SubmissionQueueSubmitter::new(
+        &mut sq,
+        &mut backlog,
+        None,
+        wrap::<T> as fn(&mut io_uring::squeue::Entry, T),
+    ));
+    // nothing was submitted yet, the data of the queued requests is leaked
+    pushed?;
+    sq.sync();
+    let mut in_flight = sq.len() + backlog.iter().map(|entries| entries.len()).sum::<usize>();
+    trace!("blocking on {in_flight} requests");
+
+    let mut results = Vec::with_capacity(in_flight);
+    while in_flight > 0 {
+        sq.sync();
+        // a failure leaks the data of the requests in flight, the kernel may still use it
+        submit.submit_and_wait(1)?;
+
+        while let Some(entries) = backlog.pop_front() {
+            if unsafe { sq.push_multiple(&entries) }.is_err() {
+                backlog.push_front(entries);
+                break;
+            }
+        }
+
+        cq.sync();
+        for cqe in cq.by_ref() {
+            if cqe.user_data() == 0 {
+                // pushed raw, e.g. a cancellation
+                continue;
+            }
+            if io_uring::cqueue::more(cqe.flags()) {
+                trace!("dropped intermediate {cqe:?}");
+                continue;
+            }
+            let data = unsafe { UserDataToken::<T>::from_user_data(cqe.user_data()) }.into_box();
+            results.push((*data, cqe));
+            in_flight -= 1;
+        }
+    }
+
+    Ok(results)
+}
@@ -0,0 +1,421 @@
+//! A C ABI for embedding rings into C/C++ services, see `include/rummelplatz.h`.
+//!
+//! A ring created with [`rummelplatz_ring_new`] runs requests submitted as prepared
+//! `struct io_uring_sqe`s (e.g. with liburing's `io_uring_prep_*` functions) together with a
+//! callback and an opaque `void *` handed back to it, instead of the `UserData` enum of
+//! generated rings:
+//!
+//! ```c
+//! static int on_read(void *user, int32_t result, uint32_t flags) {
+//!     printf("read %d bytes\n", result);
+//!     return RUMMELPLATZ_EXIT;
+//! }
+//!
+//! struct rummelplatz_ring *ring = rummelplatz_ring_new(64);
+//! struct io_uring_sqe sqe = {0};
+//! io_uring_prep_read(&sqe, fd, buf, sizeof(buf), 0);
+//! rummelplatz_ring_submit(ring, &sqe, on_read, NULL);
+//! rummelplatz_ring_run(ring);
+//! rummelplatz_ring_free(ring);
+//! ```
+//!
+//! Build the library with `cargo rustc --release --features capi --crate-type cdylib` (or
+//! `staticlib`).
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::{c_int, c_void};
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::num::NonZeroU32;
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use tracing::{debug, warn};
+
+use crate::ops::{Error, RemoteOp, RingHandle};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+// expanded inside the crate, lints on the generated code are not suppressed like for users
+#[allow(dead_code, unused_imports)]
+mod ring {
+    crate::ring! {
+        capi_ring,
+        stop: crate::ops::RemoteOp<()>,
+        callbacks: crate::capi::CallbackOp
+    }
+}
+
+/// Keep the ring running.
+pub const RUMMELPLATZ_CONTINUE: c_int = 0;
+/// Stop the ring, [`rummelplatz_ring_run`] returns 0.
+pub const RUMMELPLATZ_EXIT: c_int = 1;
+
+/// Called with the result and flags of every completion of a request and the `user` pointer
+/// it was submitted with. Returns [`RUMMELPLATZ_CONTINUE`], [`RUMMELPLATZ_EXIT`] or a
+/// negative errno failing the ring.
+///
+/// Multishot requests call it for every completion, `IORING_CQE_F_MORE` in `flags` tells
+/// whether more follow. Requests canceled when the ring stops get a last call with their
+/// final (usually `-ECANCELED`) result, whose return value is ignored.
+pub type RummelplatzCallback =
+    unsafe extern "C" fn(user: *mut c_void, result: i32, flags: u32) -> c_int;
+
+#[derive(Copy, Clone)]
+pub struct Callback {
+    function: RummelplatzCallback,
+    user: *mut c_void,
+}
+
+impl Debug for Callback {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Callback")
+            .field("user", &self.user)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Callback {
+    fn call(&self, completion_entry: &Entry) -> c_int {
+        unsafe {
+            (self.function)(
+                self.user,
+                completion_entry.result(),
+                completion_entry.flags(),
+            )
+        }
+    }
+}
+
+type Queue = Rc<RefCell<VecDeque<(io_uring::squeue::Entry, Callback)>>>;
+
+/// Runs the requests submitted through the C API and calls their callbacks.
+pub struct CallbackOp {
+    // filled by `rummelplatz_ring_submit`, also from within callbacks
+    queue: Queue,
+    in_flight: usize,
+}
+
+impl Debug for CallbackOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallbackOp")
+            .field("queued", &self.queue.borrow().len())
+            .field("in_flight", &self.in_flight)
+            .finish()
+    }
+}
+
+impl CallbackOp {
+    fn push_queued<W: Fn(&mut io_uring::squeue::Entry, Callback)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<Callback, W>,
+    ) -> Result<(), Error> {
+        loop {
+            // released before pushing, the queue is shared with the C side
+            let Some((entry, callback)) = self.queue.borrow_mut().pop_front() else {
+                return Ok(());
+            };
+            submitter.push(entry, callback)?;
+            self.in_flight += 1;
+        }
+    }
+}
+
+impl RingOperation for CallbackOp {
+    type RingData = Callback;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.push_queued(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let more = io_uring::cqueue::more(completion_entry.flags());
+        if !more {
+            self.in_flight -= 1;
+        }
+
+        let flow = match ring_data.call(&completion_entry) {
+            RUMMELPLATZ_CONTINUE => ControlFlow::Continue,
+            RUMMELPLATZ_EXIT => ControlFlow::Exit,
+            errno if errno < 0 => ControlFlow::Error(io::Error::from_raw_os_error(-errno).into()),
+            other => {
+                warn!("callback returned {other}, continuing");
+                ControlFlow::Continue
+            }
+        };
+        (flow, more.then_some(ring_data))
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        // only the final completion ends the request, like in `on_completion`
+        if !io_uring::cqueue::more(completion_entry.flags()) {
+            self.in_flight -= 1;
+        }
+        ring_data.call(&completion_entry);
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> ControlFlow<Error, Error> {
+        match self.push_queued(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
+
+/// Opaque ring handle of the C API.
+pub struct RummelplatzRing {
+    ring: ring::capi_ring::Ring,
+    queue: Queue,
+    stop: RingHandle<()>,
+}
+
+/// Opaque, thread-safe handle stopping a ring, see [`rummelplatz_ring_stop_handle`].
+pub struct RummelplatzStopHandle {
+    stop: RingHandle<()>,
+}
+
+fn errno(e: Error) -> c_int {
+    let e = match e {
+        Error::Io(e) => e,
+        Error::Push(_) => return -libc::EBUSY,
+    };
+    -e.raw_os_error().unwrap_or(libc::EIO)
+}
+
+fn new_ring(entries: u32) -> io::Result<Box<RummelplatzRing>> {
+    let entries =
+        NonZeroU32::new(entries).ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+    let raw = ring::capi_ring::Ring::new_raw_ring(entries)?;
+
+    let stop = RemoteOp::new(|()| ControlFlow::Exit)?;
+    let stop_handle = stop.handle();
+    let queue = Queue::default();
+    let callbacks = CallbackOp {
+        queue: queue.clone(),
+        in_flight: 0,
+    };
+
+    let ring = ring::capi_ring::Ring::new(raw, None, stop, callbacks);
+    stop_handle.connect(ring.message_target(ring::capi_ring::Operation::stop));
+    Ok(Box::new(RummelplatzRing {
+        ring,
+        queue,
+        stop: stop_handle,
+    }))
+}
+
+/// Creates a ring with `entries` submission queue entries, `NULL` on failure (with `errno`
+/// set).
+#[no_mangle]
+pub extern "C" fn rummelplatz_ring_new(entries: u32) -> *mut RummelplatzRing {
+    match new_ring(entries) {
+        Ok(ring) => Box::into_raw(ring),
+        Err(e) => {
+            debug!("unable to create ring: {e}");
+            unsafe { *libc::__errno_location() = e.raw_os_error().unwrap_or(libc::EIO) };
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Queues the request `sqe` (its `user_data` is overwritten), which is submitted once the ring
+/// runs (or with the next loop iteration if it does). `callback` is called with `user` for
+/// every completion of the request. Returns 0 or a negative errno.
+///
+/// May be called from within callbacks, but only on the thread running the ring.
+///
+/// # Safety
+/// `ring` has to come from [`rummelplatz_ring_new`], `sqe` has to point to a
+/// `struct io_uring_sqe`, and everything the request points to has to stay valid until its
+/// last callback.
+#[no_mangle]
+pub unsafe extern "C" fn rummelplatz_ring_submit(
+    ring: *mut RummelplatzRing,
+    sqe: *const c_void,
+    callback: Option<RummelplatzCallback>,
+    user: *mut c_void,
+) -> c_int {
+    let (Some(function), false, false) = (callback, ring.is_null(), sqe.is_null()) else {
+        return -libc::EINVAL;
+    };
+
+    let entry = std::ptr::read_unaligned(sqe as *const io_uring::squeue::Entry);
+    // only the queue is borrowed, the ring itself may be running
+    (*ring)
+        .queue
+        .borrow_mut()
+        .push_back((entry, Callback { function, user }));
+    0
+}
+
+/// Runs the ring until a callback returns [`RUMMELPLATZ_EXIT`] or the ring is stopped, then
+/// cancels everything still in flight. Returns 0 or a negative errno.
+///
+/// A ring runs once, calling it again returns 0 right away.
+///
+/// # Safety
+/// `ring` has to come from [`rummelplatz_ring_new`].
+#[no_mangle]
+pub unsafe extern "C" fn rummelplatz_ring_run(ring: *mut RummelplatzRing) -> c_int {
+    if ring.is_null() {
+        return -libc::EINVAL;
+    }
+
+    use ring::capi_ring::RingError;
+    match (*ring).ring.run::<Error, Error, Error>() {
+        Ok(()) => 0,
+        Err(RingError::Setup(e) | RingError::Completion(e) | RingError::Teardown(e)) => errno(e),
+        Err(RingError::Api(e)) => errno(e.into()),
+        Err(RingError::Push(e)) => errno(e.into()),
+        // no operations are registered on C rings
+        Err(RingError::Dynamic { .. }) => -libc::EIO,
+    }
+}
+
+/// A handle stopping `ring` from any thread, free it with
+/// [`rummelplatz_stop_handle_free`]. `NULL` if `ring` is `NULL`.
+///
+/// # Safety
+/// `ring` has to come from [`rummelplatz_ring_new`].
+#[no_mangle]
+pub unsafe extern "C" fn rummelplatz_ring_stop_handle(
+    ring: *const RummelplatzRing,
+) -> *mut RummelplatzStopHandle {
+    if ring.is_null() {
+        return std::ptr::null_mut();
+    }
+    Box::into_raw(Box::new(RummelplatzStopHandle {
+        stop: (*ring).stop.clone(),
+    }))
+}
+
+/// Stops the ring of `handle`, [`rummelplatz_ring_run`] returns 0 once everything in flight
+/// was canceled. Returns 0 or a negative errno (`-EPIPE` if the ring is gone).
+///
+/// # Safety
+/// `handle` has to come from [`rummelplatz_ring_stop_handle`].
+#[no_mangle]
+pub unsafe extern "C" fn rummelplatz_stop(handle: *const RummelplatzStopHandle) -> c_int {
+    if handle.is_null() {
+        return -libc::EINVAL;
+    }
+    match (*handle).stop.send(()) {
+        Ok(()) => 0,
+        Err(_) => -libc::EPIPE,
+    }
+}
+
+/// # Safety
+/// `handle` has to come from [`rummelplatz_ring_stop_handle`] and is invalid afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn rummelplatz_stop_handle_free(handle: *mut RummelplatzStopHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Frees `ring`. Requests queued but never run are dropped without calling their callbacks.
+///
+/// # Safety
+/// `ring` has to come from [`rummelplatz_ring_new`], must not be running and is invalid
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn rummelplatz_ring_free(ring: *mut RummelplatzRing) {
+    if !ring.is_null() {
+        drop(Box::from_raw(ring));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    use io_uring::opcode::{Nop, PollAdd};
+    use io_uring::types::Fd;
+
+    use super::*;
+
+    type Calls = RefCell<Vec<(i32, u32)>>;
+
+    unsafe extern "C" fn exit(user: *mut c_void, result: i32, flags: u32) -> c_int {
+        (*(user as *const Calls)).borrow_mut().push((result, flags));
+        RUMMELPLATZ_EXIT
+    }
+
+    unsafe extern "C" fn record(user: *mut c_void, result: i32, flags: u32) -> c_int {
+        (*(user as *const Calls)).borrow_mut().push((result, flags));
+        RUMMELPLATZ_CONTINUE
+    }
+
+    unsafe fn submit(
+        ring: *mut RummelplatzRing,
+        entry: io_uring::squeue::Entry,
+        callback: RummelplatzCallback,
+        calls: &Calls,
+    ) {
+        let sqe = &entry as *const io_uring::squeue::Entry as *const c_void;
+        let user = calls as *const Calls as *mut c_void;
+        assert_eq!(rummelplatz_ring_submit(ring, sqe, Some(callback), user), 0);
+    }
+
+    #[test]
+    fn nop_callback_exits_the_ring() {
+        let calls = Calls::default();
+        unsafe {
+            let ring = rummelplatz_ring_new(8);
+            assert!(!ring.is_null());
+            submit(ring, Nop::new().build(), exit, &calls);
+            assert_eq!(rummelplatz_ring_run(ring), 0);
+            rummelplatz_ring_free(ring);
+        }
+        assert_eq!(calls.take(), [(0, 0)]);
+    }
+
+    #[test]
+    fn multishot_request_is_canceled_on_exit() {
+        let eventfd = unsafe { libc::eventfd(1, libc::EFD_CLOEXEC) };
+        assert!(eventfd >= 0, "{}", io::Error::last_os_error());
+        let eventfd = unsafe { OwnedFd::from_raw_fd(eventfd) };
+        let (nop, poll) = (Calls::default(), Calls::default());
+        unsafe {
+            let ring = rummelplatz_ring_new(8);
+            submit(ring, Nop::new().build(), exit, &nop);
+            // readable right away, it completes after the nop exited the ring
+            let entry = PollAdd::new(Fd(eventfd.as_raw_fd()), libc::POLLIN as u32)
+                .multi(true)
+                .build();
+            submit(ring, entry, record, &poll);
+            assert_eq!(rummelplatz_ring_run(ring), 0);
+            rummelplatz_ring_free(ring);
+        }
+
+        assert_eq!(nop.take(), [(0, 0)]);
+        let poll = poll.take();
+        let (last, more) = poll.split_last().unwrap();
+        assert_eq!(last.0, -libc::ECANCELED);
+        assert!(!io_uring::cqueue::more(last.1));
+        assert!(more.iter().all(|&(_, flags)| io_uring::cqueue::more(flags)));
+    }
+}
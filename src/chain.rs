@@ -0,0 +1,158 @@
+//! Tracing spans over the requests that make up one logical operation.
+//!
+//! A [`Chain`] is a `tracing` span keyed by an id, e.g. of a connection or of a request that
+//! is served by several SQEs. An operation opens one and carries a [`ChainStep`] in the
+//! `RingData` of every SQE it submits for it: the step is a child span from the submission to
+//! the last completion of the SQE, so a read, the processing of its data, the write of the
+//! response and its fsync show up as one trace with timed children:
+//!
+//! ```
+//! use rummelplatz::chain::{Chain, ChainStep};
+//!
+//! #[derive(Debug)]
+//! enum Data {
+//!     Read(ChainStep),
+//!     Write(ChainStep),
+//! }
+//!
+//! // on accept
+//! let chain = Chain::new(42);
+//! let data = Data::Read(chain.step("read"));
+//! // submit the read with `data`, then on its completion:
+//! let Data::Read(step) = data else { unreachable!() };
+//! let chain = step.complete(512);
+//! chain.in_scope(|| {
+//!     // processing, events logged here belong to the chain
+//! });
+//! let data = Data::Write(chain.step("write"));
+//! # drop(data);
+//! ```
+//!
+//! Spans and events are at `DEBUG` level, a chain costs next to nothing while that level is
+//! disabled. The chain span closes once the last step and clone of it are dropped.
+
+use std::fmt::{Debug, Formatter};
+use std::time::Instant;
+
+use tracing::{debug, debug_span, field, Span};
+
+/// A span keyed by `chain.id` the steps of a logical operation are children of, cheap to
+/// clone.
+#[derive(Clone)]
+pub struct Chain {
+    id: u64,
+    span: Span,
+}
+
+impl Debug for Chain {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Chain").field(&self.id).finish()
+    }
+}
+
+impl Chain {
+    /// Opens a span `chain` with the field `chain.id`, a child of the current span.
+    pub fn new(id: u64) -> Self {
+        Self {
+            id,
+            span: debug_span!("chain", chain.id = id),
+        }
+    }
+
+    /// A chain using `span`, e.g. one with more fields or of the connection the chain is part of.
+    pub fn with_span(id: u64, span: Span) -> Self {
+        Self { id, span }
+    }
+
+    #[inline]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    #[inline]
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Runs `f` inside the chain span, for the work between two steps.
+    pub fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.span.in_scope(f)
+    }
+
+    /// Opens the span of a step named `name`, to carry in the `RingData` of the SQE of it.
+    pub fn step(&self, name: &'static str) -> ChainStep {
+        let span = debug_span!(
+            parent: &self.span,
+            "step",
+            step = name,
+            result = field::Empty,
+        );
+        ChainStep {
+            chain: self.clone(),
+            name,
+            span,
+            submitted: Instant::now(),
+            completions: 0,
+        }
+    }
+}
+
+/// The span of one SQE of a [`Chain`], from its submission until [`ChainStep::complete`].
+pub struct ChainStep {
+    chain: Chain,
+    name: &'static str,
+    span: Span,
+    submitted: Instant,
+    completions: u32,
+}
+
+impl Debug for ChainStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChainStep")
+            .field("chain", &self.chain.id)
+            .field("step", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ChainStep {
+    #[inline]
+    pub fn chain(&self) -> &Chain {
+        &self.chain
+    }
+
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    #[inline]
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Logs a completion of a multishot SQE that stays in flight.
+    pub fn more(&mut self, result: i32) {
+        self.completions += 1;
+        debug!(
+            parent: &self.span,
+            result,
+            completion = self.completions,
+            elapsed = ?self.submitted.elapsed(),
+            "completion"
+        );
+    }
+
+    /// Logs the last completion of the SQE with its `result` and closes the step, returns the
+    /// chain for the next one.
+    pub fn complete(self, result: i32) -> Chain {
+        self.span.record("result", result);
+        debug!(
+            parent: &self.span,
+            result,
+            elapsed = ?self.submitted.elapsed(),
+            "completed"
+        );
+        self.chain
+    }
+}
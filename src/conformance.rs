@@ -0,0 +1,120 @@
+//! Checking operations against the contract of [`RingOperation`](crate::RingOperation) without
+//! the kernel executing anything.
+//!
+//! With the `conformance` feature, every ring generated by [`ring!`](crate::ring) has
+//! `Ring::check_conformance(rounds)`. Like a [replay](crate::journal), it replaces the SQEs the
+//! operations push with `Nop`s that post no completion and posts the completions itself: each
+//! round completes every request pushed before it with result 0, handing the operation its own
+//! data. After `rounds` rounds, or once no request is waiting, the ring tears down and every
+//! request still waiting completes with `-ECANCELED` in `on_teardown_completion`, as if the
+//! kernel cancelled it.
+//!
+//! The check fails with the error of the ring if an operation fails to set up, fails on a
+//! completion or on its cancellation:
+//!
+//! ```no_run
+//! # rummelplatz::ring! { my_ring -> rummelplatz::ops::Error, tick: rummelplatz::ops::TickOp }
+//! # #[cfg(feature = "conformance")]
+//! # fn test(mut ring: my_ring::Ring) {
+//! // in a test, with a ring built like the one in production
+//! let report = ring.check_conformance(3).unwrap();
+//! assert_eq!(report.leaked, 0, "{report:?}");
+//! # }
+//! ```
+//!
+//! Buffers are not filled and no fds are created, a result of 0 may read as the end of a file
+//! or of a connection to the operations. Entries pushed raw, without data, are executed.
+
+/// The outcome of `Ring::check_conformance`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ConformanceReport {
+    /// Requests the operations pushed with data
+    pub submitted: usize,
+    /// Completions with result 0 posted to the ring
+    pub completions: usize,
+    /// Requests completed with `-ECANCELED` on teardown
+    pub cancelled: usize,
+    /// Requests whose data was not handed back to their operation, pushed by
+    /// `on_teardown_completion`
+    pub leaked: usize,
+}
+
+#[cfg(all(test, feature = "conformance"))]
+// the ring is generated inside the crate, its unused parts are not exempt from lints
+#[allow(dead_code, unused_imports)]
+mod tests {
+    use std::cell::Cell;
+    use std::num::NonZeroU32;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use crate::ops::{CloseOp, FsyncOp, TickOp};
+    use crate::ControlFlow;
+
+    crate::ring! {
+        ops_ring -> crate::ops::Error,
+        tick: crate::ops::TickOp,
+        fsync: crate::ops::FsyncOp,
+        close: crate::ops::CloseOp
+    }
+
+    #[test]
+    fn built_in_operations_conform() {
+        let fsync = FsyncOp::new();
+        let synced = Rc::new(Cell::new(false));
+        {
+            let synced = synced.clone();
+            // replaced by a `Nop` like every other SQE, the fd is never used
+            fsync
+                .handle()
+                .fsync(-1, move |result| synced.set(result.is_ok()));
+        }
+        let close = CloseOp::new();
+        close.handle().close_fixed(0);
+
+        let mut ring = ops_ring::Ring::builder()
+            .tick(TickOp::new(Duration::from_secs(60), |_| {
+                ControlFlow::Continue
+            }))
+            .fsync(fsync)
+            .close(close)
+            .build()
+            .unwrap();
+        let report = ring.check_conformance(3).unwrap();
+
+        assert!(synced.get());
+        assert_eq!(ring.operations().close.closed(), 1);
+        assert!(report.submitted >= 3, "{report:?}");
+        assert!(report.completions >= 3, "{report:?}");
+        assert_eq!(report.leaked, 0, "{report:?}");
+    }
+
+    #[test]
+    fn rounds_span_iterations_when_the_queue_is_full() {
+        let fsync = FsyncOp::new();
+        let synced = Rc::new(Cell::new(0));
+        for fd in 0..8 {
+            let synced = synced.clone();
+            fsync.handle().fsync(-1 - fd, move |result| {
+                if result.is_ok() {
+                    synced.set(synced.get() + 1);
+                }
+            });
+        }
+
+        // the completions of the single round do not fit into the submission queue at once
+        let mut ring = ops_ring::Ring::builder()
+            .ring_size(NonZeroU32::new(2).unwrap())
+            .tick(TickOp::new(Duration::from_secs(60), |_| {
+                ControlFlow::Continue
+            }))
+            .fsync(fsync)
+            .close(CloseOp::new())
+            .build()
+            .unwrap();
+        let report = ring.check_conformance(1).unwrap();
+
+        assert_eq!(synced.get(), 8, "{report:?}");
+        assert_eq!(report.leaked, 0, "{report:?}");
+    }
+}
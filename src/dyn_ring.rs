@@ -0,0 +1,618 @@
+//! Rings composed at runtime instead of with [`ring!`](crate::ring).
+//!
+//! A [`DynRing`] holds a registry of type-erased operations: any [`RingOperation`] whose data
+//! and errors are `'static` can be registered (or a [`DynOperation`] implemented directly,
+//! e.g. by plugins loaded at runtime). The data of every request is boxed as `Box<dyn Any>` and
+//! handed back to the operation that pushed it, its errors are boxed as `Box<dyn Debug>`. That
+//! costs an allocation and a dynamic call per completion more than a generated ring:
+//!
+//! ```no_run
+//! # use std::num::NonZeroU32;
+//! # use std::time::Duration;
+//! # use rummelplatz::dyn_ring::{DynOperation, DynRing, DynRingError};
+//! # use rummelplatz::ops::TickOp;
+//! # use rummelplatz::ControlFlow;
+//! # struct Plugin;
+//! # impl Plugin {
+//! #     fn name(&self) -> &'static str { unimplemented!() }
+//! #     fn operation(self) -> Box<dyn DynOperation> { unimplemented!() }
+//! # }
+//! # fn load_plugins() -> Vec<Plugin> { vec![] }
+//! # fn example() -> Result<(), DynRingError> {
+//! let mut ring = DynRing::new(DynRing::new_raw_ring(NonZeroU32::new(128).unwrap())?, None);
+//! ring.register("tick", TickOp::new(Duration::from_secs(1), |_| ControlFlow::Continue));
+//! for plugin in load_plugins() {
+//!     ring.register_boxed(plugin.name(), plugin.operation());
+//! }
+//! ring.run()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Operations registered while the ring runs are set up at the beginning of the next step.
+//! Rings generated by `ring!` take dynamic operations next to their declared ones with their
+//! `register` and `register_boxed` in the same way.
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+
+use io_uring::cqueue::{self, Entry};
+use io_uring::squeue::{EntryMarker, PushError};
+use io_uring::IoUring;
+use tracing::{debug, error, trace, warn};
+
+use crate::message::{MessageTarget, RingMessage, MAX_OPERATIONS, WAKE_OPERATION};
+use crate::user_data::UserDataToken;
+use crate::{ControlFlow, RingOperation, RingStep, SubmissionQueueSubmitter};
+
+/// Data of a request of a [`DynOperation`].
+pub type DynData = Box<dyn Any>;
+
+/// Warning or error of a [`DynOperation`].
+pub type DynError = Box<dyn Debug>;
+
+/// The submitter handed to a [`DynOperation`].
+pub type DynSubmitter<'a, 'b, 'c, 'w, S = io_uring::squeue::Entry> =
+    SubmissionQueueSubmitter<'a, 'b, 'c, DynData, &'w dyn Fn(&mut S, DynData), S>;
+
+/// A [`RingOperation`] with its data and errors erased, see [`dyn_ring`](self). Implemented for
+/// every `RingOperation` with `'static` data and errors, for rings with 128 byte SQEs as
+/// `DynOperation<Entry128, Entry32>`.
+pub trait DynOperation<S: EntryMarker = io_uring::squeue::Entry, C: cqueue::EntryMarker = Entry>:
+    Debug
+{
+    fn setup(&mut self, submitter: DynSubmitter<'_, '_, '_, '_, S>) -> Result<(), DynError>;
+    fn on_completion(
+        &mut self,
+        completion_entry: C,
+        ring_data: DynData,
+        submitter: DynSubmitter<'_, '_, '_, '_, S>,
+    ) -> (ControlFlow<DynError, DynError>, Option<DynData>);
+    fn on_teardown_completion(
+        &mut self,
+        completion_entry: C,
+        ring_data: DynData,
+        submitter: DynSubmitter<'_, '_, '_, '_, S>,
+    ) -> Result<(), DynError>;
+    fn housekeeping(
+        &mut self,
+        submitter: DynSubmitter<'_, '_, '_, '_, S>,
+    ) -> ControlFlow<DynError, DynError>;
+    fn on_message(
+        &mut self,
+        message: RingMessage,
+        submitter: DynSubmitter<'_, '_, '_, '_, S>,
+    ) -> ControlFlow<DynError, DynError>;
+}
+
+fn erase_flow<W: Debug + 'static, E: Debug + 'static>(
+    flow: ControlFlow<W, E>,
+) -> ControlFlow<DynError, DynError> {
+    match flow {
+        ControlFlow::Continue => ControlFlow::Continue,
+        ControlFlow::Exit => ControlFlow::Exit,
+        ControlFlow::Warn(w) => ControlFlow::Warn(Box::new(w)),
+        ControlFlow::Error(e) => ControlFlow::Error(Box::new(e)),
+    }
+}
+
+fn erase_data<D: 'static>(data: D) -> DynData {
+    Box::new(data)
+}
+
+fn restore_data<D: 'static>(data: DynData) -> D {
+    // requests are routed to the operation that pushed them
+    *data
+        .downcast::<D>()
+        .expect("ring data of another operation")
+}
+
+impl<S, C, T> DynOperation<S, C> for T
+where
+    S: EntryMarker,
+    C: cqueue::EntryMarker,
+    T: RingOperation<S, C>,
+    T::RingData: 'static,
+    T::SetupError: Debug + 'static,
+    T::TeardownError: Debug + 'static,
+    T::ControlFlowWarn: Debug + 'static,
+    T::ControlFlowError: Debug + 'static,
+{
+    fn setup(&mut self, mut submitter: DynSubmitter<'_, '_, '_, '_, S>) -> Result<(), DynError> {
+        RingOperation::<S, C>::setup(self, submitter.map_data(erase_data))
+            .map_err(|e| Box::new(e) as DynError)
+    }
+
+    fn on_completion(
+        &mut self,
+        completion_entry: C,
+        ring_data: DynData,
+        mut submitter: DynSubmitter<'_, '_, '_, '_, S>,
+    ) -> (ControlFlow<DynError, DynError>, Option<DynData>) {
+        let (flow, data) = RingOperation::<S, C>::on_completion(
+            self,
+            completion_entry,
+            restore_data(ring_data),
+            submitter.map_data(erase_data),
+        );
+        (erase_flow(flow), data.map(erase_data))
+    }
+
+    fn on_teardown_completion(
+        &mut self,
+        completion_entry: C,
+        ring_data: DynData,
+        mut submitter: DynSubmitter<'_, '_, '_, '_, S>,
+    ) -> Result<(), DynError> {
+        RingOperation::<S, C>::on_teardown_completion(
+            self,
+            completion_entry,
+            restore_data(ring_data),
+            submitter.map_data(erase_data),
+        )
+        .map_err(|e| Box::new(e) as DynError)
+    }
+
+    fn housekeeping(
+        &mut self,
+        mut submitter: DynSubmitter<'_, '_, '_, '_, S>,
+    ) -> ControlFlow<DynError, DynError> {
+        erase_flow(RingOperation::<S, C>::housekeeping(
+            self,
+            submitter.map_data(erase_data),
+        ))
+    }
+
+    fn on_message(
+        &mut self,
+        message: RingMessage,
+        mut submitter: DynSubmitter<'_, '_, '_, '_, S>,
+    ) -> ControlFlow<DynError, DynError> {
+        erase_flow(RingOperation::<S, C>::on_message(
+            self,
+            message,
+            submitter.map_data(erase_data),
+        ))
+    }
+}
+
+/// Index of an operation registered with a [`DynRing`] or with `Ring::register` of a ring
+/// generated by `ring!`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct OperationId(usize);
+
+impl OperationId {
+    #[doc(hidden)]
+    #[inline]
+    pub fn new(index: usize) -> Self {
+        Self(index)
+    }
+
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DynRingError {
+    #[error("ring operation {operation} setup failed: {error:?}")]
+    Setup {
+        operation: &'static str,
+        error: DynError,
+    },
+
+    #[error("ring operation {operation} failed to complete: {error:?}")]
+    Completion {
+        operation: &'static str,
+        error: DynError,
+    },
+
+    #[error("ring operation {operation} teardown failed: {error:?}")]
+    Teardown {
+        operation: &'static str,
+        error: DynError,
+    },
+
+    #[error("ring api error: {0}")]
+    Api(#[from] std::io::Error),
+
+    #[error("unable to push to submission queue: {0}")]
+    Push(#[from] PushError),
+}
+
+struct UserData {
+    operation: usize,
+    data: DynData,
+}
+
+fn wrap(entry: &mut io_uring::squeue::Entry, operation: usize, data: DynData) {
+    let user_data = UserDataToken::new(Box::new(UserData { operation, data })).into_user_data();
+    take_mut::take(entry, |entry| entry.user_data(user_data));
+}
+
+/// A ring running the operations registered at runtime, see [`dyn_ring`](self).
+pub struct DynRing {
+    ring: IoUring,
+    backlog: VecDeque<Box<[io_uring::squeue::Entry]>>,
+    backlog_limit: Option<NonZeroUsize>,
+    operations: Vec<(&'static str, Box<dyn DynOperation>)>,
+    // operations whose setup ran, the ones registered later are set up by the next step
+    set_up: usize,
+    readiness: Option<OwnedFd>,
+    finished: bool,
+}
+
+impl Debug for DynRing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DynRing")
+            .field("backlog_limit", &self.backlog_limit)
+            .field("backlog", &self.backlog)
+            .field("operations", &self.operations)
+            .finish()
+    }
+}
+
+impl DynRing {
+    /// A ring set up like the ones of `ring!`.
+    pub fn new_raw_ring(ring_size: NonZeroU32) -> std::io::Result<IoUring> {
+        IoUring::builder()
+            .setup_single_issuer()
+            .setup_coop_taskrun()
+            .setup_defer_taskrun()
+            .build(ring_size.get())
+    }
+
+    pub fn new(ring: IoUring, backlog_limit: Option<NonZeroUsize>) -> Self {
+        Self {
+            ring,
+            backlog: Default::default(),
+            backlog_limit,
+            operations: vec![],
+            set_up: 0,
+            readiness: None,
+            finished: false,
+        }
+    }
+
+    /// Registers `operation` under `name`, used in errors and logs.
+    pub fn register<T>(&mut self, name: &'static str, operation: T) -> OperationId
+    where
+        T: RingOperation + 'static,
+        T::RingData: 'static,
+        T::SetupError: Debug + 'static,
+        T::TeardownError: Debug + 'static,
+        T::ControlFlowWarn: Debug + 'static,
+        T::ControlFlowError: Debug + 'static,
+    {
+        self.register_boxed(name, Box::new(operation))
+    }
+
+    /// Registers an already erased operation under `name`.
+    pub fn register_boxed(
+        &mut self,
+        name: &'static str,
+        operation: Box<dyn DynOperation>,
+    ) -> OperationId {
+        self.operations.push((name, operation));
+        OperationId(self.operations.len() - 1)
+    }
+
+    /// The operations registered so far, with their names.
+    pub fn operations(&self) -> impl Iterator<Item = (OperationId, &'static str)> + '_ {
+        self.operations
+            .iter()
+            .enumerate()
+            .map(|(index, (name, _))| (OperationId(index), *name))
+    }
+
+    /// Address of `operation` on this ring for messages sent from other rings. Only the first
+    /// [`MAX_OPERATIONS`] operations can be addressed.
+    pub fn message_target(&self, operation: OperationId) -> Option<MessageTarget> {
+        (operation.0 < MAX_OPERATIONS)
+            .then(|| MessageTarget::new(self.ring.as_raw_fd(), operation.0 as u8))
+    }
+
+    /// Sets the operations up and runs the ring until an operation exits it or fails, then
+    /// cancels everything still in flight.
+    pub fn run(&mut self) -> Result<(), DynRingError> {
+        loop {
+            if let RingStep::Finished = self.step(1)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Like one iteration of [`DynRing::run`] without blocking, see `Ring::run_step` of the
+    /// rings generated by `ring!`.
+    pub fn run_step(&mut self) -> Result<RingStep, DynRingError> {
+        if let Some(fd) = &self.readiness {
+            crate::reset_readiness_fd(fd);
+        }
+        self.step(0)
+    }
+
+    /// An eventfd that is readable while this ring has completions [`DynRing::run_step`] did
+    /// not handle yet, created and registered with the ring on the first call.
+    pub fn readiness_fd(&mut self) -> std::io::Result<RawFd> {
+        if self.readiness.is_none() {
+            self.readiness = Some(crate::register_readiness_fd(&self.ring)?);
+        }
+        Ok(self.readiness.as_ref().unwrap().as_raw_fd())
+    }
+
+    fn step(&mut self, want: usize) -> Result<RingStep, DynRingError> {
+        if self.finished {
+            return Ok(RingStep::Finished);
+        }
+
+        let result = match self.setup().and_then(|()| self.iteration(want)) {
+            Ok(std::ops::ControlFlow::Continue(completions)) => {
+                return Ok(RingStep::Running { completions });
+            }
+            Ok(std::ops::ControlFlow::Break(result)) => result,
+            Err(e) => {
+                self.finished = true;
+                return Err(e);
+            }
+        };
+
+        self.finished = true;
+        self.teardown(result)?;
+        Ok(RingStep::Finished)
+    }
+
+    fn setup(&mut self) -> Result<(), DynRingError> {
+        let (_, mut sq, _) = self.ring.split();
+
+        while let Some((name, operation)) = self.operations.get_mut(self.set_up) {
+            let index = self.set_up;
+            let wrapper = move |e: &mut io_uring::squeue::Entry, d| wrap(e, index, d);
+            self.set_up += 1;
+            if let Err(error) = operation.setup(SubmissionQueueSubmitter::new(
+                &mut sq,
+                &mut self.backlog,
+                self.backlog_limit,
+                &wrapper,
+            )) {
+                return Err(DynRingError::Setup {
+                    operation: name,
+                    error,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn iteration(
+        &mut self,
+        want: usize,
+    ) -> Result<std::ops::ControlFlow<Result<(), DynRingError>, usize>, DynRingError> {
+        let (submit, mut sq, mut cq) = self.ring.split();
+
+        sq.sync();
+        match want {
+            0 => crate::submit_and_collect(&submit, sq.len())?,
+            want => submit.submit_and_wait(want)?,
+        };
+
+        sq.sync();
+        while let Some(entries) = self.backlog.pop_front() {
+            trace!("push from backlog");
+            if unsafe { sq.push_multiple(&entries) }.is_err() {
+                self.backlog.push_front(entries);
+                break;
+            }
+        }
+
+        cq.sync();
+        let completions = cq.len();
+        for cqe in cq.by_ref() {
+            trace!("> CQE: {cqe:?}");
+            if cqe.user_data() == 0 {
+                continue;
+            }
+
+            if let Some((operation, message)) = RingMessage::decode(&cqe) {
+                if operation == WAKE_OPERATION {
+                    continue;
+                }
+                let Some((name, op)) = self.operations.get_mut(operation as usize) else {
+                    warn!("dropped message for unknown operation {operation}: {message:?}");
+                    continue;
+                };
+                let index = operation as usize;
+                let wrapper = move |e: &mut io_uring::squeue::Entry, d| wrap(e, index, d);
+                let flow = op.on_message(
+                    message,
+                    SubmissionQueueSubmitter::new(
+                        &mut sq,
+                        &mut self.backlog,
+                        self.backlog_limit,
+                        &wrapper,
+                    ),
+                );
+                match flow {
+                    ControlFlow::Exit => return Ok(std::ops::ControlFlow::Break(Ok(()))),
+                    ControlFlow::Error(error) => {
+                        return Ok(std::ops::ControlFlow::Break(Err(
+                            DynRingError::Completion {
+                                operation: name,
+                                error,
+                            },
+                        )));
+                    }
+                    ControlFlow::Warn(e) => warn!("{name} unable to handle ring message: {e:?}"),
+                    ControlFlow::Continue => {}
+                }
+                continue;
+            }
+
+            let mut user_data =
+                unsafe { UserDataToken::<UserData>::from_user_data(cqe.user_data()) }.into_box();
+            let index = user_data.operation;
+            let (name, op) = &mut self.operations[index];
+            let wrapper = move |e: &mut io_uring::squeue::Entry, d| wrap(e, index, d);
+            let data = std::mem::replace(&mut user_data.data, Box::new(()));
+            let (flow, new_data) = op.on_completion(
+                cqe,
+                data,
+                SubmissionQueueSubmitter::new(
+                    &mut sq,
+                    &mut self.backlog,
+                    self.backlog_limit,
+                    &wrapper,
+                ),
+            );
+            if let Some(new_data) = new_data {
+                user_data.data = new_data;
+                // still owned by the request
+                UserDataToken::new(user_data).into_user_data();
+            }
+
+            match flow {
+                ControlFlow::Exit => return Ok(std::ops::ControlFlow::Break(Ok(()))),
+                ControlFlow::Error(error) => {
+                    return Ok(std::ops::ControlFlow::Break(Err(
+                        DynRingError::Completion {
+                            operation: name,
+                            error,
+                        },
+                    )));
+                }
+                ControlFlow::Warn(e) => {
+                    warn!("{name} unable to handle ring completion entry: {e:?}")
+                }
+                ControlFlow::Continue => {}
+            }
+        }
+
+        for (index, (name, op)) in self.operations.iter_mut().enumerate() {
+            let wrapper = move |e: &mut io_uring::squeue::Entry, d| wrap(e, index, d);
+            match op.housekeeping(SubmissionQueueSubmitter::new(
+                &mut sq,
+                &mut self.backlog,
+                self.backlog_limit,
+                &wrapper,
+            )) {
+                ControlFlow::Exit => return Ok(std::ops::ControlFlow::Break(Ok(()))),
+                ControlFlow::Error(error) => {
+                    return Ok(std::ops::ControlFlow::Break(Err(
+                        DynRingError::Completion {
+                            operation: name,
+                            error,
+                        },
+                    )));
+                }
+                ControlFlow::Warn(e) => warn!("{name} ring operation housekeeping: {e:?}"),
+                ControlFlow::Continue => {}
+            }
+        }
+
+        Ok(std::ops::ControlFlow::Continue(completions))
+    }
+
+    /// Cancels everything in flight and hands the final completions to the operations.
+    fn teardown(&mut self, mut result: Result<(), DynRingError>) -> Result<(), DynRingError> {
+        let (submit, mut sq, mut cq) = self.ring.split();
+
+        debug!("shutting down ring...");
+        // backlogged requests never reach the kernel, they are cancelled right away
+        let mut cancelled: Vec<io_uring::cqueue::Entry> =
+            crate::backlog_user_data(&mut self.backlog)
+                .into_iter()
+                .map(crate::cancelled_cqe)
+                .collect();
+        crate::push_teardown(&submit, &mut sq)?;
+
+        'cancel_loop: loop {
+            sq.sync();
+            submit.submit_and_wait(1)?;
+
+            cq.sync();
+            for cqe in cancelled.drain(..).chain(cq.by_ref()) {
+                trace!("> CQE: {cqe:?}");
+                if cqe.user_data() == 0 {
+                    continue;
+                }
+                if cqe.user_data() == crate::TEARDOWN_DONE {
+                    break 'cancel_loop;
+                }
+                if let Some((operation, message)) = RingMessage::decode(&cqe) {
+                    debug!("dropped message for operation {operation} on teardown: {message:?}");
+                    continue;
+                }
+
+                let mut user_data =
+                    unsafe { UserDataToken::<UserData>::from_user_data(cqe.user_data()) }
+                        .into_box();
+                let index = user_data.operation;
+                let (name, op) = &mut self.operations[index];
+                let wrapper = move |e: &mut io_uring::squeue::Entry, d| wrap(e, index, d);
+                let submitter: DynSubmitter = SubmissionQueueSubmitter::new(
+                    &mut sq,
+                    &mut self.backlog,
+                    self.backlog_limit,
+                    &wrapper,
+                );
+
+                // a multishot request that is not finished yet still owns its user data,
+                // it is completed like during normal operation
+                if io_uring::cqueue::more(cqe.flags()) {
+                    let data = std::mem::replace(&mut user_data.data, Box::new(()));
+                    let (flow, new_data) = op.on_completion(cqe, data, submitter);
+                    if let Some(new_data) = new_data {
+                        user_data.data = new_data;
+                        UserDataToken::new(user_data).into_user_data();
+                    }
+                    match flow {
+                        ControlFlow::Warn(e) => {
+                            warn!(
+                                "{name} unable to handle ring completion entry on teardown: {e:?}"
+                            )
+                        }
+                        ControlFlow::Error(e) => {
+                            error!(
+                                "{name} unable to handle ring completion entry on teardown: {e:?}"
+                            )
+                        }
+                        ControlFlow::Continue | ControlFlow::Exit => {}
+                    }
+                    continue;
+                }
+
+                if let Err(error) = op.on_teardown_completion(cqe, user_data.data, submitter) {
+                    error!("{name} unable to handle ring completion entry on teardown: {error:?}");
+                    result = Err(DynRingError::Teardown {
+                        operation: name,
+                        error,
+                    });
+                }
+            }
+        }
+
+        // requests pushed on teardown that did not fit into the queue are dropped with their data
+        let unsubmitted = crate::backlog_user_data(&mut self.backlog);
+        if !unsubmitted.is_empty() {
+            warn!(
+                "{} requests pushed on teardown were never submitted",
+                unsubmitted.len()
+            );
+        }
+        for user_data in unsubmitted {
+            drop(unsafe { UserDataToken::<UserData>::from_user_data(user_data) });
+        }
+        debug!("ring finished: {result:?}");
+        result
+    }
+}
+
+impl AsRawFd for DynRing {
+    fn as_raw_fd(&self) -> RawFd {
+        self.ring.as_raw_fd()
+    }
+}
@@ -0,0 +1,59 @@
+//! Running rings inside the event loop of another runtime.
+//!
+//! Every ring generated by [`ring!`](crate::ring) can be driven step by step: its
+//! `readiness_fd` becomes readable when completions arrive and `run_step` handles them without
+//! blocking. The adapters in here wait for the readiness fd with the reactor of the runtime
+//! and call the step in between, so the ring runs on the runtime's thread instead of a
+//! dedicated one.
+
+#[cfg(feature = "async-io")]
+pub use self::async_io::run_async_io;
+
+#[cfg(feature = "async-io")]
+mod async_io {
+    use std::io;
+    use std::os::fd::{AsFd, BorrowedFd, RawFd};
+
+    use ::async_io::Async;
+    use tracing::trace;
+
+    use crate::RingStep;
+
+    /// The readiness fd stays owned by its ring.
+    struct ReadinessFd(RawFd);
+
+    impl AsFd for ReadinessFd {
+        fn as_fd(&self) -> BorrowedFd<'_> {
+            unsafe { BorrowedFd::borrow_raw(self.0) }
+        }
+    }
+
+    /// Drives a ring from an `async` task on the `async-io` reactor (smol, async-std, ...)
+    /// until an operation exits it: registers `readiness_fd` (see `Ring::readiness_fd`) and
+    /// calls `step` (`Ring::run_step`) whenever it is readable.
+    ///
+    /// ```no_run
+    /// # rummelplatz::ring! { my_ring, tick: rummelplatz::ops::TickOp }
+    /// # use rummelplatz::ops::Error;
+    /// # async fn example(mut ring: my_ring::Ring) -> Result<(), my_ring::RingError<Error, Error, Error>> {
+    /// let readiness_fd = ring.readiness_fd()?;
+    /// rummelplatz::embed::run_async_io(readiness_fd, || ring.run_step()).await
+    /// # }
+    /// ```
+    ///
+    /// Rings are not `Send`, spawn the task on a local executor (e.g. `smol::LocalExecutor`) or
+    /// `block_on` it. `step` blocks while the ring tears down once it exits.
+    pub async fn run_async_io<E: From<io::Error>>(
+        readiness_fd: RawFd,
+        mut step: impl FnMut() -> Result<RingStep, E>,
+    ) -> Result<(), E> {
+        let readiness = Async::new_nonblocking(ReadinessFd(readiness_fd))?;
+        loop {
+            if let RingStep::Finished = step()? {
+                return Ok(());
+            }
+            readiness.readable().await?;
+            trace!("ring is readable");
+        }
+    }
+}
@@ -0,0 +1,769 @@
+//! Recording the SQEs and CQEs of a ring and replaying the completions against its operations.
+//!
+//! A [`Journal`] attached with `Ring::record_journal` keeps every SQE the operations push (the
+//! 64 bytes of the entry, not the buffers it points to) and every CQE the ring handles, either
+//! in memory or streamed to a file. `Ring::replay` feeds the completions of a journal to a
+//! fresh ring with the same operations in the recorded order, so a completion ordering that
+//! only happened in production can be reproduced in a test:
+//!
+//! ```no_run
+//! # rummelplatz::ring! { my_ring, tick: rummelplatz::ops::TickOp }
+//! use rummelplatz::journal::Journal;
+//!
+//! # fn production(mut ring: my_ring::Ring) -> std::io::Result<()> {
+//! ring.record_journal(Journal::create("ring.journal")?);
+//! # Ok(())
+//! # }
+//! # fn test(mut ring: my_ring::Ring) -> std::io::Result<()> {
+//! // in the test, with a ring built like the one in production
+//! let entries = Journal::load("ring.journal")?;
+//! let report = ring
+//!     .replay::<rummelplatz::ops::Error, rummelplatz::ops::Error, rummelplatz::ops::Error>(entries)
+//!     .unwrap();
+//! assert!(report.diverged.is_none(), "{report:?}");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! During a replay the SQEs of the operations are replaced with `Nop`s that post no
+//! completion, nothing is executed by the kernel. The n-th SQE pushed in the replay stands in
+//! for the n-th SQE of the journal, and the recorded completions are posted to the ring with
+//! `IORING_OP_MSG_RING`, result and flags included. Buffers are not filled, operations see the
+//! results but not the data of the recorded completions; fds passed between rings are not
+//! installed. Replaying works as long as the operations push the same SQEs in the same order
+//! for the same completions, the replay stops at the first SQE that differs. Entries pushed
+//! raw, without data, are neither recorded nor replaced.
+//!
+//! With the `serde` feature, rings whose operation data is `serde::Serialize` get
+//! `Ring::serialize_data`, which records the data of every SQE as JSON next to it, e.g.
+//! `{"read":{"fd":7,"offset":4096}}`, so a journal tells which request an entry was.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::os::fd::RawFd;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use io_uring::opcode::{MsgRingData, Nop};
+use io_uring::squeue::Flags;
+use io_uring::types::Fd;
+use io_uring::{cqueue, squeue, SubmissionQueue};
+use tracing::{debug, warn};
+
+use crate::conformance::ConformanceReport;
+use crate::message::{MessageTarget, OutgoingMessage, RingMessage};
+
+/// Payloads of the messages a replay sends to its ring with `WAKE_OPERATION`
+const REPLAY_SYNC: u64 = 1;
+const REPLAY_END: u64 = 2;
+
+/// A submission or completion recorded by a [`Journal`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum JournalEntry {
+    Submission {
+        /// Time since the journal was created
+        at: Duration,
+        /// Name of the operation in `ring!`
+        operation: Cow<'static, str>,
+        user_data: u64,
+        /// The raw `io_uring_sqe`
+        sqe: [u8; 64],
+        /// The data the operation attached to the request as JSON, recorded by rings that
+        /// `serialize_data` (feature `serde`)
+        data: Option<String>,
+    },
+    Completion {
+        at: Duration,
+        user_data: u64,
+        result: i32,
+        flags: u32,
+    },
+    /// The ring started to cancel everything in flight, the completions after this are the
+    /// ones of teardown
+    Teardown { at: Duration },
+    /// A journal kept in memory dropped the `count` entries before this one, a replay stops
+    /// here
+    Dropped { count: u64 },
+}
+
+impl JournalEntry {
+    /// Opcode of a submission.
+    pub fn opcode(&self) -> Option<u8> {
+        match self {
+            JournalEntry::Submission { sqe, .. } => Some(sqe[0]),
+            _ => None,
+        }
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            JournalEntry::Submission {
+                at,
+                operation,
+                user_data,
+                sqe,
+                data,
+            } => {
+                write!(writer, "S {} {user_data:x} {operation} ", at.as_nanos())?;
+                for byte in sqe {
+                    write!(writer, "{byte:02x}")?;
+                }
+                match data {
+                    // the rest of the line, JSON written by `serde_json::to_string` has no newlines
+                    Some(data) => writeln!(writer, " {data}"),
+                    None => writeln!(writer),
+                }
+            }
+            JournalEntry::Completion {
+                at,
+                user_data,
+                result,
+                flags,
+            } => writeln!(
+                writer,
+                "C {} {user_data:x} {result} {flags:x}",
+                at.as_nanos()
+            ),
+            JournalEntry::Teardown { at } => writeln!(writer, "T {}", at.as_nanos()),
+            JournalEntry::Dropped { count } => writeln!(writer, "D {count}"),
+        }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        // the data of a submission takes the rest of the line
+        let mut fields = line.splitn(6, ' ');
+        let kind = fields.next()?;
+        if kind == "D" {
+            return Some(JournalEntry::Dropped {
+                count: fields.next()?.parse().ok()?,
+            });
+        }
+        let at = Duration::from_nanos(fields.next()?.parse().ok()?);
+        let entry = match kind {
+            "S" => {
+                let user_data = u64::from_str_radix(fields.next()?, 16).ok()?;
+                let operation = Cow::Owned(fields.next()?.to_string());
+                let hex = fields.next()?;
+                if hex.len() != 128 {
+                    return None;
+                }
+                let mut sqe = [0; 64];
+                for (index, byte) in sqe.iter_mut().enumerate() {
+                    *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+                }
+                JournalEntry::Submission {
+                    at,
+                    operation,
+                    user_data,
+                    sqe,
+                    data: fields.next().map(str::to_string),
+                }
+            }
+            "C" => JournalEntry::Completion {
+                at,
+                user_data: u64::from_str_radix(fields.next()?, 16).ok()?,
+                result: fields.next()?.parse().ok()?,
+                flags: u32::from_str_radix(fields.next()?, 16).ok()?,
+            },
+            "T" => JournalEntry::Teardown { at },
+            _ => return None,
+        };
+        Some(entry)
+    }
+}
+
+enum Sink {
+    Memory {
+        capacity: usize,
+        entries: VecDeque<JournalEntry>,
+        dropped: u64,
+    },
+    File(BufWriter<File>),
+}
+
+struct Inner {
+    epoch: Instant,
+    sink: Sink,
+    // the first write error, recording stops with it
+    error: Option<io::Error>,
+}
+
+impl Inner {
+    fn record(&mut self, entry: JournalEntry) {
+        match &mut self.sink {
+            Sink::Memory {
+                capacity,
+                entries,
+                dropped,
+            } => {
+                if *capacity == 0 {
+                    return;
+                }
+                if entries.len() == *capacity {
+                    entries.pop_front();
+                    *dropped += 1;
+                }
+                entries.push_back(entry);
+            }
+            Sink::File(writer) => {
+                if self.error.is_some() {
+                    return;
+                }
+                if let Err(e) = entry.write(writer) {
+                    warn!("unable to write journal, recording stopped: {e}");
+                    self.error = Some(e);
+                }
+            }
+        }
+    }
+}
+
+/// Cloneable and [`Send`] recorder of the SQEs and CQEs of one ring.
+#[derive(Clone)]
+pub struct Journal {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Debug for Journal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        let mut f = f.debug_struct("Journal");
+        match &inner.sink {
+            Sink::Memory {
+                capacity,
+                entries,
+                dropped,
+            } => f
+                .field("capacity", capacity)
+                .field("entries", &entries.len())
+                .field("dropped", dropped),
+            Sink::File(_) => f.field("file", &true).field("error", &inner.error),
+        };
+        f.finish()
+    }
+}
+
+impl Journal {
+    fn with_sink(sink: Sink) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                epoch: Instant::now(),
+                sink,
+                error: None,
+            })),
+        }
+    }
+
+    /// A journal keeping the last `capacity` entries in memory. Only a journal that did not
+    /// drop entries yet can be replayed, the entries of one that did start with
+    /// [`JournalEntry::Dropped`].
+    pub fn in_memory(capacity: usize) -> Self {
+        Self::with_sink(Sink::Memory {
+            capacity,
+            entries: VecDeque::with_capacity(capacity.min(1 << 16)),
+            dropped: 0,
+        })
+    }
+
+    /// A journal writing every entry to a new file at `path`, buffered until
+    /// [`Journal::flush`] or the last clone is dropped.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::with_sink(Sink::File(BufWriter::new(File::create(
+            path,
+        )?))))
+    }
+
+    #[doc(hidden)]
+    pub fn submitted<E: squeue::EntryMarker>(
+        &self,
+        operation: &'static str,
+        entry: &E,
+        data: Option<String>,
+    ) {
+        // `Entry` is a `repr(C)` `io_uring_sqe`, `Entry128` starts with one and keeps the
+        // command data of its second half out of the journal
+        let entry = unsafe { &*(entry as *const E as *const squeue::Entry) };
+        let sqe = unsafe { *(entry as *const squeue::Entry as *const [u8; 64]) };
+        let mut inner = self.inner.lock().unwrap();
+        let at = inner.epoch.elapsed();
+        inner.record(JournalEntry::Submission {
+            at,
+            operation: Cow::Borrowed(operation),
+            user_data: entry.get_user_data(),
+            sqe,
+            data,
+        });
+    }
+
+    #[doc(hidden)]
+    pub fn completed<E: cqueue::EntryMarker>(&self, entry: &E) {
+        let entry: cqueue::Entry = entry.clone().into();
+        let mut inner = self.inner.lock().unwrap();
+        let at = inner.epoch.elapsed();
+        inner.record(JournalEntry::Completion {
+            at,
+            user_data: entry.user_data(),
+            result: entry.result(),
+            flags: entry.flags(),
+        });
+    }
+
+    #[doc(hidden)]
+    pub fn teardown(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        let at = inner.epoch.elapsed();
+        inner.record(JournalEntry::Teardown { at });
+    }
+
+    /// The entries kept in memory, empty for a journal written to a file. They start with
+    /// [`JournalEntry::Dropped`] if older entries were dropped.
+    pub fn entries(&self) -> Vec<JournalEntry> {
+        match &self.inner.lock().unwrap().sink {
+            Sink::Memory {
+                entries, dropped, ..
+            } => (*dropped > 0)
+                .then_some(JournalEntry::Dropped { count: *dropped })
+                .into_iter()
+                .chain(entries.iter().cloned())
+                .collect(),
+            Sink::File(_) => Vec::new(),
+        }
+    }
+
+    /// Writes the buffered entries of a journal written to a file, fails with the error that
+    /// stopped recording if there was one.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(e) = inner.error.take() {
+            return Err(e);
+        }
+        match &mut inner.sink {
+            Sink::Memory { .. } => Ok(()),
+            Sink::File(writer) => writer.flush(),
+        }
+    }
+
+    /// Writes the entries kept in memory in the format of [`Journal::create`].
+    pub fn write(&self, writer: impl Write) -> io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        for entry in self.entries() {
+            entry.write(&mut writer)?;
+        }
+        writer.flush()
+    }
+
+    /// Saves the entries kept in memory to `path`, see [`Journal::write`].
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write(File::create(path)?)
+    }
+
+    /// Reads the entries written by a journal.
+    pub fn read(reader: impl io::Read) -> io::Result<Vec<JournalEntry>> {
+        let mut entries = Vec::new();
+        for (index, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let entry = JournalEntry::parse(&line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid journal entry in line {}", index + 1),
+                )
+            })?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// Reads the entries of the journal file at `path`, see [`Journal::read`].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Vec<JournalEntry>> {
+        Self::read(File::open(path)?)
+    }
+}
+
+/// The outcome of `Ring::replay`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ReplayReport {
+    /// Completions posted to the ring
+    pub completions: usize,
+    /// Completions of requests submitted before the journal started, they were not posted
+    pub skipped: usize,
+    /// Why the replay stopped before the end of the journal (or its teardown), `None` if it
+    /// did not
+    pub diverged: Option<String>,
+}
+
+struct ReplayState {
+    entries: Vec<JournalEntry>,
+    cursor: usize,
+    // the n-th submission of the journal is the n-th one of the replay
+    journal_submissions: usize,
+    submissions: Vec<(u64, u8)>,
+    // user data of the journal to the one of the replay
+    user_data: HashMap<u64, u64>,
+    // a batch was posted, its sync message was not handled yet
+    syncing: bool,
+    ending: bool,
+    report: ReplayReport,
+    // completions made up by `Replay::synthetic` instead of the ones of a journal
+    synthetic: Option<Synthetic>,
+}
+
+struct Synthetic {
+    // requests pushed since the current round started, in the order they were pushed
+    pending: Vec<u64>,
+    // requests of the current round the submission queue had no room for yet
+    round: VecDeque<u64>,
+    rounds: usize,
+    report: ConformanceReport,
+}
+
+/// The state of `Ring::replay`, used by the ring.
+#[doc(hidden)]
+pub struct Replay {
+    state: RefCell<ReplayState>,
+}
+
+impl Debug for Replay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.borrow();
+        f.debug_struct("Replay")
+            .field("entries", &state.entries.len())
+            .field("cursor", &state.cursor)
+            .field("report", &state.report)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Replay {
+    pub fn new(entries: Vec<JournalEntry>) -> Self {
+        Self {
+            state: RefCell::new(ReplayState {
+                entries,
+                cursor: 0,
+                journal_submissions: 0,
+                submissions: Vec::new(),
+                user_data: HashMap::new(),
+                syncing: false,
+                ending: false,
+                report: Default::default(),
+                synthetic: None,
+            }),
+        }
+    }
+
+    /// Completes every SQE the operations push with result 0 for `rounds` rounds instead of
+    /// replaying a journal, see [`conformance`](crate::conformance).
+    pub fn synthetic(rounds: usize) -> Self {
+        let replay = Self::new(Vec::new());
+        replay.state.borrow_mut().synthetic = Some(Synthetic {
+            pending: Vec::new(),
+            round: VecDeque::new(),
+            rounds,
+            report: Default::default(),
+        });
+        replay
+    }
+
+    /// Takes the place of an SQE an operation pushed, with a `Nop` that posts no completion.
+    pub fn submitted<E: squeue::EntryMarker>(&self, entry: &mut E) {
+        // `Entry128` starts with an `Entry`
+        let user_data = unsafe { &*(entry as *const E as *const squeue::Entry) }.get_user_data();
+        let mut state = self.state.borrow_mut();
+        match &mut state.synthetic {
+            Some(synthetic) => {
+                synthetic.pending.push(user_data);
+                synthetic.report.submitted += 1;
+            }
+            None => state
+                .submissions
+                .push((user_data, crate::entry_opcode(entry))),
+        }
+        *entry = Nop::new()
+            .build()
+            .flags(Flags::SKIP_SUCCESS)
+            .user_data(user_data)
+            .into();
+    }
+
+    /// Posts the recorded completions up to the next SQE the operations did not push yet, and
+    /// a message to tell when they were handled.
+    pub fn post<E: squeue::EntryMarker>(&self, sq: &mut SubmissionQueue<'_, E>, ring_fd: RawFd) {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        if state.syncing || state.ending {
+            return;
+        }
+
+        if let Some(synthetic) = &mut state.synthetic {
+            let posted = match synthetic.post(sq, ring_fd) {
+                Some(posted) => posted,
+                None => {
+                    state.ending = true;
+                    0
+                }
+            };
+            Self::sync(state, posted, sq, ring_fd);
+            return;
+        }
+
+        let mut posted = 0;
+        while let Some(entry) = state.entries.get(state.cursor) {
+            match entry {
+                JournalEntry::Submission { user_data, sqe, .. } => {
+                    let Some(&(replayed, opcode)) =
+                        state.submissions.get(state.journal_submissions)
+                    else {
+                        if posted == 0 {
+                            // everything posted was handled and the operations did not push it
+                            state.report.diverged = Some(format!(
+                                "submission {} (opcode {}) was not pushed",
+                                state.journal_submissions, sqe[0]
+                            ));
+                            state.ending = true;
+                        }
+                        break;
+                    };
+                    if opcode != sqe[0] {
+                        state.report.diverged = Some(format!(
+                            "submission {} has opcode {opcode} instead of {}",
+                            state.journal_submissions, sqe[0]
+                        ));
+                        state.ending = true;
+                        break;
+                    }
+                    state.user_data.insert(*user_data, replayed);
+                    state.journal_submissions += 1;
+                }
+                &JournalEntry::Completion {
+                    user_data,
+                    result,
+                    flags,
+                    ..
+                } => {
+                    let is_message = RingMessage::is_message(user_data);
+                    let replayed = match is_message {
+                        true => Some(user_data),
+                        false => state.user_data.get(&user_data).copied(),
+                    };
+                    match replayed {
+                        Some(replayed) => {
+                            let flags = (flags != 0).then_some(flags);
+                            let message: E = MsgRingData::new(Fd(ring_fd), result, replayed, flags)
+                                .build()
+                                .user_data(0)
+                                .into();
+                            // retried with the next iteration
+                            if unsafe { sq.push(&message) }.is_err() {
+                                break;
+                            }
+                            posted += 1;
+                            state.report.completions += 1;
+                            if !is_message && !cqueue::more(flags.unwrap_or(0)) {
+                                state.user_data.remove(&user_data);
+                            }
+                        }
+                        None => {
+                            debug!("skipping completion of {user_data:#x} submitted before the journal");
+                            state.report.skipped += 1;
+                        }
+                    }
+                }
+                JournalEntry::Teardown { .. } => {
+                    state.ending = true;
+                    break;
+                }
+                JournalEntry::Dropped { count } => {
+                    // the completions left refer to requests that are not in the journal
+                    state.report.diverged = Some(format!(
+                        "the journal dropped {count} entries, it cannot be replayed"
+                    ));
+                    state.ending = true;
+                    break;
+                }
+            }
+            state.cursor += 1;
+        }
+        if state.cursor == state.entries.len() {
+            state.ending = true;
+        }
+        Self::sync(state, posted, sq, ring_fd);
+    }
+
+    /// Posts a message to tell when the `posted` completions were handled, or that the replay
+    /// ended.
+    fn sync<E: squeue::EntryMarker>(
+        state: &mut ReplayState,
+        posted: usize,
+        sq: &mut SubmissionQueue<'_, E>,
+        ring_fd: RawFd,
+    ) {
+        let payload = match state.ending {
+            true => REPLAY_END,
+            false if posted > 0 => REPLAY_SYNC,
+            false => return,
+        };
+        let message: E = OutgoingMessage::data(MessageTarget::wake(ring_fd), payload, 0)
+            .build()
+            .user_data(0)
+            .into();
+        // the sync of a full queue is posted with the next iteration
+        match unsafe { sq.push(&message) } {
+            Ok(()) => state.syncing = true,
+            Err(_) if state.ending => state.ending = false,
+            Err(_) => {}
+        }
+    }
+
+    /// Takes the requests of a synthetic replay without a completion on teardown, their `Nop`s
+    /// cannot be cancelled, the ring completes them with `-ECANCELED` itself.
+    pub fn cancel(&self) -> Vec<u64> {
+        let mut state = self.state.borrow_mut();
+        let Some(synthetic) = &mut state.synthetic else {
+            return Vec::new();
+        };
+
+        let mut cancelled: Vec<u64> = std::mem::take(&mut synthetic.round).into();
+        cancelled.append(&mut synthetic.pending);
+        synthetic.report.cancelled += cancelled.len();
+        cancelled
+    }
+
+    /// Handles a message to `WAKE_OPERATION`, returns whether the replay is finished.
+    pub fn on_wake(&self, message: &RingMessage) -> bool {
+        let mut state = self.state.borrow_mut();
+        match message.payload() {
+            REPLAY_SYNC => {
+                state.syncing = false;
+                false
+            }
+            REPLAY_END => true,
+            _ => false,
+        }
+    }
+
+    pub fn report(self) -> ReplayReport {
+        self.state.into_inner().report
+    }
+
+    /// The report of a synthetic replay.
+    pub fn conformance_report(self) -> ConformanceReport {
+        self.state
+            .into_inner()
+            .synthetic
+            .map(|synthetic| synthetic.report)
+            .unwrap_or_default()
+    }
+}
+
+impl Synthetic {
+    /// Completes the pending requests with result 0, `None` once the rounds are over or no
+    /// request is waiting.
+    fn post<E: squeue::EntryMarker>(
+        &mut self,
+        sq: &mut SubmissionQueue<'_, E>,
+        ring_fd: RawFd,
+    ) -> Option<usize> {
+        if self.round.is_empty() {
+            if self.rounds == 0 || self.pending.is_empty() {
+                return None;
+            }
+            // requests pushed while these are handled complete with the next round
+            self.round = std::mem::take(&mut self.pending).into();
+        }
+
+        let mut posted = 0;
+        while let Some(&user_data) = self.round.front() {
+            let message: E = MsgRingData::new(Fd(ring_fd), 0, user_data, None)
+                .build()
+                .user_data(0)
+                .into();
+            // the rest of the round is posted with the next iteration
+            if unsafe { sq.push(&message) }.is_err() {
+                break;
+            }
+            self.round.pop_front();
+            posted += 1;
+        }
+        if self.round.is_empty() {
+            self.rounds -= 1;
+        }
+        self.report.completions += posted;
+        Some(posted)
+    }
+}
+
+#[cfg(test)]
+// the ring is generated inside the crate, its unused parts are not exempt from lints
+#[allow(dead_code, unused_imports)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::ops::{Error, TickOp};
+    use crate::ControlFlow;
+
+    crate::ring! { tick_ring -> crate::ops::Error, tick: crate::ops::TickOp }
+
+    /// A ring exiting after 3 ticks, counted in `ticks`.
+    fn ring(ticks: &Rc<Cell<u64>>) -> tick_ring::Ring {
+        let ticks = ticks.clone();
+        let tick = TickOp::new(Duration::from_millis(1), move |_| {
+            ticks.set(ticks.get() + 1);
+            match ticks.get() {
+                3 => ControlFlow::Exit,
+                _ => ControlFlow::Continue,
+            }
+        });
+        tick_ring::Ring::builder().tick(tick).build().unwrap()
+    }
+
+    #[test]
+    fn saved_journal_replays() {
+        let journal = Journal::in_memory(1024);
+        let mut recorded = ring(&Rc::default());
+        recorded.record_journal(journal.clone());
+        recorded.run().unwrap();
+
+        let path = std::env::temp_dir().join(format!("rummelplatz-journal-{}", std::process::id()));
+        journal.save(&path).unwrap();
+        let entries = Journal::load(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(entries, journal.entries());
+
+        let ticks = Rc::default();
+        let report = ring(&ticks).replay(entries).unwrap();
+        assert!(report.diverged.is_none(), "{report:?}");
+        assert_eq!(report.completions, 3);
+        assert_eq!(ticks.get(), 3);
+    }
+
+    #[test]
+    fn journal_that_dropped_entries_does_not_replay() {
+        let journal = Journal::in_memory(2);
+        let mut recorded = ring(&Rc::default());
+        recorded.record_journal(journal.clone());
+        recorded.run().unwrap();
+
+        let entries = journal.entries();
+        assert!(matches!(entries[0], JournalEntry::Dropped { count } if count > 0));
+        let mut saved = Vec::new();
+        journal.write(&mut saved).unwrap();
+        assert_eq!(Journal::read(saved.as_slice()).unwrap(), entries);
+
+        let ticks = Rc::default();
+        let report = ring(&ticks).replay(entries).unwrap();
+        assert!(report
+            .diverged
+            .is_some_and(|reason| reason.contains("dropped")));
+        assert_eq!(report.completions, 0);
+        assert_eq!(ticks.get(), 0);
+    }
+}
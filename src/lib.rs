@@ -2,14 +2,59 @@ use std::collections::VecDeque;
 use std::fmt::Debug;
 use std::iter::zip;
 use std::marker::PhantomData;
+use std::mem::{align_of, size_of};
 use std::num::NonZeroUsize;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
 
 pub use io_uring;
 use io_uring::cqueue::Entry;
 use io_uring::squeue::{EntryMarker, PushError};
 use io_uring::SubmissionQueue;
+#[cfg(feature = "mio")]
+pub use mio;
+#[doc(hidden)]
+pub use paste;
+#[cfg(feature = "serde")]
+pub use serde;
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+pub use serde_json;
 use tracing::{trace, warn};
 
+pub mod blocking;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod chain;
+pub mod conformance;
+pub mod dyn_ring;
+pub mod embed;
+pub mod journal;
+#[cfg(feature = "macros")]
+pub mod macros;
+pub mod message;
+pub mod ops;
+#[cfg(feature = "opentelemetry")]
+pub mod otel;
+pub mod pool;
+#[cfg(feature = "metrics-prometheus")]
+pub mod prometheus;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "async")]
+pub mod reactor;
+pub mod runtime;
+pub mod snapshot;
+pub mod stats;
+pub mod systemd;
+pub mod timeline;
+#[cfg(feature = "async")]
+pub mod tokio_bridge;
+#[cfg(feature = "tokio-uring")]
+pub mod tokio_uring;
+pub mod trace_level;
+pub mod usdt;
+pub mod user_data;
+
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum ControlFlow<Warn, Error> {
@@ -19,31 +64,495 @@ pub enum ControlFlow<Warn, Error> {
     Error(Error),
 }
 
+/// What a call to `Ring::run_step` did.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RingStep {
+    /// The ring keeps running, `completions` completion entries were handled
+    Running { completions: usize },
+    /// An operation exited the ring (or it failed) and everything in flight was canceled
+    Finished,
+}
+
+/// Flag of `io_uring_enter` asking the kernel for completions, not exported by `io_uring`
+const IORING_ENTER_GETEVENTS: u32 = 1;
+
+/// Submits `to_submit` entries without waiting for completions. Unlike
+/// `io_uring::Submitter::submit` it asks for completions, which runs the task work deferred by
+/// `DEFER_TASKRUN` rings.
+#[doc(hidden)]
+pub fn submit_and_collect(
+    submitter: &io_uring::Submitter,
+    to_submit: usize,
+) -> std::io::Result<usize> {
+    unsafe { submitter.enter::<libc::sigset_t>(to_submit as u32, 0, IORING_ENTER_GETEVENTS, None) }
+}
+
+/// Moves the batches waiting in `backlog` into `sq` in order, as long as they fit.
+///
+/// # Safety
+/// Like `io_uring::SubmissionQueue::push_multiple`, what the entries point to has to live until
+/// they completed.
+#[doc(hidden)]
+pub unsafe fn push_backlog<E: EntryMarker>(
+    sq: &mut io_uring::SubmissionQueue<'_, E>,
+    backlog: &mut VecDeque<Box<[E]>>,
+) {
+    // the queue only sees the entries the kernel consumed after a sync
+    sq.sync();
+    while let Some(entries) = backlog.pop_front() {
+        trace!("push from backlog");
+        if sq.push_multiple(&entries).is_err() {
+            backlog.push_front(entries);
+            break;
+        }
+    }
+}
+
+/// The opcode of `entry`, which `io_uring` does not expose.
+#[doc(hidden)]
+#[inline]
+pub fn entry_opcode<E: EntryMarker>(entry: &E) -> u8 {
+    // `Entry` is a `repr(C)` `io_uring_sqe`, which starts with the opcode, and `Entry128` starts
+    // with an `Entry`
+    unsafe { *(entry as *const E as *const u8) }
+}
+
+/// A completion with `-ECANCELED` for the request with `user_data`, for requests teardown hands
+/// to their operations without the kernel having completed them. `io_uring` cannot build CQEs.
+#[doc(hidden)]
+pub fn cancelled_cqe<C: io_uring::cqueue::EntryMarker>(user_data: u64) -> C {
+    // `Entry` is a `repr(C)` `io_uring_cqe` of user data, result and flags, `Entry32` starts with
+    // an `Entry`, all zeroes are a valid CQE of either
+    unsafe {
+        let mut cqe = std::mem::MaybeUninit::<C>::zeroed();
+        let raw = cqe.as_mut_ptr().cast::<u8>();
+        raw.cast::<u64>().write(user_data);
+        raw.add(8).cast::<i32>().write(-libc::ECANCELED);
+        cqe.assume_init()
+    }
+}
+
+/// The user data of the `Nop` teardown waits for after cancelling everything in flight. It is
+/// even, so it is no message, and in the upper half of the address space, where no box of a ring
+/// lives.
+#[doc(hidden)]
+pub const TEARDOWN_DONE: u64 = !0b111;
+
+/// Pushes the cancellation of everything in flight and the `Nop` with [`TEARDOWN_DONE`] that
+/// completes after it, submitting what the operations left in a full submission queue first.
+/// Returns the number of submitted entries.
+#[doc(hidden)]
+pub fn push_teardown<E: EntryMarker>(
+    submitter: &io_uring::Submitter,
+    sq: &mut SubmissionQueue<E>,
+) -> std::io::Result<usize> {
+    let cancel = unmanaged(E::from(
+        io_uring::opcode::AsyncCancel2::new(io_uring::types::CancelBuilder::any()).build(),
+    ));
+    let done = E::from(
+        io_uring::opcode::Nop::new()
+            .build()
+            .flags(io_uring::squeue::Flags::IO_DRAIN)
+            .user_data(TEARDOWN_DONE),
+    );
+
+    let mut submitted = 0;
+    for entry in [cancel, done] {
+        sq.sync();
+        if sq.is_full() {
+            submitted += submit_and_collect(submitter, sq.len())?;
+            sq.sync();
+        }
+        // both entries own no data
+        unsafe { sq.push(&entry) }
+            .map_err(|_| std::io::Error::other("the submission queue stays full"))?;
+    }
+    Ok(submitted)
+}
+
+/// Offset of `user_data` in an `io_uring_sqe`, also the start of every `Entry128`.
+const SQE_USER_DATA_OFFSET: usize = 32;
+
+/// The user data of `entry`, which `io_uring` only exposes for `Entry`.
+#[inline]
+fn entry_user_data<E: EntryMarker>(entry: &E) -> u64 {
+    unsafe {
+        (entry as *const E as *const u8)
+            .add(SQE_USER_DATA_OFFSET)
+            .cast::<u64>()
+            .read()
+    }
+}
+
+/// Sets the user data of `entry` to 0, the rings drop its completion.
+#[inline]
+fn unmanaged<E: EntryMarker>(mut entry: E) -> E {
+    unsafe {
+        (&mut entry as *mut E as *mut u8)
+            .add(SQE_USER_DATA_OFFSET)
+            .cast::<u64>()
+            .write(0);
+    }
+    entry
+}
+
+/// Debug builds check entries pushed raw: 0 marks an entry whose completion the ring drops, others
+/// have to be the user data of the ring. Odd values are reserved for messages and
+/// [`TEARDOWN_DONE`] for teardown, the data of the ring is never unaligned.
+#[inline]
+fn debug_assert_raw<E: EntryMarker>(entries: &[E]) {
+    for entry in entries {
+        let user_data = entry_user_data(entry);
+        debug_assert!(
+            !message::RingMessage::is_message(user_data),
+            "raw entry with the user data {user_data:#x} of a message: {entry:?}"
+        );
+        debug_assert_ne!(
+            user_data, TEARDOWN_DONE,
+            "raw entry with the user data teardown waits for: {entry:?}"
+        );
+        debug_assert!(
+            user_data == 0 || user_data.is_multiple_of(align_of::<u64>() as u64),
+            "raw entry with user data {user_data:#x} the ring cannot tell apart, \
+             push entries whose completion the ring should ignore with `push_unmanaged`: {entry:?}"
+        );
+    }
+}
+
+/// Takes the batches waiting in `backlog` and returns the user data of their requests. Teardown
+/// does not submit them, writes or sends would still run while the ring shuts down, it completes
+/// them with [`cancelled_cqe`] instead. Entries without data and messages are skipped.
+#[doc(hidden)]
+pub fn backlog_user_data<E: EntryMarker>(backlog: &mut VecDeque<Box<[E]>>) -> Vec<u64> {
+    backlog
+        .drain(..)
+        .flat_map(|entries| entries.into_vec())
+        .map(|entry| entry_user_data(&entry))
+        .filter(|&user_data| user_data != 0 && !message::RingMessage::is_message(user_data))
+        .collect()
+}
+
+/// Creates a non-blocking eventfd and registers it with `ring`, see `Ring::readiness_fd`.
+#[doc(hidden)]
+pub fn register_readiness_fd<S: EntryMarker, C: io_uring::cqueue::EntryMarker>(
+    ring: &io_uring::IoUring<S, C>,
+) -> std::io::Result<OwnedFd> {
+    let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    ring.submitter().register_eventfd(fd.as_raw_fd())?;
+    Ok(fd)
+}
+
+/// Resets the counter of a readiness fd, so it only becomes readable again with the next
+/// completion.
+#[doc(hidden)]
+pub fn reset_readiness_fd(fd: &OwnedFd) {
+    let mut value = 0u64;
+    // fails with `EAGAIN` if it was not signalled, nothing to reset then
+    unsafe {
+        libc::read(
+            fd.as_raw_fd(),
+            &mut value as *mut u64 as *mut libc::c_void,
+            size_of::<u64>(),
+        )
+    };
+}
+
+/// Implements `mio::event::Source` for a generated ring, expands to nothing without the `mio`
+/// feature of this crate.
+#[cfg(feature = "mio")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __mio_source {
+    ($ring:ident [$($generics:tt)*] [$($args:tt)*] [$($bounds:tt)*]) => {
+        /// Registers the [readiness fd](Ring::readiness_fd) of the ring, call
+        /// [`Ring::run_step`] on every readable event.
+        impl<$($generics)*> $crate::mio::event::Source for $ring<$($args)*>
+        where
+            $($bounds)*
+        {
+            fn register(
+                &mut self,
+                registry: &$crate::mio::Registry,
+                token: $crate::mio::Token,
+                interests: $crate::mio::Interest,
+            ) -> std::io::Result<()> {
+                let fd = self.readiness_fd()?;
+                $crate::mio::event::Source::register(
+                    &mut $crate::mio::unix::SourceFd(&fd),
+                    registry,
+                    token,
+                    interests,
+                )
+            }
+
+            fn reregister(
+                &mut self,
+                registry: &$crate::mio::Registry,
+                token: $crate::mio::Token,
+                interests: $crate::mio::Interest,
+            ) -> std::io::Result<()> {
+                let fd = self.readiness_fd()?;
+                $crate::mio::event::Source::reregister(
+                    &mut $crate::mio::unix::SourceFd(&fd),
+                    registry,
+                    token,
+                    interests,
+                )
+            }
+
+            fn deregister(&mut self, registry: &$crate::mio::Registry) -> std::io::Result<()> {
+                let fd = self.readiness_fd()?;
+                $crate::mio::event::Source::deregister(
+                    &mut $crate::mio::unix::SourceFd(&fd),
+                    registry,
+                )
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "mio"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __mio_source {
+    ($($tokens:tt)*) => {};
+}
+
+/// Adds `Ring::check_conformance` to a generated ring, expands to nothing without the
+/// `conformance` feature of this crate.
+#[cfg(feature = "conformance")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __conformance {
+    (
+        [$($generics:tt)*] [$($args:tt)*] [$($bounds:tt)*] [$($error_params:tt)*]
+        [$setup_error:ty, $completion_error:ty, $teardown_error:ty] [$sqe:ty, $cqe:ty],
+        $($ring_op_name:ident: $ring_op:path),+
+    ) => {
+        impl<$($generics)*> Ring<$($args)*> where $($bounds)* {
+            /// Runs the operations of this ring, which must not have run yet, against `rounds`
+            /// rounds of completions with result 0 and cancels what is left, see
+            /// [`conformance`]($crate::conformance).
+            pub fn check_conformance<$($error_params)*>(&mut self, rounds: usize) -> Result<$crate::conformance::ConformanceReport, RingError<$setup_error, $completion_error, $teardown_error>>
+            where
+                $setup_error: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::SetupError>)+,
+                $completion_error: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::ControlFlowError>)+,
+                $teardown_error: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::TeardownError>)+,
+            {
+                // the requests without data back are the ones still counted in flight
+                self.count_in_flight();
+                self.replay = Some($crate::journal::Replay::synthetic(rounds));
+                let result = self.run();
+                let mut report = self.replay.take().unwrap().conformance_report();
+                report.leaked = self.operations.total().current;
+                result.map(|()| report)
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "conformance"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __conformance {
+    ($($tokens:tt)*) => {};
+}
+
+/// Implements `serde::Serialize` for the `UserData` of a generated ring whose operation data is
+/// serializable and adds `Ring::serialize_data`, expands to nothing without the `serde` feature
+/// of this crate.
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __serde_user_data {
+    (
+        [$($generics:tt)*] [$($args:tt)*] [$($bounds:tt)*] [$sqe:ty, $cqe:ty],
+        $($ring_op_name:ident: $ring_op:path),+
+    ) => {
+        /// Operations by their name, e.g. `{"tick":42}`. Operations added with
+        /// `Ring::register` are written as their index, `{"Dynamic":0}`.
+        impl<$($generics)*> $crate::serde::Serialize for UserData<$($args)*>
+        where
+            // higher-ranked so rings whose data is not serializable just lack the impl
+            $(for<'__serde> <$ring_op as RingOperation<$sqe, $cqe>>::RingData: $crate::serde::Serialize,)+
+            $($bounds)*
+        {
+            fn serialize<S: $crate::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let operations = [$(Operation::$ring_op_name),+].len() as u32;
+                match self {
+                    $(UserData::$ring_op_name(data) => serializer.serialize_newtype_variant(
+                        "UserData",
+                        Operation::$ring_op_name as u32,
+                        stringify!($ring_op_name),
+                        data,
+                    ),)+
+                    UserData::Dynamic(index, _) => serializer.serialize_newtype_variant("UserData", operations, "Dynamic", index),
+                    UserData::Cancel(user_data) => serializer.serialize_newtype_variant("UserData", operations + 1, "Cancel", user_data),
+                }
+            }
+        }
+
+        impl<$($generics)*> Ring<$($args)*>
+        where
+            // higher-ranked so rings whose data is not serializable just lack the impl
+            $(for<'__serde> <$ring_op as RingOperation<$sqe, $cqe>>::RingData: $crate::serde::Serialize,)+
+            $($bounds)*
+        {
+            /// Records the data of every request as JSON in the [journal]($crate::journal) and
+            /// [snapshots]($crate::snapshot) instead of its `Debug` output, from the next
+            /// request on.
+            pub fn serialize_data(&mut self) {
+                self.describe_data = Some(|data| {
+                    $crate::serde_json::to_string(data)
+                        .unwrap_or_else(|e| $crate::serde_json::json!({ "unserializable": e.to_string() }).to_string())
+                });
+            }
+        }
+    };
+}
+
+#[cfg(not(feature = "serde"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __serde_user_data {
+    ($($tokens:tt)*) => {};
+}
+
 type CompletionResult<W, E, D> = (ControlFlow<W, E>, Option<D>);
 
-pub trait RingOperation: Debug {
+/// An operation of a ring, see [`ring!`]. Rings with 128 byte SQEs and 32 byte CQEs (`sqe128`
+/// in `ring!`) run operations implementing `RingOperation<Entry128, Entry32>` instead.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not a ring operation",
+    label = "does not implement `RingOperation`",
+    note = "every operation of a ring implements `rummelplatz::RingOperation`, operations of `sqe128` rings `RingOperation<Entry128, Entry32>`"
+)]
+pub trait RingOperation<
+    S: EntryMarker = io_uring::squeue::Entry,
+    C: io_uring::cqueue::EntryMarker = Entry,
+>: Debug
+{
     type RingData;
     type SetupError;
     type TeardownError;
     type ControlFlowWarn;
     type ControlFlowError;
 
-    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+    fn setup<W: Fn(&mut S, Self::RingData)>(
         &mut self,
-        submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W, S>,
     ) -> Result<(), Self::SetupError>;
-    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+    fn on_completion<W: Fn(&mut S, Self::RingData)>(
         &mut self,
-        completion_entry: Entry,
+        completion_entry: C,
         ring_data: Self::RingData,
-        submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W, S>,
     ) -> CompletionResult<Self::ControlFlowWarn, Self::ControlFlowError, Self::RingData>;
-    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+    fn on_teardown_completion<W: Fn(&mut S, Self::RingData)>(
         &mut self,
-        completion_entry: Entry,
+        completion_entry: C,
         ring_data: Self::RingData,
-        submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W, S>,
     ) -> Result<(), Self::TeardownError>;
+
+    /// Called once per ring loop iteration after all available completions were handled.
+    /// Operations may use it to flush work queued up during the iteration.
+    #[inline]
+    fn housekeeping<W: Fn(&mut S, Self::RingData)>(
+        &mut self,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W, S>,
+    ) -> ControlFlow<Self::ControlFlowWarn, Self::ControlFlowError> {
+        ControlFlow::Continue
+    }
+
+    /// Called for every [`RingMessage`](message::RingMessage) another ring sent to this
+    /// operation, see [`message`].
+    #[inline]
+    fn on_message<W: Fn(&mut S, Self::RingData)>(
+        &mut self,
+        message: message::RingMessage,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W, S>,
+    ) -> ControlFlow<Self::ControlFlowWarn, Self::ControlFlowError> {
+        warn!("{self:?} ignored {message:?}");
+        ControlFlow::Continue
+    }
+}
+
+/// An optional operation, e.g. one behind a feature flag or a setting. `None` sets nothing up
+/// and ignores messages, so one ring declaration fits configurations with and without it.
+impl<S: EntryMarker, C: io_uring::cqueue::EntryMarker, T: RingOperation<S, C>> RingOperation<S, C>
+    for Option<T>
+{
+    type RingData = T::RingData;
+    type SetupError = T::SetupError;
+    type TeardownError = T::TeardownError;
+    type ControlFlowWarn = T::ControlFlowWarn;
+    type ControlFlowError = T::ControlFlowError;
+
+    fn setup<W: Fn(&mut S, Self::RingData)>(
+        &mut self,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W, S>,
+    ) -> Result<(), Self::SetupError> {
+        match self {
+            Some(operation) => operation.setup(submitter),
+            None => Ok(()),
+        }
+    }
+
+    fn on_completion<W: Fn(&mut S, Self::RingData)>(
+        &mut self,
+        completion_entry: C,
+        ring_data: Self::RingData,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W, S>,
+    ) -> CompletionResult<Self::ControlFlowWarn, Self::ControlFlowError, Self::RingData> {
+        match self {
+            Some(operation) => operation.on_completion(completion_entry, ring_data, submitter),
+            // taken out of the ring with requests in flight
+            None => (ControlFlow::Continue, None),
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut S, Self::RingData)>(
+        &mut self,
+        completion_entry: C,
+        ring_data: Self::RingData,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W, S>,
+    ) -> Result<(), Self::TeardownError> {
+        match self {
+            Some(operation) => {
+                operation.on_teardown_completion(completion_entry, ring_data, submitter)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn housekeeping<W: Fn(&mut S, Self::RingData)>(
+        &mut self,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W, S>,
+    ) -> ControlFlow<Self::ControlFlowWarn, Self::ControlFlowError> {
+        match self {
+            Some(operation) => operation.housekeeping(submitter),
+            None => ControlFlow::Continue,
+        }
+    }
+
+    fn on_message<W: Fn(&mut S, Self::RingData)>(
+        &mut self,
+        message: message::RingMessage,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W, S>,
+    ) -> ControlFlow<Self::ControlFlowWarn, Self::ControlFlowError> {
+        match self {
+            Some(operation) => operation.on_message(message, submitter),
+            None => {
+                trace!("absent operation ignored {message:?}");
+                ControlFlow::Continue
+            }
+        }
+    }
 }
 
 pub struct SubmissionQueueSubmitter<
@@ -79,6 +588,31 @@ impl<'a, 'b, 'c, D, W: Fn(&mut E, D), E: EntryMarker>
         }
     }
 
+    /// Borrows this submitter for an operation with a different `RingData`, e.g. one that is
+    /// nested inside another operation. `f` converts the nested data into this submitter's data.
+    #[inline]
+    pub fn map_data<'s, D2, F: Fn(D2) -> D + 's>(
+        &'s mut self,
+        f: F,
+    ) -> SubmissionQueueSubmitter<'s, 'b, 's, D2, impl Fn(&mut E, D2) + 's, E> {
+        let wrapper = &self.wrapper;
+        SubmissionQueueSubmitter::new(
+            self.sq,
+            self.backlog,
+            self.backlog_limit,
+            move |e: &mut E, d: D2| wrapper(e, f(d)),
+        )
+    }
+
+    /// Whether `n` more entries fit into the submission queue or, once it is full, the backlog.
+    #[inline]
+    pub fn has_capacity(&self, n: usize) -> bool {
+        self.sq.capacity() - self.sq.len() >= n
+            || self
+                .backlog_limit
+                .is_none_or(|limit| self.backlog.len() + n <= limit.get())
+    }
+
     #[inline]
     pub fn push(&mut self, entry: E, data: D) -> Result<(), PushError> {
         self.push_multiple([entry], [data])
@@ -91,17 +625,24 @@ impl<'a, 'b, 'c, D, W: Fn(&mut E, D), E: EntryMarker>
         self.push_multiple_raw([entry])
     }
 
+    /// Pushes an entry no operation owns, e.g. a cancellation. Its user data is set to 0 and the
+    /// ring drops its completion.
+    #[inline]
+    pub fn push_unmanaged(&mut self, entry: E) -> Result<(), PushError> {
+        unsafe { self.push_entries([unmanaged(entry)]) }
+    }
+
     #[inline]
     pub fn push_multiple<const N: usize>(
         &mut self,
         mut entries: [E; N],
         data: [D; N],
     ) -> Result<(), PushError> {
-        for (entry, data) in zip(entries.iter_mut(), data.into_iter()) {
+        for (entry, data) in zip(entries.iter_mut(), data) {
             (self.wrapper)(entry, data);
         }
 
-        unsafe { self.push_multiple_raw(entries) }
+        unsafe { self.push_entries(entries) }
     }
 
     /// # Safety
@@ -111,14 +652,19 @@ impl<'a, 'b, 'c, D, W: Fn(&mut E, D), E: EntryMarker>
         &mut self,
         entries: [E; N],
     ) -> Result<(), PushError> {
+        debug_assert_raw(&entries);
+        self.push_entries(entries)
+    }
+
+    #[inline]
+    unsafe fn push_entries<const N: usize>(&mut self, entries: [E; N]) -> Result<(), PushError> {
         trace!("push sqes: {entries:?}");
 
         match self.sq.push_multiple(entries.as_slice()) {
             Ok(()) => Ok(()),
             Err(e) => {
-                warn!(
-                    "exceeding ring submission queue, using backlog... (may degrade performance)"
-                );
+                // the ring summarizes spills in throttled warnings, see `Ring::spill_warning_interval`
+                trace!("exceeding ring submission queue, using backlog...");
 
                 match self.backlog_limit {
                     None => {
@@ -145,17 +691,23 @@ impl<'a, 'b, 'c, D, W: Fn(&mut E, D), E: EntryMarker>
 {
     #[inline]
     pub fn push_slice(&mut self, mut entries: Box<[E]>, data: Box<[D]>) -> Result<(), PushError> {
-        for (entry, data) in zip(entries.iter_mut(), Vec::from(data).into_iter()) {
+        for (entry, data) in zip(entries.iter_mut(), Vec::from(data)) {
             (self.wrapper)(entry, data);
         }
 
-        unsafe { self.push_slice_raw(entries) }
+        unsafe { self.push_slice_entries(entries) }
     }
 
     /// # Safety
     /// The caller must ensure that userdata of all entries are valid and can be understood by rummelplatz.
     #[inline]
     pub unsafe fn push_slice_raw(&mut self, entries: Box<[E]>) -> Result<(), PushError> {
+        debug_assert_raw(&entries);
+        self.push_slice_entries(entries)
+    }
+
+    #[inline]
+    unsafe fn push_slice_entries(&mut self, entries: Box<[E]>) -> Result<(), PushError> {
         match self.sq.push_multiple(&entries) {
             Ok(()) => Ok(()),
             Err(e) => match self.backlog_limit {
@@ -176,10 +728,190 @@ impl<'a, 'b, 'c, D, W: Fn(&mut E, D), E: EntryMarker>
     }
 }
 
+/// Generates a module `$ring_name` with a `Ring` running the given operations, addressed by the
+/// name in front of each of them. The names are all that is needed to tell the operations apart,
+/// one type can be registered several times, e.g. to accept on two listeners:
+///
+/// ```no_run
+/// rummelplatz::ring! {
+///     my_ring,
+///     public: rummelplatz::ops::AcceptOp,
+///     admin: rummelplatz::ops::AcceptOp
+/// }
+/// ```
+///
+/// Generic operations declare their type parameters after the name of the ring, bounds that do
+/// not fit there go into `where { ... }`. Operations declared as `Option<Op>` are optional, the
+/// ring is created with `Some(op)` or `None`.
+///
+/// `Ring::handle` gives out a `Handle` to the running ring with a `send_<name>(data)` method per
+/// operation whose data is `Send`, the operation receives `data` in `on_completion` as if one of
+/// its requests completed with result 0. It is sent with `IORING_OP_MSG_RING` from any thread,
+/// and counted in flight until the operation got it. Once the ring is dropped sends fail.
+///
+/// `run`, `run_step` and `replay` of the ring are generic over the setup, completion and teardown
+/// error they return, each one converted from the errors of the operations. `-> Error` after the
+/// name (and type parameters) fixes all of them to `Error`, `-> (Setup, Completion, Teardown)` to
+/// one type each, so they need no annotations:
+///
+/// ```no_run
+/// rummelplatz::ring! { my_ring -> rummelplatz::ops::Error, tick: rummelplatz::ops::TickOp }
+///
+/// fn run(mut ring: my_ring::Ring) {
+///     if let Err(e) = ring.run() {
+///         eprintln!("ring failed: {e:?}");
+///     }
+/// }
+/// ```
+///
+/// `sqe128` in front of the operations generates a ring with 128 byte SQEs and 32 byte CQEs,
+/// e.g. for `UringCmd80` commands. Its operations implement
+/// `RingOperation<Entry128, Entry32>`, submit `squeue::Entry128`s and complete with
+/// `cqueue::Entry32`s:
+///
+/// ```no_run
+/// # use rummelplatz::io_uring::{cqueue::Entry32, squeue::Entry128};
+/// # use rummelplatz::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+/// # #[derive(Debug)]
+/// # pub struct NvmeCmd;
+/// # impl RingOperation<Entry128, Entry32> for NvmeCmd {
+/// #     type RingData = ();
+/// #     type SetupError = ();
+/// #     type TeardownError = ();
+/// #     type ControlFlowWarn = ();
+/// #     type ControlFlowError = ();
+/// #     fn setup<W: Fn(&mut Entry128, ())>(&mut self, _: SubmissionQueueSubmitter<(), W, Entry128>) -> Result<(), ()> { Ok(()) }
+/// #     fn on_completion<W: Fn(&mut Entry128, ())>(&mut self, _: Entry32, _: (), _: SubmissionQueueSubmitter<(), W, Entry128>) -> (ControlFlow<(), ()>, Option<()>) { (ControlFlow::Exit, None) }
+/// #     fn on_teardown_completion<W: Fn(&mut Entry128, ())>(&mut self, _: Entry32, _: (), _: SubmissionQueueSubmitter<(), W, Entry128>) -> Result<(), ()> { Ok(()) }
+/// # }
+/// rummelplatz::ring! { nvme_ring -> (), sqe128, nvme: super::NvmeCmd }
+/// # fn main() {}
+/// ```
+///
+/// `decorate = function` in front of the operations (after `sqe128`) passes every SQE the
+/// operations push with data through `function: fn(Entry) -> Entry` once its user data is set,
+/// e.g. to set a personality, `IOSQE_ASYNC` or an I/O priority on all of them. Entries pushed
+/// with `push_raw` or `push_unmanaged` and the cancellations of the teardown are left as they are:
+///
+/// ```no_run
+/// rummelplatz::ring! {
+///     my_ring,
+///     decorate = |entry| entry.flags(rummelplatz::io_uring::squeue::Flags::ASYNC),
+///     tick: rummelplatz::ops::TickOp
+/// }
+/// ```
+///
+/// The module is `pub` unless the name is preceded by a visibility and `mod`, e.g.
+/// `pub(crate) mod my_ring` or just `mod my_ring`. With `use` instead of `mod` the module is
+/// private and hidden, its `Ring`, `Builder`, `Handle`, `UserData`, `Operation`, `Operations`
+/// and `RingError` are imported into the current scope with the given visibility:
+///
+/// ```no_run
+/// rummelplatz::ring! { pub(crate) use my_ring, tick: rummelplatz::ops::TickOp }
+///
+/// pub(crate) fn ring_fd(ring: &Ring) -> std::os::fd::RawFd {
+///     std::os::fd::AsRawFd::as_raw_fd(ring)
+/// }
+/// ```
 #[macro_export]
 macro_rules! ring {
-    ($ring_name:ident, $($ring_op_name:ident: $ring_op:path),+) => {
-        pub mod $ring_name {
+    ($vis:vis mod $ring_name:ident $($rest:tt)*) => {
+        $crate::ring!(@header [$vis] {} $ring_name $($rest)*);
+    };
+    ($vis:vis use $ring_name:ident $($rest:tt)*) => {
+        $crate::ring!(@header [#[doc(hidden)]] {} $ring_name $($rest)*);
+        #[allow(unused_imports)]
+        $vis use $ring_name::{Builder, Handle, Operation, Operations, Ring, RingError, UserData};
+    };
+    (
+        @header [$($mod_vis:tt)*] { $($prelude:tt)* }
+        $ring_name:ident $(<$($generic:ident $(: $bound:path)?),+>)? $(where { $($where:tt)* })?
+        -> ($setup:ty, $completion:ty, $teardown:ty),
+        $($ops:tt)+
+    ) => {
+        $crate::ring!(
+            @ring [$($mod_vis)*] { $($prelude)* } $ring_name [$($($generic $(: $bound)?),+)?] [$($($generic),+)?] [$($($where)*)?]
+            [] [$setup, $completion, $teardown],
+            $($ops)+
+        );
+    };
+    (
+        @header [$($mod_vis:tt)*] { $($prelude:tt)* }
+        $ring_name:ident $(<$($generic:ident $(: $bound:path)?),+>)? $(where { $($where:tt)* })?
+        -> $error:ty,
+        $($ops:tt)+
+    ) => {
+        $crate::ring!(
+            @ring [$($mod_vis)*] { $($prelude)* } $ring_name [$($($generic $(: $bound)?),+)?] [$($($generic),+)?] [$($($where)*)?]
+            [] [$error, $error, $error],
+            $($ops)+
+        );
+    };
+    (
+        @header [$($mod_vis:tt)*] { $($prelude:tt)* }
+        $ring_name:ident $(<$($generic:ident $(: $bound:path)?),+>)? $(where { $($where:tt)* })?,
+        $($ops:tt)+
+    ) => {
+        $crate::ring!(
+            @ring [$($mod_vis)*] { $($prelude)* } $ring_name [$($($generic $(: $bound)?),+)?] [$($($generic),+)?] [$($($where)*)?]
+            [SetupError, CompletionError, TeardownError] [SetupError, CompletionError, TeardownError],
+            $($ops)+
+        );
+    };
+    (
+        @ring [$($mod_vis:tt)*] { $($prelude:tt)* } $ring_name:ident [$($generics:tt)*] [$($args:tt)*] [$($where:tt)*]
+        [$($error_params:tt)*] [$($errors:tt)*],
+        sqe128, $($ops:tt)+
+    ) => {
+        $crate::ring!(
+            @ops [$($mod_vis)*] { $($prelude)* } $ring_name [$($generics)*] [$($args)*] [$($where)*]
+            [$($error_params)*] [$($errors)*] [$crate::io_uring::squeue::Entry128, $crate::io_uring::cqueue::Entry32],
+            $($ops)+
+        );
+    };
+    (
+        @ring [$($mod_vis:tt)*] { $($prelude:tt)* } $ring_name:ident [$($generics:tt)*] [$($args:tt)*] [$($where:tt)*]
+        [$($error_params:tt)*] [$($errors:tt)*],
+        $($ops:tt)+
+    ) => {
+        $crate::ring!(
+            @ops [$($mod_vis)*] { $($prelude)* } $ring_name [$($generics)*] [$($args)*] [$($where)*]
+            [$($error_params)*] [$($errors)*] [$crate::io_uring::squeue::Entry, $crate::io_uring::cqueue::Entry],
+            $($ops)+
+        );
+    };
+    (
+        @ops [$($mod_vis:tt)*] { $($prelude:tt)* } $ring_name:ident [$($generics:tt)*] [$($args:tt)*] [$($where:tt)*]
+        [$($error_params:tt)*] [$($errors:tt)*] [$sqe:ty, $cqe:ty],
+        decorate = $decorate:expr, $($ring_op_name:ident: $ring_op:path),+
+    ) => {
+        $crate::ring!(
+            @module [$($mod_vis)*] { $($prelude)* } $ring_name [$($generics)*] [$($args)*]
+            [$(<$ring_op as $crate::RingOperation<$sqe, $cqe>>::RingData: std::fmt::Debug,)+ $($where)*]
+            [$($error_params)*] [$($errors)*] [$sqe, $cqe] [$decorate],
+            $($ring_op_name: $ring_op),+
+        );
+    };
+    (
+        @ops [$($mod_vis:tt)*] { $($prelude:tt)* } $ring_name:ident [$($generics:tt)*] [$($args:tt)*] [$($where:tt)*]
+        [$($error_params:tt)*] [$($errors:tt)*] [$sqe:ty, $cqe:ty],
+        $($ring_op_name:ident: $ring_op:path),+
+    ) => {
+        $crate::ring!(
+            @module [$($mod_vis)*] { $($prelude)* } $ring_name [$($generics)*] [$($args)*]
+            [$(<$ring_op as $crate::RingOperation<$sqe, $cqe>>::RingData: std::fmt::Debug,)+ $($where)*]
+            [$($error_params)*] [$($errors)*] [$sqe, $cqe] [],
+            $($ring_op_name: $ring_op),+
+        );
+    };
+    (
+        @module [$($mod_vis:tt)*] { $($prelude:tt)* } $ring_name:ident [$($generics:tt)*] [$($args:tt)*] [$($bounds:tt)*]
+        [$($error_params:tt)*] [$setup_error:ty, $completion_error:ty, $teardown_error:ty] [$sqe:ty, $cqe:ty] [$($decorate:expr)?],
+        $($ring_op_name:ident: $ring_op:path),+
+    ) => {
+        $($mod_vis)* mod $ring_name {
+            $($prelude)*
+
             use std::num::{NonZeroU32, NonZeroUsize};
             use std::collections::VecDeque;
             use std::fmt::{Debug, Formatter};
@@ -188,45 +920,87 @@ macro_rules! ring {
             use tracing::{debug, error, trace, warn};
             use $crate::io_uring::squeue::PushError;
             use $crate::io_uring::types::Timespec;
-            use $crate::io_uring::squeue::Flags;
             use $crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
 
             // Enforce trait on $ring_op
             const _: () = {
-                fn assert_ring_operation<T: RingOperation>() {}
-                fn assert_all() {
+                fn assert_ring_operation<T: RingOperation<$sqe, $cqe>>() {}
+                fn assert_all<$($generics)*>() where $($bounds)* {
                     $(assert_ring_operation::<$ring_op>());+
                 }
             };
 
-            #[derive(Debug)]
+            /// Index of every operation of this ring, used to address it with messages.
+            #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+            #[allow(non_camel_case_types)]
+            #[repr(u8)]
+            pub enum Operation {
+                $($ring_op_name),+
+            }
+
+            const _: () = assert!(
+                [$(Operation::$ring_op_name),+].len() <= $crate::message::MAX_OPERATIONS,
+                "too many operations to address them with messages",
+            );
+
+            /// The data of a request in flight, tagged with the operation that pushed it. Boxed
+            /// into the `user_data` of its SQEs.
             #[allow(non_camel_case_types)]
-            pub enum UserData {
-                $($ring_op_name(<$ring_op as RingOperation>::RingData)),+,
+            pub enum UserData<$($generics)*> where $($bounds)* {
+                $($ring_op_name(<$ring_op as RingOperation<$sqe, $cqe>>::RingData)),+,
+                /// Data of an operation added with `Ring::register`, by its index.
+                Dynamic(usize, $crate::dyn_ring::DynData),
                 Cancel(u64),
             }
 
-            impl From<UserData> for u64 {
+            impl<$($generics)*> Debug for UserData<$($args)*> where $($bounds)* {
+                fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                    match self {
+                        $(UserData::$ring_op_name(data) => f.debug_tuple(stringify!($ring_op_name)).field(data).finish()),+,
+                        UserData::Dynamic(index, data) => f.debug_tuple("Dynamic").field(index).field(data).finish(),
+                        UserData::Cancel(user_data) => f.debug_tuple("Cancel").field(user_data).finish(),
+                    }
+                }
+            }
+
+            impl<$($generics)*> From<UserData<$($args)*>> for u64 where $($bounds)* {
                 #[inline]
-                fn from(value: UserData) -> u64 {
+                fn from(value: UserData<$($args)*>) -> u64 {
                     Box::new(value).into()
                 }
             }
 
-            impl From<Box<UserData>> for u64 {
+            impl<$($generics)*> From<Box<UserData<$($args)*>>> for u64 where $($bounds)* {
                 #[inline]
-                fn from(value: Box<UserData>) -> u64 {
-                    unsafe { std::mem::transmute(value) }
+                fn from(value: Box<UserData<$($args)*>>) -> u64 {
+                    $crate::user_data::UserDataToken::new(value).into_user_data()
                 }
             }
 
-            impl UserData {
+            impl<$($generics)*> UserData<$($args)*> where $($bounds)* {
+                fn operation_name(&self) -> &'static str {
+                    match self {
+                        $(UserData::$ring_op_name(_) => stringify!($ring_op_name)),+,
+                        UserData::Dynamic(..) => "dynamic",
+                        UserData::Cancel(_) => "cancel",
+                    }
+                }
+
+                fn operation(&self) -> Option<Operation> {
+                    match self {
+                        $(UserData::$ring_op_name(_) => Some(Operation::$ring_op_name)),+,
+                        UserData::Dynamic(..) | UserData::Cancel(_) => None,
+                    }
+                }
+
                 #[inline]
                 unsafe fn from_raw(user_data: u64) -> Box<Self> {
-                    std::mem::transmute(user_data)
+                    $crate::user_data::UserDataToken::from_user_data(user_data).into_box()
                 }
             }
 
+            /// Why [`Ring::run`] or [`Ring::run_step`] failed, the operation errors are converted
+            /// into `SetupError`, `CompletionError` and `TeardownError`.
             #[derive(Debug, thiserror::Error)]
             pub enum RingError<SetupError, CompletionError, TeardownError> {
                 #[error("ring operation setup failed: {}", 0)]
@@ -243,18 +1017,180 @@ macro_rules! ring {
 
                 #[error("unable to push to submission queue: {}", 0)]
                 Push(#[from] PushError),
+
+                #[error("ring operation {operation} failed: {error}")]
+                Dynamic {
+                    /// The name it was registered under with `Ring::register`
+                    operation: &'static str,
+                    error: String,
+                },
             }
 
-            pub struct Ring {
-                ring: $crate::io_uring::IoUring,
-                backlog: VecDeque<Box<[$crate::io_uring::squeue::Entry]>>,
+            /// The operations of the ring by their name in `ring!`, kept apart from the fields
+            /// and methods of `Ring` so names like `stats` work and one type can be registered
+            /// under several names. Reached through `Ring::operations` and `Ring::operations_mut`.
+            pub struct Operations<$($generics)*> where $($bounds)* {
+                $(pub $ring_op_name: $ring_op),+,
+            }
+
+            #[doc = concat!("The ring `", stringify!($ring_name), "`, running the operations `", stringify!($($ring_op_name),+), "`.")]
+            pub struct Ring<$($generics)*> where $($bounds)* {
+                ring: $crate::io_uring::IoUring<$sqe, $cqe>,
+                backlog: VecDeque<Box<[$sqe]>>,
                 backlog_limit: Option<NonZeroUsize>,
-                $($ring_op_name: $ring_op),+,
+                load: Option<$crate::pool::balance::LoadReporter>,
+                timeline: Option<$crate::timeline::Timeline>,
+                journal: Option<$crate::journal::Journal>,
+                replay: Option<$crate::journal::Replay>,
+                stats: $crate::stats::RingStats,
+                operations: std::sync::Arc<$crate::stats::OperationGauges>,
+                trace_level: $crate::trace_level::TraceLevelHandle,
+                stats_observer: Option<Box<dyn FnMut(&$crate::stats::RingStats)>>,
+                latencies: Option<$crate::stats::Latencies>,
+                errors: Option<$crate::stats::CompletionErrors>,
+                in_flight: Option<$crate::stats::InFlight>,
+                slow_completions: Option<$crate::stats::SlowCompletions>,
+                snapshots: Option<std::sync::Arc<$crate::snapshot::SnapshotRequests>>,
+                spill_warnings: $crate::stats::SpillWarnings,
+                readiness: Option<std::os::fd::OwnedFd>,
+                started: bool,
+                finished: bool,
+                // batches left in the backlog by the previous iteration
+                carried: usize,
+                ops: Operations<$($args)*>,
+                dynamic: Vec<(&'static str, Box<dyn $crate::dyn_ring::DynOperation<$sqe, $cqe>>)>,
+                // dynamic operations whose setup ran, the ones registered later are set up by the next step
+                dynamic_set_up: usize,
+                // set by `serialize_data`
+                describe_data: Option<fn(&UserData<$($args)*>) -> String>,
+            }
+
+            /// Builds a `Ring` with its operations set by name, see `Ring::builder`. Operations
+            /// named `ring_size`, `backlog_limit`, `io_uring` or `build` clash with its methods.
+            pub struct Builder<$($generics)*> where $($bounds)* {
+                ring: Option<$crate::io_uring::IoUring<$sqe, $cqe>>,
+                ring_size: NonZeroU32,
+                backlog_limit: Option<NonZeroUsize>,
+                ops: BuilderOperations<$($args)*>,
+            }
+
+            struct BuilderOperations<$($generics)*> where $($bounds)* {
+                $($ring_op_name: Option<$ring_op>),+,
+            }
+
+            impl<$($generics)*> Builder<$($args)*> where $($bounds)* {
+                /// Submission queue entries of the ring created by `build`, defaults to 256.
+                pub fn ring_size(mut self, ring_size: NonZeroU32) -> Self {
+                    self.ring_size = ring_size;
+                    self
+                }
+
+                /// Bounds the backlog like `Ring::new` does, unbounded by default.
+                pub fn backlog_limit(mut self, backlog_limit: NonZeroUsize) -> Self {
+                    self.backlog_limit = Some(backlog_limit);
+                    self
+                }
+
+                /// Runs on `ring` instead of one from `Ring::new_raw_ring`, `ring_size` is ignored.
+                pub fn io_uring(mut self, ring: $crate::io_uring::IoUring<$sqe, $cqe>) -> Self {
+                    self.ring = Some(ring);
+                    self
+                }
+
+                $(
+                #[doc = concat!("Sets the operation `", stringify!($ring_op_name), "`.")]
+                pub fn $ring_op_name(mut self, operation: $ring_op) -> Self {
+                    self.ops.$ring_op_name = Some(operation);
+                    self
+                }
+                )+
+
+                /// Creates the ring, fails with `InvalidInput` if an operation was not set.
+                pub fn build(self) -> std::io::Result<Ring<$($args)*>> {
+                    $(
+                    if self.ops.$ring_op_name.is_none() {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            concat!("operation `", stringify!($ring_op_name), "` was not set"),
+                        ));
+                    }
+                    )+
+
+                    let ring = match self.ring {
+                        Some(ring) => ring,
+                        None => Ring::<$($args)*>::new_raw_ring(self.ring_size)?,
+                    };
+                    Ok(Ring::new(ring, self.backlog_limit, $(self.ops.$ring_op_name.unwrap()),+))
+                }
+            }
+
+            /// Hands data to the operations of a running `Ring` from any thread, created with
+            /// `Ring::handle`. `send_<operation>(data)` posts `data` into the completion queue of
+            /// the ring with `IORING_OP_MSG_RING`, the operation gets it in `on_completion` like
+            /// the data of one of its requests, with result 0.
+            ///
+            /// It holds a duplicate of the ring fd, so its number is never reused for another
+            /// ring while a handle exists, and sends fail once the `Ring` is dropped. Data still in
+            /// the completion queue when the ring stops (or sent while it is dropped) is leaked,
+            /// and it is not replayed from a journal.
+            pub struct Handle<$($generics)*> where $($bounds)* {
+                ring_fd: std::sync::Arc<std::os::fd::OwnedFd>,
+                // the gauges of the ring, only alive as long as the ring
+                operations: std::sync::Weak<$crate::stats::OperationGauges>,
+                ring: std::marker::PhantomData<fn() -> Ring<$($args)*>>,
+            }
+
+            impl<$($generics)*> Clone for Handle<$($args)*> where $($bounds)* {
+                fn clone(&self) -> Self {
+                    Self { ring_fd: self.ring_fd.clone(), operations: self.operations.clone(), ring: std::marker::PhantomData }
+                }
+            }
+
+            impl<$($generics)*> Debug for Handle<$($args)*> where $($bounds)* {
+                fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                    f.debug_struct("Handle").field("ring_fd", &self.ring_fd).field("alive", &(self.operations.strong_count() > 0)).finish()
+                }
+            }
+
+            $crate::paste::paste! {
+                impl<$($generics)*> Handle<$($args)*> where $($bounds)* {
+                    $(
+                    #[doc = concat!("Completes `data` as a request of the operation `", stringify!($ring_op_name), "`, fails if the ring is gone.")]
+                    pub fn [<send_ $ring_op_name>](&self, data: <$ring_op as RingOperation<$sqe, $cqe>>::RingData) -> std::io::Result<()>
+                    where
+                        // higher-ranked so operations whose data is not `Send` just lack the method
+                        for<'__send> <$ring_op as RingOperation<$sqe, $cqe>>::RingData: Send,
+                    {
+                        self.post(UserData::$ring_op_name(data))
+                    }
+                    )+
+                }
+            }
+
+            impl<$($generics)*> Handle<$($args)*> where $($bounds)* {
+                fn post(&self, data: UserData<$($args)*>) -> std::io::Result<()> {
+                    let Some(operations) = self.operations.upgrade() else {
+                        return Err(std::io::Error::new(std::io::ErrorKind::NotConnected, "the ring is gone"));
+                    };
+                    // completed like a pushed request, which drops it from the gauges
+                    let operation = data.operation();
+                    if let Some(operation) = operation {
+                        operations.pushed(operation as usize);
+                    }
+                    let user_data: u64 = data.into();
+                    $crate::message::post_completion(self.ring_fd.as_raw_fd(), user_data, 0).inspect_err(|_| {
+                        // the ring did not get the data
+                        drop(unsafe { UserData::<$($args)*>::from_raw(user_data) });
+                        if let Some(operation) = operation {
+                            operations.dropped(operation as usize);
+                        }
+                    })
+                }
             }
 
-            impl Debug for Ring {
+            impl<$($generics)*> Debug for Ring<$($args)*> where $($bounds)* {
                 fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-                    let operations = ($(&self.$ring_op_name),+);
+                    let operations = ($(&self.ops.$ring_op_name),+);
 
                     if f.alternate() {
                         write!(f, r"Ring: {{
@@ -268,10 +1204,11 @@ macro_rules! ring {
                 }
             }
 
-            impl Ring
+            impl<$($generics)*> Ring<$($args)*>
+            where $($bounds)*
             {
-                pub fn new_raw_ring(ring_size: NonZeroU32) -> std::io::Result<$crate::io_uring::IoUring> {
-                    $crate::io_uring::IoUring::builder()
+                pub fn new_raw_ring(ring_size: NonZeroU32) -> std::io::Result<$crate::io_uring::IoUring<$sqe, $cqe>> {
+                    $crate::io_uring::IoUring::<$sqe, $cqe>::builder()
                         .setup_single_issuer()
                         .setup_coop_taskrun()
                         .setup_defer_taskrun()
@@ -279,141 +1216,896 @@ macro_rules! ring {
                 }
 
                 #[tracing::instrument(skip_all)]
-                pub fn new(ring: $crate::io_uring::IoUring, backlog_limit: Option<NonZeroUsize>, $($ring_op_name: $ring_op),+) -> Self {
+                pub fn new(ring: $crate::io_uring::IoUring<$sqe, $cqe>, backlog_limit: Option<NonZeroUsize>, $($ring_op_name: $ring_op),+) -> Self {
+                    let mut stats = $crate::stats::RingStats::default();
+                    stats.sq = $crate::stats::QueueFill::new(ring.params().sq_entries() as usize);
+                    stats.cq = $crate::stats::QueueFill::new(ring.params().cq_entries() as usize);
                     Self {
                         ring,
                         backlog: Default::default(),
                         backlog_limit,
-                        $($ring_op_name),+
+                        load: None,
+                        timeline: None,
+                        journal: None,
+                        replay: None,
+                        stats,
+                        operations: std::sync::Arc::new($crate::stats::OperationGauges::new(&[$(stringify!($ring_op_name)),+])),
+                        trace_level: Default::default(),
+                        stats_observer: None,
+                        latencies: None,
+                        errors: None,
+                        in_flight: None,
+                        slow_completions: None,
+                        snapshots: None,
+                        spill_warnings: $crate::stats::SpillWarnings::new(std::time::Duration::from_secs(10)),
+                        readiness: None,
+                        started: false,
+                        finished: false,
+                        carried: 0,
+                        ops: Operations { $($ring_op_name),+ },
+                        dynamic: vec![],
+                        dynamic_set_up: 0,
+                        describe_data: None,
+                    }
+                }
+
+                /// A builder setting the operations by name, with a ring of 256 entries and an
+                /// unbounded backlog unless told otherwise.
+                pub fn builder() -> Builder<$($args)*> {
+                    Builder {
+                        ring: None,
+                        ring_size: NonZeroU32::new(256).unwrap(),
+                        backlog_limit: None,
+                        ops: BuilderOperations { $($ring_op_name: None),+ },
                     }
                 }
 
+                /// The operations of this ring, to inspect the state they hold (counters, caches,
+                /// open connections) while the ring is idle or after `run` returned.
+                pub fn operations(&self) -> &Operations<$($args)*> {
+                    &self.ops
+                }
+
+                /// The operations of this ring, mutably, e.g. to reconfigure one between
+                /// `run_step`s.
+                pub fn operations_mut(&mut self) -> &mut Operations<$($args)*> {
+                    &mut self.ops
+                }
+
+                /// Registers `operation` under `name` next to the operations of `ring!`, e.g. for
+                /// plugins. It is set up at the beginning of the next step, its data is boxed per
+                /// request like on a [`DynRing`]($crate::dyn_ring::DynRing) and it can not be
+                /// addressed by messages.
+                pub fn register(&mut self, name: &'static str, operation: impl $crate::dyn_ring::DynOperation<$sqe, $cqe> + 'static) -> $crate::dyn_ring::OperationId {
+                    self.register_boxed(name, Box::new(operation))
+                }
+
+                /// Registers an already erased operation under `name`, see `Ring::register`.
+                pub fn register_boxed(&mut self, name: &'static str, operation: Box<dyn $crate::dyn_ring::DynOperation<$sqe, $cqe>>) -> $crate::dyn_ring::OperationId {
+                    self.dynamic.push((name, operation));
+                    $crate::dyn_ring::OperationId::new(self.dynamic.len() - 1)
+                }
+
+                /// Reports the completion queue backlog and the submission, completion and
+                /// backlog spill counters of this ring to a
+                /// [`LoadBoard`]($crate::pool::balance::LoadBoard) once per loop iteration.
+                pub fn report_load(&mut self, reporter: $crate::pool::balance::LoadReporter) {
+                    self.load = Some(reporter);
+                }
+
+                /// Records the submission and completions of every request of this ring on
+                /// `timeline`, see [`timeline`]($crate::timeline).
+                pub fn record_timeline(&mut self, timeline: $crate::timeline::Timeline) {
+                    self.timeline = Some(timeline);
+                }
+
+                /// Records every SQE pushed and CQE handled from now on in `journal`, see
+                /// [`journal`]($crate::journal).
+                pub fn record_journal(&mut self, journal: $crate::journal::Journal) {
+                    self.journal = Some(journal);
+                }
+
+                /// Runs the operations of this ring, which must not have run yet, against the
+                /// completions recorded in `entries` instead of the kernel, until the journal
+                /// ends, reaches teardown or the operations push other SQEs than recorded. See
+                /// [`journal`]($crate::journal).
+                pub fn replay<$($error_params)*>(&mut self, entries: Vec<$crate::journal::JournalEntry>) -> Result<$crate::journal::ReplayReport, RingError<$setup_error, $completion_error, $teardown_error>>
+                where
+                    $setup_error: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::SetupError>)+,
+                    $completion_error: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::ControlFlowError>)+,
+                    $teardown_error: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::TeardownError>)+,
+                {
+                    self.replay = Some($crate::journal::Replay::new(entries));
+                    let result = self.run();
+                    let report = self.replay.take().unwrap().report();
+                    result.map(|()| report)
+                }
+
+                /// Counters of the run loop so far, see [`stats`]($crate::stats).
+                pub fn stats(&self) -> $crate::stats::RingStats {
+                    $crate::stats::RingStats {
+                        in_flight: self.operations.total(),
+                        ..self.stats
+                    }
+                }
+
+                /// Requests in flight of every operation and the most there were at once, in the
+                /// order of `ring!`. Counted from the push of a request until its data is dropped,
+                /// e.g. to size the completion queue, buffer pools or fixed file tables by. All 0
+                /// unless [`Ring::count_in_flight`] turned counting on.
+                pub fn in_flight_by_operation(&self) -> Vec<(&'static str, $crate::stats::InFlightGauge)> {
+                    self.operations.gauges()
+                }
+
+                /// Counts the requests of every operation pushed from now on until their data is
+                /// dropped, see [`Ring::in_flight_by_operation`]. Off by default, it costs atomic
+                /// updates on every push and completion.
+                pub fn count_in_flight(&mut self) {
+                    self.operations.count();
+                }
+
+                /// Calls `observer` with the counters of the run loop after every iteration and
+                /// once more after teardown, on the ring thread.
+                pub fn observe_stats(&mut self, observer: impl FnMut(&$crate::stats::RingStats) + 'static) {
+                    self.stats_observer = Some(Box::new(observer));
+                }
+
+                /// Timestamps every request pushed from now on and records the time to its last
+                /// completion per operation, see [`Latencies`]($crate::stats::Latencies).
+                pub fn record_latencies(&mut self) {
+                    if self.latencies.is_none() {
+                        self.latencies = Some(Default::default());
+                    }
+                    self.track_in_flight();
+                }
+
+                /// The latencies recorded since [`Ring::record_latencies`].
+                pub fn latencies(&self) -> Option<&$crate::stats::Latencies> {
+                    self.latencies.as_ref()
+                }
+
+                /// Counts the completions handled from now on that failed, by opcode and errno, see
+                /// [`CompletionErrors`]($crate::stats::CompletionErrors). The opcode is known for
+                /// requests pushed from now on, see [`Ring::track_in_flight`].
+                pub fn count_errors(&mut self) {
+                    if self.errors.is_none() {
+                        self.errors = Some(Default::default());
+                    }
+                    self.track_in_flight();
+                }
+
+                /// The errors counted since [`Ring::count_errors`].
+                pub fn errors(&self) -> Option<&$crate::stats::CompletionErrors> {
+                    self.errors.as_ref()
+                }
+
+                /// Keeps track of every request pushed from now on until its data is dropped, to list
+                /// them in [`Ring::debug_snapshot`].
+                pub fn track_in_flight(&mut self) {
+                    if self.in_flight.is_none() {
+                        self.in_flight = Some(Default::default());
+                    }
+                    self.count_in_flight();
+                }
+
+                /// Calls `observer` on the ring thread for every request that took longer than
+                /// `threshold` from being pushed to its last completion, e.g. a read stuck in
+                /// io-wq. Tracks the requests in flight from now on, see [`Ring::track_in_flight`].
+                pub fn detect_slow_completions(&mut self, threshold: std::time::Duration, observer: impl FnMut(&$crate::stats::SlowCompletion) + 'static) {
+                    self.slow_completions = Some($crate::stats::SlowCompletions::new(threshold, Box::new(observer)));
+                    self.track_in_flight();
+                }
+
+                /// The limits of bounded and unbounded io-wq workers running the requests of this
+                /// ring that can not complete inline and the number of workers of both kinds, see
+                /// [`IoWqStats`]($crate::stats::IoWqStats). Call it on the ring thread, the
+                /// workers of the calling thread are counted.
+                pub fn io_wq_stats(&self) -> std::io::Result<$crate::stats::IoWqStats> {
+                    self.limit_io_wq_workers(0, 0)
+                }
+
+                /// Limits the io-wq workers of this ring to `bounded` workers for regular files and
+                /// block devices and `unbounded` workers for everything else, 0 keeps a limit.
+                /// Returns the [`Ring::io_wq_stats`] with the new limits.
+                pub fn limit_io_wq_workers(&self, bounded: u32, unbounded: u32) -> std::io::Result<$crate::stats::IoWqStats> {
+                    $crate::stats::IoWqStats::read(
+                        &self.ring.submitter(),
+                        self.ring.as_raw_fd(),
+                        self.ring.params().is_setup_sqpoll(),
+                        bounded,
+                        unbounded,
+                    )
+                }
+
+                /// Reports the requests in flight (once tracked, see [`Ring::track_in_flight`]),
+                /// the backlog and the fill levels of the submission and completion queue, see
+                /// [`snapshot`]($crate::snapshot).
+                pub fn debug_snapshot(&mut self) -> $crate::snapshot::RingSnapshot {
+                    let (_, mut sq, mut cq) = self.ring.split();
+                    sq.sync();
+                    cq.sync();
+                    Self::snapshot(&self.stats, &self.operations, sq.len(), cq.len(), self.in_flight.as_ref(), &self.backlog, self.describe_data)
+                }
+
+                /// A handle to request [`Ring::debug_snapshot`]s from other threads, answered at
+                /// the end of the next loop iteration.
+                pub fn snapshot_handle(&mut self) -> $crate::snapshot::SnapshotHandle {
+                    let requests = self.snapshots.get_or_insert_with(Default::default);
+                    $crate::snapshot::SnapshotHandle::new(self.ring.as_raw_fd(), requests.clone())
+                }
+
+                fn snapshot(
+                    stats: &$crate::stats::RingStats,
+                    operations: &$crate::stats::OperationGauges,
+                    sq: usize,
+                    cq: usize,
+                    in_flight: Option<&$crate::stats::InFlight>,
+                    backlog: &VecDeque<Box<[$sqe]>>,
+                    describe: Option<fn(&UserData<$($args)*>) -> String>,
+                ) -> $crate::snapshot::RingSnapshot {
+                    let in_flight = in_flight.map(|in_flight| {
+                        in_flight
+                            .ages()
+                            .into_iter()
+                            .map(|(user_data, opcode, age)| {
+                                // tracked requests are forgotten before their data is dropped
+                                let data = unsafe { $crate::user_data::UserDataToken::<UserData<$($args)*>>::borrow(user_data) };
+                                $crate::snapshot::InFlightRequest {
+                                    operation: data.operation_name(),
+                                    opcode,
+                                    user_data,
+                                    data: describe.map_or_else(|| format!("{data:?}"), |describe| describe(data)),
+                                    age,
+                                }
+                            })
+                            .collect()
+                    });
+
+                    $crate::snapshot::RingSnapshot {
+                        ring: stringify!($ring_name),
+                        stats: $crate::stats::RingStats {
+                            in_flight: operations.total(),
+                            ..*stats
+                        },
+                        sq: stats.sq.with_len(sq),
+                        cq: stats.cq.with_len(cq),
+                        operations: operations.gauges(),
+                        in_flight,
+                        backlog: backlog.iter().map(|entries| format!("{entries:?}")).collect(),
+                    }
+                }
+
+                /// Logs the completions this ring handles at `level` from the next loop iteration on
+                /// instead of `TRACE`, `None` turns their logging off. See
+                /// [`trace_level`]($crate::trace_level).
+                pub fn set_trace_level(&self, level: Option<tracing::Level>) {
+                    self.trace_level.set(level);
+                }
+
+                /// A handle to [`Ring::set_trace_level`] from other threads.
+                pub fn trace_level_handle(&self) -> $crate::trace_level::TraceLevelHandle {
+                    self.trace_level.clone()
+                }
+
+                /// Batches of SQEs that did not fit into the submission queue are summarized in at
+                /// most one warning per `interval`, 10 seconds by default. Their count is in
+                /// [`Ring::stats`] either way.
+                pub fn spill_warning_interval(&mut self, interval: std::time::Duration) {
+                    self.spill_warnings = $crate::stats::SpillWarnings::new(interval);
+                }
+
+                /// Address of `operation` on this ring for messages sent from other rings.
+                pub fn message_target(&self, operation: Operation) -> $crate::message::MessageTarget {
+                    $crate::message::MessageTarget::new(self.ring.as_raw_fd(), operation as u8)
+                }
+
+                /// A [`Handle`] to send data to the operations of this ring from other threads,
+                /// fails if the ring fd cannot be duplicated.
+                pub fn handle(&self) -> std::io::Result<Handle<$($args)*>> {
+                    // open as long as `self.ring`
+                    let ring_fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(self.ring.as_raw_fd()) };
+                    Ok(Handle {
+                        ring_fd: std::sync::Arc::new(ring_fd.try_clone_to_owned()?),
+                        operations: std::sync::Arc::downgrade(&self.operations),
+                        ring: std::marker::PhantomData,
+                    })
+                }
+
                 #[inline]
-                fn sqe_wrapper(e: &mut $crate::io_uring::squeue::Entry, user_data: UserData) {
-                    take_mut::take(e, |e| e.user_data(user_data.into()));
+                fn sqe_wrapper(e: &mut $sqe, user_data: UserData<$($args)*>, timeline: Option<&$crate::timeline::Timeline>, in_flight: Option<&$crate::stats::InFlight>, journal: Option<(&$crate::journal::Journal, Option<fn(&UserData<$($args)*>) -> String>)>, replay: Option<&$crate::journal::Replay>, operations: &$crate::stats::OperationGauges) {
+                    if let Some(operation) = user_data.operation() {
+                        operations.pushed(operation as usize);
+                    }
+                    let operation = user_data.operation_name();
+                    let journal = journal.map(|(journal, describe)| (journal, describe.map(|describe| describe(&user_data))));
+                    let user_data: u64 = user_data.into();
+                    if let Some(timeline) = timeline {
+                        timeline.submitted(stringify!($ring_name), operation, user_data);
+                    }
+                    if let Some(in_flight) = in_flight {
+                        in_flight.submitted(user_data, $crate::entry_opcode(e));
+                    }
+                    take_mut::take(e, |e| e.user_data(user_data));
+                    $(
+                    let decorate: fn($sqe) -> $sqe = $decorate;
+                    take_mut::take(e, decorate);
+                    )?
+                    if let Some((journal, data)) = journal {
+                        journal.submitted(operation, e, data);
+                    }
+                    if let Some(replay) = replay {
+                        replay.submitted(e);
+                    }
                 }
 
+                /// Sets the operations up (on the first call) and runs the ring until an operation
+                /// exits it or fails, then cancels everything still in flight.
                 #[tracing::instrument(skip_all)]
-                pub fn run<SetupError, CompletionError, TeardownError>(&mut self) -> Result<(), RingError<SetupError, CompletionError, TeardownError>>
+                pub fn run<$($error_params)*>(&mut self) -> Result<(), RingError<$setup_error, $completion_error, $teardown_error>>
                 where
-                    SetupError: Debug $(+ std::convert::From<<$ring_op as RingOperation>::SetupError>)+,
-                    CompletionError: Debug $(+ std::convert::From<<$ring_op as RingOperation>::ControlFlowError>)+,
-                    TeardownError: Debug $(+ std::convert::From<<$ring_op as RingOperation>::TeardownError>)+,
+                    $setup_error: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::SetupError>)+,
+                    $completion_error: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::ControlFlowError>)+,
+                    $teardown_error: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::TeardownError>)+,
                 {
-                    let mut result = Ok(());
-                    let (submit, mut sq, mut cq) = self.ring.split();
+                    loop {
+                        if let $crate::RingStep::Finished = self.step(1)? {
+                            return Ok(());
+                        }
+                    }
+                }
+
+                /// Like one iteration of [`Ring::run`], but without blocking: submits what is
+                /// queued, handles the completions that are already there and runs the
+                /// housekeeping of all operations. The first call sets the operations up.
+                ///
+                /// Meant for rings embedded in another event loop, e.g. with epoll: register
+                /// [`Ring::readiness_fd`] (level-triggered) and call `run_step` whenever it is
+                /// readable, every step handles all completions that are there. The fd of the
+                /// ring itself (see [`AsRawFd`]) only works for rings without `DEFER_TASKRUN`:
+                /// rings like [`Ring::new_raw_ring`] defer posting completions until the ring
+                /// thread enters the kernel and do not wake pollers of the ring fd in the
+                /// meantime. Operations that submit work from housekeeping only make progress
+                /// while steps keep coming, call it at least once after handing work to the
+                /// ring from outside as well.
+                ///
+                /// Once an operation exits the ring the step cancels everything in flight, which
+                /// blocks until the cancellations completed, and returns
+                /// [`RingStep::Finished`]($crate::RingStep::Finished) from then on.
+                #[tracing::instrument(skip_all)]
+                pub fn run_step<$($error_params)*>(&mut self) -> Result<$crate::RingStep, RingError<$setup_error, $completion_error, $teardown_error>>
+                where
+                    $setup_error: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::SetupError>)+,
+                    $completion_error: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::ControlFlowError>)+,
+                    $teardown_error: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::TeardownError>)+,
+                {
+                    if let Some(fd) = &self.readiness {
+                        $crate::reset_readiness_fd(fd);
+                    }
+                    self.step(0)
+                }
+
+                /// An eventfd that is readable while this ring has completions
+                /// [`Ring::run_step`] did not handle yet, created and registered with the ring on
+                /// the first call. It stays owned by the ring.
+                ///
+                /// With the `mio` feature the ring is a `mio::event::Source` registering this fd.
+                /// mio polls edge-triggered, which works as every completion signals the fd again
+                /// and every step handles all of them.
+                pub fn readiness_fd(&mut self) -> std::io::Result<RawFd> {
+                    if self.readiness.is_none() {
+                        self.readiness = Some($crate::register_readiness_fd(&self.ring)?);
+                    }
+                    Ok(self.readiness.as_ref().unwrap().as_raw_fd())
+                }
+
+                fn step<SetupError, CompletionError, TeardownError>(&mut self, want: usize) -> Result<$crate::RingStep, RingError<SetupError, CompletionError, TeardownError>>
+                where
+                    SetupError: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::SetupError>)+,
+                    CompletionError: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::ControlFlowError>)+,
+                    TeardownError: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::TeardownError>)+,
+                {
+                    if self.finished {
+                        return Ok($crate::RingStep::Finished);
+                    }
+                    if !self.started {
+                        self.started = true;
+                        if let Err(e) = self.setup() {
+                            self.finished = true;
+                            return Err(e);
+                        }
+                    }
+                    if let Err(e) = self.setup_dynamic() {
+                        self.finished = true;
+                        return Err(e);
+                    }
+
+                    let result = match self.iteration(want) {
+                        Ok(std::ops::ControlFlow::Continue(completions)) => {
+                            return Ok($crate::RingStep::Running { completions });
+                        }
+                        Ok(std::ops::ControlFlow::Break(result)) => {
+                            // the iteration an operation exited in
+                            self.stats.in_flight = self.operations.total();
+                            if let Some(observer) = &mut self.stats_observer {
+                                observer(&self.stats);
+                            }
+                            result
+                        }
+                        Err(e) => {
+                            self.finished = true;
+                            return Err(e);
+                        }
+                    };
+
+                    self.finished = true;
+                    self.teardown(result)?;
+                    Ok($crate::RingStep::Finished)
+                }
+
+                fn setup<SetupError, CompletionError, TeardownError>(&mut self) -> Result<(), RingError<SetupError, CompletionError, TeardownError>>
+                where
+                    SetupError: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::SetupError>)+,
+                {
+                    let (_, mut sq, _) = self.ring.split();
+                    let timeline = self.timeline.as_ref();
+                    let in_flight = self.in_flight.as_ref();
+                    let journal = self.journal.as_ref();
+                    let replay = self.replay.as_ref();
+                    // the journal with the description of the data of its SQEs
+                    let submissions = journal.map(|journal| (journal, self.describe_data));
+                    let operations = &self.operations;
 
-                    $(if let Err(e) = self.$ring_op_name.setup(SubmissionQueueSubmitter::new(
+                    $(if let Err(e) = self.ops.$ring_op_name.setup(SubmissionQueueSubmitter::new(
                         &mut sq,
                         &mut self.backlog,
                         self.backlog_limit,
-                        |e, d| Self::sqe_wrapper(e, UserData::$ring_op_name(d)),
+                        |e, d| Self::sqe_wrapper(e, UserData::$ring_op_name(d), timeline, in_flight, submissions, replay, operations),
                     )) {
                         return Err(RingError::Setup(e.into()));
                     })+
 
+                    Ok(())
+                }
+
+                /// Sets up the operations registered since the last step.
+                fn setup_dynamic<SetupError, CompletionError, TeardownError>(&mut self) -> Result<(), RingError<SetupError, CompletionError, TeardownError>> {
+                    let (_, mut sq, _) = self.ring.split();
+                    let timeline = self.timeline.as_ref();
+                    let in_flight = self.in_flight.as_ref();
+                    let journal = self.journal.as_ref();
+                    let replay = self.replay.as_ref();
+                    // the journal with the description of the data of its SQEs
+                    let submissions = journal.map(|journal| (journal, self.describe_data));
+                    let operations = &self.operations;
+
+                    while let Some((name, operation)) = self.dynamic.get_mut(self.dynamic_set_up) {
+                        let index = self.dynamic_set_up;
+                        self.dynamic_set_up += 1;
+                        let wrapper = |e: &mut $sqe, d| Self::sqe_wrapper(e, UserData::Dynamic(index, d), timeline, in_flight, submissions, replay, operations);
+                        let submitter: $crate::dyn_ring::DynSubmitter<$sqe> = SubmissionQueueSubmitter::new(&mut sq, &mut self.backlog, self.backlog_limit, &wrapper);
+                        if let Err(error) = operation.setup(submitter) {
+                            return Err(RingError::Dynamic { operation: name, error: format!("{error:?}") });
+                        }
+                    }
+
+                    Ok(())
+                }
+
+                /// Submits, waits for `want` completions, handles them and runs the housekeeping.
+                /// Breaks with the result of the ring once an operation exits it or fails.
+                fn iteration<SetupError, CompletionError, TeardownError>(&mut self, want: usize) -> Result<std::ops::ControlFlow<Result<(), RingError<SetupError, CompletionError, TeardownError>>, usize>, RingError<SetupError, CompletionError, TeardownError>>
+                where
+                    CompletionError: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::ControlFlowError>)+,
+                {
+                    let ring_fd = self.ring.as_raw_fd();
+                    let (submit, mut sq, mut cq) = self.ring.split();
+                    let timeline = self.timeline.as_ref();
+                    let latencies = self.latencies.as_ref();
+                    let errors = self.errors.as_ref();
+                    let in_flight = self.in_flight.as_ref();
+                    let journal = self.journal.as_ref();
+                    let replay = self.replay.as_ref();
+                    // the journal with the description of the data of its SQEs
+                    let submissions = journal.map(|journal| (journal, self.describe_data));
+                    let operations = &self.operations;
+                    let trace_level = self.trace_level.raw();
+
                     unsafe {
-                        'ring_loop: loop {
+                        if let Some(replay) = replay {
+                            // without room for its completions the ring would wait for them forever
                             sq.sync();
-                            submit.submit_and_wait(1)?;
-
-                            while let Some(entries) = self.backlog.pop_front() {
-                                trace!("push from backlog");
-                                if let Err(_) = sq.push_multiple(&entries) {
-                                    self.backlog.push_front(entries);
-                                    break;
+                            if sq.is_full() {
+                                self.stats.submitted += $crate::submit_and_collect(&submit, sq.len())? as u64;
+                                sq.sync();
+                            }
+                            replay.post(&mut sq, ring_fd);
+                        }
+                        sq.sync();
+                        self.stats.sq.set(sq.len());
+                        let spilled = self.backlog.len().saturating_sub(self.carried);
+                        if spilled > 0 {
+                            $crate::__probe!("backlog_spill", concat!(stringify!($ring_name), "\0").as_ptr(), spilled, self.backlog.len());
+                        }
+                        let submitted = match want {
+                            0 => $crate::submit_and_collect(&submit, sq.len())?,
+                            want => {
+                                // the completions waited for may depend on backlogged entries, they
+                                // have to reach the kernel before the ring blocks
+                                let mut submitted = 0;
+                                while !self.backlog.is_empty() {
+                                    submitted += $crate::submit_and_collect(&submit, sq.len())?;
+                                    let carried = self.backlog.len();
+                                    $crate::push_backlog(&mut sq, &mut self.backlog);
+                                    sq.sync();
+                                    if self.backlog.len() == carried {
+                                        break;
+                                    }
                                 }
+                                let waiting = std::time::Instant::now();
+                                submitted += submit.submit_and_wait(want)?;
+                                self.stats.blocked += waiting.elapsed();
+                                submitted
                             }
+                        };
+                        $crate::__probe!("submit", concat!(stringify!($ring_name), "\0").as_ptr(), submitted, want);
 
-                            cq.sync();
-                            'completion_loop: for cqe in cq.by_ref() {
-                                trace!("> CQE: {cqe:?}");
-                                if cqe.user_data() == 0 {
-                                    trace!("dropped {cqe:?}");
+                        $crate::push_backlog(&mut sq, &mut self.backlog);
+                        self.carried = self.backlog.len();
 
-                                    // ignore
-                                    continue;
-                                }
+                        cq.sync();
+                        let completions = cq.len();
+                        self.stats.iterations += 1;
+                        self.stats.syscalls += 1;
+                        self.stats.submitted += submitted as u64;
+                        self.stats.completed += completions as u64;
+                        self.stats.backlog_spills += spilled as u64;
+                        self.stats.backlog = self.backlog.len();
+                        self.stats.cq.set(completions);
+                        if let Some((spills, window)) = self.spill_warnings.add(spilled) {
+                            warn!("submission queue full, {spills} batches of SQEs waited in the backlog in the last {window:?} (may degrade performance)");
+                        }
+                        if let Some(load) = &self.load {
+                            load.set_cq_backlog(completions);
+                            load.add_submitted(submitted);
+                            load.add_completed(completions);
+                            load.add_backlog_spills(spilled);
+                        }
+                        'completion_loop: for cqe in cq.by_ref() {
+                            $crate::__ring_event!(trace_level, "> CQE: {cqe:?}");
+                            if cqe.user_data() == 0 {
+                                $crate::__ring_event!(trace_level, "dropped {cqe:?}");
 
-                                let mut user_data = UserData::from_raw(cqe.user_data());
-                                trace!("> CQE userdata: {user_data:?}");
-                                let flow = match *user_data {
-                                    $(UserData::$ring_op_name(data) => {
-                                        let (flow, new_data) = self.$ring_op_name.on_completion(
-                                            cqe,
-                                            data,
-                                            SubmissionQueueSubmitter::new(
-                                                &mut sq,
-                                                &mut self.backlog,
-                                                self.backlog_limit, |e, d| Self::sqe_wrapper(e, UserData::$ring_op_name(d)),
-                                            ),
-                                        );
-                                        if let Some(new_data) = new_data {
-                                            *user_data = UserData::$ring_op_name(new_data);
-                                            std::mem::forget(std::hint::black_box(user_data));
-                                        }
+                                // ignore
+                                continue;
+                            }
+                            if let Some(journal) = journal {
+                                journal.completed(&cqe);
+                            }
 
-                                        flow
-                                    }),+
-                                    UserData::Cancel(_) => unreachable!(),
+                            if let Some((operation, message)) = $crate::message::RingMessage::decode(&cqe) {
+                                $crate::__ring_event!(trace_level, "> message for operation {operation}: {message:?}");
+                                let flow = match operation {
+                                    $(operation if operation == Operation::$ring_op_name as u8 => self.ops.$ring_op_name.on_message(
+                                        message,
+                                        SubmissionQueueSubmitter::new(
+                                            &mut sq,
+                                            &mut self.backlog,
+                                            self.backlog_limit, |e, d| Self::sqe_wrapper(e, UserData::$ring_op_name(d), timeline, in_flight, submissions, replay, operations),
+                                        ),
+                                    ),)+
+                                    $crate::message::WAKE_OPERATION => match replay {
+                                        Some(replay) if replay.on_wake(&message) => ControlFlow::Exit,
+                                        _ => ControlFlow::Continue,
+                                    },
+                                    _ => {
+                                        warn!("dropped message for unknown operation {operation}: {message:?}");
+                                        ControlFlow::Continue
+                                    }
                                 };
 
                                 match flow {
-                                    ControlFlow::Exit => break 'ring_loop,
+                                    ControlFlow::Exit => return Ok(std::ops::ControlFlow::Break(Ok(()))),
                                     ControlFlow::Error(e) => {
-                                        result = Err(RingError::Completion(e.into()));
-                                        break 'ring_loop;
-                                    }
-                                    ControlFlow::Warn(e) => {
-                                        warn!("unable to handle ring completion entry: {e:?}");
-                                        continue 'completion_loop;
+                                        return Ok(std::ops::ControlFlow::Break(Err(RingError::Completion(e.into()))));
                                     }
+                                    ControlFlow::Warn(e) => warn!("unable to handle ring message: {e:?}"),
                                     ControlFlow::Continue => {}
                                 }
+                                continue 'completion_loop;
+                            }
+
+                            if let Some(timeline) = timeline {
+                                timeline.completed(cqe.user_data(), cqe.result(), cqe.flags());
+                            }
+                            let completed = cqe.user_data();
+                            let more = $crate::io_uring::cqueue::more(cqe.flags());
+                            let mut user_data = UserData::<$($args)*>::from_raw(completed);
+                            $crate::__ring_event!(trace_level, "> CQE userdata: {user_data:?}");
+                            let operation = user_data.operation();
+                            let failed = errors.is_some() && cqe.result() < 0;
+                            let submitted = in_flight.filter(|_| !more || failed).and_then(|in_flight| in_flight.elapsed(completed));
+                            if let (Some(errors), true) = (errors, failed) {
+                                errors.record(submitted.map(|(opcode, _)| opcode), -cqe.result());
+                            }
+                            if let (Some((opcode, elapsed)), false) = (submitted, more) {
+                                if let Some(latencies) = latencies {
+                                    latencies.record(user_data.operation_name(), elapsed);
+                                }
+                                if let Some(slow_completions) = &mut self.slow_completions {
+                                    slow_completions.check(user_data.operation_name(), opcode, completed, cqe.result(), elapsed);
+                                }
+                            }
+                            let mut kept = false;
+                            let mut dynamic_flow = None;
+                            let flow = match *user_data {
+                                $(UserData::$ring_op_name(data) => {
+                                    $crate::__probe!(
+                                        "completion",
+                                        concat!(stringify!($ring_name), "\0").as_ptr(),
+                                        concat!(stringify!($ring_op_name), "\0").as_ptr(),
+                                        completed,
+                                        cqe.result() as i64,
+                                        cqe.flags()
+                                    );
+                                    let (flow, new_data) = self.ops.$ring_op_name.on_completion(
+                                        cqe,
+                                        data,
+                                        SubmissionQueueSubmitter::new(
+                                            &mut sq,
+                                            &mut self.backlog,
+                                            self.backlog_limit, |e, d| Self::sqe_wrapper(e, UserData::$ring_op_name(d), timeline, in_flight, submissions, replay, operations),
+                                        ),
+                                    );
+                                    if let Some(new_data) = new_data {
+                                        *user_data = UserData::$ring_op_name(new_data);
+                                        let kept_user_data: u64 = user_data.into();
+                                        debug_assert_eq!(kept_user_data, completed, "kept data moved");
+                                        kept = true;
+                                    }
+
+                                    flow
+                                }),+
+                                UserData::Dynamic(index, data) => {
+                                    let (name, operation) = &mut self.dynamic[index];
+                                    let wrapper = |e: &mut $sqe, d| Self::sqe_wrapper(e, UserData::Dynamic(index, d), timeline, in_flight, submissions, replay, operations);
+                                    let submitter: $crate::dyn_ring::DynSubmitter<$sqe> = SubmissionQueueSubmitter::new(&mut sq, &mut self.backlog, self.backlog_limit, &wrapper);
+                                    let (flow, new_data) = operation.on_completion(cqe, data, submitter);
+                                    if let Some(new_data) = new_data {
+                                        *user_data = UserData::Dynamic(index, new_data);
+                                        let kept_user_data: u64 = user_data.into();
+                                        debug_assert_eq!(kept_user_data, completed, "kept data moved");
+                                        kept = true;
+                                    }
+
+                                    // the errors of dynamic operations are not converted into `CompletionError`
+                                    dynamic_flow = Some((*name, flow));
+                                    ControlFlow::Continue
+                                }
+                                UserData::Cancel(_) => unreachable!(),
+                            };
+                            if let (Some(in_flight), false) = (in_flight, more && kept) {
+                                in_flight.remove(completed);
+                            }
+                            if let (Some(operation), false) = (operation, more && kept) {
+                                operations.dropped(operation as usize);
+                            }
+
+                            match dynamic_flow {
+                                Some((_, ControlFlow::Exit)) => return Ok(std::ops::ControlFlow::Break(Ok(()))),
+                                Some((name, ControlFlow::Error(error))) => {
+                                    return Ok(std::ops::ControlFlow::Break(Err(RingError::Dynamic { operation: name, error: format!("{error:?}") })));
+                                }
+                                Some((_, ControlFlow::Warn(e))) => {
+                                    warn!("unable to handle ring completion entry: {e:?}");
+                                    continue 'completion_loop;
+                                }
+                                Some((_, ControlFlow::Continue)) | None => {}
+                            }
+
+                            match flow {
+                                ControlFlow::Exit => return Ok(std::ops::ControlFlow::Break(Ok(()))),
+                                ControlFlow::Error(e) => {
+                                    return Ok(std::ops::ControlFlow::Break(Err(RingError::Completion(e.into()))));
+                                }
+                                ControlFlow::Warn(e) => {
+                                    warn!("unable to handle ring completion entry: {e:?}");
+                                    continue 'completion_loop;
+                                }
+                                ControlFlow::Continue => {}
                             }
                         }
-                    }
 
-                    debug!("shutting down ring...");
-                    unsafe {
-                        let cancel = $crate::io_uring::opcode::AsyncCancel2::new($crate::io_uring::types::CancelBuilder::any())
-                            .build()
-                            .user_data(0);
-                        sq.push(&cancel)?;
+                        $(match self.ops.$ring_op_name.housekeeping(SubmissionQueueSubmitter::new(
+                            &mut sq,
+                            &mut self.backlog,
+                            self.backlog_limit,
+                            |e, d| Self::sqe_wrapper(e, UserData::$ring_op_name(d), timeline, in_flight, submissions, replay, operations),
+                        )) {
+                            ControlFlow::Exit => return Ok(std::ops::ControlFlow::Break(Ok(()))),
+                            ControlFlow::Error(e) => {
+                                return Ok(std::ops::ControlFlow::Break(Err(RingError::Completion(e.into()))));
+                            }
+                            ControlFlow::Warn(e) => warn!("ring operation housekeeping: {e:?}"),
+                            ControlFlow::Continue => {}
+                        })+
+
+                        for (index, (name, operation)) in self.dynamic.iter_mut().enumerate() {
+                            let wrapper = |e: &mut $sqe, d| Self::sqe_wrapper(e, UserData::Dynamic(index, d), timeline, in_flight, submissions, replay, operations);
+                            let submitter: $crate::dyn_ring::DynSubmitter<$sqe> = SubmissionQueueSubmitter::new(&mut sq, &mut self.backlog, self.backlog_limit, &wrapper);
+                            match operation.housekeeping(submitter) {
+                                ControlFlow::Exit => return Ok(std::ops::ControlFlow::Break(Ok(()))),
+                                ControlFlow::Error(error) => {
+                                    return Ok(std::ops::ControlFlow::Break(Err(RingError::Dynamic { operation: name, error: format!("{error:?}") })));
+                                }
+                                ControlFlow::Warn(e) => warn!("ring operation housekeeping: {e:?}"),
+                                ControlFlow::Continue => {}
+                            }
+                        }
+
+                        self.stats.in_flight = self.operations.total();
+                        if let Some(observer) = &mut self.stats_observer {
+                            observer(&self.stats);
+                        }
+                        if let Some(snapshots) = self.snapshots.as_ref().filter(|snapshots| snapshots.requested()) {
+                            cq.sync();
+                            let (sq, cq) = (sq.len(), cq.len());
+                            snapshots.answer(|| Self::snapshot(&self.stats, operations, sq, cq, in_flight, &self.backlog, self.describe_data));
+                        }
+                        Ok(std::ops::ControlFlow::Continue(completions))
+                    }
+                }
 
-                        let cancel_timeout = $crate::io_uring::opcode::Nop::new()
-                            .build()
-                            .flags(Flags::IO_DRAIN)
-                            .user_data(UserData::Cancel(u64::MAX).into());
+                /// Cancels everything in flight and hands the final completions to the operations.
+                fn teardown<SetupError, CompletionError, TeardownError>(&mut self, mut result: Result<(), RingError<SetupError, CompletionError, TeardownError>>) -> Result<(), RingError<SetupError, CompletionError, TeardownError>>
+                where
+                    CompletionError: Debug,
+                    SetupError: Debug,
+                    TeardownError: Debug $(+ std::convert::From<<$ring_op as RingOperation<$sqe, $cqe>>::TeardownError>)+,
+                {
+                    let (submit, mut sq, mut cq) = self.ring.split();
+                    let timeline = self.timeline.as_ref();
+                    let in_flight = self.in_flight.as_ref();
+                    let journal = self.journal.as_ref();
+                    let replay = self.replay.as_ref();
+                    // the journal with the description of the data of its SQEs
+                    let submissions = journal.map(|journal| (journal, self.describe_data));
+                    let operations = &self.operations;
+                    let trace_level = self.trace_level.raw();
 
-                        sq.push(&cancel_timeout)?;
+                    debug!("shutting down ring...");
+                    $crate::__probe!("teardown", concat!(stringify!($ring_name), "\0").as_ptr(), in_flight.map_or(0, |in_flight| in_flight.len()));
+                    if let Some(journal) = journal {
+                        journal.teardown();
                     }
+                    // the `Nop`s of a synthetic replay cannot be cancelled, their cancellations are
+                    // handed to the operations with the first completions
+                    let mut cancelled = replay.map(|replay| replay.cancel()).unwrap_or_default();
+                    // backlogged requests never reach the kernel, they are cancelled the same way,
+                    // a synthetic replay already took the ones it replaced with `Nop`s
+                    let replaced: std::collections::HashSet<u64> = cancelled.iter().copied().collect();
+                    cancelled.extend($crate::backlog_user_data(&mut self.backlog).into_iter().filter(|user_data| !replaced.contains(user_data)));
+                    let mut cancelled: Vec<$cqe> = cancelled.into_iter().map($crate::cancelled_cqe).collect();
+                    self.stats.submitted += $crate::push_teardown(&submit, &mut sq)? as u64;
 
                     unsafe {
                         'cancel_loop: loop {
                             sq.sync();
-                            submit.submit_and_wait(1)?;
+                            let waiting = std::time::Instant::now();
+                            let submitted = submit.submit_and_wait(1)?;
+                            self.stats.blocked += waiting.elapsed();
+                            self.stats.syscalls += 1;
+                            self.stats.submitted += submitted as u64;
 
                             cq.sync();
-                            for cqe in cq.by_ref() {
-                                trace!("> CQE: {cqe:?}");
+                            self.stats.completed += (cancelled.len() + cq.len()) as u64;
+                            for cqe in cancelled.drain(..).chain(cq.by_ref()) {
+                                $crate::__ring_event!(trace_level, "> CQE: {cqe:?}");
                                 if cqe.user_data() == 0 {
-                                    trace!("dropped {cqe:?}");
+                                    $crate::__ring_event!(trace_level, "dropped {cqe:?}");
 
                                     // ignore
                                     continue;
                                 }
+                                if cqe.user_data() == $crate::TEARDOWN_DONE {
+                                    break 'cancel_loop;
+                                }
+                                if let Some(journal) = journal {
+                                    journal.completed(&cqe);
+                                }
+
+                                if let Some((operation, message)) = $crate::message::RingMessage::decode(&cqe) {
+                                    debug!("dropped message for operation {operation} on teardown: {message:?}");
+                                    continue;
+                                }
+
+                                if let Some(timeline) = timeline {
+                                    timeline.completed(cqe.user_data(), cqe.result(), cqe.flags());
+                                }
+                                let completed = cqe.user_data();
+                                let mut user_data = UserData::<$($args)*>::from_raw(completed);
+                                $crate::__ring_event!(trace_level, "> CQE userdata: {user_data:?}");
+                                let operation = user_data.operation();
+
+                                // a multishot request that is not finished yet still owns its user data,
+                                // it is completed like during normal operation
+                                if $crate::io_uring::cqueue::more(cqe.flags()) {
+                                    let mut kept = false;
+                                    match *user_data {
+                                        $(UserData::$ring_op_name(data) => {
+                                            let (flow, new_data) = self.ops.$ring_op_name.on_completion(
+                                                cqe,
+                                                data,
+                                                SubmissionQueueSubmitter::new(
+                                                    &mut sq,
+                                                    &mut self.backlog,
+                                                    self.backlog_limit, |e, d| Self::sqe_wrapper(e, UserData::$ring_op_name(d), timeline, in_flight, submissions, replay, operations),
+                                                ),
+                                            );
+                                            if let Some(new_data) = new_data {
+                                                *user_data = UserData::$ring_op_name(new_data);
+                                                let kept_user_data: u64 = user_data.into();
+                                                debug_assert_eq!(kept_user_data, completed, "kept data moved");
+                                                kept = true;
+                                            }
 
-                                let user_data = UserData::from_raw(cqe.user_data());
-                                trace!("> CQE userdata: {user_data:?}");
+                                            match flow {
+                                                ControlFlow::Warn(e) => warn!("unable to handle ring completion entry on teardown: {e:?}"),
+                                                ControlFlow::Error(e) => error!("unable to handle ring completion entry on teardown: {e:?}"),
+                                                ControlFlow::Continue | ControlFlow::Exit => {}
+                                            }
+                                        }),+
+                                        UserData::Dynamic(index, data) => {
+                                            let (_, operation) = &mut self.dynamic[index];
+                                            let wrapper = |e: &mut $sqe, d| Self::sqe_wrapper(e, UserData::Dynamic(index, d), timeline, in_flight, submissions, replay, operations);
+                                            let submitter: $crate::dyn_ring::DynSubmitter<$sqe> = SubmissionQueueSubmitter::new(&mut sq, &mut self.backlog, self.backlog_limit, &wrapper);
+                                            let (flow, new_data) = operation.on_completion(cqe, data, submitter);
+                                            if let Some(new_data) = new_data {
+                                                *user_data = UserData::Dynamic(index, new_data);
+                                                let kept_user_data: u64 = user_data.into();
+                                                debug_assert_eq!(kept_user_data, completed, "kept data moved");
+                                                kept = true;
+                                            }
+
+                                            match flow {
+                                                ControlFlow::Warn(e) => warn!("unable to handle ring completion entry on teardown: {e:?}"),
+                                                ControlFlow::Error(e) => error!("unable to handle ring completion entry on teardown: {e:?}"),
+                                                ControlFlow::Continue | ControlFlow::Exit => {}
+                                            }
+                                        }
+                                        UserData::Cancel(_) => unreachable!(),
+                                    }
+                                    if let (Some(in_flight), false) = (in_flight, kept) {
+                                        in_flight.remove(completed);
+                                    }
+                                    if let (Some(operation), false) = (operation, kept) {
+                                        operations.dropped(operation as usize);
+                                    }
+                                    continue;
+                                }
+
+                                if let Some(in_flight) = in_flight {
+                                    in_flight.remove(completed);
+                                }
+                                if let Some(operation) = operation {
+                                    operations.dropped(operation as usize);
+                                }
                                 let teardown_result = match *user_data {
-                                    $(UserData::$ring_op_name(data) => self.$ring_op_name.on_teardown_completion(cqe, data, SubmissionQueueSubmitter::new(
+                                    $(UserData::$ring_op_name(data) => self.ops.$ring_op_name.on_teardown_completion(cqe, data, SubmissionQueueSubmitter::new(
                                         &mut sq,
                                         &mut self.backlog,
                                         self.backlog_limit,
-                                        |e, d| Self::sqe_wrapper(e, UserData::$ring_op_name(d)),
+                                        |e, d| Self::sqe_wrapper(e, UserData::$ring_op_name(d), timeline, in_flight, submissions, replay, operations),
                                     ))),+,
-                                    UserData::Cancel(u64::MAX) => break 'cancel_loop,
+                                    UserData::Dynamic(index, data) => {
+                                        let (name, operation) = &mut self.dynamic[index];
+                                        let wrapper = |e: &mut $sqe, d| Self::sqe_wrapper(e, UserData::Dynamic(index, d), timeline, in_flight, submissions, replay, operations);
+                                        let submitter: $crate::dyn_ring::DynSubmitter<$sqe> = SubmissionQueueSubmitter::new(&mut sq, &mut self.backlog, self.backlog_limit, &wrapper);
+                                        if let Err(error) = operation.on_teardown_completion(cqe, data, submitter) {
+                                            error!("unable to handle ring completion entry on teardown: {error:?}");
+                                            result = Err(RingError::Dynamic { operation: name, error: format!("{error:?}") });
+                                        }
+                                        Ok(())
+                                    }
                                     UserData::Cancel(_) => unreachable!(),
                                 };
 
@@ -425,16 +2117,352 @@ macro_rules! ring {
                         }
                     }
 
+                    // requests pushed on teardown that did not fit into the queue are dropped with their data
+                    let unsubmitted = $crate::backlog_user_data(&mut self.backlog);
+                    if !unsubmitted.is_empty() {
+                        warn!("{} requests pushed on teardown were never submitted", unsubmitted.len());
+                    }
+                    for user_data in unsubmitted {
+                        let user_data = unsafe { UserData::<$($args)*>::from_raw(user_data) };
+                        if let Some(operation) = user_data.operation() {
+                            operations.dropped(operation as usize);
+                        }
+                    }
+                    if let Some(in_flight) = &self.in_flight {
+                        in_flight.clear();
+                    }
+                    self.stats.in_flight = self.operations.total();
+                    if let Some(observer) = &mut self.stats_observer {
+                        observer(&self.stats);
+                    }
+                    $crate::__probe!("teardown_done", concat!(stringify!($ring_name), "\0").as_ptr(), result.is_err());
                     debug!("ring finished: {result:?}");
                     result
                 }
             }
 
-            impl AsRawFd for Ring {
+            impl<$($generics)*> AsRawFd for Ring<$($args)*> where $($bounds)* {
                 fn as_raw_fd(&self) -> RawFd {
                     self.ring.as_raw_fd()
                 }
             }
+
+            $crate::__mio_source!(Ring [$($generics)*] [$($args)*] [$($bounds)*]);
+            $crate::__conformance!(
+                [$($generics)*] [$($args)*] [$($bounds)*] [$($error_params)*] [$setup_error, $completion_error, $teardown_error] [$sqe, $cqe],
+                $($ring_op_name: $ring_op),+
+            );
+            $crate::__serde_user_data!([$($generics)*] [$($args)*] [$($bounds)*] [$sqe, $cqe], $($ring_op_name: $ring_op),+);
+        }
+    };
+    ($ring_name:ident $($rest:tt)*) => {
+        $crate::ring!(@header [pub] {} $ring_name $($rest)*);
+    };
+}
+
+#[cfg(test)]
+// the ring is generated inside the crate, its unused parts are not exempt from lints
+#[allow(dead_code, unused_imports)]
+mod tests {
+    use io_uring::cqueue;
+    use io_uring::squeue::Entry;
+
+    use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+    /// Exits the ring with the first data it completes.
+    #[derive(Debug, Default)]
+    pub struct ExitOp(Option<u32>);
+
+    impl RingOperation for ExitOp {
+        type RingData = u32;
+        type SetupError = crate::ops::Error;
+        type TeardownError = crate::ops::Error;
+        type ControlFlowWarn = crate::ops::Error;
+        type ControlFlowError = crate::ops::Error;
+
+        fn setup<W: Fn(&mut Entry, u32)>(
+            &mut self,
+            _submitter: SubmissionQueueSubmitter<u32, W>,
+        ) -> Result<(), Self::SetupError> {
+            Ok(())
+        }
+
+        fn on_completion<W: Fn(&mut Entry, u32)>(
+            &mut self,
+            _completion_entry: cqueue::Entry,
+            ring_data: u32,
+            _submitter: SubmissionQueueSubmitter<u32, W>,
+        ) -> (
+            ControlFlow<Self::ControlFlowWarn, Self::ControlFlowError>,
+            Option<u32>,
+        ) {
+            self.0 = Some(ring_data);
+            (ControlFlow::Exit, None)
+        }
+
+        fn on_teardown_completion<W: Fn(&mut Entry, u32)>(
+            &mut self,
+            _completion_entry: cqueue::Entry,
+            _ring_data: u32,
+            _submitter: SubmissionQueueSubmitter<u32, W>,
+        ) -> Result<(), Self::TeardownError> {
+            Ok(())
+        }
+    }
+
+    /// Pushes `Nop`s for the data it gets sent and exits, records the results of teardown.
+    #[derive(Debug, Default)]
+    pub struct BacklogOp(Vec<(u32, i32)>);
+
+    impl RingOperation for BacklogOp {
+        type RingData = u32;
+        type SetupError = crate::ops::Error;
+        type TeardownError = crate::ops::Error;
+        type ControlFlowWarn = crate::ops::Error;
+        type ControlFlowError = crate::ops::Error;
+
+        fn setup<W: Fn(&mut Entry, u32)>(
+            &mut self,
+            _submitter: SubmissionQueueSubmitter<u32, W>,
+        ) -> Result<(), Self::SetupError> {
+            Ok(())
+        }
+
+        fn on_completion<W: Fn(&mut Entry, u32)>(
+            &mut self,
+            _completion_entry: cqueue::Entry,
+            ring_data: u32,
+            mut submitter: SubmissionQueueSubmitter<u32, W>,
+        ) -> (
+            ControlFlow<Self::ControlFlowWarn, Self::ControlFlowError>,
+            Option<u32>,
+        ) {
+            for data in 0..ring_data {
+                submitter
+                    .push(io_uring::opcode::Nop::new().build(), data)
+                    .unwrap();
+            }
+            (ControlFlow::Exit, None)
+        }
+
+        fn on_teardown_completion<W: Fn(&mut Entry, u32)>(
+            &mut self,
+            completion_entry: cqueue::Entry,
+            ring_data: u32,
+            _submitter: SubmissionQueueSubmitter<u32, W>,
+        ) -> Result<(), Self::TeardownError> {
+            self.0.push((ring_data, completion_entry.result()));
+            Ok(())
+        }
+    }
+
+    /// Reads from a pipe and writes to it right after, exits once the read completed.
+    #[derive(Debug)]
+    pub struct PipeOp {
+        fds: [std::os::fd::RawFd; 2],
+        buf: Box<[u8; 1]>,
+    }
+
+    impl Drop for PipeOp {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fds[0]);
+                libc::close(self.fds[1]);
+            }
+        }
+    }
+
+    impl RingOperation for PipeOp {
+        type RingData = u32;
+        type SetupError = crate::ops::Error;
+        type TeardownError = crate::ops::Error;
+        type ControlFlowWarn = crate::ops::Error;
+        type ControlFlowError = crate::ops::Error;
+
+        fn setup<W: Fn(&mut Entry, u32)>(
+            &mut self,
+            mut submitter: SubmissionQueueSubmitter<u32, W>,
+        ) -> Result<(), Self::SetupError> {
+            let [read_fd, write_fd] = self.fds;
+            let read =
+                io_uring::opcode::Read::new(io_uring::types::Fd(read_fd), self.buf.as_mut_ptr(), 1);
+            let write =
+                io_uring::opcode::Write::new(io_uring::types::Fd(write_fd), b"x".as_ptr(), 1);
+            submitter.push(read.build(), 0).unwrap();
+            submitter.push(write.build(), 1).unwrap();
+            Ok(())
+        }
+
+        fn on_completion<W: Fn(&mut Entry, u32)>(
+            &mut self,
+            _completion_entry: cqueue::Entry,
+            ring_data: u32,
+            _submitter: SubmissionQueueSubmitter<u32, W>,
+        ) -> (
+            ControlFlow<Self::ControlFlowWarn, Self::ControlFlowError>,
+            Option<u32>,
+        ) {
+            match ring_data {
+                0 => (ControlFlow::Exit, None),
+                _ => (ControlFlow::Continue, None),
+            }
         }
+
+        fn on_teardown_completion<W: Fn(&mut Entry, u32)>(
+            &mut self,
+            _completion_entry: cqueue::Entry,
+            _ring_data: u32,
+            _submitter: SubmissionQueueSubmitter<u32, W>,
+        ) -> Result<(), Self::TeardownError> {
+            Ok(())
+        }
+    }
+
+    crate::ring! { handle_ring -> crate::ops::Error, exit: super::ExitOp }
+    crate::ring! { pipe_ring -> crate::ops::Error, pipe: super::PipeOp }
+    crate::ring! { backlog_ring -> crate::ops::Error, backlog: super::BacklogOp }
+    crate::ring! {
+        async_ring -> crate::ops::Error,
+        decorate = |entry| entry.flags(io_uring::squeue::Flags::ASYNC),
+        backlog: super::BacklogOp
+    }
+
+    #[test]
+    fn handle_counts_sent_data_in_flight() {
+        let mut ring = handle_ring::Ring::builder()
+            .exit(ExitOp::default())
+            .build()
+            .unwrap();
+        ring.count_in_flight();
+        let handle = ring.handle().unwrap();
+
+        handle.send_exit(7).unwrap();
+        ring.run().unwrap();
+
+        assert_eq!(ring.operations().exit.0, Some(7));
+        let [(_, gauge)] = ring.in_flight_by_operation()[..] else {
+            unreachable!()
+        };
+        assert_eq!((gauge.current, gauge.high_water), (0, 1));
+    }
+
+    #[test]
+    fn in_flight_is_not_counted_by_default() {
+        let mut ring = handle_ring::Ring::builder()
+            .exit(ExitOp::default())
+            .build()
+            .unwrap();
+
+        ring.handle().unwrap().send_exit(7).unwrap();
+        ring.run().unwrap();
+
+        assert_eq!(ring.operations().exit.0, Some(7));
+        assert_eq!(ring.stats().in_flight, Default::default());
+    }
+
+    #[test]
+    fn handle_fails_once_the_ring_is_dropped() {
+        let ring = handle_ring::Ring::builder()
+            .exit(ExitOp::default())
+            .build()
+            .unwrap();
+        let handle = ring.handle().unwrap();
+        drop(ring);
+
+        assert!(handle.send_exit(7).is_err());
+    }
+
+    #[test]
+    fn backlog_is_submitted_before_the_ring_blocks() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+        // the write completing the read waits in the backlog behind it
+        let mut ring = pipe_ring::Ring::builder()
+            .ring_size(std::num::NonZeroU32::new(1).unwrap())
+            .pipe(PipeOp {
+                fds,
+                buf: Box::new([0]),
+            })
+            .build()
+            .unwrap();
+        ring.run().unwrap();
+
+        assert_eq!(*ring.operations().pipe.buf, *b"x");
+    }
+
+    #[test]
+    fn teardown_cancels_the_backlog_without_submitting_it() {
+        let mut ring = backlog_ring::Ring::builder()
+            .ring_size(std::num::NonZeroU32::new(1).unwrap())
+            .backlog(BacklogOp::default())
+            .build()
+            .unwrap();
+        ring.count_in_flight();
+        ring.handle().unwrap().send_backlog(4).unwrap();
+        ring.run().unwrap();
+
+        // the first `Nop` fits into the submission queue, the others wait in the backlog
+        let mut results = std::mem::take(&mut ring.operations_mut().backlog.0);
+        results.sort();
+        assert_eq!(
+            results,
+            [
+                (0, 0),
+                (1, -libc::ECANCELED),
+                (2, -libc::ECANCELED),
+                (3, -libc::ECANCELED)
+            ]
+        );
+        let [(_, gauge)] = ring.in_flight_by_operation()[..] else {
+            unreachable!()
+        };
+        assert_eq!(gauge.current, 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "of a message")]
+    fn raw_entries_with_the_user_data_of_a_message_are_rejected() {
+        let mut ring = io_uring::IoUring::new(2).unwrap();
+        let mut sq = ring.submission();
+        let mut backlog = std::collections::VecDeque::new();
+        let mut submitter =
+            SubmissionQueueSubmitter::new(&mut sq, &mut backlog, None, |_: &mut Entry, _: u32| {});
+        let nop = io_uring::opcode::Nop::new().build().user_data(1);
+        let _ = unsafe { submitter.push_raw(nop) };
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "teardown waits for")]
+    fn raw_entries_with_the_user_data_of_teardown_are_rejected() {
+        let mut ring = io_uring::IoUring::new(2).unwrap();
+        let mut sq = ring.submission();
+        let mut backlog = std::collections::VecDeque::new();
+        let mut submitter =
+            SubmissionQueueSubmitter::new(&mut sq, &mut backlog, None, |_: &mut Entry, _: u32| {});
+        let nop = io_uring::opcode::Nop::new()
+            .build()
+            .user_data(crate::TEARDOWN_DONE);
+        let _ = unsafe { submitter.push_raw(nop) };
+    }
+
+    #[test]
+    fn io_wq_stats_of_a_live_ring() {
+        let mut ring = async_ring::Ring::builder()
+            .backlog(BacklogOp::default())
+            .build()
+            .unwrap();
+
+        let stats = ring.limit_io_wq_workers(2, 3).unwrap();
+        assert_eq!((stats.bounded_max, stats.unbounded_max), (2, 3));
+
+        // `IOSQE_ASYNC` punts the `Nop`s to io-wq, its idle workers linger for a while
+        ring.handle().unwrap().send_backlog(2).unwrap();
+        ring.run().unwrap();
+
+        let stats = ring.io_wq_stats().unwrap();
+        assert_eq!((stats.bounded_max, stats.unbounded_max), (2, 3));
+        assert!(stats.combined_workers >= 1, "{stats}");
     }
 }
@@ -0,0 +1,26 @@
+//! Attribute macros, an alternative to [`ring!`](crate::ring) reporting errors at the
+//! operation they concern instead of inside the expansion of the macro.
+//!
+//! ```no_run
+//! use std::num::NonZeroU32;
+//! use std::time::Duration;
+//! use rummelplatz::ops::TickOp;
+//! use rummelplatz::ControlFlow;
+//!
+//! /// Ticks until it is stopped.
+//! #[rummelplatz::macros::ring(error = rummelplatz::ops::Error)]
+//! pub struct TickRing {
+//!     tick: TickOp,
+//! }
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let tick = TickOp::new(Duration::from_secs(1), |_| ControlFlow::Continue);
+//! let mut ring = TickRing::new(tick_ring::Ring::new_raw_ring(NonZeroU32::new(64).unwrap())?, None, tick);
+//! ring.run()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The name of `ring!` is taken by the declarative macro, the attribute lives in this module.
+
+pub use rummelplatz_macros::ring;
@@ -0,0 +1,285 @@
+//! Inter-ring messaging on top of `IORING_OP_MSG_RING`.
+//!
+//! A message is posted straight into the completion queue of another ring. Its `user_data` is
+//! tagged with the lowest bit, which can never be set for the boxed `UserData` of a regular
+//! request, and carries the index of the receiving operation and a 56 bit payload.
+//! The receiving ring hands it to [`RingOperation::on_message`](crate::RingOperation::on_message)
+//! of that operation.
+
+use std::cell::RefCell;
+use std::io;
+use std::os::fd::RawFd;
+
+use io_uring::cqueue::{Entry, EntryMarker};
+use io_uring::IoUring;
+
+thread_local! {
+    static SENDER_RING: RefCell<Option<IoUring>> = const { RefCell::new(None) };
+}
+
+const TAG: u64 = 0b01;
+const FD_FLAG: u64 = 0b10;
+const OPERATION_SHIFT: u32 = 2;
+const OPERATION_MASK: u64 = 0x3f;
+const PAYLOAD_SHIFT: u32 = 8;
+
+/// Maximum number of operations a ring can have to be addressable by messages.
+pub const MAX_OPERATIONS: usize = OPERATION_MASK as usize;
+
+/// Index of messages that only wake the ring, e.g. to answer a
+/// [`SnapshotHandle`](crate::snapshot::SnapshotHandle).
+#[doc(hidden)]
+pub const WAKE_OPERATION: u8 = OPERATION_MASK as u8;
+
+/// A message another ring posted into this ring's completion queue.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RingMessage {
+    /// Sent with [`OutgoingMessage::data`].
+    Data { payload: u64, value: i32 },
+    /// Sent with [`OutgoingMessage::fd`], the fd was installed into the fixed file `slot` of
+    /// the receiving ring.
+    Fd { payload: u64, slot: u32 },
+}
+
+impl RingMessage {
+    /// Largest payload that fits into a message.
+    pub const MAX_PAYLOAD: u64 = u64::MAX >> PAYLOAD_SHIFT;
+
+    #[inline]
+    pub fn payload(&self) -> u64 {
+        match *self {
+            RingMessage::Data { payload, .. } | RingMessage::Fd { payload, .. } => payload,
+        }
+    }
+
+    /// Decodes a completion entry, returns the index of the receiving operation and the
+    /// message or `None` if the entry does not belong to a message.
+    #[doc(hidden)]
+    #[inline]
+    pub fn decode<E: EntryMarker>(completion_entry: &E) -> Option<(u8, RingMessage)> {
+        let completion_entry: Entry = completion_entry.clone().into();
+        let user_data = completion_entry.user_data();
+        if user_data & TAG == 0 {
+            return None;
+        }
+
+        let operation = ((user_data >> OPERATION_SHIFT) & OPERATION_MASK) as u8;
+        let payload = user_data >> PAYLOAD_SHIFT;
+        let message = if user_data & FD_FLAG == 0 {
+            RingMessage::Data {
+                payload,
+                value: completion_entry.result(),
+            }
+        } else {
+            RingMessage::Fd {
+                payload,
+                slot: completion_entry.result() as u32,
+            }
+        };
+
+        Some((operation, message))
+    }
+
+    /// Whether `user_data` is the one of a message.
+    #[inline]
+    pub(crate) fn is_message(user_data: u64) -> bool {
+        user_data & TAG != 0
+    }
+
+    #[inline]
+    fn encode(operation: u8, fd: bool, payload: u64) -> u64 {
+        debug_assert!(payload <= Self::MAX_PAYLOAD);
+        debug_assert!((operation as u64) <= OPERATION_MASK);
+
+        let fd = if fd { FD_FLAG } else { 0 };
+        (payload << PAYLOAD_SHIFT) | ((operation as u64) << OPERATION_SHIFT) | fd | TAG
+    }
+}
+
+/// Address of an operation on another ring, created with `Ring::message_target`.
+///
+/// It only holds the raw fd of the ring, the receiving ring has to outlive every message sent
+/// to it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct MessageTarget {
+    ring_fd: RawFd,
+    operation: u8,
+}
+
+impl MessageTarget {
+    /// Prefer `Ring::message_target`, `operation` is the index of the receiving operation in
+    /// its `ring!` declaration.
+    pub fn new(ring_fd: RawFd, operation: u8) -> Self {
+        assert!(
+            (operation as usize) < MAX_OPERATIONS,
+            "operation index {operation} is not addressable by messages"
+        );
+        Self { ring_fd, operation }
+    }
+
+    /// Messages to this target only wake the ring, no operation receives them.
+    #[doc(hidden)]
+    pub fn wake(ring_fd: RawFd) -> Self {
+        Self {
+            ring_fd,
+            operation: WAKE_OPERATION,
+        }
+    }
+
+    #[inline]
+    pub fn ring_fd(&self) -> RawFd {
+        self.ring_fd
+    }
+
+    #[inline]
+    pub fn operation(&self) -> u8 {
+        self.operation
+    }
+}
+
+/// A message on its way to another ring, see [`crate::ops::MsgRingOp`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct OutgoingMessage {
+    target: MessageTarget,
+    kind: OutgoingKind,
+    payload: u64,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum OutgoingKind {
+    Data {
+        value: i32,
+    },
+    Fd {
+        fixed_slot: u32,
+        dest_slot: Option<u32>,
+    },
+}
+
+impl OutgoingMessage {
+    /// Arrives as [`RingMessage::Data`] with the same `payload` and `value`.
+    ///
+    /// # Panics
+    /// If `payload` exceeds [`RingMessage::MAX_PAYLOAD`].
+    pub fn data(target: MessageTarget, payload: u64, value: i32) -> Self {
+        assert_payload(payload);
+        Self {
+            target,
+            kind: OutgoingKind::Data { value },
+            payload,
+        }
+    }
+
+    /// Passes the fixed file `fixed_slot` of the sending ring into the fixed file table of the
+    /// receiving ring, either into `dest_slot` or a slot allocated by the kernel.
+    /// Arrives as [`RingMessage::Fd`].
+    ///
+    /// # Panics
+    /// If `payload` exceeds [`RingMessage::MAX_PAYLOAD`].
+    pub fn fd(
+        target: MessageTarget,
+        payload: u64,
+        fixed_slot: u32,
+        dest_slot: Option<u32>,
+    ) -> Self {
+        assert_payload(payload);
+        Self {
+            target,
+            kind: OutgoingKind::Fd {
+                fixed_slot,
+                dest_slot,
+            },
+            payload,
+        }
+    }
+
+    #[inline]
+    pub fn target(&self) -> MessageTarget {
+        self.target
+    }
+
+    #[inline]
+    pub fn payload(&self) -> u64 {
+        self.payload
+    }
+
+    /// Sends the message from a thread without a ring of its own and waits until the kernel
+    /// posted it. A small ring private to the calling thread is created on first use.
+    pub fn send_blocking(&self) -> io::Result<()> {
+        submit_blocking(&self.build())
+    }
+
+    pub(crate) fn build(&self) -> io_uring::squeue::Entry {
+        use io_uring::opcode::{MsgRingData, MsgRingSendFd};
+        use io_uring::types::{DestinationSlot, Fd, Fixed};
+
+        let ring_fd = Fd(self.target.ring_fd);
+        match self.kind {
+            OutgoingKind::Data { value } => MsgRingData::new(
+                ring_fd,
+                value,
+                RingMessage::encode(self.target.operation, false, self.payload),
+                None,
+            )
+            .build(),
+            OutgoingKind::Fd {
+                fixed_slot,
+                dest_slot,
+            } => {
+                let dest_slot = match dest_slot {
+                    None => DestinationSlot::auto_target(),
+                    Some(slot) => DestinationSlot::try_from_slot_target(slot)
+                        .expect("destination slot out of range"),
+                };
+                MsgRingSendFd::new(
+                    ring_fd,
+                    Fixed(fixed_slot),
+                    dest_slot,
+                    RingMessage::encode(self.target.operation, true, self.payload),
+                )
+                .build()
+            }
+        }
+    }
+}
+
+/// Posts a completion with `user_data` and `result` into the ring behind `ring_fd`, used by the
+/// `Handle` of generated rings to hand them the boxed data of a request.
+#[doc(hidden)]
+pub fn post_completion(ring_fd: RawFd, user_data: u64, result: i32) -> io::Result<()> {
+    use io_uring::opcode::MsgRingData;
+    use io_uring::types::Fd;
+
+    submit_blocking(&MsgRingData::new(Fd(ring_fd), result, user_data, None).build())
+}
+
+/// Submits `entry` on a small ring private to the calling thread and waits for its completion.
+fn submit_blocking(entry: &io_uring::squeue::Entry) -> io::Result<()> {
+    SENDER_RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+        let ring = match ring.as_mut() {
+            Some(ring) => ring,
+            None => ring.insert(IoUring::new(2)?),
+        };
+
+        unsafe { ring.submission().push(&entry.clone().user_data(0)) }
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        ring.submit_and_wait(1)?;
+
+        let cqe = ring.completion().next();
+        match cqe {
+            Some(cqe) if cqe.result() < 0 => Err(io::Error::from_raw_os_error(-cqe.result())),
+            Some(_) => Ok(()),
+            None => Err(io::Error::other("message completion missing")),
+        }
+    })
+}
+
+#[inline]
+fn assert_payload(payload: u64) {
+    assert!(
+        payload <= RingMessage::MAX_PAYLOAD,
+        "message payload exceeds {} bits",
+        64 - PAYLOAD_SHIFT
+    );
+}
@@ -0,0 +1,106 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::AcceptMulti;
+use io_uring::types::Fd;
+use tracing::trace;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// Accepts connections on the listening socket `fd` with a multishot `Accept` and hands them
+/// to `on_accept`. Accepted sockets are created with `SOCK_CLOEXEC`.
+///
+/// The accept is re-armed whenever the kernel terminates the multishot request, failed
+/// accepts (e.g. `EMFILE`) are reported as warnings.
+pub struct AcceptOp {
+    fd: RawFd,
+    on_accept: Box<dyn FnMut(OwnedFd) -> OpControlFlow>,
+}
+
+impl Debug for AcceptOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AcceptOp")
+            .field("fd", &self.fd)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AcceptOp {
+    pub fn new(fd: RawFd, on_accept: impl FnMut(OwnedFd) -> OpControlFlow + 'static) -> Self {
+        Self {
+            fd,
+            on_accept: Box::new(on_accept),
+        }
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    fn accept(&self) -> io_uring::squeue::Entry {
+        AcceptMulti::new(Fd(self.fd))
+            .flags(libc::SOCK_CLOEXEC)
+            .build()
+    }
+}
+
+impl RingOperation for AcceptOp {
+    type RingData = ();
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        submitter.push(self.accept(), ())?;
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let more = io_uring::cqueue::more(completion_entry.flags());
+        let flow = match completion_entry.result() {
+            res if res < 0 => match -res {
+                libc::ECANCELED => ControlFlow::Continue,
+                errno => ControlFlow::Warn(io::Error::from_raw_os_error(errno).into()),
+            },
+            fd => (self.on_accept)(unsafe { OwnedFd::from_raw_fd(fd) }),
+        };
+
+        if more {
+            return (flow, Some(ring_data));
+        }
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return (flow, None);
+        }
+
+        trace!("re-arm multishot accept");
+        if let Err(e) = submitter.push(self.accept(), ()) {
+            return (ControlFlow::Error(e.into()), None);
+        }
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        if completion_entry.result() >= 0 {
+            // close connections accepted during the cancellation
+            drop(unsafe { OwnedFd::from_raw_fd(completion_entry.result()) });
+        }
+        Ok(())
+    }
+}
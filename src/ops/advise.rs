@@ -0,0 +1,163 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{Fadvise, Madvise};
+use io_uring::types::Fd;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Advice {
+    /// `posix_fadvise(fd, offset, len, advice)`
+    File {
+        fd: RawFd,
+        offset: u64,
+        len: i64,
+        advice: i32,
+    },
+    /// `madvise(addr, len, advice)`
+    Memory { addr: usize, len: i64, advice: i32 },
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to queue hints on an
+/// [`AdviseOp`].
+#[derive(Debug, Clone, Default)]
+pub struct AdviseHandle {
+    queue: Rc<RefCell<Vec<Advice>>>,
+}
+
+impl AdviseHandle {
+    /// e.g. `libc::POSIX_FADV_DONTNEED` after streaming a large file
+    pub fn fadvise(&self, fd: RawFd, offset: u64, len: i64, advice: i32) {
+        self.queue.borrow_mut().push(Advice::File {
+            fd,
+            offset,
+            len,
+            advice,
+        });
+    }
+
+    /// # Safety
+    /// `addr..addr + len` must be a mapping the advice is valid for, until it completed.
+    pub unsafe fn madvise(&self, addr: *const u8, len: i64, advice: i32) {
+        self.queue.borrow_mut().push(Advice::Memory {
+            addr: addr as usize,
+            len,
+            advice,
+        });
+    }
+}
+
+/// Issues `posix_fadvise`/`madvise` hints through the ring instead of blocking syscalls.
+///
+/// Hints queued during a loop iteration are submitted from [`RingOperation::housekeeping`].
+/// Hints are best effort, failures are reported as warnings.
+pub struct AdviseOp {
+    handle: AdviseHandle,
+}
+
+impl Debug for AdviseOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdviseOp")
+            .field("queued", &self.handle.queue.borrow().len())
+            .finish()
+    }
+}
+
+impl Default for AdviseOp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AdviseOp {
+    pub fn new() -> Self {
+        Self {
+            handle: Default::default(),
+        }
+    }
+
+    pub fn handle(&self) -> AdviseHandle {
+        self.handle.clone()
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, Advice)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<Advice, W>,
+    ) -> Result<(), Error> {
+        let advices = std::mem::take(&mut *self.handle.queue.borrow_mut());
+        if advices.is_empty() {
+            return Ok(());
+        }
+
+        let entries = advices
+            .iter()
+            .map(|advice| match *advice {
+                Advice::File {
+                    fd,
+                    offset,
+                    len,
+                    advice,
+                } => Fadvise::new(Fd(fd), len, advice).offset(offset).build(),
+                Advice::Memory { addr, len, advice } => {
+                    Madvise::new(addr as *const libc::c_void, len, advice).build()
+                }
+            })
+            .collect();
+
+        submitter.push_slice(entries, advices.into_boxed_slice())?;
+        Ok(())
+    }
+}
+
+impl RingOperation for AdviseOp {
+    type RingData = Advice;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let flow = match completion_entry.result() {
+            res if res < 0 => ControlFlow::Warn(io::Error::from_raw_os_error(-res).into()),
+            _ => ControlFlow::Continue,
+        };
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::time::{Duration, Instant};
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Nop;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct NopBenchReport {
+    pub completions: u64,
+    pub in_flight: u32,
+    pub elapsed: Duration,
+}
+
+impl NopBenchReport {
+    pub fn completions_per_sec(&self) -> f64 {
+        self.completions as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Wall time per completion, i.e. the whole round trip through the kernel, the user data
+    /// boxing and the dispatch of the ring loop.
+    pub fn per_completion(&self) -> Duration {
+        self.elapsed / self.completions.max(1) as u32
+    }
+}
+
+/// Floods the ring with `Nop`s and measures the round trip overhead of the framework.
+///
+/// Keeps `in_flight` `Nop`s submitted until `total` of them completed, then hands a
+/// [`NopBenchReport`] to `on_done`. Run it alone in a ring to get a baseline.
+pub struct NopBenchOp {
+    total: u64,
+    in_flight: u32,
+    submitted: u64,
+    completed: u64,
+    start: Option<Instant>,
+    on_done: Box<dyn FnMut(NopBenchReport) -> OpControlFlow>,
+}
+
+impl Debug for NopBenchOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NopBenchOp")
+            .field("total", &self.total)
+            .field("in_flight", &self.in_flight)
+            .field("submitted", &self.submitted)
+            .field("completed", &self.completed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl NopBenchOp {
+    pub fn new(
+        total: u64,
+        in_flight: u32,
+        on_done: impl FnMut(NopBenchReport) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            total,
+            in_flight: in_flight.max(1),
+            submitted: 0,
+            completed: 0,
+            start: None,
+            on_done: Box::new(on_done),
+        }
+    }
+
+    /// The numbers so far, `None` before the ring started.
+    pub fn report(&self) -> Option<NopBenchReport> {
+        self.start.map(|start| NopBenchReport {
+            completions: self.completed,
+            in_flight: self.in_flight,
+            elapsed: start.elapsed(),
+        })
+    }
+
+    fn submit<W: Fn(&mut io_uring::squeue::Entry, ())>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<(), W>,
+    ) -> Result<(), Error> {
+        if self.submitted < self.total {
+            submitter.push(Nop::new().build(), ())?;
+            self.submitted += 1;
+        }
+        Ok(())
+    }
+}
+
+impl RingOperation for NopBenchOp {
+    type RingData = ();
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.start = Some(Instant::now());
+        for _ in 0..self.in_flight {
+            self.submit(&mut submitter)?;
+        }
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        _ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        if completion_entry.result() < 0 {
+            let e = io::Error::from_raw_os_error(-completion_entry.result());
+            return (ControlFlow::Error(e.into()), None);
+        }
+
+        self.completed += 1;
+        if self.completed == self.total {
+            let report = self.report().expect("completion before setup");
+            return ((self.on_done)(report), None);
+        }
+
+        match self.submit(&mut submitter) {
+            Ok(()) => (ControlFlow::Continue, None),
+            Err(e) => (ControlFlow::Error(e), None),
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+use std::cell::{Cell, RefCell};
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::rc::Rc;
+
+struct PoolInner {
+    memory: *mut u8,
+    buf_size: usize,
+    count: u16,
+    free: RefCell<Vec<u16>>,
+    registered: Cell<bool>,
+}
+
+impl Drop for PoolInner {
+    fn drop(&mut self) {
+        let len = self.buf_size * self.count as usize;
+        drop(unsafe { Vec::from_raw_parts(self.memory, len, len) });
+    }
+}
+
+/// Equally sized buffers registered with a ring for `ReadFixed`/`WriteFixed`.
+///
+/// Cloning is cheap, all clones share the same buffers. Buffers are handed out as [`FixedBuf`]s
+/// and go back to the pool once those are dropped.
+#[derive(Clone)]
+pub struct RegisteredBufferPool {
+    inner: Rc<PoolInner>,
+}
+
+impl Debug for RegisteredBufferPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RegisteredBufferPool")
+            .field("buf_size", &self.inner.buf_size)
+            .field("count", &self.inner.count)
+            .field("available", &self.available())
+            .field("registered", &self.inner.registered.get())
+            .finish()
+    }
+}
+
+impl RegisteredBufferPool {
+    pub fn new(count: u16, buf_size: usize) -> Self {
+        let mut memory = std::mem::ManuallyDrop::new(vec![0u8; buf_size * count as usize]);
+        Self {
+            inner: Rc::new(PoolInner {
+                memory: memory.as_mut_ptr(),
+                buf_size,
+                count,
+                free: RefCell::new((0..count).rev().collect()),
+                registered: Cell::new(false),
+            }),
+        }
+    }
+
+    /// Registers the buffers with `submitter`'s ring, at buffer index 0.
+    ///
+    /// # Safety
+    /// A clone of the pool has to outlive the ring, e.g. by handing it to one of the ring's
+    /// operations. No other buffers may be registered with the ring.
+    pub unsafe fn register(&self, submitter: &io_uring::Submitter) -> io::Result<()> {
+        let iovecs: Vec<_> = (0..self.inner.count)
+            .map(|i| libc::iovec {
+                iov_base: self.buffer_ptr(i) as *mut libc::c_void,
+                iov_len: self.inner.buf_size,
+            })
+            .collect();
+
+        submitter.register_buffers(&iovecs)?;
+        self.inner.registered.set(true);
+        Ok(())
+    }
+
+    pub fn is_registered(&self) -> bool {
+        self.inner.registered.get()
+    }
+
+    pub fn buf_size(&self) -> usize {
+        self.inner.buf_size
+    }
+
+    pub fn available(&self) -> usize {
+        self.inner.free.borrow().len()
+    }
+
+    /// Takes a buffer out of the pool, `None` if all buffers are in use.
+    pub fn acquire(&self) -> Option<FixedBuf> {
+        let slot = self.inner.free.borrow_mut().pop()?;
+        Some(FixedBuf {
+            pool: self.clone(),
+            slot,
+            len: 0,
+        })
+    }
+
+    pub(super) fn owns(&self, buf: &FixedBuf) -> bool {
+        Rc::ptr_eq(&self.inner, &buf.pool.inner)
+    }
+
+    fn buffer_ptr(&self, slot: u16) -> *mut u8 {
+        assert!(slot < self.inner.count);
+        unsafe { self.inner.memory.add(slot as usize * self.inner.buf_size) }
+    }
+}
+
+/// A buffer of a [`RegisteredBufferPool`], returned to the pool on drop.
+///
+/// `len` bytes of its capacity are initialized data, e.g. what a `ReadFixed` read into it.
+pub struct FixedBuf {
+    pool: RegisteredBufferPool,
+    slot: u16,
+    len: usize,
+}
+
+impl Debug for FixedBuf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FixedBuf")
+            .field("index", &self.index())
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl FixedBuf {
+    /// Index of the buffer in the ring's registered buffer table.
+    pub fn index(&self) -> u16 {
+        self.slot
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.pool.inner.buf_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.capacity());
+        self.len = len;
+    }
+
+    /// Copies `data` into the buffer, returns the number of bytes that fit.
+    pub fn fill(&mut self, data: &[u8]) -> usize {
+        let n = data.len().min(self.capacity());
+        self.as_full_mut()[..n].copy_from_slice(&data[..n]);
+        self.len = n;
+        n
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+
+    /// The whole capacity of the buffer, independent of `len`.
+    pub fn as_full_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.as_mut_ptr(), self.capacity()) }
+    }
+
+    pub fn pool(&self) -> &RegisteredBufferPool {
+        &self.pool
+    }
+
+    pub(super) fn as_ptr(&self) -> *const u8 {
+        self.pool.buffer_ptr(self.slot)
+    }
+
+    pub(super) fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.pool.buffer_ptr(self.slot)
+    }
+}
+
+impl Drop for FixedBuf {
+    fn drop(&mut self) {
+        self.pool.inner.free.borrow_mut().push(self.slot);
+    }
+}
@@ -0,0 +1,305 @@
+use std::error::Error as StdError;
+use std::fmt::{Debug, Display, Formatter};
+use std::io;
+use std::marker::PhantomData;
+use std::os::fd::{FromRawFd, IntoRawFd, OwnedFd};
+use std::sync::{Arc, OnceLock};
+
+use io_uring::cqueue::Entry;
+use tracing::warn;
+
+use crate::message::{MessageTarget, OutgoingMessage, RingMessage};
+use crate::ops::{Error, MsgRingHandle, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// A value that travels inside a single message: 56 bits of payload and a 32 bit value.
+///
+/// Implemented for the primitive types, [`OwnedFd`] (the fd number, the receiver takes over
+/// ownership) and `Box<T>` (the pointer, for anything larger).
+pub trait RingPayload: Send + Sized {
+    fn encode(self) -> (u64, i32);
+
+    /// # Safety
+    /// `payload` and `value` have to come from [`RingPayload::encode`] of the same type, and
+    /// owning types may only be decoded once.
+    unsafe fn decode(payload: u64, value: i32) -> Self;
+}
+
+impl RingPayload for () {
+    #[inline]
+    fn encode(self) -> (u64, i32) {
+        (0, 0)
+    }
+
+    #[inline]
+    unsafe fn decode(_payload: u64, _value: i32) -> Self {}
+}
+
+impl RingPayload for bool {
+    #[inline]
+    fn encode(self) -> (u64, i32) {
+        (self as u64, 0)
+    }
+
+    #[inline]
+    unsafe fn decode(payload: u64, _value: i32) -> Self {
+        payload != 0
+    }
+}
+
+impl RingPayload for char {
+    #[inline]
+    fn encode(self) -> (u64, i32) {
+        (self as u64, 0)
+    }
+
+    #[inline]
+    unsafe fn decode(payload: u64, _value: i32) -> Self {
+        char::from_u32_unchecked(payload as u32)
+    }
+}
+
+macro_rules! int_payload {
+    ($($int:ty),+) => {
+        $(impl RingPayload for $int {
+            /// The low 56 bits go into the payload, the rest into the value.
+            #[inline]
+            fn encode(self) -> (u64, i32) {
+                let bits = self as u64;
+                (bits & RingMessage::MAX_PAYLOAD, (bits >> 56) as i32)
+            }
+
+            #[inline]
+            unsafe fn decode(payload: u64, value: i32) -> Self {
+                (payload | (value as u64) << 56) as $int
+            }
+        })+
+    };
+}
+
+int_payload!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl RingPayload for OwnedFd {
+    #[inline]
+    fn encode(self) -> (u64, i32) {
+        (0, self.into_raw_fd())
+    }
+
+    #[inline]
+    unsafe fn decode(_payload: u64, value: i32) -> Self {
+        OwnedFd::from_raw_fd(value)
+    }
+}
+
+impl<T: Send> RingPayload for Box<T> {
+    #[inline]
+    fn encode(self) -> (u64, i32) {
+        let address = Box::into_raw(self) as u64;
+        assert!(
+            address <= RingMessage::MAX_PAYLOAD,
+            "box address exceeds the message payload"
+        );
+        (address, 0)
+    }
+
+    #[inline]
+    unsafe fn decode(payload: u64, _value: i32) -> Self {
+        Box::from_raw(payload as *mut T)
+    }
+}
+
+/// Returned by [`ChannelSender::send`] with the value that was not sent.
+pub struct ChannelSendError<T> {
+    pub value: T,
+    pub error: io::Error,
+}
+
+impl<T> Debug for ChannelSendError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChannelSendError")
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Display for ChannelSendError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unable to send on channel: {}", self.error)
+    }
+}
+
+impl<T> StdError for ChannelSendError<T> {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Creates a channel whose receiver is a [`ChannelOp`] calling `on_recv` for every value.
+///
+/// The sender is cheap to clone and [`Send`], every value is posted into the receiving ring's
+/// completion queue as a message of its own, there is no queue shared between the threads.
+/// The sender has to be [connected](ChannelSender::connect) once the receiving ring exists.
+pub fn channel<T: RingPayload>(
+    on_recv: impl FnMut(T) -> OpControlFlow + 'static,
+) -> (ChannelSender<T>, ChannelOp<T>) {
+    let target: Arc<OnceLock<MessageTarget>> = Default::default();
+    (
+        ChannelSender {
+            target: target.clone(),
+            marker: PhantomData,
+        },
+        ChannelOp {
+            target,
+            received: 0,
+            on_recv: Box::new(on_recv),
+        },
+    )
+}
+
+/// Sending half of a [`channel`].
+pub struct ChannelSender<T> {
+    target: Arc<OnceLock<MessageTarget>>,
+    marker: PhantomData<fn(T)>,
+}
+
+impl<T> Clone for ChannelSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            target: self.target.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Debug for ChannelSender<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChannelSender")
+            .field("target", &self.target.get())
+            .finish()
+    }
+}
+
+impl<T: RingPayload> ChannelSender<T> {
+    /// Sets the address of the receiving [`ChannelOp`], see `Ring::message_target`.
+    /// Only the first call (on any clone) has an effect.
+    ///
+    /// # Safety
+    /// `target` has to address the [`ChannelOp`] created together with this sender, any other
+    /// operation decodes the values as a different type.
+    pub unsafe fn connect(&self, target: MessageTarget) {
+        let _ = self.target.set(target);
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.target.get().is_some()
+    }
+
+    /// Sends `value` from any thread and waits until the kernel posted it. Fails with the value
+    /// if the sender is not connected or the receiving ring is gone.
+    pub fn send(&self, value: T) -> Result<(), ChannelSendError<T>> {
+        let Some(&target) = self.target.get() else {
+            return Err(ChannelSendError {
+                value,
+                error: io::Error::new(io::ErrorKind::NotConnected, "channel is not connected"),
+            });
+        };
+
+        let (payload, encoded) = value.encode();
+        match OutgoingMessage::data(target, payload, encoded).send_blocking() {
+            Ok(()) => Ok(()),
+            Err(error) => Err(ChannelSendError {
+                // not delivered, the value is still ours
+                value: unsafe { T::decode(payload, encoded) },
+                error,
+            }),
+        }
+    }
+
+    /// Queues `value` on the [`MsgRingOp`](crate::ops::MsgRingOp) of the calling ring, which
+    /// sends it batched with the other messages of the iteration.
+    ///
+    /// A value that can not be delivered is reported to the `MsgRingOp`'s failure callback as
+    /// an encoded message and leaks if it owns resources (`Box`, `OwnedFd`).
+    ///
+    /// # Panics
+    /// If the sender is not connected.
+    pub fn send_via(&self, value: T, messages: &MsgRingHandle) {
+        let target = *self.target.get().expect("channel is not connected");
+        let (payload, encoded) = value.encode();
+        messages.send(target, payload, encoded);
+    }
+}
+
+/// Receiving half of a [`channel`], a [`RingOperation`] without requests of its own: values
+/// arrive as messages.
+///
+/// Messages arriving while the ring tears down are dropped, owning values sent in them leak.
+pub struct ChannelOp<T> {
+    // shared with the senders
+    target: Arc<OnceLock<MessageTarget>>,
+    received: u64,
+    on_recv: Box<dyn FnMut(T) -> OpControlFlow>,
+}
+
+impl<T> Debug for ChannelOp<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChannelOp")
+            .field("target", &self.target.get())
+            .field("received", &self.received)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: RingPayload> ChannelOp<T> {
+    /// Number of values received.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+}
+
+impl<T: RingPayload> RingOperation for ChannelOp<T> {
+    type RingData = ();
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        (ControlFlow::Continue, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn on_message<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        message: RingMessage,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        let RingMessage::Data { payload, value } = message else {
+            warn!("channel dropped {message:?}");
+            return ControlFlow::Continue;
+        };
+
+        self.received += 1;
+        (self.on_recv)(unsafe { T::decode(payload, value) })
+    }
+}
@@ -0,0 +1,237 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::{IntoRawFd, OwnedFd, RawFd};
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Close;
+use io_uring::types::{Fd, Fixed};
+use tracing::trace;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CloseTarget {
+    Fd(RawFd),
+    Fixed(u32),
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to hand fds to a [`CloseOp`].
+#[derive(Debug, Clone, Default)]
+pub struct CloseHandle {
+    queue: Rc<RefCell<Vec<CloseTarget>>>,
+}
+
+impl CloseHandle {
+    pub fn close(&self, fd: OwnedFd) {
+        self.queue
+            .borrow_mut()
+            .push(CloseTarget::Fd(fd.into_raw_fd()));
+    }
+
+    /// # Safety
+    /// The caller must own `fd` and must not use it afterwards.
+    pub unsafe fn close_raw(&self, fd: RawFd) {
+        self.queue.borrow_mut().push(CloseTarget::Fd(fd));
+    }
+
+    pub fn close_fixed(&self, slot: u32) {
+        self.queue.borrow_mut().push(CloseTarget::Fixed(slot));
+    }
+}
+
+/// Closes fds handed to its [`CloseHandle`]s asynchronously.
+///
+/// Everything queued during a loop iteration is submitted as one batch of `Close` sqes from
+/// [`RingOperation::housekeeping`]. `-EBADF` is expected (e.g. the fd raced another close) and
+/// ignored.
+pub struct CloseOp {
+    handle: CloseHandle,
+    closed: u64,
+}
+
+impl Debug for CloseOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CloseOp")
+            .field("queued", &self.handle.queue.borrow().len())
+            .field("closed", &self.closed)
+            .finish()
+    }
+}
+
+impl Default for CloseOp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CloseOp {
+    pub fn new() -> Self {
+        Self {
+            handle: Default::default(),
+            closed: 0,
+        }
+    }
+
+    pub fn handle(&self) -> CloseHandle {
+        self.handle.clone()
+    }
+
+    pub fn closed(&self) -> u64 {
+        self.closed
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, CloseTarget)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<CloseTarget, W>,
+    ) -> Result<(), Error> {
+        let targets = std::mem::take(&mut *self.handle.queue.borrow_mut());
+        if targets.is_empty() {
+            return Ok(());
+        }
+
+        trace!("closing {} fds", targets.len());
+        let entries = targets
+            .iter()
+            .map(|target| match *target {
+                CloseTarget::Fd(fd) => Close::new(Fd(fd)).build(),
+                CloseTarget::Fixed(slot) => Close::new(Fixed(slot)).build(),
+            })
+            .collect();
+
+        submitter.push_slice(entries, targets.into_boxed_slice())?;
+        Ok(())
+    }
+}
+
+impl RingOperation for CloseOp {
+    type RingData = CloseTarget;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let flow = match -completion_entry.result() {
+            0 => {
+                self.closed += 1;
+                ControlFlow::Continue
+            }
+            libc::EBADF => {
+                trace!("{ring_data:?} was already closed");
+                ControlFlow::Continue
+            }
+            errno => ControlFlow::Warn(io::Error::from_raw_os_error(errno).into()),
+        };
+
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
+
+#[cfg(test)]
+// the ring is generated inside the crate, its unused parts are not exempt from lints
+#[allow(dead_code, unused_imports)]
+mod tests {
+    use std::cell::Cell;
+    use std::fs::File;
+    use std::io::Read as _;
+    use std::os::fd::FromRawFd;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::ops::TickOp;
+
+    crate::ring! { close_ring -> crate::ops::Error, close: super::CloseOp, tick: crate::ops::TickOp }
+
+    /// Read and write end of a new pipe.
+    fn pipe() -> (File, OwnedFd) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+        unsafe { (File::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) }
+    }
+
+    #[test]
+    fn closes_queued_fds_in_batches() {
+        let close = CloseOp::new();
+        let handle = close.handle();
+        let mut readers = Vec::new();
+        // closed by the batch of `setup`
+        for _ in 0..3 {
+            let (reader, writer) = pipe();
+            handle.close(writer);
+            readers.push(reader);
+        }
+        // closed by the batch of the iteration the first tick is handled in
+        let mut later = Vec::new();
+        for _ in 0..2 {
+            let (reader, writer) = pipe();
+            later.push(writer);
+            readers.push(reader);
+        }
+
+        let ticks = Rc::new(Cell::new(0));
+        let tick = TickOp::new(Duration::from_millis(1), {
+            let ticks = ticks.clone();
+            move |_| {
+                ticks.set(ticks.get() + 1);
+                match ticks.get() {
+                    1 => {
+                        for writer in later.drain(..) {
+                            handle.close(writer);
+                        }
+                        // beyond any open fd, its `-EBADF` is ignored
+                        unsafe { handle.close_raw(1 << 30) };
+                        ControlFlow::Continue
+                    }
+                    5 => ControlFlow::Exit,
+                    _ => ControlFlow::Continue,
+                }
+            }
+        });
+        let mut ring = close_ring::Ring::builder()
+            .close(close)
+            .tick(tick)
+            .build()
+            .unwrap();
+        ring.run().unwrap();
+
+        assert_eq!(ring.operations().close.closed(), 5);
+        // every write end is closed, the read ends are at their end
+        for mut reader in readers {
+            assert_eq!(reader.read(&mut [0; 1]).unwrap(), 0);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::net::SocketAddr;
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::time::Duration;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{Connect, LinkTimeout, Socket};
+use io_uring::squeue::Flags;
+use io_uring::types::{Fd, Timespec};
+
+use crate::ops::{Error, OpControlFlow, SockAddr};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+#[derive(Debug)]
+pub enum ConnectOutcome {
+    Connected(OwnedFd),
+    TimedOut,
+    Refused,
+    Failed(io::Error),
+}
+
+/// A pending connection attempt. Owns the socket and address until the attempt is finished.
+#[derive(Debug)]
+pub struct ConnectRequest {
+    addr: Box<SockAddr>,
+    timeout: Duration,
+    socket: Option<OwnedFd>,
+}
+
+impl ConnectRequest {
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr.to_std()
+    }
+}
+
+#[derive(Debug)]
+pub enum ConnectStage {
+    Socket(ConnectRequest),
+    Connect(ConnectRequest),
+    Timeout(Box<Timespec>),
+}
+
+/// Creates a socket and connects it with a linked timeout for every queued address.
+pub struct ConnectOp {
+    pending: VecDeque<ConnectRequest>,
+    on_result: Box<dyn FnMut(SocketAddr, ConnectOutcome) -> OpControlFlow>,
+}
+
+impl Debug for ConnectOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectOp")
+            .field("pending", &self.pending)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ConnectOp {
+    pub fn new(
+        on_result: impl FnMut(SocketAddr, ConnectOutcome) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            pending: Default::default(),
+            on_result: Box::new(on_result),
+        }
+    }
+
+    /// Queues a TCP connection attempt to `addr` that is given up after `timeout`. The ring
+    /// submits it with its next housekeeping, which runs after completions: nothing wakes a
+    /// ring blocked in `Ring::run` for it, queue attempts before running the ring or between
+    /// `Ring::run_step`s.
+    pub fn connect(&mut self, addr: SocketAddr, timeout: Duration) {
+        self.pending.push_back(ConnectRequest {
+            addr: Box::new(addr.into()),
+            timeout,
+            socket: None,
+        });
+    }
+
+    /// Creates the sockets of the queued attempts, the rest stays queued once the ring is full.
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, ConnectStage)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<ConnectStage, W>,
+    ) -> Result<(), Error> {
+        while submitter.has_capacity(1) {
+            let Some(request) = self.pending.pop_front() else {
+                break;
+            };
+            let socket = Socket::new(
+                request.addr.family(),
+                libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+                0,
+            )
+            .build();
+            submitter.push(socket, ConnectStage::Socket(request))?;
+        }
+        Ok(())
+    }
+
+    fn report(&mut self, request: &ConnectRequest, outcome: ConnectOutcome) -> OpControlFlow {
+        match request.addr() {
+            Some(addr) => (self.on_result)(addr, outcome),
+            None => ControlFlow::Continue,
+        }
+    }
+
+    fn on_socket<W: Fn(&mut io_uring::squeue::Entry, ConnectStage)>(
+        &mut self,
+        res: i32,
+        mut request: ConnectRequest,
+        submitter: &mut SubmissionQueueSubmitter<ConnectStage, W>,
+    ) -> Result<OpControlFlow, Error> {
+        if res < 0 {
+            let e = io::Error::from_raw_os_error(-res);
+            return Ok(self.report(&request, ConnectOutcome::Failed(e)));
+        }
+
+        let socket = unsafe { OwnedFd::from_raw_fd(res) };
+        let connect = Connect::new(Fd(res), request.addr.as_ptr(), request.addr.len())
+            .build()
+            .flags(Flags::IO_LINK);
+        let timespec = Box::new(Timespec::from(request.timeout));
+        let timeout = LinkTimeout::new(timespec.as_ref()).build();
+        request.socket = Some(socket);
+
+        submitter.push_multiple(
+            [connect, timeout],
+            [
+                ConnectStage::Connect(request),
+                ConnectStage::Timeout(timespec),
+            ],
+        )?;
+        Ok(ControlFlow::Continue)
+    }
+
+    fn on_connect(&mut self, res: i32, mut request: ConnectRequest) -> OpControlFlow {
+        let outcome = match -res {
+            0 => match request.socket.take() {
+                Some(socket) => ConnectOutcome::Connected(socket),
+                None => unreachable!(),
+            },
+            // canceled by the linked timeout
+            libc::ECANCELED | libc::ETIMEDOUT => ConnectOutcome::TimedOut,
+            libc::ECONNREFUSED => ConnectOutcome::Refused,
+            errno => ConnectOutcome::Failed(io::Error::from_raw_os_error(errno)),
+        };
+
+        self.report(&request, outcome)
+    }
+}
+
+impl RingOperation for ConnectOp {
+    type RingData = ConnectStage;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        let flow = match ring_data {
+            ConnectStage::Socket(request) => self.on_socket(res, request, &mut submitter),
+            ConnectStage::Connect(request) => Ok(self.on_connect(res, request)),
+            ConnectStage::Timeout(_) => Ok(ControlFlow::Continue),
+        };
+
+        match flow {
+            Ok(flow) => (flow, None),
+            Err(e) => (ControlFlow::Error(e), None),
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        // a socket created during the cancellation would leak otherwise
+        if let ConnectStage::Socket(_) = ring_data {
+            if completion_entry.result() >= 0 {
+                drop(unsafe { OwnedFd::from_raw_fd(completion_entry.result()) });
+            }
+        }
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
+
+#[cfg(test)]
+// the ring is generated inside the crate, its unused parts are not exempt from lints
+#[allow(dead_code, unused_imports)]
+mod tests {
+    use std::cell::RefCell;
+    use std::net::TcpListener;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::RingStep;
+
+    crate::ring! { connect_ring -> crate::ops::Error, connect: super::ConnectOp }
+
+    #[test]
+    fn connects_after_setup() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let results = Rc::new(RefCell::new(Vec::new()));
+        let on_result = {
+            let results = results.clone();
+            move |addr, outcome| {
+                results.borrow_mut().push((addr, outcome));
+                ControlFlow::Exit
+            }
+        };
+        let mut ring = connect_ring::Ring::builder()
+            .connect(ConnectOp::new(on_result))
+            .build()
+            .unwrap();
+
+        assert!(matches!(ring.run_step(), Ok(RingStep::Running { .. })));
+        let addr = listener.local_addr().unwrap();
+        ring.operations_mut()
+            .connect
+            .connect(addr, Duration::from_secs(5));
+        // housekeeping starts it, nothing would wake a ring blocked in `run`
+        while let RingStep::Running { .. } = ring.run_step().unwrap() {}
+
+        let results = results.borrow();
+        assert!(
+            matches!(results.as_slice(), [(connected, ConnectOutcome::Connected(_))] if *connected == addr),
+            "{results:?}"
+        );
+    }
+}
@@ -0,0 +1,371 @@
+use std::cell::Cell;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{Read, Write};
+use io_uring::squeue::Flags;
+use io_uring::types::Fd;
+use tracing::trace;
+
+use crate::ops::file::ChunkBuffers;
+use crate::ops::{Error, OpControlFlow, SpliceEvent, SpliceOp, SpliceStage};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+const DEFAULT_CHUNK_SIZE: u32 = 128 * 1024;
+const DEFAULT_DEPTH: usize = 8;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FileCopyEvent {
+    /// `copied` bytes of `total` (if the source size is known) have been written
+    Progress {
+        copied: u64,
+        total: Option<u64>,
+    },
+    Done {
+        copied: u64,
+    },
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CopyStage {
+    Read(usize),
+    Write(usize),
+    Splice(SpliceStage),
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct CopySlot {
+    offset: u64,
+    len: u32,
+    read: u32,
+    written: u32,
+}
+
+enum Engine {
+    ReadWrite {
+        buffers: ChunkBuffers,
+        slots: Vec<CopySlot>,
+    },
+    Splice {
+        op: Box<SpliceOp>,
+        event: Rc<Cell<Option<SpliceEvent>>>,
+    },
+}
+
+impl Debug for Engine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Engine::ReadWrite { buffers, slots } => f
+                .debug_struct("ReadWrite")
+                .field("buffers", buffers)
+                .field("slots", slots)
+                .finish(),
+            Engine::Splice { op, .. } => f.debug_tuple("Splice").field(op).finish(),
+        }
+    }
+}
+
+/// Copies `src` to `dst`, essentially `cp` as a [`RingOperation`].
+///
+/// By default every chunk is copied by a `Read` linked to a `Write` at the same offset, with up to
+/// `depth` chains in flight. [`FileCopyOp::splice`] switches to a [`SpliceOp`], which copies from
+/// the current file positions and also works for pipes and sockets.
+pub struct FileCopyOp {
+    src: RawFd,
+    dst: RawFd,
+    chunk_size: u32,
+    next_offset: u64,
+    size: Option<u64>,
+    eof: bool,
+    copied: u64,
+    engine: Engine,
+    on_event: Box<dyn FnMut(FileCopyEvent) -> OpControlFlow>,
+}
+
+impl Debug for FileCopyOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileCopyOp")
+            .field("src", &self.src)
+            .field("dst", &self.dst)
+            .field("chunk_size", &self.chunk_size)
+            .field("next_offset", &self.next_offset)
+            .field("size", &self.size)
+            .field("eof", &self.eof)
+            .field("copied", &self.copied)
+            .field("engine", &self.engine)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FileCopyOp {
+    pub fn new(
+        src: RawFd,
+        dst: RawFd,
+        on_event: impl FnMut(FileCopyEvent) -> OpControlFlow + 'static,
+    ) -> io::Result<Self> {
+        let mut stat = MaybeUninit::<libc::stat>::uninit();
+        if unsafe { libc::fstat(src, stat.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let stat = unsafe { stat.assume_init() };
+        let size = (stat.st_mode & libc::S_IFMT == libc::S_IFREG).then_some(stat.st_size as u64);
+
+        Ok(Self {
+            src,
+            dst,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            next_offset: 0,
+            size,
+            eof: false,
+            copied: 0,
+            engine: Engine::ReadWrite {
+                buffers: ChunkBuffers::new(DEFAULT_CHUNK_SIZE, DEFAULT_DEPTH),
+                slots: vec![Default::default(); DEFAULT_DEPTH],
+            },
+            on_event: Box::new(on_event),
+        })
+    }
+
+    pub fn chunks(mut self, chunk_size: u32, depth: usize) -> Self {
+        self.chunk_size = chunk_size;
+        if let Engine::ReadWrite { buffers, slots } = &mut self.engine {
+            *buffers = ChunkBuffers::new(chunk_size, depth);
+            *slots = vec![Default::default(); depth];
+        }
+        self
+    }
+
+    pub fn splice(mut self) -> io::Result<Self> {
+        let event = Rc::new(Cell::new(None));
+        let op = {
+            let event = event.clone();
+            SpliceOp::new(self.src, self.dst, move |e| {
+                event.set(Some(e));
+                ControlFlow::Continue
+            })?
+            .chunk_size(self.chunk_size)
+        };
+
+        self.engine = Engine::Splice {
+            op: Box::new(op),
+            event,
+        };
+        Ok(self)
+    }
+
+    pub fn copied(&self) -> u64 {
+        self.copied
+    }
+
+    fn fill<W: Fn(&mut io_uring::squeue::Entry, CopyStage)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<CopyStage, W>,
+    ) -> Result<(), Error> {
+        let Engine::ReadWrite { buffers, slots } = &mut self.engine else {
+            return Ok(());
+        };
+
+        while !self.eof {
+            let len = match self.size {
+                Some(size) if self.next_offset >= size => break,
+                Some(size) => (size - self.next_offset).min(self.chunk_size as u64) as u32,
+                None => self.chunk_size,
+            };
+            let Some(slot) = buffers.free.pop() else {
+                break;
+            };
+
+            slots[slot] = CopySlot {
+                offset: self.next_offset,
+                len,
+                read: 0,
+                written: 0,
+            };
+            let buf = buffers.buffers[slot].as_mut_ptr();
+            let read = Read::new(Fd(self.src), buf, len)
+                .offset(self.next_offset)
+                .build()
+                .flags(Flags::IO_LINK);
+            let write = Write::new(Fd(self.dst), buf, len)
+                .offset(self.next_offset)
+                .build();
+
+            if let Err(e) = submitter.push_multiple(
+                [read, write],
+                [CopyStage::Read(slot), CopyStage::Write(slot)],
+            ) {
+                buffers.free.push(slot);
+                return Err(e.into());
+            }
+            self.next_offset += len as u64;
+        }
+
+        Ok(())
+    }
+
+    fn on_read(&mut self, res: i32, slot: usize) -> Result<OpControlFlow, Error> {
+        let Engine::ReadWrite { slots, .. } = &mut self.engine else {
+            unreachable!()
+        };
+
+        if res < 0 {
+            return Err(io::Error::from_raw_os_error(-res).into());
+        }
+
+        let slot = &mut slots[slot];
+        slot.read = res as u32;
+        if slot.read < slot.len {
+            trace!("short read at {}, assuming EOF", slot.offset);
+            self.eof = true;
+        }
+
+        Ok(ControlFlow::Continue)
+    }
+
+    fn on_write<W: Fn(&mut io_uring::squeue::Entry, CopyStage)>(
+        &mut self,
+        res: i32,
+        index: usize,
+        submitter: &mut SubmissionQueueSubmitter<CopyStage, W>,
+    ) -> Result<OpControlFlow, Error> {
+        let Engine::ReadWrite { buffers, slots } = &mut self.engine else {
+            unreachable!()
+        };
+        let slot = &mut slots[index];
+
+        match -res {
+            // a short read broke the link
+            libc::ECANCELED => {}
+            errno if errno > 0 => return Err(io::Error::from_raw_os_error(errno).into()),
+            0 if slot.written < slot.read => {
+                return Err(io::Error::from(io::ErrorKind::WriteZero).into())
+            }
+            _ => slot.written += res as u32,
+        }
+
+        if slot.written < slot.read {
+            let buf = unsafe { buffers.buffers[index].as_ptr().add(slot.written as usize) };
+            let write = Write::new(Fd(self.dst), buf, slot.read - slot.written)
+                .offset(slot.offset + slot.written as u64)
+                .build();
+            submitter.push(write, CopyStage::Write(index))?;
+            return Ok(ControlFlow::Continue);
+        }
+
+        buffers.free.push(index);
+        let written = slot.written;
+
+        if written > 0 {
+            self.copied += written as u64;
+            let flow = (self.on_event)(FileCopyEvent::Progress {
+                copied: self.copied,
+                total: self.size,
+            });
+            if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+                return Ok(flow);
+            }
+        }
+
+        self.fill(submitter)?;
+
+        let Engine::ReadWrite { buffers, .. } = &self.engine else {
+            unreachable!()
+        };
+        if buffers.in_flight() == 0 {
+            return Ok((self.on_event)(FileCopyEvent::Done {
+                copied: self.copied,
+            }));
+        }
+
+        Ok(ControlFlow::Continue)
+    }
+
+    fn on_splice<W: Fn(&mut io_uring::squeue::Entry, CopyStage)>(
+        &mut self,
+        completion_entry: Entry,
+        stage: SpliceStage,
+        submitter: &mut SubmissionQueueSubmitter<CopyStage, W>,
+    ) -> OpControlFlow {
+        let Engine::Splice { op, event } = &mut self.engine else {
+            unreachable!()
+        };
+
+        let (flow, _) = op.on_completion(
+            completion_entry,
+            stage,
+            submitter.map_data(CopyStage::Splice),
+        );
+        if !matches!(flow, ControlFlow::Continue) {
+            return flow;
+        }
+
+        match event.take() {
+            Some(SpliceEvent::Transferred(n)) => {
+                self.copied += n as u64;
+                (self.on_event)(FileCopyEvent::Progress {
+                    copied: self.copied,
+                    total: self.size,
+                })
+            }
+            Some(SpliceEvent::Eof { total }) => {
+                (self.on_event)(FileCopyEvent::Done { copied: total })
+            }
+            None => ControlFlow::Continue,
+        }
+    }
+}
+
+impl RingOperation for FileCopyOp {
+    type RingData = CopyStage;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        match &mut self.engine {
+            Engine::ReadWrite { .. } => self.fill(&mut submitter),
+            Engine::Splice { op, .. } => op.setup(submitter.map_data(CopyStage::Splice)),
+        }
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        let flow = match ring_data {
+            CopyStage::Read(slot) => self.on_read(res, slot),
+            CopyStage::Write(slot) => self.on_write(res, slot, &mut submitter),
+            CopyStage::Splice(stage) => Ok(self.on_splice(completion_entry, stage, &mut submitter)),
+        };
+
+        match flow {
+            Ok(flow) => (flow, None),
+            Err(e) => (ControlFlow::Error(e), None),
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        if let (CopyStage::Write(slot), Engine::ReadWrite { buffers, .. }) =
+            (ring_data, &mut self.engine)
+        {
+            buffers.free.push(slot);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::Arc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Read;
+use io_uring::types::Fd;
+use tracing::trace;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// Keeps a read on an `eventfd` in flight and calls `on_event` with the counter value whenever
+/// someone writes to it, e.g. through an [`EventFdHandle`] from another thread.
+pub struct EventFdOp {
+    fd: Arc<OwnedFd>,
+    value: Box<u64>,
+    on_event: Box<dyn FnMut(u64) -> OpControlFlow>,
+}
+
+impl Debug for EventFdOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventFdOp")
+            .field("fd", &self.fd)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Cloneable and [`Send`] handle to wake up an [`EventFdOp`].
+#[derive(Debug, Clone)]
+pub struct EventFdHandle {
+    fd: Arc<OwnedFd>,
+}
+
+impl EventFdHandle {
+    pub fn notify(&self) -> io::Result<()> {
+        self.add(1)
+    }
+
+    pub fn add(&self, value: u64) -> io::Result<()> {
+        let res = unsafe {
+            libc::write(
+                self.fd.as_raw_fd(),
+                &value as *const u64 as *const libc::c_void,
+                size_of::<u64>(),
+            )
+        };
+
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for EventFdHandle {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl EventFdOp {
+    pub fn new(on_event: impl FnMut(u64) -> OpControlFlow + 'static) -> io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            fd: Arc::new(unsafe { OwnedFd::from_raw_fd(fd) }),
+            value: Box::new(0),
+            on_event: Box::new(on_event),
+        })
+    }
+
+    pub fn handle(&self) -> EventFdHandle {
+        EventFdHandle {
+            fd: self.fd.clone(),
+        }
+    }
+
+    fn read(&mut self) -> io_uring::squeue::Entry {
+        Read::new(
+            Fd(self.fd.as_raw_fd()),
+            self.value.as_mut() as *mut u64 as *mut u8,
+            size_of::<u64>() as u32,
+        )
+        .build()
+    }
+}
+
+impl AsRawFd for EventFdOp {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl RingOperation for EventFdOp {
+    type RingData = ();
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        submitter.push(self.read(), ())?;
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        _ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let flow = match completion_entry.result() {
+            res if res == size_of::<u64>() as i32 => {
+                trace!("eventfd value: {}", self.value);
+                (self.on_event)(*self.value)
+            }
+            // transient, read again
+            res if res == -libc::EINTR || res == -libc::EAGAIN => {
+                ControlFlow::Warn(io::Error::from_raw_os_error(-res).into())
+            }
+            // every further read fails the same way, re-arming would spin
+            res if res < 0 => ControlFlow::Error(io::Error::from_raw_os_error(-res).into()),
+            res => ControlFlow::Warn(
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("short read on eventfd: {res} bytes"),
+                )
+                .into(),
+            ),
+        };
+
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return (flow, None);
+        }
+
+        if let Err(e) = submitter.push(self.read(), ()) {
+            return (ControlFlow::Error(e.into()), None);
+        }
+
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+//! Complete operations built from the built-in ones, meant as starting points.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use tracing::debug;
+
+use crate::ops::{AcceptOp, Error, OpControlFlow, RecvEvent, RecvOp, SendBuf, SendOp};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+#[derive(Debug)]
+pub enum EchoData {
+    Accept(()),
+    Recv(u64, Box<[u8]>),
+    Send(u64, SendBuf),
+}
+
+struct Connection {
+    fd: OwnedFd,
+    recv: RecvOp,
+    send: SendOp,
+}
+
+/// A TCP echo server: accepts connections on `listener` and sends everything it receives back.
+///
+/// Composes an [`AcceptOp`] with a [`RecvOp`]/[`SendOp`] pair per connection, each one driven
+/// through [`SubmissionQueueSubmitter::map_data`]. Connection errors are reported as warnings
+/// and only close the affected connection.
+pub struct EchoServer {
+    accept: AcceptOp,
+    accepted: Rc<RefCell<Vec<OwnedFd>>>,
+    closed: Rc<RefCell<Vec<u64>>>,
+    connections: HashMap<u64, Connection>,
+    next_id: u64,
+}
+
+impl Debug for EchoServer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EchoServer")
+            .field("accept", &self.accept)
+            .field("connections", &self.connections.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl EchoServer {
+    /// `listener` is borrowed and must stay open while the ring runs.
+    pub fn new(listener: RawFd) -> Self {
+        let accepted: Rc<RefCell<Vec<OwnedFd>>> = Default::default();
+        let queue = accepted.clone();
+
+        Self {
+            accept: AcceptOp::new(listener, move |fd| {
+                queue.borrow_mut().push(fd);
+                ControlFlow::Continue
+            }),
+            accepted,
+            closed: Default::default(),
+            connections: Default::default(),
+            next_id: 0,
+        }
+    }
+
+    pub fn connections(&self) -> usize {
+        self.connections.len()
+    }
+
+    fn open<W: Fn(&mut io_uring::squeue::Entry, EchoData)>(
+        &mut self,
+        fd: OwnedFd,
+        submitter: &mut SubmissionQueueSubmitter<EchoData, W>,
+    ) -> Result<(), Error> {
+        let id = self.next_id;
+        self.next_id += 1;
+        debug!("echo connection {id} opened");
+
+        let send = SendOp::new(fd.as_raw_fd(), |result| match result {
+            Ok(_) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Warn(e.into()),
+        });
+        let handle = send.handle();
+        let closed = self.closed.clone();
+        let mut recv = RecvOp::new(fd.as_raw_fd(), move |event| {
+            match event {
+                RecvEvent::Data(data) => handle.send(data.to_vec()),
+                RecvEvent::Closed => closed.borrow_mut().push(id),
+            }
+            ControlFlow::Continue
+        });
+
+        recv.setup(submitter.map_data(move |d| EchoData::Recv(id, d)))?;
+        self.connections.insert(id, Connection { fd, recv, send });
+        Ok(())
+    }
+
+    /// Shuts the connection down, its pending `Recv` completes and the connection is removed
+    /// once its sends are done.
+    fn abort(&mut self, id: u64) {
+        if let Some(connection) = self.connections.get(&id) {
+            unsafe { libc::shutdown(connection.fd.as_raw_fd(), libc::SHUT_RDWR) };
+        }
+    }
+}
+
+impl RingOperation for EchoServer {
+    type RingData = EchoData;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.accept.setup(submitter.map_data(EchoData::Accept))
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        match ring_data {
+            EchoData::Accept(data) => {
+                let (flow, data) = self.accept.on_completion(
+                    completion_entry,
+                    data,
+                    submitter.map_data(EchoData::Accept),
+                );
+
+                let accepted = std::mem::take(&mut *self.accepted.borrow_mut());
+                for fd in accepted {
+                    if let Err(e) = self.open(fd, &mut submitter) {
+                        return (ControlFlow::Error(e), data.map(EchoData::Accept));
+                    }
+                }
+                (flow, data.map(EchoData::Accept))
+            }
+            EchoData::Recv(id, data) => {
+                let Some(connection) = self.connections.get_mut(&id) else {
+                    return (ControlFlow::Continue, None);
+                };
+
+                let (flow, data) = connection.recv.on_completion(
+                    completion_entry,
+                    data,
+                    submitter.map_data(move |d| EchoData::Recv(id, d)),
+                );
+                let flow = match flow {
+                    ControlFlow::Error(e) => {
+                        self.closed.borrow_mut().push(id);
+                        ControlFlow::Warn(e)
+                    }
+                    flow => flow,
+                };
+                (flow, data.map(|d| EchoData::Recv(id, d)))
+            }
+            EchoData::Send(id, data) => {
+                let Some(connection) = self.connections.get_mut(&id) else {
+                    return (ControlFlow::Continue, None);
+                };
+
+                let (flow, data) = connection.send.on_completion(
+                    completion_entry,
+                    data,
+                    submitter.map_data(move |d| EchoData::Send(id, d)),
+                );
+                let flow = match flow {
+                    ControlFlow::Warn(e) | ControlFlow::Error(e) => {
+                        self.abort(id);
+                        ControlFlow::Warn(e)
+                    }
+                    flow => flow,
+                };
+                (flow, data.map(|d| EchoData::Send(id, d)))
+            }
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        match ring_data {
+            EchoData::Accept(data) => self.accept.on_teardown_completion(
+                completion_entry,
+                data,
+                submitter.map_data(EchoData::Accept),
+            ),
+            EchoData::Recv(..) | EchoData::Send(..) => Ok(()),
+        }
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        let closed = std::mem::take(&mut *self.closed.borrow_mut());
+        for id in closed {
+            let idle = self
+                .connections
+                .get(&id)
+                .is_some_and(|connection| connection.send.is_idle());
+            if idle {
+                debug!("echo connection {id} closed");
+                self.connections.remove(&id);
+            } else {
+                // wait for the pending echo
+                self.closed.borrow_mut().push(id);
+            }
+        }
+
+        for (&id, connection) in self.connections.iter_mut() {
+            let flow = connection
+                .send
+                .housekeeping(submitter.map_data(move |d| EchoData::Send(id, d)));
+            if let ControlFlow::Error(e) = flow {
+                return ControlFlow::Error(e);
+            }
+        }
+        ControlFlow::Continue
+    }
+}
@@ -0,0 +1,179 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Fallocate;
+use io_uring::types::Fd;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FallocateMode {
+    /// Allocates the range, growing the file if it ends past EOF
+    Allocate,
+    /// Allocates the range without changing the file size
+    KeepSize,
+    /// Deallocates the range, reads return zeroes afterwards. Never changes the file size.
+    PunchHole,
+    /// Zeroes the range, growing the file if it ends past EOF
+    ZeroRange,
+    /// Removes the range from the file without leaving a hole
+    CollapseRange,
+    /// Inserts a hole at `offset`, shifting the rest of the file
+    InsertRange,
+}
+
+impl FallocateMode {
+    fn bits(self) -> i32 {
+        match self {
+            FallocateMode::Allocate => 0,
+            FallocateMode::KeepSize => libc::FALLOC_FL_KEEP_SIZE,
+            FallocateMode::PunchHole => libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            FallocateMode::ZeroRange => libc::FALLOC_FL_ZERO_RANGE,
+            FallocateMode::CollapseRange => libc::FALLOC_FL_COLLAPSE_RANGE,
+            FallocateMode::InsertRange => libc::FALLOC_FL_INSERT_RANGE,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FallocateRequest {
+    pub fd: RawFd,
+    pub offset: u64,
+    pub len: u64,
+    pub mode: FallocateMode,
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to queue requests on a
+/// [`FallocateOp`].
+#[derive(Debug, Clone, Default)]
+pub struct FallocateHandle {
+    queue: Rc<RefCell<Vec<FallocateRequest>>>,
+}
+
+impl FallocateHandle {
+    pub fn fallocate(&self, fd: RawFd, offset: u64, len: u64, mode: FallocateMode) {
+        self.queue.borrow_mut().push(FallocateRequest {
+            fd,
+            offset,
+            len,
+            mode,
+        });
+    }
+
+    /// Reserves space for a segment that is going to be written, without changing the size.
+    pub fn preallocate(&self, fd: RawFd, offset: u64, len: u64) {
+        self.fallocate(fd, offset, len, FallocateMode::KeepSize);
+    }
+
+    pub fn punch_hole(&self, fd: RawFd, offset: u64, len: u64) {
+        self.fallocate(fd, offset, len, FallocateMode::PunchHole);
+    }
+}
+
+/// Runs `fallocate` through the ring, e.g. to preallocate or hole-punch log segments next to
+/// the reads and writes on them.
+///
+/// Requests queued during a loop iteration are submitted from [`RingOperation::housekeeping`].
+pub struct FallocateOp {
+    handle: FallocateHandle,
+    on_result: Box<dyn FnMut(FallocateRequest, io::Result<()>) -> OpControlFlow>,
+}
+
+impl Debug for FallocateOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FallocateOp")
+            .field("handle", &self.handle)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FallocateOp {
+    pub fn new(
+        on_result: impl FnMut(FallocateRequest, io::Result<()>) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            handle: Default::default(),
+            on_result: Box::new(on_result),
+        }
+    }
+
+    pub fn handle(&self) -> FallocateHandle {
+        self.handle.clone()
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, FallocateRequest)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<FallocateRequest, W>,
+    ) -> Result<(), Error> {
+        let requests = std::mem::take(&mut *self.handle.queue.borrow_mut());
+        if requests.is_empty() {
+            return Ok(());
+        }
+
+        let entries = requests
+            .iter()
+            .map(|request| {
+                Fallocate::new(Fd(request.fd), request.len)
+                    .offset(request.offset)
+                    .mode(request.mode.bits())
+                    .build()
+            })
+            .collect();
+
+        submitter.push_slice(entries, requests.into_boxed_slice())?;
+        Ok(())
+    }
+}
+
+impl RingOperation for FallocateOp {
+    type RingData = FallocateRequest;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let result = match completion_entry.result() {
+            res if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+            _ => Ok(()),
+        };
+
+        ((self.on_result)(ring_data, result), None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
@@ -0,0 +1,634 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{Read, ReadFixed, Write, WriteFixed};
+use io_uring::types::Fd;
+use tracing::{trace, warn};
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+const DEFAULT_CHUNK_SIZE: u32 = 128 * 1024;
+const DEFAULT_DEPTH: usize = 4;
+
+/// A chunk in flight. `done` bytes of the `len` bytes at `offset` are already transferred.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FileChunk {
+    slot: usize,
+    offset: u64,
+    len: u32,
+    done: u32,
+}
+
+/// The flow of a completion that also ended the operation: the last chunk's warning is kept
+/// over the flow of the final event unless that fails or exits the ring, it is only logged then.
+fn then_final(chunk: OpControlFlow, last: OpControlFlow) -> OpControlFlow {
+    match (chunk, last) {
+        (_, last @ ControlFlow::Error(_)) => last,
+        (ControlFlow::Warn(e), ControlFlow::Exit) => {
+            warn!("unable to handle ring completion entry: {e:?}");
+            ControlFlow::Exit
+        }
+        (warn @ ControlFlow::Warn(_), _) => warn,
+        (_, last) => last,
+    }
+}
+
+pub(super) struct ChunkBuffers {
+    pub(super) buffers: Vec<Box<[u8]>>,
+    pub(super) free: Vec<usize>,
+    pub(super) fixed: Option<u16>,
+}
+
+impl Debug for ChunkBuffers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChunkBuffers")
+            .field("buffers", &self.buffers.len())
+            .field("free", &self.free)
+            .field("fixed", &self.fixed)
+            .finish()
+    }
+}
+
+impl ChunkBuffers {
+    pub(super) fn new(chunk_size: u32, depth: usize) -> Self {
+        Self {
+            buffers: (0..depth)
+                .map(|_| vec![0; chunk_size as usize].into_boxed_slice())
+                .collect(),
+            free: (0..depth).rev().collect(),
+            fixed: None,
+        }
+    }
+
+    fn iovecs(&mut self) -> Vec<libc::iovec> {
+        self.buffers
+            .iter_mut()
+            .map(|b| libc::iovec {
+                iov_base: b.as_mut_ptr() as *mut libc::c_void,
+                iov_len: b.len(),
+            })
+            .collect()
+    }
+
+    pub(super) fn in_flight(&self) -> usize {
+        self.buffers.len() - self.free.len()
+    }
+}
+
+#[derive(Debug)]
+pub enum FileReadEvent<'a> {
+    Chunk { offset: u64, data: &'a [u8] },
+    Eof { total: u64 },
+}
+
+/// Reads a file chunk by chunk at explicit offsets, with up to `depth` reads in flight.
+///
+/// Chunks may complete out of order, each event carries the offset of its data.
+pub struct FileReadOp {
+    fd: RawFd,
+    chunk_size: u32,
+    next_offset: u64,
+    end: Option<u64>,
+    eof: bool,
+    total: u64,
+    buffers: ChunkBuffers,
+    on_event: Box<dyn FnMut(FileReadEvent<'_>) -> OpControlFlow>,
+}
+
+impl Debug for FileReadOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileReadOp")
+            .field("fd", &self.fd)
+            .field("chunk_size", &self.chunk_size)
+            .field("next_offset", &self.next_offset)
+            .field("end", &self.end)
+            .field("eof", &self.eof)
+            .field("total", &self.total)
+            .field("buffers", &self.buffers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FileReadOp {
+    pub fn new(
+        fd: RawFd,
+        on_event: impl FnMut(FileReadEvent<'_>) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            fd,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            next_offset: 0,
+            end: None,
+            eof: false,
+            total: 0,
+            buffers: ChunkBuffers::new(DEFAULT_CHUNK_SIZE, DEFAULT_DEPTH),
+            on_event: Box::new(on_event),
+        }
+    }
+
+    /// Only read `len` bytes starting at `offset`.
+    pub fn range(mut self, offset: u64, len: u64) -> Self {
+        self.next_offset = offset;
+        self.end = Some(offset + len);
+        self
+    }
+
+    /// Reallocates the buffers, call it before [`FileReadOp::iovecs`].
+    pub fn chunks(mut self, chunk_size: u32, depth: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self.buffers = ChunkBuffers::new(chunk_size, depth);
+        self
+    }
+
+    /// Use `ReadFixed` with buffer indices starting at `first_index`.
+    ///
+    /// # Safety
+    /// The caller must have registered [`FileReadOp::iovecs`] at `first_index`.
+    pub unsafe fn fixed_buffers(mut self, first_index: u16) -> Self {
+        self.buffers.fixed = Some(first_index);
+        self
+    }
+
+    /// The chunk buffers, to be registered with the ring for [`FileReadOp::fixed_buffers`].
+    pub fn iovecs(&mut self) -> Vec<libc::iovec> {
+        self.buffers.iovecs()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn read(&mut self, chunk: FileChunk) -> io_uring::squeue::Entry {
+        let buf = unsafe {
+            self.buffers.buffers[chunk.slot]
+                .as_mut_ptr()
+                .add(chunk.done as usize)
+        };
+        let len = chunk.len - chunk.done;
+        let offset = chunk.offset + chunk.done as u64;
+
+        match self.buffers.fixed {
+            None => Read::new(Fd(self.fd), buf, len).offset(offset).build(),
+            Some(first) => ReadFixed::new(Fd(self.fd), buf, len, first + chunk.slot as u16)
+                .offset(offset)
+                .build(),
+        }
+    }
+
+    fn fill<W: Fn(&mut io_uring::squeue::Entry, FileChunk)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<FileChunk, W>,
+    ) -> Result<(), Error> {
+        while !self.eof {
+            let len = match self.end {
+                Some(end) if self.next_offset >= end => break,
+                Some(end) => (end - self.next_offset).min(self.chunk_size as u64) as u32,
+                None => self.chunk_size,
+            };
+            let Some(slot) = self.buffers.free.pop() else {
+                break;
+            };
+
+            let chunk = FileChunk {
+                slot,
+                offset: self.next_offset,
+                len,
+                done: 0,
+            };
+            let entry = self.read(chunk);
+            if let Err(e) = submitter.push(entry, chunk) {
+                self.buffers.free.push(slot);
+                return Err(e.into());
+            }
+            self.next_offset += len as u64;
+        }
+
+        Ok(())
+    }
+
+    fn on_chunk<W: Fn(&mut io_uring::squeue::Entry, FileChunk)>(
+        &mut self,
+        res: i32,
+        mut chunk: FileChunk,
+        submitter: &mut SubmissionQueueSubmitter<FileChunk, W>,
+    ) -> Result<OpControlFlow, Error> {
+        if res < 0 {
+            self.buffers.free.push(chunk.slot);
+            return Err(io::Error::from_raw_os_error(-res).into());
+        }
+
+        if res > 0 {
+            chunk.done += res as u32;
+            if chunk.done < chunk.len {
+                // short read, the next one tells whether we hit EOF
+                trace!("short read at {}", chunk.offset);
+                let entry = self.read(chunk);
+                submitter.push(entry, chunk)?;
+                return Ok(ControlFlow::Continue);
+            }
+        } else {
+            self.eof = true;
+        }
+
+        self.buffers.free.push(chunk.slot);
+        self.total += chunk.done as u64;
+
+        let mut flow = ControlFlow::Continue;
+        if chunk.done > 0 {
+            let data = &self.buffers.buffers[chunk.slot][..chunk.done as usize];
+            flow = (self.on_event)(FileReadEvent::Chunk {
+                offset: chunk.offset,
+                data,
+            });
+            if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+                return Ok(flow);
+            }
+        }
+
+        self.fill(submitter)?;
+
+        if self.buffers.in_flight() == 0 {
+            let eof = (self.on_event)(FileReadEvent::Eof { total: self.total });
+            return Ok(then_final(flow, eof));
+        }
+
+        Ok(flow)
+    }
+}
+
+impl RingOperation for FileReadOp {
+    type RingData = FileChunk;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.fill(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        match self.on_chunk(completion_entry.result(), ring_data, &mut submitter) {
+            Ok(flow) => (flow, None),
+            Err(e) => (ControlFlow::Error(e), None),
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        self.buffers.free.push(ring_data.slot);
+        Ok(())
+    }
+}
+
+type Producer = Box<dyn FnMut(u64, &mut [u8]) -> usize>;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FileWriteEvent {
+    Chunk { offset: u64, len: u32 },
+    Done { total: u64 },
+}
+
+/// Writes a file chunk by chunk at explicit offsets, with up to `depth` writes in flight.
+///
+/// `produce` is called with the offset and a chunk buffer to fill and returns the number of
+/// bytes it wrote into the buffer, 0 means there is nothing left to write.
+pub struct FileWriteOp {
+    fd: RawFd,
+    chunk_size: u32,
+    next_offset: u64,
+    exhausted: bool,
+    total: u64,
+    buffers: ChunkBuffers,
+    produce: Producer,
+    on_event: Box<dyn FnMut(FileWriteEvent) -> OpControlFlow>,
+}
+
+impl Debug for FileWriteOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileWriteOp")
+            .field("fd", &self.fd)
+            .field("chunk_size", &self.chunk_size)
+            .field("next_offset", &self.next_offset)
+            .field("exhausted", &self.exhausted)
+            .field("total", &self.total)
+            .field("buffers", &self.buffers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FileWriteOp {
+    pub fn new(
+        fd: RawFd,
+        produce: impl FnMut(u64, &mut [u8]) -> usize + 'static,
+        on_event: impl FnMut(FileWriteEvent) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            fd,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            next_offset: 0,
+            exhausted: false,
+            total: 0,
+            buffers: ChunkBuffers::new(DEFAULT_CHUNK_SIZE, DEFAULT_DEPTH),
+            produce: Box::new(produce),
+            on_event: Box::new(on_event),
+        }
+    }
+
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.next_offset = offset;
+        self
+    }
+
+    /// Reallocates the buffers, call it before [`FileWriteOp::iovecs`].
+    pub fn chunks(mut self, chunk_size: u32, depth: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self.buffers = ChunkBuffers::new(chunk_size, depth);
+        self
+    }
+
+    /// Use `WriteFixed` with buffer indices starting at `first_index`.
+    ///
+    /// # Safety
+    /// The caller must have registered [`FileWriteOp::iovecs`] at `first_index`.
+    pub unsafe fn fixed_buffers(mut self, first_index: u16) -> Self {
+        self.buffers.fixed = Some(first_index);
+        self
+    }
+
+    /// The chunk buffers, to be registered with the ring for [`FileWriteOp::fixed_buffers`].
+    pub fn iovecs(&mut self) -> Vec<libc::iovec> {
+        self.buffers.iovecs()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn write(&mut self, chunk: FileChunk) -> io_uring::squeue::Entry {
+        let buf = unsafe {
+            self.buffers.buffers[chunk.slot]
+                .as_ptr()
+                .add(chunk.done as usize)
+        };
+        let len = chunk.len - chunk.done;
+        let offset = chunk.offset + chunk.done as u64;
+
+        match self.buffers.fixed {
+            None => Write::new(Fd(self.fd), buf, len).offset(offset).build(),
+            Some(first) => WriteFixed::new(Fd(self.fd), buf, len, first + chunk.slot as u16)
+                .offset(offset)
+                .build(),
+        }
+    }
+
+    fn fill<W: Fn(&mut io_uring::squeue::Entry, FileChunk)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<FileChunk, W>,
+    ) -> Result<(), Error> {
+        while !self.exhausted {
+            let Some(slot) = self.buffers.free.pop() else {
+                break;
+            };
+
+            let len = (self.produce)(self.next_offset, &mut self.buffers.buffers[slot]);
+            if len == 0 {
+                self.exhausted = true;
+                self.buffers.free.push(slot);
+                break;
+            }
+
+            let chunk = FileChunk {
+                slot,
+                offset: self.next_offset,
+                len: len as u32,
+                done: 0,
+            };
+            let entry = self.write(chunk);
+            if let Err(e) = submitter.push(entry, chunk) {
+                self.buffers.free.push(slot);
+                return Err(e.into());
+            }
+            self.next_offset += len as u64;
+        }
+
+        Ok(())
+    }
+
+    fn on_chunk<W: Fn(&mut io_uring::squeue::Entry, FileChunk)>(
+        &mut self,
+        res: i32,
+        mut chunk: FileChunk,
+        submitter: &mut SubmissionQueueSubmitter<FileChunk, W>,
+    ) -> Result<OpControlFlow, Error> {
+        if res <= 0 {
+            self.buffers.free.push(chunk.slot);
+            return Err(match res {
+                0 => io::Error::from(io::ErrorKind::WriteZero),
+                res => io::Error::from_raw_os_error(-res),
+            }
+            .into());
+        }
+
+        chunk.done += res as u32;
+        if chunk.done < chunk.len {
+            trace!("short write at {}", chunk.offset);
+            let entry = self.write(chunk);
+            submitter.push(entry, chunk)?;
+            return Ok(ControlFlow::Continue);
+        }
+
+        self.buffers.free.push(chunk.slot);
+        self.total += chunk.len as u64;
+
+        let flow = (self.on_event)(FileWriteEvent::Chunk {
+            offset: chunk.offset,
+            len: chunk.len,
+        });
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return Ok(flow);
+        }
+
+        self.fill(submitter)?;
+
+        if self.buffers.in_flight() == 0 {
+            let done = (self.on_event)(FileWriteEvent::Done { total: self.total });
+            return Ok(then_final(flow, done));
+        }
+
+        Ok(flow)
+    }
+}
+
+impl RingOperation for FileWriteOp {
+    type RingData = FileChunk;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.fill(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        match self.on_chunk(completion_entry.result(), ring_data, &mut submitter) {
+            Ok(flow) => (flow, None),
+            Err(e) => (ControlFlow::Error(e), None),
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        self.buffers.free.push(ring_data.slot);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+// the ring is generated inside the crate, its unused parts are not exempt from lints
+#[allow(dead_code, unused_imports)]
+mod tests {
+    use std::cell::RefCell;
+    use std::fs::{File, OpenOptions};
+    use std::io::Write as _;
+    use std::os::fd::AsRawFd;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+
+    use super::*;
+
+    crate::ring! { read_ring -> crate::ops::Error, read: super::FileReadOp }
+    crate::ring! { write_ring -> crate::ops::Error, write: super::FileWriteOp }
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rummelplatz-file-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn short_read_ends_at_eof_mid_chunk() {
+        let path = path("read");
+        let data: Vec<u8> = (0..100).collect();
+        File::create(&path).unwrap().write_all(&data).unwrap();
+        let file = File::open(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let chunks = Rc::new(RefCell::new(Vec::new()));
+        let op = FileReadOp::new(file.as_raw_fd(), {
+            let chunks = chunks.clone();
+            move |event| match event {
+                FileReadEvent::Chunk { offset, data } => {
+                    chunks.borrow_mut().push((offset, data.to_vec()));
+                    ControlFlow::Continue
+                }
+                FileReadEvent::Eof { total } => {
+                    assert_eq!(total, 100);
+                    ControlFlow::Exit
+                }
+            }
+        })
+        // the second chunk ends 28 bytes short of its 64
+        .chunks(64, 2);
+        let mut ring = read_ring::Ring::builder().read(op).build().unwrap();
+        ring.run().unwrap();
+
+        let mut chunks = chunks.take();
+        chunks.sort();
+        assert_eq!(
+            chunks,
+            [(0, data[..64].to_vec()), (64, data[64..].to_vec())]
+        );
+    }
+
+    fn write_op(fd: RawFd, events: &Rc<RefCell<Vec<FileWriteEvent>>>) -> FileWriteOp {
+        let mut left = 3;
+        FileWriteOp::new(
+            fd,
+            move |_, buf| {
+                if left == 0 {
+                    return 0;
+                }
+                left -= 1;
+                buf.fill(b'x');
+                buf.len()
+            },
+            {
+                let events = events.clone();
+                move |event| {
+                    events.borrow_mut().push(event);
+                    match event {
+                        FileWriteEvent::Chunk { .. } => ControlFlow::Continue,
+                        FileWriteEvent::Done { .. } => ControlFlow::Exit,
+                    }
+                }
+            },
+        )
+        .chunks(16, 2)
+    }
+
+    #[test]
+    fn writes_all_chunks() {
+        let path = path("write");
+        let file = File::create(&path).unwrap();
+        let events = Rc::default();
+        let mut ring = write_ring::Ring::builder()
+            .write(write_op(file.as_raw_fd(), &events))
+            .build()
+            .unwrap();
+        ring.run().unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), [b'x'; 48]);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            events.borrow().last(),
+            Some(&FileWriteEvent::Done { total: 48 })
+        );
+    }
+
+    #[test]
+    fn write_errors_fail_the_ring() {
+        let path = path("write-error");
+        File::create(&path).unwrap();
+        // writes to a file opened for reading fail with EBADF
+        let file = OpenOptions::new().read(true).open(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let events = Rc::default();
+        let mut ring = write_ring::Ring::builder()
+            .write(write_op(file.as_raw_fd(), &events))
+            .build()
+            .unwrap();
+        let Err(write_ring::RingError::Completion(Error::Io(e))) = ring.run() else {
+            panic!("the write did not fail");
+        };
+        assert_eq!(e.raw_os_error(), Some(libc::EBADF));
+        assert!(events.borrow().is_empty());
+    }
+}
@@ -0,0 +1,214 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{ReadFixed, WriteFixed};
+use io_uring::types::Fd;
+
+use crate::ops::{Error, FixedBuf, OpControlFlow, RegisteredBufferPool};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// A `ReadFixed`/`WriteFixed` in flight. Owns its buffer until the completion arrives.
+#[derive(Debug)]
+pub enum FixedIo {
+    /// Reads up to the capacity of `buf`, its `len` is the number of bytes read afterwards
+    Read {
+        fd: RawFd,
+        offset: u64,
+        buf: FixedBuf,
+    },
+    /// Writes `len` bytes of `buf`, short writes are continued until everything is written
+    Write {
+        fd: RawFd,
+        offset: u64,
+        buf: FixedBuf,
+        done: usize,
+    },
+}
+
+impl FixedIo {
+    pub fn buf(&self) -> &FixedBuf {
+        match self {
+            FixedIo::Read { buf, .. } | FixedIo::Write { buf, .. } => buf,
+        }
+    }
+
+    pub fn into_buf(self) -> FixedBuf {
+        match self {
+            FixedIo::Read { buf, .. } | FixedIo::Write { buf, .. } => buf,
+        }
+    }
+
+    fn entry(&mut self) -> io_uring::squeue::Entry {
+        match self {
+            FixedIo::Read { fd, offset, buf } => {
+                let len = buf.capacity() as u32;
+                ReadFixed::new(Fd(*fd), buf.as_mut_ptr(), len, buf.index())
+                    .offset(*offset)
+                    .build()
+            }
+            FixedIo::Write {
+                fd,
+                offset,
+                buf,
+                done,
+            } => {
+                let ptr = unsafe { buf.as_ptr().add(*done) };
+                let len = (buf.len() - *done) as u32;
+                WriteFixed::new(Fd(*fd), ptr, len, buf.index())
+                    .offset(*offset + *done as u64)
+                    .build()
+            }
+        }
+    }
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to queue reads and writes
+/// on a [`FixedIoOp`].
+#[derive(Debug, Clone, Default)]
+pub struct FixedIoHandle {
+    queue: Rc<RefCell<Vec<FixedIo>>>,
+}
+
+impl FixedIoHandle {
+    pub fn read(&self, fd: RawFd, offset: u64, buf: FixedBuf) {
+        self.queue
+            .borrow_mut()
+            .push(FixedIo::Read { fd, offset, buf });
+    }
+
+    pub fn write(&self, fd: RawFd, offset: u64, buf: FixedBuf) {
+        self.queue.borrow_mut().push(FixedIo::Write {
+            fd,
+            offset,
+            buf,
+            done: 0,
+        });
+    }
+}
+
+type FixedIoCallback = Box<dyn FnMut(FixedIo, io::Result<usize>) -> OpControlFlow>;
+
+/// `ReadFixed`/`WriteFixed` with [`FixedBuf`]s of a [`RegisteredBufferPool`].
+///
+/// `on_result` gets the request back together with its buffer, dropping it returns the buffer
+/// to the pool. Buffers of other pools or of a pool that is not registered are rejected.
+pub struct FixedIoOp {
+    pool: RegisteredBufferPool,
+    handle: FixedIoHandle,
+    on_result: FixedIoCallback,
+}
+
+impl Debug for FixedIoOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FixedIoOp")
+            .field("pool", &self.pool)
+            .field("handle", &self.handle)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FixedIoOp {
+    pub fn new(
+        pool: RegisteredBufferPool,
+        on_result: impl FnMut(FixedIo, io::Result<usize>) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            pool,
+            handle: Default::default(),
+            on_result: Box::new(on_result),
+        }
+    }
+
+    pub fn handle(&self) -> FixedIoHandle {
+        self.handle.clone()
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, FixedIo)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<FixedIo, W>,
+    ) -> Result<(), Error> {
+        let requests = std::mem::take(&mut *self.handle.queue.borrow_mut());
+        for mut request in requests {
+            assert!(
+                self.pool.owns(request.buf()),
+                "fixed buffer of a different pool"
+            );
+            assert!(self.pool.is_registered(), "buffer pool is not registered");
+
+            submitter.push(request.entry(), request)?;
+        }
+        Ok(())
+    }
+}
+
+impl RingOperation for FixedIoOp {
+    type RingData = FixedIo;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        mut ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        if res < 0 {
+            let error = io::Error::from_raw_os_error(-res);
+            return ((self.on_result)(ring_data, Err(error)), None);
+        }
+
+        let n = res as usize;
+        let result = match &mut ring_data {
+            FixedIo::Read { buf, .. } => {
+                buf.set_len(n);
+                Ok(n)
+            }
+            FixedIo::Write { .. } if n == 0 => Err(io::Error::from(io::ErrorKind::WriteZero)),
+            FixedIo::Write { buf, done, .. } => {
+                *done += n;
+                if *done < buf.len() {
+                    return match submitter.push(ring_data.entry(), ring_data) {
+                        Ok(()) => (ControlFlow::Continue, None),
+                        Err(e) => (ControlFlow::Error(e.into()), None),
+                    };
+                }
+                Ok(*done)
+            }
+        };
+
+        ((self.on_result)(ring_data, result), None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{OpenAt2, Statx};
+use io_uring::types::{Fd, OpenHow};
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// A pending `statx`. Owns the path and the result buffer until its completion arrives.
+#[derive(Debug)]
+pub struct StatxRequest {
+    dirfd: RawFd,
+    path: CString,
+    flags: i32,
+    mask: u32,
+    statx: Box<MaybeUninit<libc::statx>>,
+}
+
+type StatxCallback = Box<dyn FnMut(&CStr, io::Result<&libc::statx>) -> OpControlFlow>;
+
+/// Runs queued `statx` calls and hands the results to `on_result`.
+pub struct StatxOp {
+    pending: VecDeque<StatxRequest>,
+    on_result: StatxCallback,
+}
+
+impl Debug for StatxOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StatxOp")
+            .field("pending", &self.pending)
+            .finish_non_exhaustive()
+    }
+}
+
+impl StatxOp {
+    pub fn new(
+        on_result: impl FnMut(&CStr, io::Result<&libc::statx>) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            pending: Default::default(),
+            on_result: Box::new(on_result),
+        }
+    }
+
+    /// Queues a `statx` of `path` relative to the current working directory.
+    pub fn stat(&mut self, path: impl Into<Vec<u8>>) -> io::Result<()> {
+        self.stat_at(libc::AT_FDCWD, path, 0, libc::STATX_BASIC_STATS)
+    }
+
+    pub fn stat_at(
+        &mut self,
+        dirfd: RawFd,
+        path: impl Into<Vec<u8>>,
+        flags: i32,
+        mask: u32,
+    ) -> io::Result<()> {
+        self.pending.push_back(StatxRequest {
+            dirfd,
+            path: CString::new(path)?,
+            flags,
+            mask,
+            statx: Box::new(MaybeUninit::uninit()),
+        });
+        Ok(())
+    }
+}
+
+impl RingOperation for StatxOp {
+    type RingData = StatxRequest;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        while let Some(mut request) = self.pending.pop_front() {
+            let entry = Statx::new(
+                Fd(request.dirfd),
+                request.path.as_ptr(),
+                request.statx.as_mut_ptr() as *mut io_uring::types::statx,
+            )
+            .flags(request.flags)
+            .mask(request.mask)
+            .build();
+            submitter.push(entry, request)?;
+        }
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let result = match completion_entry.result() {
+            res if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+            _ => Ok(unsafe { ring_data.statx.assume_init_ref() }),
+        };
+
+        ((self.on_result)(&ring_data.path, result), None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+}
+
+/// A pending `openat2`. Owns the path and the `open_how` until its completion arrives.
+#[derive(Debug)]
+pub struct OpenAt2Request {
+    dirfd: RawFd,
+    path: CString,
+    how: Box<OpenHow>,
+}
+
+type OpenAt2Callback = Box<dyn FnMut(&CStr, io::Result<OwnedFd>) -> OpControlFlow>;
+
+/// Runs queued `openat2` calls and hands the opened fds to `on_result`.
+pub struct OpenAt2Op {
+    pending: VecDeque<OpenAt2Request>,
+    on_result: OpenAt2Callback,
+}
+
+impl Debug for OpenAt2Op {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OpenAt2Op")
+            .field("pending", &self.pending)
+            .finish_non_exhaustive()
+    }
+}
+
+impl OpenAt2Op {
+    pub fn new(
+        on_result: impl FnMut(&CStr, io::Result<OwnedFd>) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            pending: Default::default(),
+            on_result: Box::new(on_result),
+        }
+    }
+
+    /// Queues an `openat2` of `path` relative to the current working directory.
+    pub fn open(&mut self, path: impl Into<Vec<u8>>, how: OpenHow) -> io::Result<()> {
+        self.open_at(libc::AT_FDCWD, path, how)
+    }
+
+    pub fn open_at(
+        &mut self,
+        dirfd: RawFd,
+        path: impl Into<Vec<u8>>,
+        how: OpenHow,
+    ) -> io::Result<()> {
+        self.pending.push_back(OpenAt2Request {
+            dirfd,
+            path: CString::new(path)?,
+            how: Box::new(how),
+        });
+        Ok(())
+    }
+}
+
+impl RingOperation for OpenAt2Op {
+    type RingData = OpenAt2Request;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        while let Some(request) = self.pending.pop_front() {
+            let entry = OpenAt2::new(
+                Fd(request.dirfd),
+                request.path.as_ptr(),
+                request.how.as_ref(),
+            )
+            .build();
+            submitter.push(entry, request)?;
+        }
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let result = match completion_entry.result() {
+            res if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+            fd => Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+        };
+
+        ((self.on_result)(&ring_data.path, result), None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        // the open raced the cancellation, nobody is going to take the fd
+        if completion_entry.result() >= 0 {
+            drop(unsafe { OwnedFd::from_raw_fd(completion_entry.result()) });
+        }
+        Ok(())
+    }
+}
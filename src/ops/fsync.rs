@@ -0,0 +1,313 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+use std::time::Duration;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{Fsync, Timeout};
+use io_uring::squeue::Flags;
+use io_uring::types::{Fd, FsyncFlags, Timespec};
+use tracing::trace;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+type Waiter = Box<dyn FnOnce(io::Result<()>)>;
+
+#[derive(Debug)]
+pub enum FsyncData {
+    Fsync(RawFd),
+    Window(Box<Timespec>),
+}
+
+#[derive(Default)]
+struct Queue {
+    pending: HashMap<RawFd, Vec<Waiter>>,
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to request an fsync.
+#[derive(Clone, Default)]
+pub struct FsyncHandle {
+    queue: Rc<RefCell<Queue>>,
+}
+
+impl Debug for FsyncHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FsyncHandle")
+            .field("pending", &self.queue.borrow().pending.len())
+            .finish()
+    }
+}
+
+impl FsyncHandle {
+    /// `waiter` is called once an fsync of `fd`, submitted after this call, completed, or with
+    /// `ECANCELED` if the ring stops before.
+    pub fn fsync(&self, fd: RawFd, waiter: impl FnOnce(io::Result<()>) + 'static) {
+        self.queue
+            .borrow_mut()
+            .pending
+            .entry(fd)
+            .or_default()
+            .push(Box::new(waiter));
+    }
+}
+
+/// Group commit: coalesces all fsync requests per fd within a window into a single `Fsync` and
+/// notifies all waiters when it completes.
+///
+/// Without a window requests are collected for one loop iteration. Requests for an fd with an
+/// fsync in flight wait for the next one, as their writes may not be covered by it. With
+/// [`FsyncOp::drain`] the fsync is submitted with `IO_DRAIN`, so it is also ordered after
+/// writes that were submitted but did not complete yet.
+pub struct FsyncOp {
+    handle: FsyncHandle,
+    in_flight: HashMap<RawFd, Vec<Waiter>>,
+    window: Option<Duration>,
+    window_armed: bool,
+    datasync: bool,
+    drain: bool,
+}
+
+impl Debug for FsyncOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FsyncOp")
+            .field("handle", &self.handle)
+            .field("in_flight", &self.in_flight.keys())
+            .field("window", &self.window)
+            .field("window_armed", &self.window_armed)
+            .field("datasync", &self.datasync)
+            .field("drain", &self.drain)
+            .finish()
+    }
+}
+
+impl Default for FsyncOp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FsyncOp {
+    pub fn new() -> Self {
+        Self {
+            handle: Default::default(),
+            in_flight: Default::default(),
+            window: None,
+            window_armed: false,
+            datasync: false,
+            drain: false,
+        }
+    }
+
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// Use `fdatasync` semantics.
+    pub fn datasync(mut self, datasync: bool) -> Self {
+        self.datasync = datasync;
+        self
+    }
+
+    pub fn drain(mut self, drain: bool) -> Self {
+        self.drain = drain;
+        self
+    }
+
+    pub fn handle(&self) -> FsyncHandle {
+        self.handle.clone()
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, FsyncData)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<FsyncData, W>,
+    ) -> Result<(), Error> {
+        let mut queue = self.handle.queue.borrow_mut();
+        let ready: Vec<RawFd> = queue
+            .pending
+            .keys()
+            .filter(|fd| !self.in_flight.contains_key(fd))
+            .copied()
+            .collect();
+
+        for fd in ready {
+            let flags = if self.datasync {
+                FsyncFlags::DATASYNC
+            } else {
+                FsyncFlags::empty()
+            };
+            let mut entry = Fsync::new(Fd(fd)).flags(flags).build();
+            if self.drain {
+                entry = entry.flags(Flags::IO_DRAIN);
+            }
+
+            // the waiters stay queued if the fsync cannot be pushed
+            submitter.push(entry, FsyncData::Fsync(fd))?;
+            let waiters = queue.pending.remove(&fd).unwrap_or_default();
+            trace!("fsync {fd} for {} waiters", waiters.len());
+            self.in_flight.insert(fd, waiters);
+        }
+
+        Ok(())
+    }
+
+    fn schedule<W: Fn(&mut io_uring::squeue::Entry, FsyncData)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<FsyncData, W>,
+    ) -> Result<(), Error> {
+        if self.handle.queue.borrow().pending.is_empty() {
+            return Ok(());
+        }
+
+        match self.window {
+            None => self.flush(submitter),
+            Some(_) if self.window_armed => Ok(()),
+            Some(window) => {
+                let timespec = Box::new(Timespec::from(window));
+                let entry = Timeout::new(timespec.as_ref()).build();
+                submitter.push(entry, FsyncData::Window(timespec))?;
+                self.window_armed = true;
+                Ok(())
+            }
+        }
+    }
+
+    /// Fails the waiters whose fsync was never submitted.
+    fn cancel_pending(&mut self) {
+        let pending = std::mem::take(&mut self.handle.queue.borrow_mut().pending);
+        for waiter in pending.into_values().flatten() {
+            waiter(Err(io::Error::from_raw_os_error(libc::ECANCELED)));
+        }
+    }
+
+    fn notify(&mut self, fd: RawFd, res: i32) {
+        for waiter in self.in_flight.remove(&fd).into_iter().flatten() {
+            waiter(match res {
+                res if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+                _ => Ok(()),
+            });
+        }
+    }
+}
+
+impl RingOperation for FsyncOp {
+    type RingData = FsyncData;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.schedule(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let result = match ring_data {
+            FsyncData::Fsync(fd) => {
+                self.notify(fd, completion_entry.result());
+                Ok(())
+            }
+            FsyncData::Window(_) => {
+                self.window_armed = false;
+                self.flush(&mut submitter)
+            }
+        };
+
+        match result {
+            Ok(()) => (ControlFlow::Continue, None),
+            Err(e) => (ControlFlow::Error(e), None),
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        if let FsyncData::Fsync(fd) = ring_data {
+            self.notify(fd, completion_entry.result());
+        }
+        self.cancel_pending();
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.schedule(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
+
+impl Drop for FsyncOp {
+    /// Fails the waiters of a ring that stopped before their fsync was submitted or completed.
+    fn drop(&mut self) {
+        for waiter in std::mem::take(&mut self.in_flight).into_values().flatten() {
+            waiter(Err(io::Error::from_raw_os_error(libc::ECANCELED)));
+        }
+        self.cancel_pending();
+    }
+}
+
+#[cfg(test)]
+// the ring is generated inside the crate, its unused parts are not exempt from lints
+#[allow(dead_code, unused_imports)]
+mod tests {
+    use std::cell::Cell;
+    use std::fs::File;
+    use std::num::{NonZeroU32, NonZeroUsize};
+    use std::os::fd::AsRawFd;
+
+    use super::*;
+
+    crate::ring! { fsync_ring -> crate::ops::Error, fsync: super::FsyncOp }
+
+    #[test]
+    fn notifies_waiters_whose_fsync_could_not_be_pushed() {
+        let dir = std::env::temp_dir();
+        let files: Vec<File> = (0..3)
+            .map(|i| {
+                let path = dir.join(format!("rummelplatz-fsync-{}-{i}", std::process::id()));
+                let file = File::create(&path).unwrap();
+                std::fs::remove_file(path).unwrap();
+                file
+            })
+            .collect();
+
+        let op = FsyncOp::new();
+        let notified = Rc::new(Cell::new(0));
+        for file in &files {
+            let notified = notified.clone();
+            op.handle()
+                .fsync(file.as_raw_fd(), move |_| notified.set(notified.get() + 1));
+        }
+
+        // one fsync fits into the submission queue, one into the backlog
+        let mut ring = fsync_ring::Ring::builder()
+            .ring_size(NonZeroU32::new(1).unwrap())
+            .backlog_limit(NonZeroUsize::new(1).unwrap())
+            .fsync(op)
+            .build()
+            .unwrap();
+        assert!(ring.run_step().is_err());
+        drop(ring);
+
+        assert_eq!(notified.get(), files.len());
+    }
+}
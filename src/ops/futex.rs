@@ -0,0 +1,212 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{FutexWait, FutexWake};
+use tracing::trace;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+// futex2(2) flags, not exposed by libc (yet)
+const FUTEX2_SIZE_U32: u32 = 0x02;
+const FUTEX2_PRIVATE: u32 = 128;
+const FUTEX_BITSET_MATCH_ANY: u64 = u32::MAX as u64;
+
+type WakeQueue = Rc<RefCell<Vec<(Arc<AtomicU32>, u32)>>>;
+
+/// Cloneable and [`Send`] handle to wake a [`FutexOp`] from any thread, e.g. the producer of a
+/// lock-free queue the ring consumes.
+#[derive(Debug, Clone)]
+pub struct FutexWaker {
+    word: Arc<AtomicU32>,
+}
+
+impl FutexWaker {
+    pub fn wake(&self) -> io::Result<()> {
+        self.word.fetch_add(1, Ordering::Release);
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                self.word.as_ptr(),
+                libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+                1,
+            )
+        };
+
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum FutexData {
+    Wait(u32),
+    /// Keeps the woken futex alive until the wake completed
+    Wake(Arc<AtomicU32>),
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to wake threads waiting on
+/// a futex through the ring.
+#[derive(Debug, Clone, Default)]
+pub struct FutexHandle {
+    queue: WakeQueue,
+}
+
+impl FutexHandle {
+    /// Wakes up to `count` waiters of `futex`.
+    pub fn wake(&self, futex: Arc<AtomicU32>, count: u32) {
+        self.queue.borrow_mut().push((futex, count));
+    }
+}
+
+/// Waits on a futex word with `FutexWait` (Linux 6.7+) and calls `on_wake` with the current
+/// value whenever a [`FutexWaker`] bumped it.
+pub struct FutexOp {
+    word: Arc<AtomicU32>,
+    handle: FutexHandle,
+    on_wake: Box<dyn FnMut(u32) -> OpControlFlow>,
+}
+
+impl Debug for FutexOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FutexOp")
+            .field("word", &self.word)
+            .field("handle", &self.handle)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FutexOp {
+    pub fn new(on_wake: impl FnMut(u32) -> OpControlFlow + 'static) -> Self {
+        Self {
+            word: Default::default(),
+            handle: Default::default(),
+            on_wake: Box::new(on_wake),
+        }
+    }
+
+    pub fn waker(&self) -> FutexWaker {
+        FutexWaker {
+            word: self.word.clone(),
+        }
+    }
+
+    pub fn handle(&self) -> FutexHandle {
+        self.handle.clone()
+    }
+
+    fn wait(&self, value: u32) -> io_uring::squeue::Entry {
+        FutexWait::new(
+            self.word.as_ptr(),
+            value as u64,
+            FUTEX_BITSET_MATCH_ANY,
+            FUTEX2_SIZE_U32 | FUTEX2_PRIVATE,
+        )
+        .build()
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, FutexData)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<FutexData, W>,
+    ) -> Result<(), Error> {
+        let wakes = std::mem::take(&mut *self.handle.queue.borrow_mut());
+        for (futex, count) in wakes {
+            let entry = FutexWake::new(
+                futex.as_ptr(),
+                count as u64,
+                FUTEX_BITSET_MATCH_ANY,
+                FUTEX2_SIZE_U32 | FUTEX2_PRIVATE,
+            )
+            .build();
+            submitter.push(entry, FutexData::Wake(futex))?;
+        }
+        Ok(())
+    }
+}
+
+impl RingOperation for FutexOp {
+    type RingData = FutexData;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        let value = self.word.load(Ordering::Acquire);
+        submitter.push(self.wait(value), FutexData::Wait(value))?;
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        let waited = match ring_data {
+            FutexData::Wait(value) => value,
+            FutexData::Wake(_) if res < 0 => {
+                return (
+                    ControlFlow::Warn(io::Error::from_raw_os_error(-res).into()),
+                    None,
+                )
+            }
+            FutexData::Wake(_) => return (ControlFlow::Continue, None),
+        };
+
+        let value = self.word.load(Ordering::Acquire);
+        let flow = match -res {
+            // woken, or the word changed before the wait was armed
+            0 | libc::EAGAIN if value != waited => (self.on_wake)(value),
+            0 | libc::EAGAIN => {
+                trace!("spurious futex wake");
+                ControlFlow::Continue
+            }
+            errno => {
+                return (
+                    ControlFlow::Error(io::Error::from_raw_os_error(errno).into()),
+                    None,
+                )
+            }
+        };
+
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return (flow, None);
+        }
+
+        if let Err(e) = submitter.push(self.wait(value), FutexData::Wait(value)) {
+            return (ControlFlow::Error(e.into()), None);
+        }
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Nop;
+use tracing::trace;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+enum Slot {
+    Waiting(Option<Waker>),
+    Completed(Entry),
+    Taken,
+}
+
+/// Resolves with the completion entry of a request submitted through
+/// [`FutureHandle::submit`].
+pub struct Completion {
+    slot: Rc<RefCell<Slot>>,
+}
+
+impl Debug for Completion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let completed = matches!(*self.slot.borrow(), Slot::Completed(_));
+        f.debug_struct("Completion")
+            .field("completed", &completed)
+            .finish()
+    }
+}
+
+impl Future for Completion {
+    type Output = Entry;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.slot.borrow_mut();
+        match std::mem::replace(&mut *slot, Slot::Taken) {
+            Slot::Completed(entry) => Poll::Ready(entry),
+            Slot::Waiting(_) => {
+                *slot = Slot::Waiting(Some(cx.waker().clone()));
+                Poll::Pending
+            }
+            Slot::Taken => panic!("completion polled after it resolved"),
+        }
+    }
+}
+
+/// Task ids woken since the last poll, wakers may be called from any thread
+#[derive(Default)]
+struct ReadyQueue(Mutex<Vec<usize>>);
+
+struct TaskWaker {
+    task: usize,
+    ready: Arc<ReadyQueue>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.ready.0.lock().unwrap().push(self.task);
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    requests: RefCell<Vec<(io_uring::squeue::Entry, Rc<RefCell<Slot>>)>>,
+    spawned: RefCell<Vec<Task>>,
+}
+
+/// Cloneable handle tasks and other operations (on the same ring thread) use to submit
+/// requests as futures and to spawn tasks onto a [`FutureOp`].
+#[derive(Clone)]
+pub struct FutureHandle {
+    shared: Rc<Shared>,
+}
+
+impl Debug for FutureHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FutureHandle")
+            .field("queued", &self.shared.requests.borrow().len())
+            .field("spawned", &self.shared.spawned.borrow().len())
+            .finish()
+    }
+}
+
+impl FutureHandle {
+    /// Submits `entry` during the next housekeeping, the returned future resolves with its
+    /// completion entry. Only one-shot requests are supported, later completions of a
+    /// multishot request are dropped.
+    ///
+    /// # Safety
+    /// Everything `entry` points to has to stay valid until the request completed, even if
+    /// the future is dropped before.
+    pub unsafe fn submit(&self, entry: io_uring::squeue::Entry) -> Completion {
+        let slot = Rc::new(RefCell::new(Slot::Waiting(None)));
+        self.shared
+            .requests
+            .borrow_mut()
+            .push((entry, slot.clone()));
+        Completion { slot }
+    }
+
+    /// Runs `task` on the ring thread, it is polled for the first time during the next
+    /// housekeeping.
+    pub fn spawn(&self, task: impl Future<Output = ()> + 'static) {
+        self.shared.spawned.borrow_mut().push(Box::pin(task));
+    }
+}
+
+/// The request a [`Completion`] waits for, `None` for the `Nop` that makes the ring poll
+/// tasks that woke themselves.
+pub struct FutureData(Option<Rc<RefCell<Slot>>>);
+
+impl Debug for FutureData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("FutureData::Request"),
+            None => f.write_str("FutureData::Yield"),
+        }
+    }
+}
+
+/// Bridges requests to `async` code: [`FutureHandle::submit`] turns a request into a
+/// [`Completion`] future and [`FutureHandle::spawn`] runs tasks awaiting them on the ring
+/// thread, polled from housekeeping whenever they were woken.
+///
+/// Tasks should only be woken from the ring thread: the ring does not notice a waker called
+/// from another thread until its next loop iteration.
+pub struct FutureOp {
+    handle: FutureHandle,
+    tasks: Vec<Option<Task>>,
+    free: Vec<usize>,
+    ready: Arc<ReadyQueue>,
+    exit_when_idle: bool,
+}
+
+impl Debug for FutureOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FutureOp")
+            .field("handle", &self.handle)
+            .field("tasks", &(self.tasks.len() - self.free.len()))
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for FutureOp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FutureOp {
+    pub fn new() -> Self {
+        Self {
+            handle: FutureHandle {
+                shared: Default::default(),
+            },
+            tasks: Vec::new(),
+            free: Vec::new(),
+            ready: Default::default(),
+            exit_when_idle: false,
+        }
+    }
+
+    /// Exits the ring once every spawned task finished.
+    pub fn exit_when_idle(mut self) -> Self {
+        self.exit_when_idle = true;
+        self
+    }
+
+    pub fn handle(&self) -> FutureHandle {
+        self.handle.clone()
+    }
+
+    /// Number of tasks that did not finish yet.
+    pub fn tasks(&self) -> usize {
+        self.tasks.len() - self.free.len()
+    }
+
+    /// Polls new and woken tasks once, then submits the requests they made.
+    fn run<W: Fn(&mut io_uring::squeue::Entry, FutureData)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<FutureData, W>,
+    ) -> Result<(), Error> {
+        let spawned = std::mem::take(&mut *self.handle.shared.spawned.borrow_mut());
+        let mut ready = std::mem::take(&mut *self.ready.0.lock().unwrap());
+        for task in spawned {
+            let index = match self.free.pop() {
+                Some(index) => {
+                    self.tasks[index] = Some(task);
+                    index
+                }
+                None => {
+                    self.tasks.push(Some(task));
+                    self.tasks.len() - 1
+                }
+            };
+            ready.push(index);
+        }
+
+        ready.sort_unstable();
+        ready.dedup();
+        for index in ready {
+            let Some(task) = self.tasks.get_mut(index).and_then(Option::as_mut) else {
+                // woken after it finished
+                continue;
+            };
+            let waker = Waker::from(Arc::new(TaskWaker {
+                task: index,
+                ready: self.ready.clone(),
+            }));
+            if task
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_ready()
+            {
+                trace!("task {index} finished");
+                self.tasks[index] = None;
+                self.free.push(index);
+            }
+        }
+
+        let requests = std::mem::take(&mut *self.handle.shared.requests.borrow_mut());
+        for (entry, slot) in requests {
+            submitter.push(entry, FutureData(Some(slot)))?;
+        }
+
+        // tasks that woke themselves (or were woken by a task polled after them) are polled
+        // in the next iteration, make sure there is one
+        let pending = !self.ready.0.lock().unwrap().is_empty()
+            || !self.handle.shared.spawned.borrow().is_empty();
+        if pending {
+            submitter.push(Nop::new().build(), FutureData(None))?;
+        }
+        Ok(())
+    }
+}
+
+impl RingOperation for FutureOp {
+    type RingData = FutureData;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.run(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let Some(slot) = &ring_data.0 else {
+            return (ControlFlow::Continue, None);
+        };
+
+        let more = io_uring::cqueue::more(completion_entry.flags());
+        let mut state = slot.borrow_mut();
+        if let Slot::Waiting(waker) = &mut *state {
+            let waker = waker.take();
+            *state = Slot::Completed(completion_entry);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+        drop(state);
+
+        (ControlFlow::Continue, more.then_some(ring_data))
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        // tasks are not polled anymore
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        if let Err(e) = self.run(&mut submitter) {
+            return ControlFlow::Error(e);
+        }
+        match self.exit_when_idle && self.tasks() == 0 {
+            true => ControlFlow::Exit,
+            false => ControlFlow::Continue,
+        }
+    }
+}
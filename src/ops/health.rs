@@ -0,0 +1,265 @@
+use std::fmt::{Debug, Formatter, Write as _};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{AcceptMulti, Recv, Send};
+use io_uring::types::Fd;
+use tracing::{debug, trace};
+
+use crate::ops::Error;
+use crate::pool::balance::LoadBoard;
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// Largest request a probe may send
+const REQUEST_LIMIT: usize = 1024;
+
+/// A connection of a probe.
+pub struct Probe {
+    fd: OwnedFd,
+    buf: Box<[u8]>,
+    /// Bytes of `buf` received, or sent once it holds the response
+    len: usize,
+}
+
+impl Debug for Probe {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Probe")
+            .field("fd", &self.fd)
+            .field("len", &self.len)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub enum HealthData {
+    Accept,
+    Recv(Box<Probe>),
+    Send(Box<Probe>),
+}
+
+/// Cloneable and [`Send`] handle switching the readiness reported by a [`HealthOp`].
+#[derive(Debug, Clone)]
+pub struct HealthHandle {
+    ready: Arc<AtomicBool>,
+}
+
+impl HealthHandle {
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+}
+
+/// Serves a minimal HTTP health endpoint on the listening socket `fd` (TCP or unix), straight
+/// from the ring: `GET /ready` answers `200` once the service was marked ready through the
+/// [`HealthHandle`] and `503` before, every other path answers `200` as long as the ring runs.
+///
+/// The body reports the counters of the rings on `board` as JSON:
+///
+/// ```json
+/// {"status":"ok","ready":true,"rings":[{"index":0,"submitted":12,"completed":12,
+///  "backlog_spills":0,"in_flight":1,"cq_backlog":0,"reachable":true}]}
+/// ```
+///
+/// Rings outside a pool report to a board of their own, see `Ring::report_load`. Every
+/// response closes its connection.
+pub struct HealthOp {
+    fd: RawFd,
+    board: LoadBoard,
+    handle: HealthHandle,
+}
+
+impl Debug for HealthOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HealthOp")
+            .field("fd", &self.fd)
+            .field("ready", &self.handle.is_ready())
+            .finish_non_exhaustive()
+    }
+}
+
+impl HealthOp {
+    pub fn new(fd: RawFd, board: LoadBoard) -> Self {
+        Self {
+            fd,
+            board,
+            handle: HealthHandle {
+                ready: Default::default(),
+            },
+        }
+    }
+
+    pub fn handle(&self) -> HealthHandle {
+        self.handle.clone()
+    }
+
+    fn accept(&self) -> io_uring::squeue::Entry {
+        AcceptMulti::new(Fd(self.fd))
+            .flags(libc::SOCK_CLOEXEC)
+            .build()
+    }
+
+    fn recv(probe: &mut Probe) -> io_uring::squeue::Entry {
+        let free = &mut probe.buf[probe.len..];
+        Recv::new(
+            Fd(probe.fd.as_raw_fd()),
+            free.as_mut_ptr(),
+            free.len() as u32,
+        )
+        .build()
+    }
+
+    fn send(probe: &Probe) -> io_uring::squeue::Entry {
+        let left = &probe.buf[probe.len..];
+        Send::new(Fd(probe.fd.as_raw_fd()), left.as_ptr(), left.len() as u32)
+            .flags(libc::MSG_NOSIGNAL)
+            .build()
+    }
+
+    /// The status report, as JSON.
+    pub fn report(&self) -> String {
+        let ready = self.handle.is_ready();
+        let mut json = format!(
+            r#"{{"status":"{}","ready":{ready},"rings":["#,
+            if ready { "ok" } else { "starting" }
+        );
+        for (index, load) in self.board.loads().into_iter().enumerate() {
+            let counters = self.board.counters(index);
+            if index > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                r#"{{"index":{index},"submitted":{},"completed":{},"backlog_spills":{},"in_flight":{},"cq_backlog":{},"reachable":{}}}"#,
+                counters.submitted,
+                counters.completed,
+                counters.backlog_spills,
+                counters.in_flight,
+                counters.cq_backlog,
+                load.reachable,
+            );
+        }
+        json.push_str("]}");
+        json
+    }
+
+    fn respond(&self, request: &[u8]) -> Vec<u8> {
+        // only the path of the request line matters
+        let path = request.split(|&b| b == b' ').nth(1).unwrap_or_default();
+        let status = match path {
+            b"/ready" if !self.handle.is_ready() => "503 Service Unavailable",
+            _ => "200 OK",
+        };
+        let body = self.report();
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+        .into_bytes()
+    }
+}
+
+impl RingOperation for HealthOp {
+    type RingData = HealthData;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        submitter.push(self.accept(), HealthData::Accept)?;
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        let pushed = match ring_data {
+            HealthData::Accept => {
+                let more = io_uring::cqueue::more(completion_entry.flags());
+                if res >= 0 {
+                    let mut probe = Box::new(Probe {
+                        fd: unsafe { OwnedFd::from_raw_fd(res) },
+                        buf: vec![0; REQUEST_LIMIT].into_boxed_slice(),
+                        len: 0,
+                    });
+                    if let Err(e) = submitter.push(Self::recv(&mut probe), HealthData::Recv(probe))
+                    {
+                        return (ControlFlow::Warn(e.into()), more.then_some(ring_data));
+                    }
+                } else if res != -libc::ECANCELED {
+                    debug!(
+                        "health probe accept failed: {}",
+                        io::Error::from_raw_os_error(-res)
+                    );
+                }
+
+                if more {
+                    return (ControlFlow::Continue, Some(HealthData::Accept));
+                }
+                trace!("re-arm health accept");
+                submitter.push(self.accept(), HealthData::Accept)
+            }
+            HealthData::Recv(mut probe) => {
+                if res <= 0 {
+                    // the probe went away, dropping it closes the connection
+                    return (ControlFlow::Continue, None);
+                }
+                probe.len += res as usize;
+                let line_end = probe.buf[..probe.len].iter().position(|&b| b == b'\n');
+                if line_end.is_none() && probe.len < probe.buf.len() {
+                    submitter.push(Self::recv(&mut probe), HealthData::Recv(probe))
+                } else {
+                    let request = &probe.buf[..line_end.unwrap_or(probe.len)];
+                    probe.buf = self.respond(request).into_boxed_slice();
+                    probe.len = 0;
+                    submitter.push(Self::send(&probe), HealthData::Send(probe))
+                }
+            }
+            HealthData::Send(mut probe) => {
+                if res <= 0 {
+                    return (ControlFlow::Continue, None);
+                }
+                probe.len += res as usize;
+                if probe.len == probe.buf.len() {
+                    // answered, dropping the probe closes the connection
+                    return (ControlFlow::Continue, None);
+                }
+                submitter.push(Self::send(&probe), HealthData::Send(probe))
+            }
+        };
+
+        match pushed {
+            Ok(()) => (ControlFlow::Continue, None),
+            Err(e) => (ControlFlow::Warn(e.into()), None),
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        if let HealthData::Accept = ring_data {
+            if completion_entry.result() >= 0 {
+                // close probes accepted during the cancellation
+                drop(unsafe { OwnedFd::from_raw_fd(completion_entry.result()) });
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,384 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{RecvMsg, Send, SendMsg};
+use io_uring::types::Fd;
+use tracing::trace;
+
+use crate::ops::{Error, MsgBuf, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+const DEFAULT_RECORD_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TlsRecordType {
+    ChangeCipherSpec,
+    Alert,
+    Handshake,
+    ApplicationData,
+    Other(u8),
+}
+
+impl From<u8> for TlsRecordType {
+    fn from(value: u8) -> Self {
+        match value {
+            20 => Self::ChangeCipherSpec,
+            21 => Self::Alert,
+            22 => Self::Handshake,
+            23 => Self::ApplicationData,
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl From<TlsRecordType> for u8 {
+    fn from(value: TlsRecordType) -> Self {
+        match value {
+            TlsRecordType::ChangeCipherSpec => 20,
+            TlsRecordType::Alert => 21,
+            TlsRecordType::Handshake => 22,
+            TlsRecordType::ApplicationData => 23,
+            TlsRecordType::Other(other) => other,
+        }
+    }
+}
+
+/// Something queued on a [`KtlsSendOp`]. Owns its buffer until it has been sent completely.
+#[derive(Debug)]
+pub enum KtlsOutgoing {
+    /// Application data, `more` keeps the record open for following data (`MSG_MORE`)
+    Data {
+        data: Vec<u8>,
+        done: usize,
+        more: bool,
+    },
+    /// A record of another type, e.g. an alert or a post-handshake message
+    Record(Box<MsgBuf>),
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to queue data on a
+/// [`KtlsSendOp`].
+#[derive(Debug, Clone, Default)]
+pub struct KtlsSendHandle {
+    queue: Rc<RefCell<VecDeque<KtlsOutgoing>>>,
+}
+
+impl KtlsSendHandle {
+    /// Sends application data. The record is closed after `data` unless more data is queued
+    /// behind it by the time it is submitted.
+    pub fn send(&self, data: Vec<u8>) {
+        self.push_data(data, false);
+    }
+
+    /// Sends application data and keeps the record open, a later [`KtlsSendHandle::send`]
+    /// closes it. Use it to coalesce small writes into full records.
+    pub fn send_more(&self, data: Vec<u8>) {
+        self.push_data(data, true);
+    }
+
+    /// Sends a record of `record_type`. Pending application data is sent first.
+    pub fn send_record(&self, record_type: TlsRecordType, data: Vec<u8>) {
+        self.queue
+            .borrow_mut()
+            .push_back(KtlsOutgoing::Record(MsgBuf::send_tls_record(
+                record_type.into(),
+                data,
+            )));
+    }
+
+    fn push_data(&self, data: Vec<u8>, more: bool) {
+        self.queue.borrow_mut().push_back(KtlsOutgoing::Data {
+            data,
+            done: 0,
+            more,
+        });
+    }
+}
+
+/// Sends on a socket with kTLS (`TLS_TX`) enabled, after the handshake has been offloaded.
+///
+/// Sends go out one after another so partial sends never reorder the stream. Application data
+/// is sent with `MSG_MORE` while more data is queued, letting the kernel fill records instead
+/// of closing one per send. Other record types go through `sendmsg` with a
+/// `TLS_SET_RECORD_TYPE` cmsg.
+pub struct KtlsSendOp {
+    fd: RawFd,
+    handle: KtlsSendHandle,
+    in_flight: bool,
+    on_sent: Box<dyn FnMut(io::Result<usize>) -> OpControlFlow>,
+}
+
+impl Debug for KtlsSendOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KtlsSendOp")
+            .field("fd", &self.fd)
+            .field("handle", &self.handle)
+            .field("in_flight", &self.in_flight)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KtlsSendOp {
+    pub fn new(
+        fd: RawFd,
+        on_sent: impl FnMut(io::Result<usize>) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            fd,
+            handle: Default::default(),
+            in_flight: false,
+            on_sent: Box::new(on_sent),
+        }
+    }
+
+    pub fn handle(&self) -> KtlsSendHandle {
+        self.handle.clone()
+    }
+
+    fn entry(&self, outgoing: &KtlsOutgoing) -> io_uring::squeue::Entry {
+        match outgoing {
+            KtlsOutgoing::Data { data, done, more } => {
+                let more = *more
+                    || matches!(
+                        self.handle.queue.borrow().front(),
+                        Some(KtlsOutgoing::Data { .. })
+                    );
+                let flags = match more {
+                    true => libc::MSG_NOSIGNAL | libc::MSG_MORE,
+                    false => libc::MSG_NOSIGNAL,
+                };
+                Send::new(
+                    Fd(self.fd),
+                    data[*done..].as_ptr(),
+                    (data.len() - done) as u32,
+                )
+                .flags(flags)
+                .build()
+            }
+            KtlsOutgoing::Record(msg) => SendMsg::new(Fd(self.fd), msg.as_ptr())
+                .flags(libc::MSG_NOSIGNAL as u32)
+                .build(),
+        }
+    }
+
+    fn submit<W: Fn(&mut io_uring::squeue::Entry, KtlsOutgoing)>(
+        &mut self,
+        outgoing: KtlsOutgoing,
+        submitter: &mut SubmissionQueueSubmitter<KtlsOutgoing, W>,
+    ) -> Result<(), Error> {
+        submitter.push(self.entry(&outgoing), outgoing)?;
+        self.in_flight = true;
+        Ok(())
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, KtlsOutgoing)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<KtlsOutgoing, W>,
+    ) -> Result<(), Error> {
+        if self.in_flight {
+            return Ok(());
+        }
+
+        let next = self.handle.queue.borrow_mut().pop_front();
+        match next {
+            Some(outgoing) => self.submit(outgoing, submitter),
+            None => Ok(()),
+        }
+    }
+}
+
+impl RingOperation for KtlsSendOp {
+    type RingData = KtlsOutgoing;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        mut ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        self.in_flight = false;
+
+        let res = completion_entry.result();
+        let result = match &mut ring_data {
+            _ if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+            KtlsOutgoing::Data { data, done, .. } => {
+                *done += res as usize;
+                if *done < data.len() && res > 0 {
+                    trace!("partial ktls send, {} bytes left", data.len() - *done);
+                    return match self.submit(ring_data, &mut submitter) {
+                        Ok(()) => (ControlFlow::Continue, None),
+                        Err(e) => (ControlFlow::Error(e), None),
+                    };
+                }
+                match *done == data.len() {
+                    true => Ok(*done),
+                    false => Err(io::Error::from(io::ErrorKind::WriteZero)),
+                }
+            }
+            // records are sent as a whole or not at all
+            KtlsOutgoing::Record(_) => Ok(res as usize),
+        };
+
+        let flow = (self.on_sent)(result);
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return (flow, None);
+        }
+
+        if let Err(e) = self.flush(&mut submitter) {
+            return (ControlFlow::Error(e), None);
+        }
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum KtlsRecvEvent<'a> {
+    /// Decrypted application data
+    Data(&'a [u8]),
+    /// A record of another type. kTLS hands them out one at a time, the caller has to handle
+    /// them (e.g. a `close_notify` alert or a `KeyUpdate`) before more data can be received.
+    Record {
+        record_type: TlsRecordType,
+        data: &'a [u8],
+    },
+    /// The peer shut down its side of the connection
+    Closed,
+}
+
+/// Keeps a `recvmsg` in flight on a socket with kTLS (`TLS_RX`) enabled and tells application
+/// data apart from other records by their `TLS_GET_RECORD_TYPE` cmsg.
+///
+/// Without room for that cmsg the kernel fails receives of non-data records with `EIO`.
+pub struct KtlsRecvOp {
+    fd: RawFd,
+    capacity: usize,
+    on_event: Box<dyn FnMut(KtlsRecvEvent<'_>) -> OpControlFlow>,
+}
+
+impl Debug for KtlsRecvOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KtlsRecvOp")
+            .field("fd", &self.fd)
+            .field("capacity", &self.capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KtlsRecvOp {
+    pub fn new(
+        fd: RawFd,
+        on_event: impl FnMut(KtlsRecvEvent<'_>) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            fd,
+            capacity: DEFAULT_RECORD_SIZE,
+            on_event: Box::new(on_event),
+        }
+    }
+
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    fn recv(&self, msg: &mut MsgBuf) -> io_uring::squeue::Entry {
+        RecvMsg::new(Fd(self.fd), msg.as_mut_ptr()).build()
+    }
+}
+
+impl RingOperation for KtlsRecvOp {
+    type RingData = Box<MsgBuf>;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        let mut msg = MsgBuf::recv_tls(self.capacity);
+        submitter.push(self.recv(&mut msg), msg)?;
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        mut ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        if res < 0 {
+            let e = io::Error::from_raw_os_error(-res);
+            return (ControlFlow::Error(e.into()), None);
+        }
+
+        let data = &ring_data.data()[..res as usize];
+        let flow = match ring_data.tls_record_type().map(TlsRecordType::from) {
+            None if res == 0 => {
+                trace!("ktls peer closed");
+                return ((self.on_event)(KtlsRecvEvent::Closed), None);
+            }
+            None | Some(TlsRecordType::ApplicationData) => {
+                (self.on_event)(KtlsRecvEvent::Data(data))
+            }
+            Some(record_type) => (self.on_event)(KtlsRecvEvent::Record { record_type, data }),
+        };
+
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return (flow, None);
+        }
+
+        ring_data.reset();
+        let entry = self.recv(&mut ring_data);
+        if let Err(e) = submitter.push(entry, ring_data) {
+            return (ControlFlow::Error(e.into()), None);
+        }
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+}
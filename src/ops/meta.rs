@@ -0,0 +1,299 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{LinkAt, MkDirAt, RenameAt, SymlinkAt, UnlinkAt};
+use io_uring::types::Fd;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// A filesystem metadata operation. Owns its paths until its completion arrives.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MetadataRequest {
+    /// `renameat2(old_dirfd, old, new_dirfd, new, flags)`
+    Rename {
+        old_dirfd: RawFd,
+        old: CString,
+        new_dirfd: RawFd,
+        new: CString,
+        flags: u32,
+    },
+    /// `unlinkat(dirfd, path, flags)`, `libc::AT_REMOVEDIR` removes directories
+    Unlink {
+        dirfd: RawFd,
+        path: CString,
+        flags: i32,
+    },
+    /// `mkdirat(dirfd, path, mode)`
+    MkDir {
+        dirfd: RawFd,
+        path: CString,
+        mode: libc::mode_t,
+    },
+    /// `linkat(old_dirfd, old, new_dirfd, new, flags)`
+    Link {
+        old_dirfd: RawFd,
+        old: CString,
+        new_dirfd: RawFd,
+        new: CString,
+        flags: i32,
+    },
+    /// `symlinkat(target, dirfd, link)`
+    Symlink {
+        target: CString,
+        dirfd: RawFd,
+        link: CString,
+    },
+}
+
+impl MetadataRequest {
+    pub fn rename(old: impl Into<Vec<u8>>, new: impl Into<Vec<u8>>) -> io::Result<Self> {
+        Ok(Self::Rename {
+            old_dirfd: libc::AT_FDCWD,
+            old: CString::new(old)?,
+            new_dirfd: libc::AT_FDCWD,
+            new: CString::new(new)?,
+            flags: 0,
+        })
+    }
+
+    pub fn unlink(path: impl Into<Vec<u8>>) -> io::Result<Self> {
+        Ok(Self::Unlink {
+            dirfd: libc::AT_FDCWD,
+            path: CString::new(path)?,
+            flags: 0,
+        })
+    }
+
+    pub fn rmdir(path: impl Into<Vec<u8>>) -> io::Result<Self> {
+        Ok(Self::Unlink {
+            dirfd: libc::AT_FDCWD,
+            path: CString::new(path)?,
+            flags: libc::AT_REMOVEDIR,
+        })
+    }
+
+    pub fn mkdir(path: impl Into<Vec<u8>>, mode: libc::mode_t) -> io::Result<Self> {
+        Ok(Self::MkDir {
+            dirfd: libc::AT_FDCWD,
+            path: CString::new(path)?,
+            mode,
+        })
+    }
+
+    pub fn link(old: impl Into<Vec<u8>>, new: impl Into<Vec<u8>>) -> io::Result<Self> {
+        Ok(Self::Link {
+            old_dirfd: libc::AT_FDCWD,
+            old: CString::new(old)?,
+            new_dirfd: libc::AT_FDCWD,
+            new: CString::new(new)?,
+            flags: 0,
+        })
+    }
+
+    pub fn symlink(target: impl Into<Vec<u8>>, link: impl Into<Vec<u8>>) -> io::Result<Self> {
+        Ok(Self::Symlink {
+            target: CString::new(target)?,
+            dirfd: libc::AT_FDCWD,
+            link: CString::new(link)?,
+        })
+    }
+
+    fn entry(&self) -> io_uring::squeue::Entry {
+        match self {
+            MetadataRequest::Rename {
+                old_dirfd,
+                old,
+                new_dirfd,
+                new,
+                flags,
+            } => RenameAt::new(Fd(*old_dirfd), old.as_ptr(), Fd(*new_dirfd), new.as_ptr())
+                .flags(*flags)
+                .build(),
+            MetadataRequest::Unlink { dirfd, path, flags } => {
+                UnlinkAt::new(Fd(*dirfd), path.as_ptr())
+                    .flags(*flags)
+                    .build()
+            }
+            MetadataRequest::MkDir { dirfd, path, mode } => {
+                MkDirAt::new(Fd(*dirfd), path.as_ptr()).mode(*mode).build()
+            }
+            MetadataRequest::Link {
+                old_dirfd,
+                old,
+                new_dirfd,
+                new,
+                flags,
+            } => LinkAt::new(Fd(*old_dirfd), old.as_ptr(), Fd(*new_dirfd), new.as_ptr())
+                .flags(*flags)
+                .build(),
+            MetadataRequest::Symlink {
+                target,
+                dirfd,
+                link,
+            } => SymlinkAt::new(Fd(*dirfd), target.as_ptr(), link.as_ptr()).build(),
+        }
+    }
+}
+
+/// Ring data of a [`MetadataOp`], the request in flight and the rest of its chain.
+#[derive(Debug)]
+pub struct MetadataChain {
+    request: MetadataRequest,
+    rest: VecDeque<MetadataRequest>,
+}
+
+impl MetadataChain {
+    fn next(mut rest: VecDeque<MetadataRequest>) -> Option<(io_uring::squeue::Entry, Self)> {
+        let request = rest.pop_front()?;
+        Some((request.entry(), Self { request, rest }))
+    }
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to queue requests on a
+/// [`MetadataOp`].
+#[derive(Debug, Clone, Default)]
+pub struct MetadataHandle {
+    queue: Rc<RefCell<Vec<VecDeque<MetadataRequest>>>>,
+}
+
+impl MetadataHandle {
+    pub fn submit(&self, request: MetadataRequest) {
+        self.queue.borrow_mut().push(VecDeque::from([request]));
+    }
+
+    /// Submits `requests` one after another, each one starts after the previous one succeeded
+    /// (e.g. `mkdir` followed by `rename` into it). Once one fails, the rest of the chain is
+    /// reported with `ECANCELED`.
+    ///
+    /// The kernel does not fail links on errors of these opcodes, so chains are sequenced by
+    /// the op instead of with `IO_LINK`.
+    pub fn chain(&self, requests: impl IntoIterator<Item = MetadataRequest>) {
+        let chain: VecDeque<_> = requests.into_iter().collect();
+        if !chain.is_empty() {
+            self.queue.borrow_mut().push(chain);
+        }
+    }
+}
+
+type MetadataCallback = Box<dyn FnMut(&MetadataRequest, io::Result<()>) -> OpControlFlow>;
+
+/// Runs `renameat`/`unlinkat`/`mkdirat`/`linkat`/`symlinkat` through the ring.
+///
+/// Everything queued during a loop iteration is submitted as a single batch from
+/// [`RingOperation::housekeeping`]. Results are reported to `on_result` in completion order.
+pub struct MetadataOp {
+    handle: MetadataHandle,
+    on_result: MetadataCallback,
+}
+
+impl Debug for MetadataOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MetadataOp")
+            .field("queued", &self.handle.queue.borrow().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl MetadataOp {
+    pub fn new(
+        on_result: impl FnMut(&MetadataRequest, io::Result<()>) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            handle: Default::default(),
+            on_result: Box::new(on_result),
+        }
+    }
+
+    pub fn handle(&self) -> MetadataHandle {
+        self.handle.clone()
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, MetadataChain)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<MetadataChain, W>,
+    ) -> Result<(), Error> {
+        let chains = std::mem::take(&mut *self.handle.queue.borrow_mut());
+        if chains.is_empty() {
+            return Ok(());
+        }
+
+        let (entries, data): (Vec<_>, Vec<_>) =
+            chains.into_iter().filter_map(MetadataChain::next).unzip();
+        submitter.push_slice(entries.into_boxed_slice(), data.into_boxed_slice())?;
+        Ok(())
+    }
+}
+
+impl RingOperation for MetadataOp {
+    type RingData = MetadataChain;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let MetadataChain { request, rest } = ring_data;
+        let res = completion_entry.result();
+        if res < 0 {
+            let mut flow = (self.on_result)(&request, Err(io::Error::from_raw_os_error(-res)));
+            for request in rest {
+                if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+                    break;
+                }
+                let canceled = io::Error::from_raw_os_error(libc::ECANCELED);
+                flow = (self.on_result)(&request, Err(canceled));
+            }
+            return (flow, None);
+        }
+
+        let flow = (self.on_result)(&request, Ok(()));
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return (flow, None);
+        }
+
+        if let Some((entry, next)) = MetadataChain::next(rest) {
+            if let Err(e) = submitter.push(entry, next) {
+                return (ControlFlow::Error(e.into()), None);
+            }
+        }
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::io;
+
+use io_uring::squeue::PushError;
+
+use crate::ControlFlow;
+
+mod accept;
+mod advise;
+mod bench;
+mod buffer_pool;
+mod channel;
+mod close;
+mod connect;
+mod copy;
+mod eventfd;
+mod fallocate;
+mod file;
+mod fixed;
+mod fs;
+mod fsync;
+mod futex;
+#[cfg(feature = "async")]
+mod future;
+mod health;
+mod ktls;
+mod meta;
+mod monitor;
+mod msg;
+mod msg_ring;
+mod poll;
+mod raw;
+mod receiver;
+mod recv;
+mod remote;
+mod send;
+mod shutdown;
+mod signal;
+mod sockaddr;
+mod socket;
+mod splice;
+mod tee;
+mod tick;
+mod udp;
+mod uring_cmd;
+mod waitid;
+mod xattr;
+
+#[cfg(feature = "examples")]
+pub mod examples;
+
+pub use accept::AcceptOp;
+pub use advise::{Advice, AdviseHandle, AdviseOp};
+pub use bench::{NopBenchOp, NopBenchReport};
+pub use buffer_pool::{FixedBuf, RegisteredBufferPool};
+pub use channel::{channel, ChannelOp, ChannelSendError, ChannelSender, RingPayload};
+pub use close::{CloseHandle, CloseOp, CloseTarget};
+pub use connect::{ConnectOp, ConnectOutcome, ConnectRequest, ConnectStage};
+pub use copy::{CopyStage, FileCopyEvent, FileCopyOp};
+pub use eventfd::{EventFdHandle, EventFdOp};
+pub use fallocate::{FallocateHandle, FallocateMode, FallocateOp, FallocateRequest};
+pub use file::{FileChunk, FileReadEvent, FileReadOp, FileWriteEvent, FileWriteOp};
+pub use fixed::{FixedIo, FixedIoHandle, FixedIoOp};
+pub use fs::{OpenAt2Op, OpenAt2Request, StatxOp, StatxRequest};
+pub use fsync::{FsyncData, FsyncHandle, FsyncOp};
+pub use futex::{FutexData, FutexHandle, FutexOp, FutexWaker};
+#[cfg(feature = "async")]
+pub use future::{Completion, FutureData, FutureHandle, FutureOp};
+pub use health::{HealthData, HealthHandle, HealthOp, Probe};
+pub use ktls::{
+    KtlsOutgoing, KtlsRecvEvent, KtlsRecvOp, KtlsSendHandle, KtlsSendOp, TlsRecordType,
+};
+pub use meta::{MetadataChain, MetadataHandle, MetadataOp, MetadataRequest};
+pub use monitor::{monitor, MonitorOp, MonitorStats, StatsReport, StatsReporter};
+pub use msg::{MsgBuf, RecvMsgEvent, RecvMsgOp, SendMsgHandle, SendMsgOp};
+pub use msg_ring::{MsgRingHandle, MsgRingOp};
+pub use poll::{PollHandle, PollOp, PollWatch};
+pub use receiver::{ChannelReceiver, ReceiverNotifier, ReceiverOp};
+pub use recv::{RecvEvent, RecvOp};
+pub use remote::{RemoteOp, RingHandle};
+pub use send::{SendBuf, SendHandle, SendOp};
+pub use shutdown::{ShutdownData, ShutdownHandle, ShutdownOp, ShutdownRequest};
+pub use signal::SignalOp;
+pub use sockaddr::SockAddr;
+pub use socket::{CreatedSocket, SocketOp, SocketRequest, SocketTarget};
+pub use splice::{SpliceEvent, SpliceOp, SpliceStage};
+pub use tee::{TeeEvent, TeeOp, TeeStage};
+pub use tick::{Clock, TickOp};
+pub use udp::{Datagram, UdpRecvData, UdpRecvOp};
+pub use uring_cmd::{UringCmd, UringCmdHandle, UringCmdOp};
+pub use waitid::{ChildExit, ChildStatus, WaitIdHandle, WaitIdOp, WaitIdRequest, WaitTarget};
+pub use xattr::{XattrHandle, XattrKind, XattrOp, XattrRequest, XattrTarget};
+
+/// Error type shared by the built-in operations.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("unable to push to submission queue: {0}")]
+    Push(#[from] PushError),
+}
+
+pub type OpControlFlow = ControlFlow<Error, Error>;
@@ -0,0 +1,213 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use io_uring::cqueue::Entry;
+use tracing::debug;
+
+use crate::message::{MessageTarget, RingMessage};
+use crate::ops::{
+    channel, ChannelOp, ChannelSendError, ChannelSender, Error, MsgRingHandle, OpControlFlow,
+};
+use crate::stats::RingStats;
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// The stats of one ring, sent to a [`MonitorOp`].
+#[derive(Debug, Copy, Clone)]
+pub struct StatsReport {
+    /// Index of the reporting ring, e.g. in its pool
+    pub ring: usize,
+    pub stats: RingStats,
+}
+
+/// The latest stats every ring reported to a [`MonitorOp`].
+#[derive(Debug, Clone, Default)]
+pub struct MonitorStats {
+    rings: Vec<Option<RingStats>>,
+}
+
+impl MonitorStats {
+    fn update(&mut self, report: &StatsReport) {
+        if self.rings.len() <= report.ring {
+            self.rings.resize(report.ring + 1, None);
+        }
+        self.rings[report.ring] = Some(report.stats);
+    }
+
+    /// The last stats ring `index` reported, `None` before its first report.
+    pub fn ring(&self, index: usize) -> Option<&RingStats> {
+        self.rings.get(index)?.as_ref()
+    }
+
+    /// The last stats of every ring that reported, by index.
+    pub fn rings(&self) -> impl Iterator<Item = (usize, &RingStats)> + '_ {
+        self.rings
+            .iter()
+            .enumerate()
+            .filter_map(|(index, stats)| Some((index, stats.as_ref()?)))
+    }
+
+    /// Sum of the last stats of all rings, see [`RingStats::sum`].
+    pub fn total(&self) -> RingStats {
+        RingStats::sum(self.rings.iter().flatten())
+    }
+}
+
+/// Creates a [`MonitorOp`] aggregating the stats rings report through the returned
+/// [`StatsReporter`], calling `on_report` with every report and the latest stats of all rings.
+///
+/// Every report is a message of its own posted into the completion queue of the monitor ring,
+/// the rings share no locks or atomics for it. The reporter has to be
+/// [connected](StatsReporter::connect) once the monitor ring exists.
+pub fn monitor(
+    mut on_report: impl FnMut(&StatsReport, &MonitorStats) -> OpControlFlow + 'static,
+) -> (StatsReporter, MonitorOp) {
+    let stats: Rc<RefCell<MonitorStats>> = Default::default();
+    let (sender, channel) = channel({
+        let stats = stats.clone();
+        move |report: Box<StatsReport>| {
+            let mut stats = stats.borrow_mut();
+            stats.update(&report);
+            on_report(&report, &stats)
+        }
+    });
+    (StatsReporter { sender }, MonitorOp { channel, stats })
+}
+
+/// Cloneable and [`Send`] handle rings report their stats to a [`MonitorOp`] with.
+#[derive(Debug, Clone)]
+pub struct StatsReporter {
+    sender: ChannelSender<Box<StatsReport>>,
+}
+
+impl StatsReporter {
+    /// Sets the address of the [`MonitorOp`] created together with this reporter, see
+    /// `Ring::message_target`. Only the first call (on any clone) has an effect.
+    ///
+    /// # Safety
+    /// See [`ChannelSender::connect`].
+    pub unsafe fn connect(&self, target: MessageTarget) {
+        self.sender.connect(target)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.sender.is_connected()
+    }
+
+    /// Sends `stats` of ring `ring` from any thread, see [`ChannelSender::send`].
+    pub fn report(
+        &self,
+        ring: usize,
+        stats: RingStats,
+    ) -> Result<(), ChannelSendError<Box<StatsReport>>> {
+        self.sender.send(Box::new(StatsReport { ring, stats }))
+    }
+
+    /// Queues `stats` of ring `ring` on the [`MsgRingOp`](crate::ops::MsgRingOp) of the calling
+    /// ring, see [`ChannelSender::send_via`].
+    pub fn report_via(&self, ring: usize, stats: RingStats, messages: &MsgRingHandle) {
+        self.sender
+            .send_via(Box::new(StatsReport { ring, stats }), messages)
+    }
+
+    /// An observer for `Ring::observe_stats` reporting the stats of ring `ring` at most once
+    /// per `interval`, and once more after teardown. Reports before the reporter is connected
+    /// or that can not be delivered are dropped.
+    pub fn stats_observer(
+        &self,
+        ring: usize,
+        interval: Duration,
+    ) -> impl FnMut(&RingStats) + 'static {
+        let reporter = self.clone();
+        let mut last: Option<Instant> = None;
+        let mut iterations = 0;
+        move |stats| {
+            // teardown reports with the iterations of the last loop
+            let final_report = stats.iterations == iterations;
+            iterations = stats.iterations;
+            let now = Instant::now();
+            if !final_report && last.is_some_and(|last| now - last < interval) {
+                return;
+            }
+            last = Some(now);
+
+            if let Err(e) = reporter.report(ring, *stats) {
+                debug!("unable to report stats of ring {ring}: {}", e.error);
+            }
+        }
+    }
+}
+
+/// Aggregates the stats rings report through a [`StatsReporter`], see [`monitor`]. A
+/// [`RingOperation`] without requests of its own: reports arrive as messages.
+///
+/// Reports arriving while the ring tears down are dropped and leak.
+pub struct MonitorOp {
+    channel: ChannelOp<Box<StatsReport>>,
+    stats: Rc<RefCell<MonitorStats>>,
+}
+
+impl Debug for MonitorOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MonitorOp")
+            .field("reports", &self.channel.received())
+            .field("stats", &self.stats.borrow())
+            .finish()
+    }
+}
+
+impl MonitorOp {
+    /// Number of reports received.
+    pub fn reports(&self) -> u64 {
+        self.channel.received()
+    }
+
+    /// The latest stats of every ring.
+    pub fn stats(&self) -> MonitorStats {
+        self.stats.borrow().clone()
+    }
+}
+
+impl RingOperation for MonitorOp {
+    type RingData = ();
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.channel.setup(submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        self.channel
+            .on_completion(completion_entry, ring_data, submitter)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        self.channel
+            .on_teardown_completion(completion_entry, ring_data, submitter)
+    }
+
+    fn on_message<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        message: RingMessage,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        self.channel.on_message(message, submitter)
+    }
+}
@@ -0,0 +1,532 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{RecvMsg, SendMsg};
+use io_uring::types::Fd;
+use tracing::trace;
+
+use crate::ops::{Error, OpControlFlow, SockAddr};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// A `msghdr` together with everything it points to: an optional peer address, the payload
+/// and the control (ancillary data) buffer.
+///
+/// It is always boxed, so the pointers inside the `msghdr` stay valid while an sqe refers to it.
+pub struct MsgBuf {
+    hdr: libc::msghdr,
+    iov: libc::iovec,
+    name: Option<SockAddr>,
+    data: Vec<u8>,
+    control: Vec<u8>,
+    fds: Vec<OwnedFd>,
+}
+
+impl Debug for MsgBuf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MsgBuf")
+            .field("name", &self.name)
+            .field("data", &self.data.len())
+            .field("control", &self.control.len())
+            .field("fds", &self.fds)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MsgBuf {
+    fn boxed(name: Option<SockAddr>, data: Vec<u8>, control: Vec<u8>) -> Box<Self> {
+        let mut msg = Box::new(Self {
+            hdr: unsafe { std::mem::zeroed() },
+            iov: libc::iovec {
+                iov_base: std::ptr::null_mut(),
+                iov_len: 0,
+            },
+            name,
+            data,
+            control,
+            fds: Vec::new(),
+        });
+        msg.link();
+        msg
+    }
+
+    fn link(&mut self) {
+        self.iov.iov_base = self.data.as_mut_ptr() as *mut libc::c_void;
+        self.iov.iov_len = self.data.len();
+        self.hdr.msg_iov = &mut self.iov;
+        self.hdr.msg_iovlen = 1;
+        self.hdr.msg_control = match self.control.is_empty() {
+            true => std::ptr::null_mut(),
+            false => self.control.as_mut_ptr() as *mut libc::c_void,
+        };
+        self.hdr.msg_controllen = self.control.len() as _;
+        match self.name.as_mut() {
+            Some(name) => {
+                self.hdr.msg_name = name.as_mut_ptr() as *mut libc::c_void;
+                self.hdr.msg_namelen = name.len();
+            }
+            None => {
+                self.hdr.msg_name = std::ptr::null_mut();
+                self.hdr.msg_namelen = 0;
+            }
+        }
+    }
+
+    /// A message to send, `fds` are passed with `SCM_RIGHTS` and closed once the message is dropped.
+    pub fn send(data: Vec<u8>, fds: Vec<OwnedFd>) -> Box<Self> {
+        let control = match fds.is_empty() {
+            true => Vec::new(),
+            false => unsafe {
+                let len = (fds.len() * size_of::<RawFd>()) as u32;
+                let mut control = vec![0u8; libc::CMSG_SPACE(len) as usize];
+
+                let mut hdr: libc::msghdr = std::mem::zeroed();
+                hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+                hdr.msg_controllen = control.len() as _;
+
+                let cmsg = libc::CMSG_FIRSTHDR(&hdr);
+                (*cmsg).cmsg_level = libc::SOL_SOCKET;
+                (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(len) as _;
+                let data = libc::CMSG_DATA(cmsg) as *mut RawFd;
+                for (i, fd) in fds.iter().enumerate() {
+                    data.add(i).write_unaligned(fd.as_raw_fd());
+                }
+                control
+            },
+        };
+
+        let mut msg = Self::boxed(None, data, control);
+        msg.fds = fds;
+        msg
+    }
+
+    /// A message to send to `addr` on an unconnected socket.
+    pub fn send_to(addr: SockAddr, data: Vec<u8>) -> Box<Self> {
+        Self::boxed(Some(addr), data, Vec::new())
+    }
+
+    /// Room for a message of up to `capacity` bytes carrying up to `max_fds` fds.
+    pub fn recv(capacity: usize, max_fds: usize) -> Box<Self> {
+        let control = match max_fds {
+            0 => Vec::new(),
+            n => vec![0u8; unsafe { libc::CMSG_SPACE((n * size_of::<RawFd>()) as u32) } as usize],
+        };
+        Self::boxed(Some(SockAddr::empty()), vec![0; capacity], control)
+    }
+
+    /// A kTLS record of `record_type` (e.g. an alert), sent with a `TLS_SET_RECORD_TYPE` cmsg.
+    pub fn send_tls_record(record_type: u8, data: Vec<u8>) -> Box<Self> {
+        let control = unsafe {
+            let mut control = vec![0u8; libc::CMSG_SPACE(1) as usize];
+
+            let mut hdr: libc::msghdr = std::mem::zeroed();
+            hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            hdr.msg_controllen = control.len() as _;
+
+            let cmsg = libc::CMSG_FIRSTHDR(&hdr);
+            (*cmsg).cmsg_level = libc::SOL_TLS;
+            (*cmsg).cmsg_type = libc::TLS_SET_RECORD_TYPE;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(1) as _;
+            *libc::CMSG_DATA(cmsg) = record_type;
+            control
+        };
+
+        Self::boxed(None, data, control)
+    }
+
+    /// Room for a kTLS record of up to `capacity` bytes and its record type.
+    pub fn recv_tls(capacity: usize) -> Box<Self> {
+        let control = vec![0u8; unsafe { libc::CMSG_SPACE(1) } as usize];
+        Self::boxed(None, vec![0; capacity], control)
+    }
+
+    pub fn as_ptr(&self) -> *const libc::msghdr {
+        &self.hdr
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut libc::msghdr {
+        &mut self.hdr
+    }
+
+    pub fn name(&self) -> Option<&SockAddr> {
+        self.name.as_ref().filter(|_| self.hdr.msg_namelen > 0)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Takes ownership of the fds received with `SCM_RIGHTS`.
+    ///
+    /// # Safety
+    /// Must only be called once after a successful `recvmsg` into this buffer.
+    pub unsafe fn take_fds(&mut self) -> Vec<OwnedFd> {
+        let mut fds = Vec::new();
+        let mut cmsg = libc::CMSG_FIRSTHDR(&self.hdr);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                for i in 0..len / size_of::<RawFd>() {
+                    fds.push(OwnedFd::from_raw_fd(data.add(i).read_unaligned()));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&self.hdr, cmsg);
+        }
+
+        self.hdr.msg_controllen = 0;
+        fds
+    }
+
+    /// The record type of a kTLS record received into this buffer.
+    pub fn tls_record_type(&self) -> Option<u8> {
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&self.hdr);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_TLS
+                    && (*cmsg).cmsg_type == libc::TLS_GET_RECORD_TYPE
+                {
+                    return Some(*libc::CMSG_DATA(cmsg));
+                }
+                cmsg = libc::CMSG_NXTHDR(&self.hdr, cmsg);
+            }
+        }
+        None
+    }
+
+    /// Prepares a receive buffer for the next `recvmsg`.
+    pub fn reset(&mut self) {
+        if let Some(name) = self.name.as_mut() {
+            *name = SockAddr::empty();
+        }
+        self.link();
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.hdr.msg_flags & (libc::MSG_TRUNC | libc::MSG_CTRUNC) != 0
+    }
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to queue messages on a
+/// [`SendMsgOp`].
+#[derive(Debug, Clone, Default)]
+pub struct SendMsgHandle {
+    queue: Rc<RefCell<VecDeque<Box<MsgBuf>>>>,
+}
+
+impl SendMsgHandle {
+    pub fn send(&self, msg: Box<MsgBuf>) {
+        self.queue.borrow_mut().push_back(msg);
+    }
+}
+
+/// Sends queued messages on `fd`, e.g. to pass fds to another process over a unix socket.
+pub struct SendMsgOp {
+    fd: RawFd,
+    handle: SendMsgHandle,
+    on_sent: Box<dyn FnMut(io::Result<usize>) -> OpControlFlow>,
+}
+
+impl Debug for SendMsgOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SendMsgOp")
+            .field("fd", &self.fd)
+            .field("handle", &self.handle)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SendMsgOp {
+    pub fn new(
+        fd: RawFd,
+        on_sent: impl FnMut(io::Result<usize>) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            fd,
+            handle: Default::default(),
+            on_sent: Box::new(on_sent),
+        }
+    }
+
+    pub fn handle(&self) -> SendMsgHandle {
+        self.handle.clone()
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, Box<MsgBuf>)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<Box<MsgBuf>, W>,
+    ) -> Result<(), Error> {
+        while let Some(msg) = self.handle.queue.borrow_mut().pop_front() {
+            let entry = SendMsg::new(Fd(self.fd), msg.as_ptr()).build();
+            submitter.push(entry, msg)?;
+        }
+        Ok(())
+    }
+}
+
+impl RingOperation for SendMsgOp {
+    type RingData = Box<MsgBuf>;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let result = match completion_entry.result() {
+            res if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+            res => Ok(res as usize),
+        };
+        ((self.on_sent)(result), None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RecvMsgEvent<'a> {
+    Message {
+        name: Option<&'a SockAddr>,
+        data: &'a [u8],
+        fds: Vec<OwnedFd>,
+        truncated: bool,
+    },
+    /// The peer shut down its side of the connection
+    Closed,
+}
+
+/// Keeps a `recvmsg` on `fd` in flight and hands every message (and the fds that came with it)
+/// to `on_message`. Received fds are created with `O_CLOEXEC`.
+pub struct RecvMsgOp {
+    fd: RawFd,
+    capacity: usize,
+    max_fds: usize,
+    on_message: Box<dyn FnMut(RecvMsgEvent<'_>) -> OpControlFlow>,
+}
+
+impl Debug for RecvMsgOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RecvMsgOp")
+            .field("fd", &self.fd)
+            .field("capacity", &self.capacity)
+            .field("max_fds", &self.max_fds)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RecvMsgOp {
+    pub fn new(
+        fd: RawFd,
+        capacity: usize,
+        max_fds: usize,
+        on_message: impl FnMut(RecvMsgEvent<'_>) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            fd,
+            capacity,
+            max_fds,
+            on_message: Box::new(on_message),
+        }
+    }
+
+    fn recv(&self, msg: &mut MsgBuf) -> io_uring::squeue::Entry {
+        RecvMsg::new(Fd(self.fd), msg.as_mut_ptr())
+            .flags(libc::MSG_CMSG_CLOEXEC as u32)
+            .build()
+    }
+}
+
+impl RingOperation for RecvMsgOp {
+    type RingData = Box<MsgBuf>;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        let mut msg = MsgBuf::recv(self.capacity, self.max_fds);
+        submitter.push(self.recv(&mut msg), msg)?;
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        mut ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let flow = match completion_entry.result() {
+            res if res < 0 => ControlFlow::Error(io::Error::from_raw_os_error(-res).into()),
+            0 if ring_data.hdr.msg_controllen == 0 => {
+                trace!("recvmsg peer closed");
+                return ((self.on_message)(RecvMsgEvent::Closed), None);
+            }
+            res => {
+                let fds = unsafe { ring_data.take_fds() };
+                let truncated = ring_data.is_truncated();
+                (self.on_message)(RecvMsgEvent::Message {
+                    name: ring_data.name(),
+                    data: &ring_data.data()[..res as usize],
+                    fds,
+                    truncated,
+                })
+            }
+        };
+
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return (flow, None);
+        }
+
+        ring_data.reset();
+        let entry = self.recv(&mut ring_data);
+        if let Err(e) = submitter.push(entry, ring_data) {
+            return (ControlFlow::Error(e.into()), None);
+        }
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        mut ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        if completion_entry.result() >= 0 {
+            // close fds that arrived during the cancellation
+            drop(unsafe { ring_data.take_fds() });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+// the ring is generated inside the crate, its unused parts are not exempt from lints
+#[allow(dead_code, unused_imports)]
+mod tests {
+    use std::fs::File;
+    use std::io::{Read as _, Write as _};
+
+    use super::*;
+
+    crate::ring! { msg_ring -> crate::ops::Error, send: super::SendMsgOp, recv: super::RecvMsgOp }
+
+    fn socketpair() -> (OwnedFd, OwnedFd) {
+        let mut fds = [0; 2];
+        let res = unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC,
+                0,
+                fds.as_mut_ptr(),
+            )
+        };
+        assert_eq!(res, 0);
+        unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) }
+    }
+
+    #[test]
+    fn passes_fds_over_a_socketpair() {
+        let (sender, receiver) = socketpair();
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+        let mut pipe_read = unsafe { File::from_raw_fd(fds[0]) };
+        let pipe_write = unsafe { OwnedFd::from_raw_fd(fds[1]) };
+
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let send = SendMsgOp::new(sender.as_raw_fd(), {
+            let sent = sent.clone();
+            let fd = sender.as_raw_fd();
+            move |result| {
+                let mut sent = sent.borrow_mut();
+                sent.push(result.unwrap());
+                if sent.len() == 2 {
+                    // the receiver sees the end of the connection after both messages
+                    assert_eq!(unsafe { libc::shutdown(fd, libc::SHUT_WR) }, 0);
+                }
+                ControlFlow::Continue
+            }
+        });
+        send.handle()
+            .send(MsgBuf::send(b"fds".to_vec(), vec![pipe_write]));
+        send.handle()
+            .send(MsgBuf::send(b"no fds".to_vec(), Vec::new()));
+
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let recv = RecvMsgOp::new(receiver.as_raw_fd(), 64, 2, {
+            let received = received.clone();
+            move |event| match event {
+                RecvMsgEvent::Message {
+                    data,
+                    fds,
+                    truncated,
+                    ..
+                } => {
+                    assert!(!truncated);
+                    received.borrow_mut().push((data.to_vec(), fds));
+                    ControlFlow::Continue
+                }
+                RecvMsgEvent::Closed => ControlFlow::Exit,
+            }
+        });
+
+        let mut ring = msg_ring::Ring::builder()
+            .send(send)
+            .recv(recv)
+            .build()
+            .unwrap();
+        ring.run().unwrap();
+        assert_eq!(*sent.borrow(), [3, 6]);
+
+        let mut received = received.take();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].0, b"fds");
+        assert_eq!(received[1].0, b"no fds");
+        assert!(received[1].1.is_empty());
+
+        // the received fd is the write end of the pipe, created with `O_CLOEXEC`
+        let [fd] = <[OwnedFd; 1]>::try_from(std::mem::take(&mut received[0].1)).unwrap();
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) };
+        assert_eq!(flags & libc::FD_CLOEXEC, libc::FD_CLOEXEC);
+        File::from(fd).write_all(b"through the pipe").unwrap();
+        let mut through = String::new();
+        pipe_read.read_to_string(&mut through).unwrap();
+        assert_eq!(through, "through the pipe");
+    }
+}
@@ -0,0 +1,168 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use tracing::trace;
+
+use crate::message::{MessageTarget, OutgoingMessage};
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// Cloneable handle other operations (on the same ring thread) use to queue messages on a
+/// [`MsgRingOp`].
+#[derive(Debug, Clone, Default)]
+pub struct MsgRingHandle {
+    queue: Rc<RefCell<Vec<OutgoingMessage>>>,
+}
+
+impl MsgRingHandle {
+    /// See [`OutgoingMessage::data`].
+    pub fn send(&self, target: MessageTarget, payload: u64, value: i32) {
+        self.queue
+            .borrow_mut()
+            .push(OutgoingMessage::data(target, payload, value));
+    }
+
+    /// See [`OutgoingMessage::fd`].
+    pub fn send_fd(
+        &self,
+        target: MessageTarget,
+        payload: u64,
+        fixed_slot: u32,
+        dest_slot: Option<u32>,
+    ) {
+        self.queue
+            .borrow_mut()
+            .push(OutgoingMessage::fd(target, payload, fixed_slot, dest_slot));
+    }
+
+    pub fn submit(&self, message: OutgoingMessage) {
+        self.queue.borrow_mut().push(message);
+    }
+}
+
+/// Sends messages into the completion queues of other rings with `IORING_OP_MSG_RING`.
+///
+/// Messages queued during a loop iteration are submitted as one batch from
+/// [`RingOperation::housekeeping`]. The receiving ring delivers them to
+/// [`RingOperation::on_message`] of the addressed operation. Messages the kernel could not
+/// deliver, e.g. because the target ring is gone, are reported as warnings unless an
+/// `on_failure` callback is set.
+pub struct MsgRingOp {
+    handle: MsgRingHandle,
+    sent: u64,
+    on_failure: Option<Box<dyn FnMut(OutgoingMessage, io::Error) -> OpControlFlow>>,
+}
+
+impl Debug for MsgRingOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MsgRingOp")
+            .field("queued", &self.handle.queue.borrow().len())
+            .field("sent", &self.sent)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for MsgRingOp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MsgRingOp {
+    pub fn new() -> Self {
+        Self {
+            handle: Default::default(),
+            sent: 0,
+            on_failure: None,
+        }
+    }
+
+    pub fn on_failure(
+        mut self,
+        on_failure: impl FnMut(OutgoingMessage, io::Error) -> OpControlFlow + 'static,
+    ) -> Self {
+        self.on_failure = Some(Box::new(on_failure));
+        self
+    }
+
+    pub fn handle(&self) -> MsgRingHandle {
+        self.handle.clone()
+    }
+
+    /// Number of messages the kernel delivered.
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, OutgoingMessage)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<OutgoingMessage, W>,
+    ) -> Result<(), Error> {
+        let messages = std::mem::take(&mut *self.handle.queue.borrow_mut());
+        if messages.is_empty() {
+            return Ok(());
+        }
+
+        trace!("sending {} messages", messages.len());
+        let entries = messages.iter().map(OutgoingMessage::build).collect();
+        submitter.push_slice(entries, messages.into_boxed_slice())?;
+        Ok(())
+    }
+}
+
+impl RingOperation for MsgRingOp {
+    type RingData = OutgoingMessage;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        if res >= 0 {
+            self.sent += 1;
+            return (ControlFlow::Continue, None);
+        }
+
+        let e = io::Error::from_raw_os_error(-res);
+        let flow = match self.on_failure.as_mut() {
+            Some(on_failure) => on_failure(ring_data, e),
+            None => ControlFlow::Warn(e.into()),
+        };
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
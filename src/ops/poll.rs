@@ -0,0 +1,176 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{AsyncCancel2, PollAdd};
+use io_uring::types::{CancelBuilder, Fd};
+use tracing::trace;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PollWatch {
+    pub fd: RawFd,
+    /// `poll(2)` events, e.g. `libc::POLLIN`
+    pub events: u32,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum PollCommand {
+    Watch(PollWatch),
+    Unwatch(RawFd),
+}
+
+/// Cloneable handle to add or remove watched fds while the ring is running.
+#[derive(Debug, Clone, Default)]
+pub struct PollHandle {
+    queue: Rc<RefCell<VecDeque<PollCommand>>>,
+}
+
+impl PollHandle {
+    pub fn watch(&self, fd: RawFd, events: u32) {
+        self.queue
+            .borrow_mut()
+            .push_back(PollCommand::Watch(PollWatch { fd, events }));
+    }
+
+    /// Stops watching `fd`. This cancels every request on `fd` in this ring, not only the poll.
+    pub fn unwatch(&self, fd: RawFd) {
+        self.queue.borrow_mut().push_back(PollCommand::Unwatch(fd));
+    }
+}
+
+/// Watches arbitrary fds (timerfd, inotify, pidfd, fds of other libraries, ...) with multishot
+/// `PollAdd`s and calls `on_ready` with the fd and the returned events.
+pub struct PollOp {
+    handle: PollHandle,
+    watched: HashMap<RawFd, u32>,
+    on_ready: Box<dyn FnMut(RawFd, u32) -> OpControlFlow>,
+}
+
+impl Debug for PollOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PollOp")
+            .field("handle", &self.handle)
+            .field("watched", &self.watched)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PollOp {
+    pub fn new(on_ready: impl FnMut(RawFd, u32) -> OpControlFlow + 'static) -> Self {
+        Self {
+            handle: Default::default(),
+            watched: Default::default(),
+            on_ready: Box::new(on_ready),
+        }
+    }
+
+    pub fn watch(self, fd: RawFd, events: u32) -> Self {
+        self.handle.watch(fd, events);
+        self
+    }
+
+    pub fn handle(&self) -> PollHandle {
+        self.handle.clone()
+    }
+
+    fn poll(watch: PollWatch) -> io_uring::squeue::Entry {
+        PollAdd::new(Fd(watch.fd), watch.events).multi(true).build()
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, PollWatch)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<PollWatch, W>,
+    ) -> Result<(), Error> {
+        while let Some(command) = self.handle.queue.borrow_mut().pop_front() {
+            match command {
+                PollCommand::Watch(watch) => {
+                    submitter.push(Self::poll(watch), watch)?;
+                    self.watched.insert(watch.fd, watch.events);
+                }
+                PollCommand::Unwatch(fd) => {
+                    if self.watched.remove(&fd).is_some() {
+                        let cancel = AsyncCancel2::new(CancelBuilder::fd(Fd(fd)).all()).build();
+                        submitter.push_unmanaged(cancel)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RingOperation for PollOp {
+    type RingData = PollWatch;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let more = io_uring::cqueue::more(completion_entry.flags());
+        let flow = match completion_entry.result() {
+            res if res < 0 => match -res {
+                libc::ECANCELED => ControlFlow::Continue,
+                errno => {
+                    self.watched.remove(&ring_data.fd);
+                    ControlFlow::Warn(io::Error::from_raw_os_error(errno).into())
+                }
+            },
+            events => (self.on_ready)(ring_data.fd, events as u32),
+        };
+
+        if more {
+            return (flow, Some(ring_data));
+        }
+
+        // the kernel ended the multishot poll, re-arm it if the fd is still watched
+        if self.watched.get(&ring_data.fd) == Some(&ring_data.events)
+            && !matches!(flow, ControlFlow::Exit | ControlFlow::Error(_))
+        {
+            trace!("re-arming poll on {}", ring_data.fd);
+            if let Err(e) = submitter.push(Self::poll(ring_data), ring_data) {
+                return (ControlFlow::Error(e.into()), None);
+            }
+        }
+
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use io_uring::opcode::Nop;
+use io_uring::squeue::Entry;
+
+/// Field layout of `struct io_uring_sqe`, for opcodes io-uring 0.6 has no builder for.
+///
+/// Union members are named after the opcode specific meaning they are used with.
+#[repr(C)]
+pub(super) struct RawSqe {
+    pub opcode: u8,
+    pub flags: u8,
+    pub ioprio: u16,
+    pub fd: i32,
+    pub addr2: u64,
+    pub addr: u64,
+    pub len: u32,
+    pub op_flags: u32,
+    pub user_data: u64,
+    pub buf_index: u16,
+    pub personality: u16,
+    pub file_index: u32,
+    pub addr3: u64,
+    _pad: u64,
+}
+
+const _: () = assert!(std::mem::size_of::<RawSqe>() == std::mem::size_of::<Entry>());
+
+impl RawSqe {
+    pub fn new(opcode: u8) -> Self {
+        // SAFETY: `Entry` is a `#[repr(C)]` wrapper around `struct io_uring_sqe`, a Nop is all
+        // zeroes apart from its opcode
+        let mut sqe: Self = unsafe { std::mem::transmute(Nop::new().build()) };
+        sqe.opcode = opcode;
+        sqe
+    }
+
+    pub fn build(self) -> Entry {
+        unsafe { std::mem::transmute(self) }
+    }
+}
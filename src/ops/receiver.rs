@@ -0,0 +1,275 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Read;
+use io_uring::types::Fd;
+use tracing::{debug, trace};
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// The receiving half of a channel a [`ReceiverOp`] drains.
+///
+/// Implemented for [`std::sync::mpsc::Receiver`] and, with the `crossbeam-channel` feature, for
+/// `crossbeam_channel::Receiver`.
+pub trait ChannelReceiver {
+    type Item;
+
+    /// Takes the next item without blocking, `Ok(None)` if the channel is empty and `Err(())`
+    /// once it is empty and all senders are gone.
+    #[allow(clippy::result_unit_err)]
+    fn try_receive(&self) -> Result<Option<Self::Item>, ()>;
+}
+
+impl<T> ChannelReceiver for mpsc::Receiver<T> {
+    type Item = T;
+
+    #[inline]
+    fn try_receive(&self) -> Result<Option<T>, ()> {
+        match self.try_recv() {
+            Ok(item) => Ok(Some(item)),
+            Err(mpsc::TryRecvError::Empty) => Ok(None),
+            Err(mpsc::TryRecvError::Disconnected) => Err(()),
+        }
+    }
+}
+
+#[cfg(feature = "crossbeam-channel")]
+impl<T> ChannelReceiver for crossbeam_channel::Receiver<T> {
+    type Item = T;
+
+    #[inline]
+    fn try_receive(&self) -> Result<Option<T>, ()> {
+        match self.try_recv() {
+            Ok(item) => Ok(Some(item)),
+            Err(crossbeam_channel::TryRecvError::Empty) => Ok(None),
+            Err(crossbeam_channel::TryRecvError::Disconnected) => Err(()),
+        }
+    }
+}
+
+struct Signal {
+    pending: AtomicBool,
+    eventfd: OwnedFd,
+}
+
+/// Cloneable and [`Send`] handle the senders of a channel bound to a [`ReceiverOp`] wake the
+/// ring with after sending.
+#[derive(Clone)]
+pub struct ReceiverNotifier {
+    signal: Arc<Signal>,
+}
+
+impl Debug for ReceiverNotifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReceiverNotifier")
+            .field("eventfd", &self.signal.eventfd)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ReceiverNotifier {
+    /// Wakes the ring to drain the channel, call it after every send. Wakeups are coalesced,
+    /// a ring that is about to drain the channel is not woken again.
+    pub fn notify(&self) -> io::Result<()> {
+        if self.signal.pending.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        let value = 1u64;
+        let res = unsafe {
+            libc::write(
+                self.signal.eventfd.as_raw_fd(),
+                &value as *const u64 as *const libc::c_void,
+                size_of::<u64>(),
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for ReceiverNotifier {
+    fn as_raw_fd(&self) -> RawFd {
+        self.signal.eventfd.as_raw_fd()
+    }
+}
+
+/// Binds the receiving half of an existing channel to the ring and calls `on_item` for every
+/// item sent on it, the way to get work from other threads into the ring without giving up
+/// the channel the application already uses (see [`RemoteOp`](crate::ops::RemoteOp) for a
+/// channel of its own).
+///
+/// Senders wake the ring with the [`ReceiverNotifier`] from [`ReceiverOp::notifier`] after
+/// sending, which signals an eventfd the op keeps a read in flight on:
+///
+/// ```no_run
+/// # use rummelplatz::ops::ReceiverOp;
+/// # use rummelplatz::ControlFlow;
+/// let (sender, receiver) = std::sync::mpsc::channel::<String>();
+/// let op = ReceiverOp::new(receiver, |line| {
+///     println!("{line}");
+///     ControlFlow::Continue
+/// })?;
+/// let notifier = op.notifier();
+///
+/// std::thread::spawn(move || {
+///     sender.send("hello ring".to_string()).unwrap();
+///     notifier.notify().unwrap();
+/// });
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// Once all senders are gone and the channel is drained the op stops reading the eventfd and
+/// stays idle.
+pub struct ReceiverOp<R: ChannelReceiver> {
+    receiver: R,
+    notifier: ReceiverNotifier,
+    value: Box<u64>,
+    received: u64,
+    disconnected: bool,
+    on_item: Box<dyn FnMut(R::Item) -> OpControlFlow>,
+}
+
+impl<R: ChannelReceiver> Debug for ReceiverOp<R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReceiverOp")
+            .field("eventfd", &self.notifier.signal.eventfd)
+            .field("received", &self.received)
+            .field("disconnected", &self.disconnected)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R: ChannelReceiver> ReceiverOp<R> {
+    pub fn new(
+        receiver: R,
+        on_item: impl FnMut(R::Item) -> OpControlFlow + 'static,
+    ) -> io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            receiver,
+            notifier: ReceiverNotifier {
+                signal: Arc::new(Signal {
+                    pending: AtomicBool::new(false),
+                    eventfd: unsafe { OwnedFd::from_raw_fd(fd) },
+                }),
+            },
+            value: Box::new(0),
+            received: 0,
+            disconnected: false,
+            on_item: Box::new(on_item),
+        })
+    }
+
+    pub fn notifier(&self) -> ReceiverNotifier {
+        self.notifier.clone()
+    }
+
+    /// Number of items received.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Whether all senders are gone and the channel was drained.
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+
+    fn read(&mut self) -> io_uring::squeue::Entry {
+        Read::new(
+            Fd(self.notifier.signal.eventfd.as_raw_fd()),
+            self.value.as_mut() as *mut u64 as *mut u8,
+            size_of::<u64>() as u32,
+        )
+        .build()
+    }
+
+    fn drain(&mut self) -> OpControlFlow {
+        self.notifier.signal.pending.store(false, Ordering::Release);
+
+        loop {
+            match self.receiver.try_receive() {
+                Ok(Some(item)) => {
+                    self.received += 1;
+                    match (self.on_item)(item) {
+                        ControlFlow::Continue => {}
+                        flow => return flow,
+                    }
+                }
+                Ok(None) => return ControlFlow::Continue,
+                Err(()) => {
+                    debug!("all senders of the channel are gone");
+                    self.disconnected = true;
+                    return ControlFlow::Continue;
+                }
+            }
+        }
+    }
+}
+
+impl<R: ChannelReceiver> RingOperation for ReceiverOp<R> {
+    type RingData = ();
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        // items sent before the ring started signalled the eventfd already
+        submitter.push(self.read(), ())?;
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        _ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        if res < 0 {
+            return (
+                ControlFlow::Error(io::Error::from_raw_os_error(-res).into()),
+                None,
+            );
+        }
+
+        trace!("woken by eventfd");
+        let flow = self.drain();
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return (flow, None);
+        }
+
+        if !self.disconnected {
+            if let Err(e) = submitter.push(self.read(), ()) {
+                return (ControlFlow::Error(e.into()), None);
+            }
+        }
+
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Recv;
+use io_uring::types::Fd;
+use tracing::trace;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+const DEFAULT_CAPACITY: usize = 16 * 1024;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RecvEvent<'a> {
+    Data(&'a [u8]),
+    /// The peer shut down its side of the connection
+    Closed,
+}
+
+/// Keeps a `Recv` on the connected socket `fd` in flight and hands everything received to
+/// `on_recv`, until the peer closes the connection or `on_recv` exits.
+pub struct RecvOp {
+    fd: RawFd,
+    capacity: usize,
+    on_recv: Box<dyn FnMut(RecvEvent<'_>) -> OpControlFlow>,
+}
+
+impl Debug for RecvOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RecvOp")
+            .field("fd", &self.fd)
+            .field("capacity", &self.capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RecvOp {
+    pub fn new(fd: RawFd, on_recv: impl FnMut(RecvEvent<'_>) -> OpControlFlow + 'static) -> Self {
+        Self {
+            fd,
+            capacity: DEFAULT_CAPACITY,
+            on_recv: Box::new(on_recv),
+        }
+    }
+
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> io_uring::squeue::Entry {
+        Recv::new(Fd(self.fd), buf.as_mut_ptr(), buf.len() as u32).build()
+    }
+}
+
+impl RingOperation for RecvOp {
+    type RingData = Box<[u8]>;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        let mut buf = vec![0; self.capacity].into_boxed_slice();
+        submitter.push(self.recv(&mut buf), buf)?;
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        mut ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let flow = match completion_entry.result() {
+            res if res < 0 => {
+                let e = io::Error::from_raw_os_error(-res);
+                return (ControlFlow::Error(e.into()), None);
+            }
+            0 => {
+                trace!("recv peer closed");
+                return ((self.on_recv)(RecvEvent::Closed), None);
+            }
+            res => (self.on_recv)(RecvEvent::Data(&ring_data[..res as usize])),
+        };
+
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return (flow, None);
+        }
+
+        let entry = self.recv(&mut ring_data);
+        if let Err(e) = submitter.push(entry, ring_data) {
+            return (ControlFlow::Error(e.into()), None);
+        }
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+}
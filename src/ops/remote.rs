@@ -0,0 +1,237 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SendError, Sender, TryRecvError};
+use std::sync::{Arc, OnceLock};
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Read;
+use io_uring::types::Fd;
+use tracing::{debug, trace};
+
+use crate::message::{MessageTarget, OutgoingMessage, RingMessage};
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+struct Wakeup {
+    pending: AtomicBool,
+    eventfd: OwnedFd,
+    target: OnceLock<MessageTarget>,
+    msg_ring: AtomicBool,
+}
+
+impl Wakeup {
+    fn wake(&self) -> io::Result<()> {
+        if self.pending.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        if let Some(target) = self.target.get() {
+            if self.msg_ring.load(Ordering::Relaxed) {
+                match OutgoingMessage::data(*target, 0, 0).send_blocking() {
+                    Ok(()) => return Ok(()),
+                    Err(e) => {
+                        debug!("MsgRing unavailable, falling back to eventfd: {e}");
+                        self.msg_ring.store(false, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+
+        let value = 1u64;
+        let res = unsafe {
+            libc::write(
+                self.eventfd.as_raw_fd(),
+                &value as *const u64 as *const libc::c_void,
+                size_of::<u64>(),
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Cloneable and [`Send`] handle to enqueue work items for a running ring from any thread,
+/// created by [`RemoteOp::handle`].
+pub struct RingHandle<T> {
+    sender: Sender<T>,
+    wakeup: Arc<Wakeup>,
+}
+
+impl<T> Clone for RingHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            wakeup: self.wakeup.clone(),
+        }
+    }
+}
+
+impl<T> Debug for RingHandle<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingHandle")
+            .field("target", &self.wakeup.target.get())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> RingHandle<T> {
+    /// Enqueues `item` and wakes the ring. Fails with the item if the ring is gone.
+    pub fn send(&self, item: T) -> Result<(), SendError<T>> {
+        self.sender.send(item)?;
+        if let Err(e) = self.wakeup.wake() {
+            debug!("unable to wake ring: {e}");
+        }
+        Ok(())
+    }
+
+    /// Wakes the ring with `IORING_OP_MSG_RING` instead of the eventfd from now on, `target`
+    /// must address the [`RemoteOp`] this handle belongs to, see `Ring::message_target`.
+    ///
+    /// Falls back to the eventfd if the kernel does not support `IORING_OP_MSG_RING`.
+    /// Only the first call has an effect.
+    pub fn connect(&self, target: MessageTarget) {
+        let _ = self.wakeup.target.set(target);
+    }
+}
+
+/// Receiving end of [`RingHandle`]s, calls `on_item` for every work item sent to the ring.
+///
+/// Senders push into a lock-free queue and wake the ring through an eventfd read kept in
+/// flight, or with a message once the handle is [connected](RingHandle::connect). Wakeups are
+/// coalesced, a ring that is already about to drain the queue is not woken again.
+pub struct RemoteOp<T> {
+    receiver: Receiver<T>,
+    handle: RingHandle<T>,
+    value: Box<u64>,
+    on_item: Box<dyn FnMut(T) -> OpControlFlow>,
+}
+
+impl<T> Debug for RemoteOp<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RemoteOp")
+            .field("eventfd", &self.handle.wakeup.eventfd)
+            .field("target", &self.handle.wakeup.target.get())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> RemoteOp<T> {
+    pub fn new(on_item: impl FnMut(T) -> OpControlFlow + 'static) -> io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        Ok(Self {
+            receiver,
+            handle: RingHandle {
+                sender,
+                wakeup: Arc::new(Wakeup {
+                    pending: AtomicBool::new(false),
+                    eventfd: unsafe { OwnedFd::from_raw_fd(fd) },
+                    target: OnceLock::new(),
+                    msg_ring: AtomicBool::new(true),
+                }),
+            },
+            value: Box::new(0),
+            on_item: Box::new(on_item),
+        })
+    }
+
+    pub fn handle(&self) -> RingHandle<T> {
+        self.handle.clone()
+    }
+
+    fn read(&mut self) -> io_uring::squeue::Entry {
+        Read::new(
+            Fd(self.handle.wakeup.eventfd.as_raw_fd()),
+            self.value.as_mut() as *mut u64 as *mut u8,
+            size_of::<u64>() as u32,
+        )
+        .build()
+    }
+
+    fn drain(&mut self) -> OpControlFlow {
+        self.handle.wakeup.pending.store(false, Ordering::Release);
+
+        loop {
+            match self.receiver.try_recv() {
+                Ok(item) => match (self.on_item)(item) {
+                    ControlFlow::Continue => {}
+                    flow => return flow,
+                },
+                // the op holds a sender itself, the queue never disconnects
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => {
+                    return ControlFlow::Continue
+                }
+            }
+        }
+    }
+}
+
+impl<T> RingOperation for RemoteOp<T> {
+    type RingData = ();
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        submitter.push(self.read(), ())?;
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        _ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        if res < 0 {
+            return (
+                ControlFlow::Error(io::Error::from_raw_os_error(-res).into()),
+                None,
+            );
+        }
+
+        trace!("woken by eventfd");
+        let flow = self.drain();
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return (flow, None);
+        }
+
+        if let Err(e) = submitter.push(self.read(), ()) {
+            return (ControlFlow::Error(e.into()), None);
+        }
+
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn on_message<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _message: RingMessage,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        trace!("woken by message");
+        self.drain()
+    }
+}
@@ -0,0 +1,189 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Send;
+use io_uring::types::Fd;
+use tracing::trace;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// A buffer queued on a [`SendOp`], `done` bytes of it have been sent.
+#[derive(Debug)]
+pub struct SendBuf {
+    data: Vec<u8>,
+    done: usize,
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to queue data on a
+/// [`SendOp`].
+#[derive(Debug, Clone, Default)]
+pub struct SendHandle {
+    queue: Rc<RefCell<VecDeque<Vec<u8>>>>,
+}
+
+impl SendHandle {
+    pub fn send(&self, data: Vec<u8>) {
+        self.queue.borrow_mut().push_back(data);
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.borrow().len()
+    }
+}
+
+/// Sends queued buffers on the connected socket `fd`, one after another so partial sends never
+/// reorder the stream. `on_sent` is called once per completely sent buffer.
+pub struct SendOp {
+    fd: RawFd,
+    handle: SendHandle,
+    in_flight: bool,
+    on_sent: Box<dyn FnMut(io::Result<usize>) -> OpControlFlow>,
+}
+
+impl Debug for SendOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SendOp")
+            .field("fd", &self.fd)
+            .field("handle", &self.handle)
+            .field("in_flight", &self.in_flight)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SendOp {
+    pub fn new(
+        fd: RawFd,
+        on_sent: impl FnMut(io::Result<usize>) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            fd,
+            handle: Default::default(),
+            in_flight: false,
+            on_sent: Box::new(on_sent),
+        }
+    }
+
+    pub fn handle(&self) -> SendHandle {
+        self.handle.clone()
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Nothing is queued or in flight.
+    pub fn is_idle(&self) -> bool {
+        !self.in_flight && self.handle.queue.borrow().is_empty()
+    }
+
+    fn submit<W: Fn(&mut io_uring::squeue::Entry, SendBuf)>(
+        &mut self,
+        buf: SendBuf,
+        submitter: &mut SubmissionQueueSubmitter<SendBuf, W>,
+    ) -> Result<(), Error> {
+        let entry = Send::new(
+            Fd(self.fd),
+            buf.data[buf.done..].as_ptr(),
+            (buf.data.len() - buf.done) as u32,
+        )
+        .flags(libc::MSG_NOSIGNAL)
+        .build();
+
+        submitter.push(entry, buf)?;
+        self.in_flight = true;
+        Ok(())
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, SendBuf)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<SendBuf, W>,
+    ) -> Result<(), Error> {
+        if self.in_flight {
+            return Ok(());
+        }
+
+        let next = self.handle.queue.borrow_mut().pop_front();
+        match next {
+            Some(data) => self.submit(SendBuf { data, done: 0 }, submitter),
+            None => Ok(()),
+        }
+    }
+}
+
+impl RingOperation for SendOp {
+    type RingData = SendBuf;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        mut ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        self.in_flight = false;
+
+        let result = match completion_entry.result() {
+            res if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+            0 => Err(io::Error::from(io::ErrorKind::WriteZero)),
+            res => {
+                ring_data.done += res as usize;
+                if ring_data.done < ring_data.data.len() {
+                    trace!(
+                        "partial send, {} bytes left",
+                        ring_data.data.len() - ring_data.done
+                    );
+                    return match self.submit(ring_data, &mut submitter) {
+                        Ok(()) => (ControlFlow::Continue, None),
+                        Err(e) => (ControlFlow::Error(e), None),
+                    };
+                }
+                Ok(ring_data.done)
+            }
+        };
+
+        let flow = (self.on_sent)(result);
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return (flow, None);
+        }
+
+        if let Err(e) = self.flush(&mut submitter) {
+            return (ControlFlow::Error(e), None);
+        }
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{Send, Shutdown};
+use io_uring::squeue::Flags;
+use io_uring::types::Fd;
+use tracing::trace;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+#[derive(Debug)]
+pub struct ShutdownRequest {
+    fd: RawFd,
+    how: i32,
+    data: Vec<u8>,
+    sent: usize,
+}
+
+#[derive(Debug)]
+pub enum ShutdownData {
+    FinalSend(ShutdownRequest),
+    Shutdown(RawFd),
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to queue shutdowns on a
+/// [`ShutdownOp`].
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    queue: Rc<RefCell<VecDeque<ShutdownRequest>>>,
+}
+
+impl ShutdownHandle {
+    /// Sends `data` (if not empty) and shuts down the write side of `fd` once it is out.
+    pub fn finish(&self, fd: RawFd, data: Vec<u8>) {
+        self.shutdown(fd, libc::SHUT_WR, data);
+    }
+
+    pub fn shutdown(&self, fd: RawFd, how: i32, data: Vec<u8>) {
+        self.queue.borrow_mut().push_back(ShutdownRequest {
+            fd,
+            how,
+            data,
+            sent: 0,
+        });
+    }
+}
+
+/// Terminates connections: the final send is linked to a `Shutdown`, so the FIN goes out right
+/// after the last byte. The send uses `MSG_WAITALL`, so only a short send breaks the link, in
+/// that case the rest is sent before a new shutdown is attempted.
+pub struct ShutdownOp {
+    handle: ShutdownHandle,
+    superseded: HashSet<RawFd>,
+    on_shutdown: Box<dyn FnMut(RawFd, io::Result<()>) -> OpControlFlow>,
+}
+
+impl Debug for ShutdownOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShutdownOp")
+            .field("handle", &self.handle)
+            .field("superseded", &self.superseded)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ShutdownOp {
+    pub fn new(on_shutdown: impl FnMut(RawFd, io::Result<()>) -> OpControlFlow + 'static) -> Self {
+        Self {
+            handle: Default::default(),
+            superseded: Default::default(),
+            on_shutdown: Box::new(on_shutdown),
+        }
+    }
+
+    pub fn handle(&self) -> ShutdownHandle {
+        self.handle.clone()
+    }
+
+    fn push<W: Fn(&mut io_uring::squeue::Entry, ShutdownData)>(
+        request: ShutdownRequest,
+        submitter: &mut SubmissionQueueSubmitter<ShutdownData, W>,
+    ) -> Result<(), Error> {
+        let fd = request.fd;
+        let shutdown = Shutdown::new(Fd(fd), request.how).build();
+
+        if request.sent >= request.data.len() {
+            submitter.push(shutdown, ShutdownData::Shutdown(fd))?;
+            return Ok(());
+        }
+
+        let remaining = &request.data[request.sent..];
+        let send = Send::new(Fd(fd), remaining.as_ptr(), remaining.len() as u32)
+            .flags(libc::MSG_NOSIGNAL | libc::MSG_WAITALL)
+            .build()
+            .flags(Flags::IO_LINK);
+        submitter.push_multiple(
+            [send, shutdown],
+            [ShutdownData::FinalSend(request), ShutdownData::Shutdown(fd)],
+        )?;
+        Ok(())
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, ShutdownData)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<ShutdownData, W>,
+    ) -> Result<(), Error> {
+        while let Some(request) = self.handle.queue.borrow_mut().pop_front() {
+            Self::push(request, submitter)?;
+        }
+        Ok(())
+    }
+
+    fn on_send<W: Fn(&mut io_uring::squeue::Entry, ShutdownData)>(
+        &mut self,
+        res: i32,
+        mut request: ShutdownRequest,
+        submitter: &mut SubmissionQueueSubmitter<ShutdownData, W>,
+    ) -> Result<OpControlFlow, Error> {
+        if res < 0 {
+            // the linked shutdown is canceled, report the send error instead
+            self.superseded.insert(request.fd);
+            let e = io::Error::from_raw_os_error(-res);
+            return Ok((self.on_shutdown)(request.fd, Err(e)));
+        }
+
+        request.sent += res as usize;
+        if request.sent < request.data.len() {
+            trace!("short final send on {}", request.fd);
+            self.superseded.insert(request.fd);
+            Self::push(request, submitter)?;
+        }
+
+        Ok(ControlFlow::Continue)
+    }
+
+    fn on_shutdown(&mut self, res: i32, fd: RawFd) -> OpControlFlow {
+        if self.superseded.remove(&fd) {
+            return ControlFlow::Continue;
+        }
+
+        (self.on_shutdown)(
+            fd,
+            match res {
+                res if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+                _ => Ok(()),
+            },
+        )
+    }
+}
+
+impl RingOperation for ShutdownOp {
+    type RingData = ShutdownData;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        let flow = match ring_data {
+            ShutdownData::FinalSend(request) => self.on_send(res, request, &mut submitter),
+            ShutdownData::Shutdown(fd) => Ok(self.on_shutdown(res, fd)),
+        };
+
+        match flow {
+            Ok(flow) => (flow, None),
+            Err(e) => (ControlFlow::Error(e), None),
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::mem::{size_of, MaybeUninit};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Read;
+use io_uring::types::Fd;
+use tracing::{debug, trace};
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// Reads signals from a `signalfd` and hands them to `on_signal`.
+///
+/// The signals are blocked for the calling thread when the operation is created,
+/// so create it on the thread that is going to run the ring (ideally before spawning any other
+/// thread, they inherit the signal mask). The previous mask is restored on drop.
+pub struct SignalOp {
+    fd: OwnedFd,
+    previous_mask: libc::sigset_t,
+    siginfo: Box<libc::signalfd_siginfo>,
+    on_signal: Box<dyn FnMut(i32) -> OpControlFlow>,
+}
+
+impl Debug for SignalOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SignalOp")
+            .field("fd", &self.fd)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SignalOp {
+    /// Exits the ring on any of `signals`.
+    pub fn new(signals: &[i32]) -> io::Result<Self> {
+        Self::with_handler(signals, |signal| {
+            debug!("received signal {signal}, exiting ring");
+            ControlFlow::Exit
+        })
+    }
+
+    pub fn with_handler(
+        signals: &[i32],
+        on_signal: impl FnMut(i32) -> OpControlFlow + 'static,
+    ) -> io::Result<Self> {
+        unsafe {
+            let mut mask = MaybeUninit::<libc::sigset_t>::uninit();
+            libc::sigemptyset(mask.as_mut_ptr());
+            let mut mask = mask.assume_init();
+            for signal in signals {
+                if libc::sigaddset(&mut mask, *signal) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            let mut previous_mask = MaybeUninit::<libc::sigset_t>::uninit();
+            let res = libc::pthread_sigmask(libc::SIG_BLOCK, &mask, previous_mask.as_mut_ptr());
+            if res != 0 {
+                return Err(io::Error::from_raw_os_error(res));
+            }
+            let previous_mask = previous_mask.assume_init();
+
+            let fd = libc::signalfd(-1, &mask, libc::SFD_CLOEXEC);
+            if fd < 0 {
+                let e = io::Error::last_os_error();
+                libc::pthread_sigmask(libc::SIG_SETMASK, &previous_mask, std::ptr::null_mut());
+                return Err(e);
+            }
+
+            Ok(Self {
+                fd: OwnedFd::from_raw_fd(fd),
+                previous_mask,
+                siginfo: Box::new(MaybeUninit::zeroed().assume_init()),
+                on_signal: Box::new(on_signal),
+            })
+        }
+    }
+
+    fn read(&mut self) -> io_uring::squeue::Entry {
+        Read::new(
+            Fd(self.fd.as_raw_fd()),
+            self.siginfo.as_mut() as *mut libc::signalfd_siginfo as *mut u8,
+            size_of::<libc::signalfd_siginfo>() as u32,
+        )
+        .build()
+    }
+}
+
+impl Drop for SignalOp {
+    fn drop(&mut self) {
+        unsafe {
+            libc::pthread_sigmask(libc::SIG_SETMASK, &self.previous_mask, std::ptr::null_mut());
+        }
+    }
+}
+
+impl AsRawFd for SignalOp {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl RingOperation for SignalOp {
+    type RingData = ();
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        submitter.push(self.read(), ())?;
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        _ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let flow = match completion_entry.result() {
+            res if res == size_of::<libc::signalfd_siginfo>() as i32 => {
+                let signal = self.siginfo.ssi_signo as i32;
+                trace!("got signal {signal}");
+                (self.on_signal)(signal)
+            }
+            // interrupted, the read is submitted again
+            res if res == -libc::EINTR || res == -libc::EAGAIN => {
+                ControlFlow::Warn(io::Error::from_raw_os_error(-res).into())
+            }
+            // would fail again right away, re-arming it busy-loops the ring
+            res if res < 0 => ControlFlow::Error(io::Error::from_raw_os_error(-res).into()),
+            res => ControlFlow::Warn(
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("short read on signalfd: {res} bytes"),
+                )
+                .into(),
+            ),
+        };
+
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return (flow, None);
+        }
+
+        if let Err(e) = submitter.push(self.read(), ()) {
+            return (ControlFlow::Error(e.into()), None);
+        }
+
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+}
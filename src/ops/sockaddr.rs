@@ -0,0 +1,125 @@
+use std::fmt::{Debug, Formatter};
+use std::mem::size_of;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+/// Kernel representation of a socket address (`sockaddr_storage` and its length).
+#[derive(Clone)]
+pub struct SockAddr {
+    storage: libc::sockaddr_storage,
+    len: libc::socklen_t,
+}
+
+impl Debug for SockAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.to_std() {
+            Some(addr) => write!(f, "SockAddr({addr})"),
+            None => write!(f, "SockAddr(family: {})", self.storage.ss_family),
+        }
+    }
+}
+
+impl SockAddr {
+    pub fn new(addr: &SocketAddr) -> Self {
+        let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+        let len = match addr {
+            SocketAddr::V4(addr) => {
+                let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = addr.port().to_be();
+                sin.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+                size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(addr) => {
+                let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = addr.port().to_be();
+                sin6.sin6_flowinfo = addr.flowinfo();
+                sin6.sin6_addr.s6_addr = addr.ip().octets();
+                sin6.sin6_scope_id = addr.scope_id();
+                size_of::<libc::sockaddr_in6>()
+            }
+        };
+
+        Self {
+            storage,
+            len: len as libc::socklen_t,
+        }
+    }
+
+    pub fn to_std(&self) -> Option<SocketAddr> {
+        match self.storage.ss_family as i32 {
+            libc::AF_INET => {
+                let sin = unsafe { &*(&self.storage as *const _ as *const libc::sockaddr_in) };
+                Some(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(sin.sin_addr.s_addr.to_ne_bytes()),
+                    u16::from_be(sin.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(&self.storage as *const _ as *const libc::sockaddr_in6) };
+                Some(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(sin6.sin6_addr.s6_addr),
+                    u16::from_be(sin6.sin6_port),
+                    sin6.sin6_flowinfo,
+                    sin6.sin6_scope_id,
+                )))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn family(&self) -> i32 {
+        self.storage.ss_family as i32
+    }
+
+    pub fn as_ptr(&self) -> *const libc::sockaddr {
+        &self.storage as *const _ as *const libc::sockaddr
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut libc::sockaddr {
+        &mut self.storage as *mut _ as *mut libc::sockaddr
+    }
+
+    pub fn len(&self) -> libc::socklen_t {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn len_mut(&mut self) -> &mut libc::socklen_t {
+        &mut self.len
+    }
+
+    /// Empty storage to be filled by the kernel, e.g. by `accept` or `recvmsg`.
+    pub fn empty() -> Self {
+        Self {
+            storage: unsafe { std::mem::zeroed() },
+            len: size_of::<libc::sockaddr_storage>() as libc::socklen_t,
+        }
+    }
+}
+
+impl From<&[u8]> for SockAddr {
+    /// Copies a raw `sockaddr` as written by the kernel, e.g. into a provided buffer.
+    fn from(value: &[u8]) -> Self {
+        let mut addr = Self::empty();
+        let len = value.len().min(size_of::<libc::sockaddr_storage>());
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                value.as_ptr(),
+                &mut addr.storage as *mut _ as *mut u8,
+                len,
+            );
+        }
+        addr.len = len as libc::socklen_t;
+        addr
+    }
+}
+
+impl From<SocketAddr> for SockAddr {
+    fn from(value: SocketAddr) -> Self {
+        Self::new(&value)
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd};
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Socket;
+use io_uring::types::DestinationSlot;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// Where a socket created by a [`SocketOp`] ends up.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SocketTarget {
+    /// A regular fd
+    Fd,
+    /// A fixed-file slot chosen by the kernel
+    FixedAuto,
+    /// A specific fixed-file slot
+    Fixed(u32),
+}
+
+#[derive(Debug)]
+pub enum CreatedSocket {
+    Fd(OwnedFd),
+    Fixed(u32),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SocketRequest {
+    pub token: u64,
+    pub domain: i32,
+    pub socket_type: i32,
+    pub protocol: i32,
+    pub target: SocketTarget,
+}
+
+type SocketCallback = Box<dyn FnMut(SocketRequest, io::Result<CreatedSocket>) -> OpControlFlow>;
+
+/// Creates sockets through the ring (`IORING_OP_SOCKET`, Linux 5.19+), optionally straight into
+/// a fixed-file slot. `token` is handed back to identify the request.
+pub struct SocketOp {
+    pending: VecDeque<SocketRequest>,
+    on_result: SocketCallback,
+}
+
+impl Debug for SocketOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SocketOp")
+            .field("pending", &self.pending)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SocketOp {
+    pub fn new(
+        on_result: impl FnMut(SocketRequest, io::Result<CreatedSocket>) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            pending: Default::default(),
+            on_result: Box::new(on_result),
+        }
+    }
+
+    /// Queues a socket creation, `SOCK_CLOEXEC` is added for regular fds.
+    pub fn socket(
+        &mut self,
+        token: u64,
+        domain: i32,
+        socket_type: i32,
+        protocol: i32,
+        target: SocketTarget,
+    ) -> io::Result<()> {
+        if let SocketTarget::Fixed(slot) = target {
+            DestinationSlot::try_from_slot_target(slot)
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        }
+
+        self.pending.push_back(SocketRequest {
+            token,
+            domain,
+            socket_type,
+            protocol,
+            target,
+        });
+        Ok(())
+    }
+
+    /// Builds the `Socket` sqe for `request`, e.g. to submit it from another operation.
+    pub fn entry(request: &SocketRequest) -> io_uring::squeue::Entry {
+        match request.target {
+            SocketTarget::Fd => Socket::new(
+                request.domain,
+                request.socket_type | libc::SOCK_CLOEXEC,
+                request.protocol,
+            )
+            .build(),
+            SocketTarget::FixedAuto => {
+                Socket::new(request.domain, request.socket_type, request.protocol)
+                    .file_index(Some(DestinationSlot::auto_target()))
+                    .build()
+            }
+            SocketTarget::Fixed(slot) => {
+                Socket::new(request.domain, request.socket_type, request.protocol)
+                    .file_index(DestinationSlot::try_from_slot_target(slot).ok())
+                    .build()
+            }
+        }
+    }
+
+    /// Interprets the result of a `Socket` sqe built by [`SocketOp::entry`].
+    pub fn result(request: &SocketRequest, res: i32) -> io::Result<CreatedSocket> {
+        if res < 0 {
+            return Err(io::Error::from_raw_os_error(-res));
+        }
+
+        Ok(match request.target {
+            SocketTarget::Fd => CreatedSocket::Fd(unsafe { OwnedFd::from_raw_fd(res) }),
+            SocketTarget::FixedAuto => CreatedSocket::Fixed(res as u32),
+            SocketTarget::Fixed(slot) => CreatedSocket::Fixed(slot),
+        })
+    }
+}
+
+impl RingOperation for SocketOp {
+    type RingData = SocketRequest;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        while let Some(request) = self.pending.pop_front() {
+            submitter.push(Self::entry(&request), request)?;
+        }
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let result = Self::result(&ring_data, completion_entry.result());
+        ((self.on_result)(ring_data, result), None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        // closes a regular fd created during the cancellation
+        drop(Self::result(&ring_data, completion_entry.result()));
+        Ok(())
+    }
+}
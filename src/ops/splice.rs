@@ -0,0 +1,342 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Splice;
+use io_uring::squeue::Flags;
+use io_uring::types::Fd;
+use tracing::trace;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SpliceEvent {
+    /// `n` bytes have been written to the output fd
+    Transferred(u32),
+    /// The input fd reached EOF and everything has been written to the output fd
+    Eof { total: u64 },
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SpliceStage {
+    /// input fd -> pipe
+    Fill,
+    /// pipe -> output fd
+    Drain,
+}
+
+/// Pumps data from `fd_in` to `fd_out` through an internal pipe pair.
+///
+/// The fill and drain `Splice`s are submitted as a linked pair. A short fill breaks the link,
+/// in that case (and on partial writes to `fd_out`) the pipe is drained before the next pair
+/// is submitted. Both fds are borrowed and must stay open while
+/// the ring runs.
+pub struct SpliceOp {
+    fd_in: RawFd,
+    fd_out: RawFd,
+    pipe_read: OwnedFd,
+    pipe_write: Option<OwnedFd>,
+    chunk_size: u32,
+    in_pipe: u32,
+    total: u64,
+    eof: bool,
+    on_event: Box<dyn FnMut(SpliceEvent) -> OpControlFlow>,
+}
+
+impl Debug for SpliceOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SpliceOp")
+            .field("fd_in", &self.fd_in)
+            .field("fd_out", &self.fd_out)
+            .field("pipe_read", &self.pipe_read)
+            .field("pipe_write", &self.pipe_write)
+            .field("chunk_size", &self.chunk_size)
+            .field("in_pipe", &self.in_pipe)
+            .field("total", &self.total)
+            .field("eof", &self.eof)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SpliceOp {
+    pub fn new(
+        fd_in: RawFd,
+        fd_out: RawFd,
+        on_event: impl FnMut(SpliceEvent) -> OpControlFlow + 'static,
+    ) -> io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            fd_in,
+            fd_out,
+            pipe_read: unsafe { OwnedFd::from_raw_fd(fds[0]) },
+            pipe_write: Some(unsafe { OwnedFd::from_raw_fd(fds[1]) }),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            in_pipe: 0,
+            total: 0,
+            eof: false,
+            on_event: Box::new(on_event),
+        })
+    }
+
+    pub fn chunk_size(mut self, chunk_size: u32) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn push_pair<W: Fn(&mut io_uring::squeue::Entry, SpliceStage)>(
+        &self,
+        submitter: &mut SubmissionQueueSubmitter<SpliceStage, W>,
+    ) -> Result<(), Error> {
+        let Some(pipe_write) = self.pipe_write.as_ref() else {
+            return Err(io::Error::from(io::ErrorKind::BrokenPipe).into());
+        };
+
+        let fill = Splice::new(
+            Fd(self.fd_in),
+            -1,
+            Fd(pipe_write.as_raw_fd()),
+            -1,
+            self.chunk_size,
+        )
+        .build()
+        .flags(Flags::IO_LINK);
+        let drain = self.drain(self.chunk_size);
+
+        submitter.push_multiple([fill, drain], [SpliceStage::Fill, SpliceStage::Drain])?;
+        Ok(())
+    }
+
+    fn drain(&self, len: u32) -> io_uring::squeue::Entry {
+        Splice::new(Fd(self.pipe_read.as_raw_fd()), -1, Fd(self.fd_out), -1, len).build()
+    }
+
+    fn on_fill(&mut self, res: i32) -> Result<OpControlFlow, Error> {
+        match res {
+            0 => {
+                trace!("splice input reached EOF");
+                self.eof = true;
+
+                // nothing is going to be written to the pipe anymore, a drain on an empty pipe
+                // completes with 0 instead of waiting forever
+                self.pipe_write = None;
+            }
+            res if res < 0 => return Err(io::Error::from_raw_os_error(-res).into()),
+            res => self.in_pipe += res as u32,
+        }
+
+        Ok(ControlFlow::Continue)
+    }
+
+    fn on_drain<W: Fn(&mut io_uring::squeue::Entry, SpliceStage)>(
+        &mut self,
+        res: i32,
+        submitter: &mut SubmissionQueueSubmitter<SpliceStage, W>,
+    ) -> Result<OpControlFlow, Error> {
+        match -res {
+            // a short fill breaks the link, drain what made it into the pipe
+            libc::ECANCELED if self.in_pipe > 0 => {
+                submitter.push(self.drain(self.in_pipe), SpliceStage::Drain)?;
+                return Ok(ControlFlow::Continue);
+            }
+            libc::ECANCELED if self.eof => {
+                return Ok((self.on_event)(SpliceEvent::Eof { total: self.total }));
+            }
+            // the fill failed and already reported its error
+            libc::ECANCELED => return Ok(ControlFlow::Continue),
+            errno if errno > 0 => return Err(io::Error::from_raw_os_error(errno).into()),
+            _ => {}
+        }
+
+        if res == 0 && self.in_pipe > 0 {
+            return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+        }
+
+        let n = res as u32;
+        self.in_pipe -= n;
+        self.total += n as u64;
+
+        let flow = if n > 0 {
+            (self.on_event)(SpliceEvent::Transferred(n))
+        } else {
+            ControlFlow::Continue
+        };
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return Ok(flow);
+        }
+
+        if self.in_pipe > 0 {
+            submitter.push(self.drain(self.in_pipe), SpliceStage::Drain)?;
+        } else if self.eof {
+            return Ok(match flow {
+                ControlFlow::Continue => (self.on_event)(SpliceEvent::Eof { total: self.total }),
+                flow => flow,
+            });
+        } else {
+            self.push_pair(submitter)?;
+        }
+
+        Ok(flow)
+    }
+}
+
+impl RingOperation for SpliceOp {
+    type RingData = SpliceStage;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.push_pair(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        let flow = match ring_data {
+            SpliceStage::Fill => self.on_fill(res),
+            SpliceStage::Drain => self.on_drain(res, &mut submitter),
+        };
+
+        match flow {
+            Ok(flow) => (flow, None),
+            Err(e) => (ControlFlow::Error(e), None),
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+// the ring is generated inside the crate, its unused parts are not exempt from lints
+#[allow(dead_code, unused_imports)]
+mod tests {
+    use std::cell::RefCell;
+    use std::fs::File;
+    use std::io::{Read as _, Write as _};
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use super::*;
+
+    crate::ring! { splice_ring -> crate::ops::Error, splice: super::SpliceOp }
+
+    /// Read and write end of a new pipe.
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    #[test]
+    fn pumps_pipe_to_pipe_until_eof() {
+        let data: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+        let (in_read, mut in_write) = pipe();
+        let (mut out_read, out_write) = pipe();
+        // a pipe of a single page takes a fraction of every drain, the rest is drained again
+        assert!(unsafe { libc::fcntl(out_write.as_raw_fd(), libc::F_SETPIPE_SZ, 4096) } > 0);
+
+        // pauses between the writes leave the fills short of the chunk size
+        let writer = std::thread::spawn({
+            let data = data.clone();
+            move || {
+                for piece in data.chunks(10_000) {
+                    in_write.write_all(piece).unwrap();
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+            }
+        });
+        let reader = std::thread::spawn(move || {
+            let mut out = Vec::new();
+            out_read.read_to_end(&mut out).unwrap();
+            out
+        });
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let op = SpliceOp::new(in_read.as_raw_fd(), out_write.as_raw_fd(), {
+            let events = events.clone();
+            move |event| {
+                events.borrow_mut().push(event);
+                match event {
+                    SpliceEvent::Transferred(_) => ControlFlow::Continue,
+                    SpliceEvent::Eof { .. } => ControlFlow::Exit,
+                }
+            }
+        })
+        .unwrap()
+        .chunk_size(16 * 1024);
+        let mut ring = splice_ring::Ring::builder().splice(op).build().unwrap();
+        ring.run().unwrap();
+        assert_eq!(ring.operations().splice.total(), data.len() as u64);
+        drop(ring);
+
+        writer.join().unwrap();
+        drop(out_write);
+        assert_eq!(reader.join().unwrap(), data);
+
+        let events = events.take();
+        let (last, transferred) = events.split_last().unwrap();
+        assert_eq!(
+            *last,
+            SpliceEvent::Eof {
+                total: data.len() as u64
+            }
+        );
+        let sizes: Vec<u32> = transferred
+            .iter()
+            .map(|event| match event {
+                SpliceEvent::Transferred(n) => *n,
+                event => panic!("unexpected {event:?}"),
+            })
+            .collect();
+        assert_eq!(sizes.iter().sum::<u32>() as usize, data.len());
+        assert!(sizes.iter().any(|&n| n < 16 * 1024), "{sizes:?}");
+    }
+
+    #[test]
+    fn empty_input_ends_right_away() {
+        let (in_read, in_write) = pipe();
+        let (_out_read, out_write) = pipe();
+        drop(in_write);
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let op = SpliceOp::new(in_read.as_raw_fd(), out_write.as_raw_fd(), {
+            let events = events.clone();
+            move |event| {
+                events.borrow_mut().push(event);
+                ControlFlow::Exit
+            }
+        })
+        .unwrap();
+        let mut ring = splice_ring::Ring::builder().splice(op).build().unwrap();
+        ring.run().unwrap();
+
+        assert_eq!(*events.borrow(), [SpliceEvent::Eof { total: 0 }]);
+    }
+}
@@ -0,0 +1,188 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{Splice, Tee};
+use io_uring::types::Fd;
+use tracing::trace;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TeeEvent {
+    /// `n` bytes have been duplicated to the tee pipe and forwarded to the output fd
+    Forwarded(u32),
+    /// The input pipe has been closed and drained
+    Eof { total: u64 },
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TeeStage {
+    /// input pipe -> tee pipe, without consuming
+    Tee,
+    /// input pipe -> output fd, consuming what has been duplicated
+    Forward,
+}
+
+/// Duplicates the data of the input pipe `fd_in` into the pipe `tee_out` and forwards it to
+/// `fd_out`, e.g. to capture proxied traffic for logging.
+///
+/// Every `Tee` is followed by a `Splice` that consumes exactly the duplicated bytes, a partial
+/// tee or forward never duplicates or drops data. `fd_out` may be any fd, the forward is not
+/// linked to the tee since its length is only known once the tee completed. All fds are
+/// borrowed and must stay open while the ring runs.
+pub struct TeeOp {
+    fd_in: RawFd,
+    tee_out: RawFd,
+    fd_out: RawFd,
+    chunk_size: u32,
+    pending: u32,
+    total: u64,
+    on_event: Box<dyn FnMut(TeeEvent) -> OpControlFlow>,
+}
+
+impl Debug for TeeOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TeeOp")
+            .field("fd_in", &self.fd_in)
+            .field("tee_out", &self.tee_out)
+            .field("fd_out", &self.fd_out)
+            .field("chunk_size", &self.chunk_size)
+            .field("pending", &self.pending)
+            .field("total", &self.total)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TeeOp {
+    pub fn new(
+        fd_in: RawFd,
+        tee_out: RawFd,
+        fd_out: RawFd,
+        on_event: impl FnMut(TeeEvent) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            fd_in,
+            tee_out,
+            fd_out,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            pending: 0,
+            total: 0,
+            on_event: Box::new(on_event),
+        }
+    }
+
+    pub fn chunk_size(mut self, chunk_size: u32) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    fn tee(&self) -> io_uring::squeue::Entry {
+        Tee::new(Fd(self.fd_in), Fd(self.tee_out), self.chunk_size).build()
+    }
+
+    fn forward(&self) -> io_uring::squeue::Entry {
+        Splice::new(Fd(self.fd_in), -1, Fd(self.fd_out), -1, self.pending).build()
+    }
+
+    fn on_tee<W: Fn(&mut io_uring::squeue::Entry, TeeStage)>(
+        &mut self,
+        res: i32,
+        submitter: &mut SubmissionQueueSubmitter<TeeStage, W>,
+    ) -> Result<OpControlFlow, Error> {
+        match res {
+            0 => {
+                trace!("tee input reached EOF");
+                Ok((self.on_event)(TeeEvent::Eof { total: self.total }))
+            }
+            res if res < 0 => Err(io::Error::from_raw_os_error(-res).into()),
+            res => {
+                self.pending = res as u32;
+                submitter.push(self.forward(), TeeStage::Forward)?;
+                Ok(ControlFlow::Continue)
+            }
+        }
+    }
+
+    fn on_forward<W: Fn(&mut io_uring::squeue::Entry, TeeStage)>(
+        &mut self,
+        res: i32,
+        submitter: &mut SubmissionQueueSubmitter<TeeStage, W>,
+    ) -> Result<OpControlFlow, Error> {
+        if res < 0 {
+            return Err(io::Error::from_raw_os_error(-res).into());
+        }
+        if res == 0 {
+            return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+        }
+
+        let n = res as u32;
+        self.pending -= n;
+        self.total += n as u64;
+
+        let flow = (self.on_event)(TeeEvent::Forwarded(n));
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return Ok(flow);
+        }
+
+        // the rest of the duplicated bytes has to be consumed before the next tee, otherwise
+        // they would be duplicated twice
+        if self.pending > 0 {
+            submitter.push(self.forward(), TeeStage::Forward)?;
+        } else {
+            submitter.push(self.tee(), TeeStage::Tee)?;
+        }
+        Ok(flow)
+    }
+}
+
+impl RingOperation for TeeOp {
+    type RingData = TeeStage;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        submitter.push(self.tee(), TeeStage::Tee)?;
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        let flow = match ring_data {
+            TeeStage::Tee => self.on_tee(res, &mut submitter),
+            TeeStage::Forward => self.on_forward(res, &mut submitter),
+        };
+
+        match flow {
+            Ok(flow) => (flow, None),
+            Err(e) => (ControlFlow::Error(e), None),
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+}
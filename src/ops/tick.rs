@@ -0,0 +1,188 @@
+use std::fmt::{Debug, Formatter};
+use std::time::Duration;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Timeout;
+use io_uring::types::{TimeoutFlags, Timespec};
+use tracing::{debug, trace};
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+// not exposed by io_uring::types::TimeoutFlags (yet)
+const IORING_TIMEOUT_MULTISHOT: u32 = 1 << 6;
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum Clock {
+    #[default]
+    Monotonic,
+    Boottime,
+    Realtime,
+}
+
+/// Calls `on_tick` every `interval`.
+///
+/// Relative ticks use a multishot `Timeout` (Linux 6.4+) and silently fall back to re-arming
+/// a single-shot timeout if the kernel rejects it. Absolute ticks are always re-armed with the
+/// next deadline, so they do not drift.
+pub struct TickOp {
+    interval: Duration,
+    deadline: Option<Duration>,
+    clock: Clock,
+    multishot: bool,
+    timespec: Box<Timespec>,
+    ticks: u64,
+    on_tick: Box<dyn FnMut(u64) -> OpControlFlow>,
+}
+
+impl Debug for TickOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TickOp")
+            .field("interval", &self.interval)
+            .field("deadline", &self.deadline)
+            .field("clock", &self.clock)
+            .field("multishot", &self.multishot)
+            .field("ticks", &self.ticks)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TickOp {
+    pub fn new(interval: Duration, on_tick: impl FnMut(u64) -> OpControlFlow + 'static) -> Self {
+        Self {
+            interval,
+            deadline: None,
+            clock: Clock::Monotonic,
+            multishot: true,
+            timespec: Box::new(interval.into()),
+            ticks: 0,
+            on_tick: Box::new(on_tick),
+        }
+    }
+
+    /// Fire the first tick at `start` (measured on the selected clock) and every `interval` after.
+    pub fn absolute(mut self, start: Duration) -> Self {
+        self.deadline = Some(start);
+        self
+    }
+
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn multishot(mut self, multishot: bool) -> Self {
+        self.multishot = multishot;
+        self
+    }
+
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    fn timeout(&mut self) -> io_uring::squeue::Entry {
+        let mut flags = match self.clock {
+            Clock::Monotonic => TimeoutFlags::empty(),
+            Clock::Boottime => TimeoutFlags::BOOTTIME,
+            Clock::Realtime => TimeoutFlags::REALTIME,
+        };
+
+        match self.deadline {
+            Some(deadline) => {
+                *self.timespec = deadline.into();
+                flags |= TimeoutFlags::ABS;
+            }
+            None => {
+                *self.timespec = self.interval.into();
+                if self.multishot {
+                    flags |= unsafe { TimeoutFlags::from_bits_unchecked(IORING_TIMEOUT_MULTISHOT) };
+                }
+            }
+        }
+
+        Timeout::new(self.timespec.as_ref()).flags(flags).build()
+    }
+
+    fn arm<W: Fn(&mut io_uring::squeue::Entry, ())>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<(), W>,
+    ) -> Result<(), Error> {
+        let entry = self.timeout();
+        submitter.push(entry, ())?;
+        Ok(())
+    }
+}
+
+impl RingOperation for TickOp {
+    type RingData = ();
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.arm(submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        _ring_data: Self::RingData,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let more = io_uring::cqueue::more(completion_entry.flags());
+
+        match -completion_entry.result() {
+            0 | libc::ETIME => {}
+            libc::ECANCELED => {
+                trace!("tick canceled");
+                return (ControlFlow::Continue, None);
+            }
+            libc::EINVAL if self.multishot && self.deadline.is_none() && self.ticks == 0 => {
+                debug!("multishot timeouts are not supported, falling back to re-arming");
+                self.multishot = false;
+                return match self.arm(submitter) {
+                    Ok(()) => (ControlFlow::Continue, None),
+                    Err(e) => (ControlFlow::Error(e), None),
+                };
+            }
+            errno => {
+                return (
+                    ControlFlow::Error(std::io::Error::from_raw_os_error(errno).into()),
+                    None,
+                )
+            }
+        }
+
+        self.ticks += 1;
+        if let Some(deadline) = self.deadline.as_mut() {
+            *deadline += self.interval;
+        }
+
+        let flow = (self.on_tick)(self.ticks);
+        if matches!(flow, ControlFlow::Exit | ControlFlow::Error(_)) {
+            return (flow, more.then_some(()));
+        }
+
+        if more {
+            return (flow, Some(()));
+        }
+
+        match self.arm(submitter) {
+            Ok(()) => (flow, None),
+            Err(e) => (ControlFlow::Error(e), None),
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::mem::size_of;
+use std::net::SocketAddr;
+use std::os::fd::RawFd;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{ProvideBuffers, RecvMsgMulti};
+use io_uring::types::{Fd, RecvMsgOut};
+use tracing::{debug, trace};
+
+use crate::ops::{Error, OpControlFlow, SockAddr};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+const DEFAULT_BUFFER_SIZE: u32 = 2048;
+const DEFAULT_BUFFER_COUNT: u16 = 64;
+
+/// A datagram decoded from a multishot `recvmsg` provided buffer.
+#[derive(Debug)]
+pub struct Datagram<'a> {
+    pub from: Option<SocketAddr>,
+    pub payload: &'a [u8],
+    pub control: &'a [u8],
+    /// The payload did not fit into the provided buffer
+    pub truncated: bool,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UdpRecvData {
+    Recv,
+    Provide(u16),
+}
+
+/// Receives datagrams on `fd` with a multishot `RecvMsg` (Linux 6.0+) into provided buffers.
+///
+/// Every buffer holds the kernel's `io_uring_recvmsg_out` header, the peer address, up to
+/// `control_len` bytes of control data and the payload. [`Datagram`] is the decoded view of it.
+pub struct UdpRecvOp {
+    fd: RawFd,
+    buffer_group: u16,
+    buffer_size: u32,
+    buffer_count: u16,
+    buffers: Vec<u8>,
+    msghdr: Box<libc::msghdr>,
+    on_datagram: Box<dyn FnMut(Datagram<'_>) -> OpControlFlow>,
+}
+
+impl Debug for UdpRecvOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UdpRecvOp")
+            .field("fd", &self.fd)
+            .field("buffer_group", &self.buffer_group)
+            .field("buffer_size", &self.buffer_size)
+            .field("buffer_count", &self.buffer_count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl UdpRecvOp {
+    pub fn new(
+        fd: RawFd,
+        buffer_group: u16,
+        on_datagram: impl FnMut(Datagram<'_>) -> OpControlFlow + 'static,
+    ) -> Self {
+        let mut msghdr: Box<libc::msghdr> = Box::new(unsafe { std::mem::zeroed() });
+        msghdr.msg_namelen = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+
+        Self {
+            fd,
+            buffer_group,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            buffer_count: DEFAULT_BUFFER_COUNT,
+            buffers: Vec::new(),
+            msghdr,
+            on_datagram: Box::new(on_datagram),
+        }
+    }
+
+    /// `size` includes the header, address and control data.
+    pub fn buffers(mut self, size: u32, count: u16) -> Self {
+        self.buffer_size = size;
+        self.buffer_count = count;
+        self
+    }
+
+    pub fn control_len(mut self, control_len: usize) -> Self {
+        self.msghdr.msg_controllen = control_len as _;
+        self
+    }
+
+    fn provide(&mut self, bid: u16, nbufs: u16) -> io_uring::squeue::Entry {
+        let addr = unsafe {
+            self.buffers
+                .as_mut_ptr()
+                .add(bid as usize * self.buffer_size as usize)
+        };
+        ProvideBuffers::new(addr, self.buffer_size as i32, nbufs, self.buffer_group, bid).build()
+    }
+
+    fn recv(&self) -> io_uring::squeue::Entry {
+        RecvMsgMulti::new(Fd(self.fd), self.msghdr.as_ref(), self.buffer_group).build()
+    }
+
+    fn on_recv<W: Fn(&mut io_uring::squeue::Entry, UdpRecvData)>(
+        &mut self,
+        completion_entry: &Entry,
+        submitter: &mut SubmissionQueueSubmitter<UdpRecvData, W>,
+    ) -> Result<OpControlFlow, Error> {
+        let res = completion_entry.result();
+        let flags = completion_entry.flags();
+
+        if res < 0 {
+            return match -res {
+                libc::ENOBUFS => {
+                    debug!("udp receive ran out of provided buffers");
+                    Ok(ControlFlow::Continue)
+                }
+                errno => Err(io::Error::from_raw_os_error(errno).into()),
+            };
+        }
+
+        let Some(bid) = io_uring::cqueue::buffer_select(flags) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no buffer selected").into());
+        };
+
+        let flow = {
+            let start = bid as usize * self.buffer_size as usize;
+            let buffer = &self.buffers[start..start + res as usize];
+            match RecvMsgOut::parse(buffer, &self.msghdr) {
+                Ok(out) => (self.on_datagram)(Datagram {
+                    from: SockAddr::from(out.name_data()).to_std(),
+                    payload: out.payload_data(),
+                    control: out.control_data(),
+                    truncated: out.is_payload_truncated(),
+                }),
+                Err(()) => ControlFlow::Warn(
+                    io::Error::new(io::ErrorKind::InvalidData, "malformed recvmsg buffer").into(),
+                ),
+            }
+        };
+
+        let entry = self.provide(bid, 1);
+        submitter.push(entry, UdpRecvData::Provide(bid))?;
+        Ok(flow)
+    }
+}
+
+impl RingOperation for UdpRecvOp {
+    type RingData = UdpRecvData;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.buffers = vec![0; self.buffer_size as usize * self.buffer_count as usize];
+        let provide = self.provide(0, self.buffer_count);
+        let recv = self.recv();
+        submitter.push_multiple(
+            [provide, recv],
+            [UdpRecvData::Provide(0), UdpRecvData::Recv],
+        )?;
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        match ring_data {
+            UdpRecvData::Provide(bid) => {
+                let flow = match completion_entry.result() {
+                    res if res < 0 => {
+                        trace!("providing buffer {bid} failed");
+                        ControlFlow::Warn(io::Error::from_raw_os_error(-res).into())
+                    }
+                    _ => ControlFlow::Continue,
+                };
+                (flow, None)
+            }
+            UdpRecvData::Recv => {
+                let more = io_uring::cqueue::more(completion_entry.flags());
+                let flow = match self.on_recv(&completion_entry, &mut submitter) {
+                    Ok(flow) => flow,
+                    Err(e) => return (ControlFlow::Error(e), more.then_some(ring_data)),
+                };
+
+                if more {
+                    return (flow, Some(ring_data));
+                }
+
+                trace!("re-arming multishot recvmsg");
+                if let Err(e) = submitter.push(self.recv(), UdpRecvData::Recv) {
+                    return (ControlFlow::Error(e.into()), None);
+                }
+                (flow, None)
+            }
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+// the ring is generated inside the crate, its unused parts are not exempt from lints
+#[allow(dead_code, unused_imports)]
+mod tests {
+    use std::cell::RefCell;
+    use std::net::UdpSocket;
+    use std::os::fd::AsRawFd;
+    use std::rc::Rc;
+
+    use super::*;
+
+    crate::ring! { udp_ring -> crate::ops::Error, udp: super::UdpRecvOp }
+
+    /// What a datagram was decoded to.
+    #[derive(Debug)]
+    struct Received {
+        from: Option<SocketAddr>,
+        payload: Vec<u8>,
+        cmsg: Option<(libc::c_int, libc::c_int)>,
+        truncated: bool,
+    }
+
+    #[test]
+    fn decodes_address_control_data_and_payload() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let enable: libc::c_int = 1;
+        assert_eq!(
+            unsafe {
+                libc::setsockopt(
+                    socket.as_raw_fd(),
+                    libc::IPPROTO_IP,
+                    libc::IP_PKTINFO,
+                    (&enable as *const libc::c_int).cast(),
+                    size_of::<libc::c_int>() as libc::socklen_t,
+                )
+            },
+            0
+        );
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let big = [7; 200];
+        for payload in [&b"one"[..], b"two", &big] {
+            sender
+                .send_to(payload, socket.local_addr().unwrap())
+                .unwrap();
+        }
+
+        let control_len = unsafe { libc::CMSG_SPACE(size_of::<libc::in_pktinfo>() as u32) };
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let op = UdpRecvOp::new(socket.as_raw_fd(), 7, {
+            let received = received.clone();
+            move |datagram| {
+                let cmsg = (datagram.control.len() >= size_of::<libc::cmsghdr>()).then(|| {
+                    let header = unsafe {
+                        datagram
+                            .control
+                            .as_ptr()
+                            .cast::<libc::cmsghdr>()
+                            .read_unaligned()
+                    };
+                    (header.cmsg_level, header.cmsg_type)
+                });
+                let mut received = received.borrow_mut();
+                received.push(Received {
+                    from: datagram.from,
+                    payload: datagram.payload.to_vec(),
+                    cmsg,
+                    truncated: datagram.truncated,
+                });
+                match received.len() {
+                    3 => ControlFlow::Exit,
+                    _ => ControlFlow::Continue,
+                }
+            }
+        })
+        // header, address and control data leave room for 80 bytes of payload
+        .buffers(
+            16 + size_of::<libc::sockaddr_storage>() as u32 + control_len + 80,
+            4,
+        )
+        .control_len(control_len as usize);
+        let mut ring = udp_ring::Ring::builder().udp(op).build().unwrap();
+        ring.run().unwrap();
+
+        let received = received.take();
+        for datagram in &received {
+            assert_eq!(datagram.from, Some(sender.local_addr().unwrap()));
+            assert_eq!(datagram.cmsg, Some((libc::IPPROTO_IP, libc::IP_PKTINFO)));
+        }
+        assert_eq!(received[0].payload, b"one");
+        assert_eq!(received[1].payload, b"two");
+        assert!(!received[0].truncated && !received[1].truncated);
+        assert!(received[2].truncated);
+        assert_eq!(received[2].payload, [7; 80]);
+    }
+}
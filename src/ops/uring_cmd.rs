@@ -0,0 +1,191 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::UringCmd16;
+use io_uring::types::Fd;
+
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// A driver command for `IORING_OP_URING_CMD`, owning the data buffer the command refers to.
+///
+/// The buffer is handed back together with the result, it can not be freed or moved while the
+/// driver may still access it.
+pub struct UringCmd {
+    fd: RawFd,
+    cmd_op: u32,
+    cmd: [u8; 16],
+    buffer: Option<Box<[u8]>>,
+}
+
+impl Debug for UringCmd {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UringCmd")
+            .field("fd", &self.fd)
+            .field("cmd_op", &self.cmd_op)
+            .field("cmd", &self.cmd)
+            .field("buffer", &self.buffer.as_ref().map(|b| b.len()))
+            .finish()
+    }
+}
+
+impl UringCmd {
+    pub fn new(fd: RawFd, cmd_op: u32, cmd: [u8; 16]) -> Self {
+        Self {
+            fd,
+            cmd_op,
+            cmd,
+            buffer: None,
+        }
+    }
+
+    /// Attaches `buffer` to the command. `encode` gets the address and length of the buffer
+    /// and returns the command payload referring to it.
+    pub fn with_buffer(
+        fd: RawFd,
+        cmd_op: u32,
+        mut buffer: Box<[u8]>,
+        encode: impl FnOnce(*mut u8, usize) -> [u8; 16],
+    ) -> Self {
+        let cmd = encode(buffer.as_mut_ptr(), buffer.len());
+        Self {
+            fd,
+            cmd_op,
+            cmd,
+            buffer: Some(buffer),
+        }
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    pub fn cmd_op(&self) -> u32 {
+        self.cmd_op
+    }
+
+    pub fn buffer(&self) -> Option<&[u8]> {
+        self.buffer.as_deref()
+    }
+
+    pub fn into_buffer(self) -> Option<Box<[u8]>> {
+        self.buffer
+    }
+
+    fn entry(&self) -> io_uring::squeue::Entry {
+        UringCmd16::new(Fd(self.fd), self.cmd_op)
+            .cmd(self.cmd)
+            .build()
+    }
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to queue commands on an
+/// [`UringCmdOp`].
+#[derive(Debug, Clone, Default)]
+pub struct UringCmdHandle {
+    queue: Rc<RefCell<Vec<UringCmd>>>,
+}
+
+impl UringCmdHandle {
+    pub fn submit(&self, cmd: UringCmd) {
+        self.queue.borrow_mut().push(cmd);
+    }
+}
+
+type UringCmdCallback = Box<dyn FnMut(UringCmd, io::Result<i32>) -> OpControlFlow>;
+
+/// Passes file/device specific commands (`IORING_OP_URING_CMD`) to drivers, e.g. socket
+/// commands like `SOCKET_URING_OP_SIOCINQ`.
+///
+/// Rings use 64 byte sqes, so commands carry a 16 byte payload. NVMe passthrough needs 80 byte
+/// payloads in `IORING_SETUP_SQE128` rings, which `ring!` does not set up.
+pub struct UringCmdOp {
+    handle: UringCmdHandle,
+    on_result: UringCmdCallback,
+}
+
+impl Debug for UringCmdOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UringCmdOp")
+            .field("handle", &self.handle)
+            .finish_non_exhaustive()
+    }
+}
+
+impl UringCmdOp {
+    pub fn new(
+        on_result: impl FnMut(UringCmd, io::Result<i32>) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            handle: Default::default(),
+            on_result: Box::new(on_result),
+        }
+    }
+
+    pub fn handle(&self) -> UringCmdHandle {
+        self.handle.clone()
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, UringCmd)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<UringCmd, W>,
+    ) -> Result<(), Error> {
+        let cmds = std::mem::take(&mut *self.handle.queue.borrow_mut());
+        for cmd in cmds {
+            submitter.push(cmd.entry(), cmd)?;
+        }
+        Ok(())
+    }
+}
+
+impl RingOperation for UringCmdOp {
+    type RingData = UringCmd;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let result = match completion_entry.result() {
+            res if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+            res => Ok(res),
+        };
+
+        ((self.on_result)(ring_data, result), None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+
+use crate::ops::raw::RawSqe;
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+// not exposed by io-uring 0.6 / libc (yet)
+const IORING_OP_WAITID: u8 = 50;
+const P_PIDFD: libc::idtype_t = 3;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WaitTarget {
+    Pid(libc::pid_t),
+    PidFd(RawFd),
+    /// Any child. Re-armed after every reaped child until there are no children left.
+    Any,
+}
+
+impl WaitTarget {
+    fn id(self) -> (libc::idtype_t, i32) {
+        match self {
+            WaitTarget::Pid(pid) => (libc::P_PID, pid),
+            WaitTarget::PidFd(fd) => (P_PIDFD, fd),
+            WaitTarget::Any => (libc::P_ALL, 0),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ChildStatus {
+    Exited(i32),
+    Signaled { signal: i32, core_dumped: bool },
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ChildExit {
+    pub pid: libc::pid_t,
+    pub status: ChildStatus,
+}
+
+/// Ring data of a [`WaitIdOp`], owns the `siginfo_t` the kernel fills in.
+pub struct WaitIdRequest {
+    target: WaitTarget,
+    info: Box<libc::siginfo_t>,
+}
+
+impl Debug for WaitIdRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WaitIdRequest")
+            .field("target", &self.target)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WaitIdRequest {
+    fn new(target: WaitTarget) -> Self {
+        Self {
+            target,
+            info: Box::new(unsafe { std::mem::zeroed() }),
+        }
+    }
+
+    fn entry(&mut self) -> io_uring::squeue::Entry {
+        let (idtype, id) = self.target.id();
+
+        let mut sqe = RawSqe::new(IORING_OP_WAITID);
+        sqe.fd = id;
+        sqe.len = idtype;
+        sqe.file_index = libc::WEXITED as u32;
+        sqe.addr2 = self.info.as_mut() as *mut libc::siginfo_t as u64;
+        sqe.build()
+    }
+
+    fn exit(&self) -> ChildExit {
+        let (pid, status) = unsafe { (self.info.si_pid(), self.info.si_status()) };
+        let status = match self.info.si_code {
+            libc::CLD_EXITED => ChildStatus::Exited(status),
+            code => ChildStatus::Signaled {
+                signal: status,
+                core_dumped: code == libc::CLD_DUMPED,
+            },
+        };
+        ChildExit { pid, status }
+    }
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to register children on a
+/// [`WaitIdOp`], e.g. right after spawning them.
+#[derive(Debug, Clone, Default)]
+pub struct WaitIdHandle {
+    queue: Rc<RefCell<Vec<WaitTarget>>>,
+}
+
+impl WaitIdHandle {
+    pub fn wait(&self, target: WaitTarget) {
+        self.queue.borrow_mut().push(target);
+    }
+}
+
+/// Reaps child processes with `IORING_OP_WAITID` (Linux 6.7+) and calls `on_exit` for every
+/// terminated child.
+///
+/// io-uring 0.6 has no `WaitId` opcode builder, the sqe is assembled by hand.
+pub struct WaitIdOp {
+    handle: WaitIdHandle,
+    on_exit: Box<dyn FnMut(ChildExit) -> OpControlFlow>,
+}
+
+impl Debug for WaitIdOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WaitIdOp")
+            .field("handle", &self.handle)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WaitIdOp {
+    pub fn new(on_exit: impl FnMut(ChildExit) -> OpControlFlow + 'static) -> Self {
+        Self {
+            handle: Default::default(),
+            on_exit: Box::new(on_exit),
+        }
+    }
+
+    pub fn wait(self, target: WaitTarget) -> Self {
+        self.handle.wait(target);
+        self
+    }
+
+    pub fn handle(&self) -> WaitIdHandle {
+        self.handle.clone()
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, WaitIdRequest)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<WaitIdRequest, W>,
+    ) -> Result<(), Error> {
+        let targets = std::mem::take(&mut *self.handle.queue.borrow_mut());
+        for target in targets {
+            let mut request = WaitIdRequest::new(target);
+            submitter.push(request.entry(), request)?;
+        }
+        Ok(())
+    }
+}
+
+impl RingOperation for WaitIdOp {
+    type RingData = WaitIdRequest;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        mut ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        match -completion_entry.result() {
+            0 => {}
+            // no (more) children to wait for
+            libc::ECHILD if ring_data.target == WaitTarget::Any => {
+                return (ControlFlow::Continue, None)
+            }
+            libc::ECANCELED => return (ControlFlow::Continue, None),
+            errno => {
+                return (
+                    ControlFlow::Warn(io::Error::from_raw_os_error(errno).into()),
+                    None,
+                )
+            }
+        }
+
+        let flow = (self.on_exit)(ring_data.exit());
+        if let ControlFlow::Exit | ControlFlow::Error(_) = flow {
+            return (flow, None);
+        }
+
+        if ring_data.target == WaitTarget::Any {
+            *ring_data.info = unsafe { std::mem::zeroed() };
+            if let Err(e) = submitter.push(ring_data.entry(), ring_data) {
+                return (ControlFlow::Error(e.into()), None);
+            }
+        }
+        (flow, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+
+use io_uring::cqueue::Entry;
+
+use crate::ops::raw::RawSqe;
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+// not exposed by io-uring 0.6 (yet)
+const IORING_OP_FSETXATTR: u8 = 41;
+const IORING_OP_SETXATTR: u8 = 42;
+const IORING_OP_FGETXATTR: u8 = 43;
+const IORING_OP_GETXATTR: u8 = 44;
+
+const DEFAULT_VALUE_SIZE: usize = 256;
+/// `XATTR_SIZE_MAX`
+const MAX_VALUE_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum XattrTarget {
+    Path(CString),
+    Fd(RawFd),
+}
+
+impl XattrTarget {
+    pub fn path(path: impl Into<Vec<u8>>) -> io::Result<Self> {
+        Ok(Self::Path(CString::new(path)?))
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum XattrKind {
+    Get,
+    /// `flags` are `XATTR_CREATE`/`XATTR_REPLACE`
+    Set {
+        flags: i32,
+    },
+}
+
+/// A pending `getxattr`/`setxattr`. Owns the target path, the name and the value buffer until
+/// its completion arrives.
+#[derive(Debug)]
+pub struct XattrRequest {
+    target: XattrTarget,
+    name: CString,
+    value: Vec<u8>,
+    kind: XattrKind,
+}
+
+impl XattrRequest {
+    pub fn target(&self) -> &XattrTarget {
+        &self.target
+    }
+
+    pub fn name(&self) -> &CStr {
+        &self.name
+    }
+
+    pub fn kind(&self) -> XattrKind {
+        self.kind
+    }
+
+    fn entry(&mut self) -> io_uring::squeue::Entry {
+        let opcode = match (&self.target, self.kind) {
+            (XattrTarget::Path(_), XattrKind::Get) => IORING_OP_GETXATTR,
+            (XattrTarget::Path(_), XattrKind::Set { .. }) => IORING_OP_SETXATTR,
+            (XattrTarget::Fd(_), XattrKind::Get) => IORING_OP_FGETXATTR,
+            (XattrTarget::Fd(_), XattrKind::Set { .. }) => IORING_OP_FSETXATTR,
+        };
+
+        let mut sqe = RawSqe::new(opcode);
+        match &self.target {
+            XattrTarget::Path(path) => sqe.addr3 = path.as_ptr() as u64,
+            XattrTarget::Fd(fd) => sqe.fd = *fd,
+        }
+        sqe.addr = self.name.as_ptr() as u64;
+        match self.kind {
+            XattrKind::Get => {
+                sqe.addr2 = self.value.spare_capacity_mut().as_mut_ptr() as u64;
+                sqe.len = self.value.capacity() as u32;
+            }
+            XattrKind::Set { flags } => {
+                sqe.addr2 = self.value.as_ptr() as u64;
+                sqe.len = self.value.len() as u32;
+                sqe.op_flags = flags as u32;
+            }
+        }
+        sqe.build()
+    }
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to queue xattr requests on
+/// an [`XattrOp`].
+#[derive(Debug, Clone, Default)]
+pub struct XattrHandle {
+    queue: Rc<RefCell<Vec<XattrRequest>>>,
+}
+
+impl XattrHandle {
+    pub fn get(&self, target: XattrTarget, name: impl Into<Vec<u8>>) -> io::Result<()> {
+        self.queue.borrow_mut().push(XattrRequest {
+            target,
+            name: CString::new(name)?,
+            value: Vec::with_capacity(DEFAULT_VALUE_SIZE),
+            kind: XattrKind::Get,
+        });
+        Ok(())
+    }
+
+    pub fn set(
+        &self,
+        target: XattrTarget,
+        name: impl Into<Vec<u8>>,
+        value: impl Into<Vec<u8>>,
+        flags: i32,
+    ) -> io::Result<()> {
+        self.queue.borrow_mut().push(XattrRequest {
+            target,
+            name: CString::new(name)?,
+            value: value.into(),
+            kind: XattrKind::Set { flags },
+        });
+        Ok(())
+    }
+}
+
+type XattrCallback = Box<dyn FnMut(&XattrRequest, io::Result<&[u8]>) -> OpControlFlow>;
+
+/// Reads and writes extended attributes with the io_uring xattr opcodes (Linux 5.19+).
+///
+/// `on_result` gets the value for gets and an empty slice for sets. Gets are retried with a
+/// bigger buffer if the value does not fit.
+pub struct XattrOp {
+    handle: XattrHandle,
+    on_result: XattrCallback,
+}
+
+impl Debug for XattrOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("XattrOp")
+            .field("handle", &self.handle)
+            .finish_non_exhaustive()
+    }
+}
+
+impl XattrOp {
+    pub fn new(
+        on_result: impl FnMut(&XattrRequest, io::Result<&[u8]>) -> OpControlFlow + 'static,
+    ) -> Self {
+        Self {
+            handle: Default::default(),
+            on_result: Box::new(on_result),
+        }
+    }
+
+    pub fn handle(&self) -> XattrHandle {
+        self.handle.clone()
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, XattrRequest)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<XattrRequest, W>,
+    ) -> Result<(), Error> {
+        let requests = std::mem::take(&mut *self.handle.queue.borrow_mut());
+        for mut request in requests {
+            submitter.push(request.entry(), request)?;
+        }
+        Ok(())
+    }
+}
+
+impl RingOperation for XattrOp {
+    type RingData = XattrRequest;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        mut ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        let result = match (res, ring_data.kind) {
+            (res, XattrKind::Get)
+                if -res == libc::ERANGE && ring_data.value.capacity() < MAX_VALUE_SIZE =>
+            {
+                let capacity = (ring_data.value.capacity() * 2).min(MAX_VALUE_SIZE);
+                ring_data.value.reserve_exact(capacity);
+                return match submitter.push(ring_data.entry(), ring_data) {
+                    Ok(()) => (ControlFlow::Continue, None),
+                    Err(e) => (ControlFlow::Error(e.into()), None),
+                };
+            }
+            (res, _) if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+            (res, XattrKind::Get) => {
+                unsafe { ring_data.value.set_len(res as usize) };
+                Ok(ring_data.value.as_slice())
+            }
+            (_, XattrKind::Set { .. }) => Ok(&[][..]),
+        };
+
+        ((self.on_result)(&ring_data, result), None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! OpenTelemetry instruments for rings and pools.
+//!
+//! An [`OtelExporter`] maps the counters rings report to a [`LoadBoard`] (submissions,
+//! completions, backlog spills, requests in flight, completion backlog) onto observable
+//! instruments read whenever the meter provider collects, and the completions recorded on a
+//! [`Timeline`] onto a request counter and a latency histogram per operation:
+//!
+//! ```no_run
+//! # fn example<E>(pool: &rummelplatz::pool::RingPool<E>) {
+//! use rummelplatz::otel::OtelExporter;
+//! use rummelplatz::timeline::Timeline;
+//!
+//! let meter = opentelemetry::global::meter("my-service");
+//! // the timeline is attached to every ring with `ring.record_timeline(timeline.clone())`
+//! let timeline = Timeline::new(0);
+//! OtelExporter::new(meter)
+//!     .observe_pool("http", pool.load_board().clone())
+//!     .observe_timeline(&timeline);
+//! # }
+//! ```
+//!
+//! | instrument | kind | attributes |
+//! |---|---|---|
+//! | `rummelplatz.ring.submissions` | counter | `rummelplatz.pool`, `rummelplatz.ring` |
+//! | `rummelplatz.ring.completions` | counter | `rummelplatz.pool`, `rummelplatz.ring` |
+//! | `rummelplatz.ring.backlog_spills` | counter | `rummelplatz.pool`, `rummelplatz.ring` |
+//! | `rummelplatz.ring.in_flight` | gauge | `rummelplatz.pool`, `rummelplatz.ring` |
+//! | `rummelplatz.ring.cq_backlog` | gauge | `rummelplatz.pool`, `rummelplatz.ring` |
+//! | `rummelplatz.queue.entries` | gauge | `rummelplatz.ring`, `rummelplatz.queue` |
+//! | `rummelplatz.queue.high_water` | gauge | `rummelplatz.ring`, `rummelplatz.queue` |
+//! | `rummelplatz.queue.capacity` | gauge | `rummelplatz.ring`, `rummelplatz.queue` |
+//! | `rummelplatz.ring.in_flight.high_water` | gauge | `rummelplatz.ring` |
+//! | `rummelplatz.request.completions` | counter | `rummelplatz.ring_type`, `rummelplatz.operation`, `error.type` |
+//! | `rummelplatz.request.duration` | histogram (s) | `rummelplatz.ring_type`, `rummelplatz.operation`, `error.type` |
+//!
+//! `rummelplatz.ring` is the index of the ring in its pool (or the label passed to
+//! [`OtelExporter::stats_observer`]), `rummelplatz.ring_type` the name given to `ring!` and
+//! `rummelplatz.queue` either `sq` or `cq`. `rummelplatz.ring.in_flight.high_water` stays 0 for
+//! rings that do not count their requests in flight (`Ring::count_in_flight`). `error.type` is only set for failed requests, to the kind of the error (or its errno).
+//! Durations are recorded for the last completion of a request only, multishot completions
+//! that keep it in flight are counted.
+
+use std::fmt::{Debug, Formatter};
+use std::io;
+
+use opentelemetry::metrics::{
+    Counter, Gauge, Histogram, Meter, ObservableCounter, ObservableGauge,
+};
+use opentelemetry::KeyValue;
+
+use crate::pool::balance::{LoadBoard, RingCounters};
+use crate::stats::RingStats;
+use crate::timeline::{Completion, Timeline};
+
+/// Bucket boundaries of `rummelplatz.request.duration` in seconds, 1µs to 10s
+const DURATION_BOUNDARIES: [f64; 15] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5,
+    1.0, 5.0, 10.0,
+];
+
+/// Registers the instruments of rings and pools with a [`Meter`].
+pub struct OtelExporter {
+    meter: Meter,
+    // the provider keeps observing these after the exporter is dropped
+    counters: Vec<ObservableCounter<u64>>,
+    gauges: Vec<ObservableGauge<u64>>,
+}
+
+impl Debug for OtelExporter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OtelExporter")
+            .field("counters", &self.counters.len())
+            .field("gauges", &self.gauges.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl OtelExporter {
+    pub fn new(meter: Meter) -> Self {
+        Self {
+            meter,
+            counters: Vec::new(),
+            gauges: Vec::new(),
+        }
+    }
+
+    /// Observes the counters of every ring reporting to `board`, rings added later included.
+    /// `pool` tells several pools apart.
+    pub fn observe_pool(&mut self, pool: &str, board: LoadBoard) -> &mut Self {
+        let counter =
+            |name: &'static str, description: &'static str, value: fn(&RingCounters) -> u64| {
+                let board = board.clone();
+                let pool = pool.to_string();
+                self.meter
+                    .u64_observable_counter(name)
+                    .with_description(description)
+                    .with_unit("{request}")
+                    .with_callback(move |observer| {
+                        observe(&board, &pool, |counters, attributes| {
+                            observer.observe(value(counters), attributes)
+                        })
+                    })
+                    .build()
+            };
+        let counters = [
+            counter(
+                "rummelplatz.ring.submissions",
+                "SQEs submitted to the kernel",
+                |counters| counters.submitted,
+            ),
+            counter(
+                "rummelplatz.ring.completions",
+                "CQEs handled by the ring",
+                |counters| counters.completed,
+            ),
+            counter(
+                "rummelplatz.ring.backlog_spills",
+                "Batches of SQEs that waited in the backlog for a full submission queue",
+                |counters| counters.backlog_spills,
+            ),
+        ];
+
+        let gauge =
+            |name: &'static str, description: &'static str, value: fn(&RingCounters) -> usize| {
+                let board = board.clone();
+                let pool = pool.to_string();
+                self.meter
+                    .u64_observable_gauge(name)
+                    .with_description(description)
+                    .with_unit("{request}")
+                    .with_callback(move |observer| {
+                        observe(&board, &pool, |counters, attributes| {
+                            observer.observe(value(counters) as u64, attributes)
+                        })
+                    })
+                    .build()
+            };
+        let gauges = [
+            gauge(
+                "rummelplatz.ring.in_flight",
+                "Requests in flight as reported by the operations",
+                |counters| counters.in_flight,
+            ),
+            gauge(
+                "rummelplatz.ring.cq_backlog",
+                "Completions waiting when the ring last synced its completion queue",
+                |counters| counters.cq_backlog,
+            ),
+        ];
+
+        self.counters.extend(counters);
+        self.gauges.extend(gauges);
+        self
+    }
+
+    /// An observer for `Ring::observe_stats` recording the fill levels of the submission and
+    /// completion queue of the ring labeled `ring`, e.g. its index in the pool as `i64`.
+    pub fn stats_observer(
+        &self,
+        ring: impl Into<opentelemetry::Value>,
+    ) -> impl FnMut(&RingStats) + 'static {
+        let gauge = |name: &'static str, description: &'static str| -> Gauge<u64> {
+            self.meter
+                .u64_gauge(name)
+                .with_description(description)
+                .with_unit("{entry}")
+                .build()
+        };
+        let entries = gauge(
+            "rummelplatz.queue.entries",
+            "Entries in the queue when the ring last submitted (sq) or synced it (cq)",
+        );
+        let high_water = gauge(
+            "rummelplatz.queue.high_water",
+            "Most entries the queue held so far",
+        );
+        let capacity = gauge("rummelplatz.queue.capacity", "Size of the queue");
+        let in_flight_high_water = self
+            .meter
+            .u64_gauge("rummelplatz.ring.in_flight.high_water")
+            .with_description("Most requests in flight at once so far")
+            .with_unit("{request}")
+            .build();
+
+        let ring = ring.into();
+        let attributes = |queue: &'static str| {
+            [
+                KeyValue::new("rummelplatz.ring", ring.clone()),
+                KeyValue::new("rummelplatz.queue", queue),
+            ]
+        };
+        let (sq, cq) = (attributes("sq"), attributes("cq"));
+        let ring = [KeyValue::new("rummelplatz.ring", ring)];
+        move |stats| {
+            in_flight_high_water.record(stats.in_flight.high_water as u64, &ring);
+            for (attributes, fill) in [(&sq, stats.sq), (&cq, stats.cq)] {
+                entries.record(fill.len as u64, attributes);
+                high_water.record(fill.high_water as u64, attributes);
+                capacity.record(fill.capacity as u64, attributes);
+            }
+        }
+    }
+
+    /// Counts and times the completions recorded on `timeline`, see
+    /// [`Timeline::on_completion`]. A timeline of capacity `0` records nothing else.
+    pub fn observe_timeline(&mut self, timeline: &Timeline) -> &mut Self {
+        let completions: Counter<u64> = self
+            .meter
+            .u64_counter("rummelplatz.request.completions")
+            .with_description("Completions of requests")
+            .with_unit("{completion}")
+            .build();
+        let duration: Histogram<f64> = self
+            .meter
+            .f64_histogram("rummelplatz.request.duration")
+            .with_description(
+                "Time from pushing a request to the submission queue to its last completion",
+            )
+            .with_unit("s")
+            .with_boundaries(DURATION_BOUNDARIES.to_vec())
+            .build();
+
+        timeline.on_completion(move |completion: &Completion| {
+            let mut attributes = [
+                KeyValue::new("rummelplatz.ring_type", completion.ring),
+                KeyValue::new("rummelplatz.operation", completion.operation),
+                KeyValue::new("error.type", ""),
+            ];
+            let attributes = match completion.result {
+                result if result < 0 => {
+                    let kind = format!("{:?}", io::Error::from_raw_os_error(-result).kind());
+                    let kind = match kind.as_str() {
+                        // e.g. `ETIME` of timeouts
+                        "Uncategorized" => format!("errno {}", -result),
+                        _ => kind,
+                    };
+                    attributes[2] = KeyValue::new("error.type", kind);
+                    &attributes[..]
+                }
+                _ => &attributes[..2],
+            };
+
+            completions.add(1, attributes);
+            if !completion.more {
+                duration.record(completion.elapsed.as_secs_f64(), attributes);
+            }
+        });
+        self
+    }
+}
+
+fn observe(board: &LoadBoard, pool: &str, mut observe: impl FnMut(&RingCounters, &[KeyValue])) {
+    for index in 0..board.len() {
+        let attributes = [
+            KeyValue::new("rummelplatz.pool", pool.to_string()),
+            KeyValue::new("rummelplatz.ring", index as i64),
+        ];
+        observe(&board.counters(index), &attributes);
+    }
+}
@@ -0,0 +1,550 @@
+//! One acceptor ring feeding worker rings.
+//!
+//! The acceptor ring runs an [`AcceptorOp`] on the listener and hands every accepted connection
+//! to the next worker in round-robin order with `IORING_OP_MSG_RING`, the [`WorkerOp`] of that
+//! worker passes it on to its connection handler. Regular fds travel as the message's value.
+//! Direct descriptors (see [`AcceptorOp::direct`]) are installed into a slot the kernel
+//! allocates in the worker's fixed file table, which therefore needs free sparse slots, and
+//! closed on the acceptor once the message was delivered.
+//!
+//! Every worker takes at most [`AcceptTopology::max_connections`] connections at a time.
+//! Saturated workers are skipped, once all of them are saturated the acceptor stops accepting
+//! and leaves new connections in the listen backlog, until a worker
+//! [releases](WorkerHandle::release) a connection and wakes it with a message.
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::{FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::{AcceptMulti, AsyncCancel2, Close};
+use io_uring::squeue::Flags;
+use io_uring::types::{CancelBuilder, Fd, Fixed};
+use tracing::{debug, trace, warn};
+
+use crate::message::{MessageTarget, OutgoingMessage, RingMessage};
+use crate::ops::{Error, OpControlFlow};
+use crate::pool::migrate::Connection;
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+struct Shared {
+    max_connections: usize,
+    acceptor: Mutex<Option<MessageTarget>>,
+    workers: Mutex<Vec<Option<MessageTarget>>>,
+    open: Vec<AtomicUsize>,
+    next: AtomicUsize,
+    // set by the acceptor when all workers are saturated, cleared by the worker waking it
+    paused: AtomicBool,
+}
+
+/// Shared by the acceptor and all workers, cheap to clone and [`Send`].
+///
+/// Worker indices are `0..workers` and independent of the ring indices of a pool, e.g. the
+/// acceptor can be ring `0` and worker `i` ring `i + 1`.
+#[derive(Clone)]
+pub struct AcceptTopology {
+    shared: Arc<Shared>,
+}
+
+impl Debug for AcceptTopology {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AcceptTopology")
+            .field("max_connections", &self.shared.max_connections)
+            .field("open", &self.shared.open)
+            .field("paused", &self.is_paused())
+            .finish_non_exhaustive()
+    }
+}
+
+impl AcceptTopology {
+    /// A topology of `workers` worker rings taking at most `max_connections` connections each.
+    pub fn new(workers: usize, max_connections: usize) -> Self {
+        assert!(workers > 0, "a topology needs at least one worker");
+        assert!(max_connections > 0, "workers have to take connections");
+        Self {
+            shared: Arc::new(Shared {
+                max_connections,
+                acceptor: Default::default(),
+                workers: Mutex::new(vec![None; workers]),
+                open: (0..workers).map(|_| AtomicUsize::new(0)).collect(),
+                next: AtomicUsize::new(0),
+                paused: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.shared.open.len()
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.shared.max_connections
+    }
+
+    /// Connections handed to worker `index` (or on their way to it) and not released yet.
+    pub fn open(&self, index: usize) -> usize {
+        self.shared.open[index].load(Ordering::Relaxed)
+    }
+
+    /// Whether the acceptor stopped accepting because all workers are saturated.
+    pub fn is_paused(&self) -> bool {
+        self.shared.paused.load(Ordering::Relaxed)
+    }
+
+    /// Sets the address of the [`AcceptorOp`], see `Ring::message_target`. Saturated workers
+    /// can only wake a registered acceptor.
+    pub fn register_acceptor(&self, target: MessageTarget) {
+        *self.shared.acceptor.lock().unwrap() = Some(target);
+    }
+
+    /// Sets the address of worker `index`'s [`WorkerOp`], see `Ring::message_target`. A worker
+    /// gets connections once it is registered, a restarted worker registers again.
+    pub fn register_worker(&self, index: usize, target: MessageTarget) {
+        self.shared.workers.lock().unwrap()[index] = Some(target);
+    }
+
+    /// Creates the operation of the acceptor ring, accepting on the listening socket
+    /// `listener`.
+    pub fn acceptor(&self, listener: RawFd) -> AcceptorOp {
+        AcceptorOp {
+            topology: self.clone(),
+            listener,
+            direct: false,
+            pending: Default::default(),
+            armed: false,
+            canceling: false,
+            accepted: 0,
+        }
+    }
+
+    /// Creates the operation of worker `index`. `on_connection` gets every connection handed
+    /// to the worker, each of them has to be [released](WorkerHandle::release) once it is
+    /// done (or rejected).
+    pub fn worker(
+        &self,
+        index: usize,
+        on_connection: impl FnMut(Connection) -> OpControlFlow + 'static,
+    ) -> WorkerOp {
+        assert!(index < self.workers(), "{index} is not a worker");
+        WorkerOp {
+            handle: WorkerHandle {
+                topology: self.clone(),
+                index,
+                released: Default::default(),
+            },
+            on_connection: Box::new(on_connection),
+        }
+    }
+
+    /// Picks the next worker in round-robin order that is registered and not saturated and
+    /// counts the connection as open on it.
+    fn reserve(&self) -> Option<(usize, MessageTarget)> {
+        let workers = self.shared.workers.lock().unwrap();
+        let start = self.shared.next.fetch_add(1, Ordering::Relaxed);
+        (0..workers.len())
+            .map(|offset| (start + offset) % workers.len())
+            .find_map(|index| {
+                let target = workers[index]?;
+                self.shared.open[index]
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                        (open < self.shared.max_connections).then_some(open + 1)
+                    })
+                    .ok()
+                    .map(|_| (index, target))
+            })
+    }
+
+    fn release(&self, index: usize) {
+        let previous = self.shared.open[index].fetch_sub(1, Ordering::SeqCst);
+        debug_assert!(previous > 0, "worker {index} released more than it got");
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Handoff {
+    Fd(RawFd),
+    Fixed(u32),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum AcceptorData {
+    Accept,
+    Sent {
+        worker: usize,
+        handoff: Handoff,
+    },
+    /// The acceptor's fixed slot, closed after the message was delivered
+    Closed,
+}
+
+/// Accepts connections and distributes them to the workers, see the [module docs](self).
+pub struct AcceptorOp {
+    topology: AcceptTopology,
+    listener: RawFd,
+    direct: bool,
+    // accepted, but no worker took them yet
+    pending: VecDeque<Connection>,
+    armed: bool,
+    canceling: bool,
+    accepted: u64,
+}
+
+impl Debug for AcceptorOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AcceptorOp")
+            .field("listener", &self.listener)
+            .field("direct", &self.direct)
+            .field("pending", &self.pending.len())
+            .field("armed", &self.armed)
+            .field("accepted", &self.accepted)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AcceptorOp {
+    /// Accepts into the acceptor ring's fixed file table (which needs free sparse slots) and
+    /// passes direct descriptors to the workers.
+    pub fn direct(mut self) -> Self {
+        self.direct = true;
+        self
+    }
+
+    /// Number of accepted connections.
+    pub fn accepted(&self) -> u64 {
+        self.accepted
+    }
+
+    /// Accepted connections waiting for a worker.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn accept(&self) -> io_uring::squeue::Entry {
+        // direct descriptors have no close-on-exec flag, the kernel rejects it
+        let flags = if self.direct { 0 } else { libc::SOCK_CLOEXEC };
+        AcceptMulti::new(Fd(self.listener))
+            .allocate_file_index(self.direct)
+            .flags(flags)
+            .build()
+    }
+
+    /// Hands pending connections to the workers, pauses accepting if all of them are
+    /// saturated and accepts again otherwise.
+    fn dispatch<W: Fn(&mut io_uring::squeue::Entry, AcceptorData)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<AcceptorData, W>,
+    ) -> Result<(), Error> {
+        let paused = &self.topology.shared.paused;
+        while !self.pending.is_empty() {
+            let reserved = self.topology.reserve().or_else(|| {
+                // a worker releasing after this sees the flag and wakes the acceptor, one that
+                // released before is seen by the second attempt
+                paused.store(true, Ordering::SeqCst);
+                self.topology.reserve()
+            });
+            let Some((worker, target)) = reserved else {
+                break;
+            };
+            paused.store(false, Ordering::SeqCst);
+
+            match self.pending.pop_front().expect("pending is not empty") {
+                Connection::Fd(fd) => {
+                    let fd = fd.into_raw_fd();
+                    let message = OutgoingMessage::data(target, 0, fd).build();
+                    let handoff = Handoff::Fd(fd);
+                    submitter.push(message, AcceptorData::Sent { worker, handoff })?;
+                }
+                Connection::Fixed(slot) => {
+                    let message = OutgoingMessage::fd(target, 0, slot, None)
+                        .build()
+                        .flags(Flags::IO_LINK);
+                    let close = Close::new(Fixed(slot)).build();
+                    let handoff = Handoff::Fixed(slot);
+                    submitter.push_multiple(
+                        [message, close],
+                        [AcceptorData::Sent { worker, handoff }, AcceptorData::Closed],
+                    )?;
+                }
+            }
+        }
+
+        if !self.pending.is_empty() {
+            if self.armed && !self.canceling {
+                debug!("all workers are saturated, pausing accept");
+                let cancel = AsyncCancel2::new(CancelBuilder::fd(Fd(self.listener)).all()).build();
+                submitter.push_unmanaged(cancel)?;
+                self.canceling = true;
+            }
+        } else if !self.armed {
+            trace!("arm multishot accept");
+            submitter.push(self.accept(), AcceptorData::Accept)?;
+            self.armed = true;
+        }
+        Ok(())
+    }
+}
+
+impl RingOperation for AcceptorOp {
+    type RingData = AcceptorData;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.dispatch(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        match ring_data {
+            AcceptorData::Accept => {
+                let more = io_uring::cqueue::more(completion_entry.flags());
+                if !more {
+                    self.armed = false;
+                    self.canceling = false;
+                }
+
+                let flow = match res {
+                    res if res < 0 => match -res {
+                        libc::ECANCELED => ControlFlow::Continue,
+                        // not a listening socket, accepting again would fail the same way
+                        errno @ (libc::EBADF | libc::EINVAL | libc::ENOTSOCK) => {
+                            return (
+                                ControlFlow::Error(io::Error::from_raw_os_error(errno).into()),
+                                None,
+                            );
+                        }
+                        errno => ControlFlow::Warn(io::Error::from_raw_os_error(errno).into()),
+                    },
+                    res => {
+                        self.accepted += 1;
+                        self.pending.push_back(match self.direct {
+                            true => Connection::Fixed(res as u32),
+                            false => Connection::Fd(unsafe { OwnedFd::from_raw_fd(res) }),
+                        });
+                        ControlFlow::Continue
+                    }
+                };
+
+                if let Err(e) = self.dispatch(&mut submitter) {
+                    return (ControlFlow::Error(e), None);
+                }
+                (flow, more.then_some(ring_data))
+            }
+            AcceptorData::Sent { worker, handoff } => {
+                if res >= 0 {
+                    return (ControlFlow::Continue, None);
+                }
+
+                // the worker never saw the connection
+                self.topology.release(worker);
+                let e = io::Error::from_raw_os_error(-res);
+                warn!("unable to hand {handoff:?} to worker {worker}: {e}");
+                let result = match handoff {
+                    Handoff::Fd(fd) => {
+                        drop(unsafe { OwnedFd::from_raw_fd(fd) });
+                        Ok(())
+                    }
+                    // the linked close was canceled
+                    Handoff::Fixed(slot) => {
+                        submitter.push(Close::new(Fixed(slot)).build(), AcceptorData::Closed)
+                    }
+                };
+                match result {
+                    Ok(()) => (ControlFlow::Warn(e.into()), None),
+                    Err(e) => (ControlFlow::Error(e.into()), None),
+                }
+            }
+            AcceptorData::Closed => match res {
+                res if res >= 0 || res == -libc::ECANCELED => (ControlFlow::Continue, None),
+                res => (
+                    ControlFlow::Warn(io::Error::from_raw_os_error(-res).into()),
+                    None,
+                ),
+            },
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        let res = completion_entry.result();
+        match ring_data {
+            // close connections accepted during the cancellation, slots go with the ring
+            AcceptorData::Accept if res >= 0 && !self.direct => {
+                drop(unsafe { OwnedFd::from_raw_fd(res) });
+            }
+            AcceptorData::Sent {
+                worker,
+                handoff: Handoff::Fd(fd),
+            } if res < 0 => {
+                self.topology.release(worker);
+                drop(unsafe { OwnedFd::from_raw_fd(fd) });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn on_message<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        message: RingMessage,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        trace!("woken by a worker with {message:?}");
+        match self.dispatch(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+}
+
+/// Cloneable handle the operations of a worker ring use to release their connections.
+#[derive(Clone)]
+pub struct WorkerHandle {
+    topology: AcceptTopology,
+    index: usize,
+    released: Rc<Cell<bool>>,
+}
+
+impl Debug for WorkerHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WorkerHandle")
+            .field("index", &self.index)
+            .field("open", &self.open())
+            .finish_non_exhaustive()
+    }
+}
+
+impl WorkerHandle {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Connections of this worker that were not released yet.
+    pub fn open(&self) -> usize {
+        self.topology.open(self.index)
+    }
+
+    /// Marks one connection handed to this worker as done, a paused acceptor is woken during
+    /// the next housekeeping.
+    pub fn release(&self) {
+        self.topology.release(self.index);
+        self.released.set(true);
+    }
+}
+
+/// Receives the connections of one worker, see the [module docs](self).
+pub struct WorkerOp {
+    handle: WorkerHandle,
+    on_connection: Box<dyn FnMut(Connection) -> OpControlFlow>,
+}
+
+impl Debug for WorkerOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WorkerOp")
+            .field("handle", &self.handle)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WorkerOp {
+    pub fn handle(&self) -> WorkerHandle {
+        self.handle.clone()
+    }
+}
+
+impl RingOperation for WorkerOp {
+    type RingData = ();
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        if res < 0 {
+            let e = io::Error::from_raw_os_error(-res);
+            debug!("unable to wake the acceptor: {e}");
+            return (ControlFlow::Warn(e.into()), None);
+        }
+        (ControlFlow::Continue, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        let shared = &self.handle.topology.shared;
+        if !self.handle.released.replace(false) || !shared.paused.swap(false, Ordering::SeqCst) {
+            return ControlFlow::Continue;
+        }
+
+        let Some(acceptor) = *shared.acceptor.lock().unwrap() else {
+            warn!(
+                "worker {} can not wake an unregistered acceptor",
+                self.handle.index
+            );
+            return ControlFlow::Continue;
+        };
+        trace!("worker {} wakes the acceptor", self.handle.index);
+        let message = OutgoingMessage::data(acceptor, self.handle.index as u64, 0).build();
+        match submitter.push(message, ()) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e.into()),
+        }
+    }
+
+    fn on_message<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        message: RingMessage,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        let connection = match message {
+            RingMessage::Data { value, .. } => {
+                Connection::Fd(unsafe { OwnedFd::from_raw_fd(value) })
+            }
+            RingMessage::Fd { slot, .. } => Connection::Fixed(slot),
+        };
+        trace!("worker {} got {connection:?}", self.handle.index);
+        (self.on_connection)(connection)
+    }
+}
@@ -0,0 +1,148 @@
+//! Where the rings of a pool (and their io-wq workers) run.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+use crate::pool::allowed_cpus;
+
+/// How [`RingPool`](crate::pool::RingPool) places its ring threads.
+///
+/// Every pinned ring also gets an io-wq affinity: the CPUs of the NUMA node its thread runs
+/// on, see [`RingContext::register_iowq_affinity`](crate::pool::RingContext::register_iowq_affinity).
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub enum AffinityPolicy {
+    /// Leaves thread placement to the scheduler.
+    #[default]
+    Unpinned,
+    /// Pins ring `i` to `cpus[i % cpus.len()]`.
+    Cpus(Vec<usize>),
+    /// Pins ring `i` to the `i`-th CPU the process may run on (wrapping around).
+    Allowed {
+        /// Only uses the first hardware thread of every core.
+        skip_smt: bool,
+    },
+    /// Spreads the rings round-robin across NUMA nodes, then across the CPUs of every node.
+    NumaBalanced {
+        /// Only uses the first hardware thread of every core.
+        skip_smt: bool,
+    },
+}
+
+/// Where a single ring runs.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Placement {
+    pub cpu: Option<usize>,
+    /// CPUs the io-wq workers of the ring may run on, empty to leave them alone
+    pub iowq_cpus: Vec<usize>,
+}
+
+impl AffinityPolicy {
+    /// Placement of `rings` rings.
+    pub fn plan(&self, rings: usize) -> io::Result<Vec<Placement>> {
+        let cpus = match self {
+            AffinityPolicy::Unpinned => return Ok(vec![Placement::default(); rings]),
+            AffinityPolicy::Cpus(cpus) => cpus.clone(),
+            AffinityPolicy::Allowed { skip_smt } => candidates(*skip_smt)?,
+            AffinityPolicy::NumaBalanced { skip_smt } => {
+                let mut nodes = BTreeMap::<usize, Vec<usize>>::new();
+                for cpu in candidates(*skip_smt)? {
+                    nodes.entry(node_of(cpu)).or_default().push(cpu);
+                }
+                interleave(nodes.into_values().collect())
+            }
+        };
+
+        if cpus.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "affinity policy selects no CPU",
+            ));
+        }
+
+        Ok((0..rings)
+            .map(|index| {
+                let cpu = cpus[index % cpus.len()];
+                Placement {
+                    cpu: Some(cpu),
+                    iowq_cpus: node_cpus(node_of(cpu)).unwrap_or_else(|| vec![cpu]),
+                }
+            })
+            .collect())
+    }
+}
+
+/// `[[0, 1], [2, 3]]` -> `[0, 2, 1, 3]`
+fn interleave(nodes: Vec<Vec<usize>>) -> Vec<usize> {
+    let longest = nodes.iter().map(Vec::len).max().unwrap_or(0);
+    (0..longest)
+        .flat_map(|i| nodes.iter().filter_map(move |cpus| cpus.get(i).copied()))
+        .collect()
+}
+
+fn candidates(skip_smt: bool) -> io::Result<Vec<usize>> {
+    let cpus = allowed_cpus()?;
+    if !skip_smt {
+        return Ok(cpus);
+    }
+
+    Ok(cpus
+        .iter()
+        .copied()
+        .filter(|&cpu| {
+            // keep the first allowed sibling of every core
+            let siblings = read_cpu_list(&format!(
+                "/sys/devices/system/cpu/cpu{cpu}/topology/thread_siblings_list"
+            ))
+            .unwrap_or_else(|| vec![cpu]);
+            siblings
+                .iter()
+                .find(|sibling| cpus.contains(sibling))
+                .is_none_or(|&first| first == cpu)
+        })
+        .collect())
+}
+
+fn node_of(cpu: usize) -> usize {
+    let Ok(entries) = fs::read_dir("/sys/devices/system/node") else {
+        return 0;
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("node")?
+                .parse::<usize>()
+                .ok()
+        })
+        .find(|&node| node_cpus(node).is_some_and(|cpus| cpus.contains(&cpu)))
+        .unwrap_or(0)
+}
+
+fn node_cpus(node: usize) -> Option<Vec<usize>> {
+    read_cpu_list(&format!("/sys/devices/system/node/node{node}/cpulist"))
+}
+
+/// Parses the kernel's cpu list format, e.g. `0-3,8,10-11`.
+fn read_cpu_list(path: &str) -> Option<Vec<usize>> {
+    let list = fs::read_to_string(path).ok()?;
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((start, end)) => cpus.extend(start.parse::<usize>().ok()?..=end.parse().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+pub(crate) fn cpu_set(cpus: &[usize]) -> libc::cpu_set_t {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in cpus {
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    set
+}
@@ -0,0 +1,395 @@
+//! Load reporting and rebalancing of new work across the rings of a pool.
+//!
+//! Every ring reports its load to a shared [`LoadBoard`], either manually through a
+//! [`LoadReporter`] or, for its completion queue backlog, automatically with
+//! `Ring::report_load`. A [`Rebalancer`] consults the board whenever new work arrives (e.g. an
+//! accepted connection) and hands it to the least loaded ring with a message.
+//!
+//! The board also collects the submission and completion counters of every ring that reports to
+//! it, see [`LoadBoard::counters`] and [`RingPool::stats`](crate::pool::RingPool::stats).
+
+use std::fmt::{Debug, Formatter};
+use std::os::fd::{FromRawFd, IntoRawFd, OwnedFd};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use tracing::trace;
+
+use crate::message::{MessageTarget, RingMessage};
+use crate::ops::MsgRingHandle;
+
+#[derive(Debug, Default)]
+struct Slot {
+    in_flight: AtomicUsize,
+    cq_backlog: AtomicUsize,
+    submitted: AtomicU64,
+    completed: AtomicU64,
+    backlog_spills: AtomicU64,
+    // 0 if the ring is unreachable, see `encode_target`
+    target: AtomicU64,
+}
+
+const REACHABLE: u64 = 1 << 63;
+
+fn encode_target(target: MessageTarget) -> u64 {
+    REACHABLE | (target.ring_fd() as u32 as u64) << 8 | target.operation() as u64
+}
+
+fn decode_target(target: u64) -> Option<MessageTarget> {
+    if target & REACHABLE == 0 {
+        return None;
+    }
+    Some(MessageTarget::new(
+        (target >> 8) as u32 as i32,
+        target as u8,
+    ))
+}
+
+/// Load of a single ring at the time it was read.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct RingLoad {
+    /// As reported by the ring's operations, e.g. open connections or requests in flight
+    pub in_flight: usize,
+    /// Completions waiting to be handled when the ring last synced its completion queue
+    pub cq_backlog: usize,
+    /// Whether the ring registered a target and can receive work
+    pub reachable: bool,
+}
+
+impl RingLoad {
+    #[inline]
+    pub fn total(&self) -> usize {
+        self.in_flight + self.cq_backlog
+    }
+}
+
+/// Cumulative counters and current load of a single ring.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct RingCounters {
+    /// SQEs submitted to the kernel
+    pub submitted: u64,
+    /// CQEs the ring handled
+    pub completed: u64,
+    /// Batches of SQEs that did not fit into the submission queue and waited in the backlog
+    pub backlog_spills: u64,
+    pub in_flight: usize,
+    pub cq_backlog: usize,
+}
+
+impl RingCounters {
+    /// Sum of the counters of several rings.
+    pub fn sum<'a>(counters: impl IntoIterator<Item = &'a RingCounters>) -> Self {
+        counters
+            .into_iter()
+            .fold(Self::default(), |total, counters| Self {
+                submitted: total.submitted + counters.submitted,
+                completed: total.completed + counters.completed,
+                backlog_spills: total.backlog_spills + counters.backlog_spills,
+                in_flight: total.in_flight + counters.in_flight,
+                cq_backlog: total.cq_backlog + counters.cq_backlog,
+            })
+    }
+}
+
+/// Shared load of every ring in a pool, cheap to clone.
+///
+/// The board starts with one slot per ring and grows when a reporter for a new ring index is
+/// created, e.g. by a ring added with [`RingPool::add_ring`](crate::pool::RingPool::add_ring).
+#[derive(Clone)]
+pub struct LoadBoard {
+    slots: Arc<RwLock<Vec<Arc<Slot>>>>,
+}
+
+impl Debug for LoadBoard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.loads()).finish()
+    }
+}
+
+impl LoadBoard {
+    pub fn new(rings: usize) -> Self {
+        Self {
+            slots: Arc::new(RwLock::new(
+                (0..rings).map(|_| Default::default()).collect(),
+            )),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn slot(&self, index: usize) -> Arc<Slot> {
+        let slots = self.slots.read().unwrap();
+        match slots.get(index) {
+            Some(slot) => slot.clone(),
+            None => panic!("ring {index} out of range"),
+        }
+    }
+
+    /// Makes ring `index` reachable for work migrated by a [`Rebalancer`], `target` addresses
+    /// the operation receiving it. A ring rebuilt after a failure registers its new target.
+    pub fn register(&self, index: usize, target: MessageTarget) {
+        self.slot(index)
+            .target
+            .store(encode_target(target), Ordering::Release);
+    }
+
+    /// Stops migrating work to ring `index` and clears its load, e.g. before it is retired.
+    pub fn unregister(&self, index: usize) {
+        let slot = self.slot(index);
+        slot.target.store(0, Ordering::Release);
+        slot.in_flight.store(0, Ordering::Relaxed);
+        slot.cq_backlog.store(0, Ordering::Relaxed);
+    }
+
+    /// The reporter of ring `index`, grows the board if needed.
+    pub fn reporter(&self, index: usize) -> LoadReporter {
+        let mut slots = self.slots.write().unwrap();
+        if slots.len() <= index {
+            slots.resize_with(index + 1, Default::default);
+        }
+        LoadReporter {
+            board: self.clone(),
+            slot: slots[index].clone(),
+            index,
+        }
+    }
+
+    pub fn load(&self, index: usize) -> RingLoad {
+        Self::load_of(&self.slot(index))
+    }
+
+    pub fn loads(&self) -> Vec<RingLoad> {
+        self.slots
+            .read()
+            .unwrap()
+            .iter()
+            .map(|slot| Self::load_of(slot))
+            .collect()
+    }
+
+    fn load_of(slot: &Slot) -> RingLoad {
+        RingLoad {
+            in_flight: slot.in_flight.load(Ordering::Relaxed),
+            cq_backlog: slot.cq_backlog.load(Ordering::Relaxed),
+            reachable: slot.target.load(Ordering::Acquire) & REACHABLE != 0,
+        }
+    }
+
+    pub fn target(&self, index: usize) -> Option<MessageTarget> {
+        decode_target(self.slot(index).target.load(Ordering::Acquire))
+    }
+
+    pub fn counters(&self, index: usize) -> RingCounters {
+        let slot = self.slot(index);
+        RingCounters {
+            submitted: slot.submitted.load(Ordering::Relaxed),
+            completed: slot.completed.load(Ordering::Relaxed),
+            backlog_spills: slot.backlog_spills.load(Ordering::Relaxed),
+            in_flight: slot.in_flight.load(Ordering::Relaxed),
+            cq_backlog: slot.cq_backlog.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Reports the load of one ring to its [`LoadBoard`].
+#[derive(Clone)]
+pub struct LoadReporter {
+    board: LoadBoard,
+    slot: Arc<Slot>,
+    index: usize,
+}
+
+impl Debug for LoadReporter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoadReporter")
+            .field("index", &self.index)
+            .field("load", &LoadBoard::load_of(&self.slot))
+            .finish()
+    }
+}
+
+impl LoadReporter {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn board(&self) -> &LoadBoard {
+        &self.board
+    }
+
+    #[inline]
+    pub fn add_in_flight(&self, n: usize) {
+        self.slot.in_flight.fetch_add(n, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn sub_in_flight(&self, n: usize) {
+        let _ = self
+            .slot
+            .in_flight
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+                Some(v.saturating_sub(n))
+            });
+    }
+
+    #[inline]
+    pub fn set_in_flight(&self, n: usize) {
+        self.slot.in_flight.store(n, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn set_cq_backlog(&self, n: usize) {
+        self.slot.cq_backlog.store(n, Ordering::Relaxed);
+    }
+
+    /// Counts `n` SQEs submitted by the ring, called by `Ring::run`.
+    #[inline]
+    pub fn add_submitted(&self, n: usize) {
+        self.slot.submitted.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Counts `n` CQEs handled by the ring, called by `Ring::run`.
+    #[inline]
+    pub fn add_completed(&self, n: usize) {
+        self.slot.completed.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Counts `n` batches spilled into the backlog, called by `Ring::run`.
+    #[inline]
+    pub fn add_backlog_spills(&self, n: usize) {
+        self.slot
+            .backlog_spills
+            .fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
+
+/// Decides which ring takes new work.
+pub trait BalancePolicy: Debug + Send + Sync {
+    /// Returns the index of the ring that should handle new work arriving at ring `local`.
+    fn pick(&self, local: usize, loads: &[RingLoad]) -> usize;
+}
+
+/// Keeps work local unless another reachable ring carries at least `threshold` less load, then
+/// picks the least loaded ring.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct LeastLoaded {
+    pub threshold: usize,
+}
+
+impl Default for LeastLoaded {
+    fn default() -> Self {
+        Self { threshold: 8 }
+    }
+}
+
+impl BalancePolicy for LeastLoaded {
+    fn pick(&self, local: usize, loads: &[RingLoad]) -> usize {
+        let local_load = loads[local].total();
+        loads
+            .iter()
+            .enumerate()
+            .filter(|(_, load)| load.reachable)
+            .min_by_key(|(_, load)| load.total())
+            .filter(|(_, load)| load.total() + self.threshold <= local_load)
+            .map_or(local, |(index, _)| index)
+    }
+}
+
+/// Where a [`Rebalancer`] put a piece of work.
+#[derive(Debug)]
+pub enum Route<T> {
+    /// Handle it on this ring.
+    Local(T),
+    /// Sent to ring `index`.
+    Migrated(usize),
+}
+
+/// Routes new work arriving at one ring according to a [`BalancePolicy`].
+#[derive(Debug, Clone)]
+pub struct Rebalancer {
+    board: LoadBoard,
+    local: usize,
+    policy: Arc<dyn BalancePolicy>,
+}
+
+impl Rebalancer {
+    pub fn new(board: LoadBoard, local: usize, policy: impl BalancePolicy + 'static) -> Self {
+        Self {
+            board,
+            local,
+            policy: Arc::new(policy),
+        }
+    }
+
+    /// Shares one policy between the rebalancers of all rings.
+    pub fn with_shared_policy(
+        board: LoadBoard,
+        local: usize,
+        policy: Arc<dyn BalancePolicy>,
+    ) -> Self {
+        Self {
+            board,
+            local,
+            policy,
+        }
+    }
+
+    /// The ring that should take new work, `local` if the policy picked a ring that has not
+    /// registered a target (yet).
+    pub fn pick(&self) -> (usize, Option<MessageTarget>) {
+        let index = self.policy.pick(self.local, &self.board.loads());
+        match (index == self.local, self.board.target(index)) {
+            (false, Some(target)) => (index, Some(target)),
+            _ => (self.local, None),
+        }
+    }
+
+    /// Routes `payload`, migrated payloads are queued on `messages` and arrive as
+    /// [`RingMessage::Data`] with `value` on the picked ring.
+    pub fn route(&self, payload: u64, value: i32, messages: &MsgRingHandle) -> Route<u64> {
+        match self.pick() {
+            (index, Some(target)) => {
+                trace!(
+                    "migrating {payload} from ring {} to ring {index}",
+                    self.local
+                );
+                messages.send(target, payload, value);
+                Route::Migrated(index)
+            }
+            _ => Route::Local(payload),
+        }
+    }
+
+    /// Routes an fd, e.g. an accepted connection. A migrated fd is owned by the message until
+    /// the receiving ring takes it back with [`fd_from_message`].
+    ///
+    /// If the message can not be delivered the fd leaks, unless the `MsgRingOp` closes it in
+    /// its `on_failure` callback.
+    pub fn route_fd(&self, fd: OwnedFd, messages: &MsgRingHandle) -> Route<OwnedFd> {
+        match self.pick() {
+            (index, Some(target)) => {
+                trace!(
+                    "migrating fd {fd:?} from ring {} to ring {index}",
+                    self.local
+                );
+                messages.send(target, fd.into_raw_fd() as u64, 0);
+                Route::Migrated(index)
+            }
+            _ => Route::Local(fd),
+        }
+    }
+}
+
+/// Takes ownership of an fd migrated with [`Rebalancer::route_fd`].
+///
+/// # Safety
+/// `message` has to be a message sent by [`Rebalancer::route_fd`] and may only be taken once.
+pub unsafe fn fd_from_message(message: RingMessage) -> OwnedFd {
+    OwnedFd::from_raw_fd(message.payload() as i32)
+}
@@ -0,0 +1,271 @@
+//! A barrier all rings of a pool reach without blocking their threads.
+//!
+//! Every ring has a [`BarrierOp`] and arrives through its [`BarrierHandle`], then keeps running.
+//! The ring arriving last runs the barrier's leader callback (e.g. to swap a configuration or
+//! advance a reclamation epoch) and releases all rings with a message, each ring's `on_release`
+//! then runs with the generation that completed. Between arriving and being released a ring must
+//! not touch the state the barrier protects.
+
+use std::cell::Cell;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use io_uring::cqueue::Entry;
+use tracing::{debug, trace};
+
+use crate::message::{MessageTarget, OutgoingMessage, RingMessage};
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+type Leader = Box<dyn Fn(u64) + Send + Sync>;
+
+#[derive(Debug)]
+struct State {
+    generation: u64,
+    arrived: Vec<bool>,
+    targets: Vec<Option<MessageTarget>>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    leader: Option<Leader>,
+}
+
+/// Shared by all rings taking part, cheap to clone and [`Send`].
+#[derive(Clone)]
+pub struct PoolBarrier {
+    shared: Arc<Shared>,
+}
+
+impl Debug for PoolBarrier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PoolBarrier")
+            .field("state", &self.shared.state)
+            .field("leader", &self.shared.leader.is_some())
+            .finish()
+    }
+}
+
+impl PoolBarrier {
+    /// A barrier for the rings `0..parties`.
+    pub fn new(parties: usize) -> Self {
+        Self::build(parties, None)
+    }
+
+    /// Like [`PoolBarrier::new`], `leader` is called with the generation on the ring that
+    /// arrives last, before any ring is released. It must not use the barrier itself.
+    pub fn with_leader(parties: usize, leader: impl Fn(u64) + Send + Sync + 'static) -> Self {
+        Self::build(parties, Some(Box::new(leader)))
+    }
+
+    fn build(parties: usize, leader: Option<Leader>) -> Self {
+        assert!(parties > 0, "a barrier needs at least one party");
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    generation: 0,
+                    arrived: vec![false; parties],
+                    targets: vec![None; parties],
+                }),
+                leader,
+            }),
+        }
+    }
+
+    pub fn parties(&self) -> usize {
+        self.shared.state.lock().unwrap().arrived.len()
+    }
+
+    /// The generation the rings are currently gathering for, starting at `0`.
+    pub fn generation(&self) -> u64 {
+        self.shared.state.lock().unwrap().generation
+    }
+
+    /// Number of rings that arrived at the current generation.
+    pub fn arrived(&self) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        state.arrived.iter().filter(|&&arrived| arrived).count()
+    }
+
+    /// Creates the operation of ring `index`. `on_release` is called on that ring whenever a
+    /// generation it arrived at completes.
+    pub fn op(
+        &self,
+        index: usize,
+        on_release: impl FnMut(u64) -> OpControlFlow + 'static,
+    ) -> BarrierOp {
+        assert!(index < self.parties(), "ring {index} is not a party");
+        BarrierOp {
+            barrier: self.clone(),
+            index,
+            handle: BarrierHandle {
+                pending: Default::default(),
+                waiting: Default::default(),
+            },
+            on_release: Box::new(on_release),
+        }
+    }
+
+    /// Sets the address of ring `index`'s [`BarrierOp`], see `Ring::message_target`. A ring
+    /// has to be registered before it arrives.
+    pub fn register(&self, index: usize, target: MessageTarget) {
+        self.shared.state.lock().unwrap().targets[index] = Some(target);
+    }
+
+    /// Marks ring `index` as arrived, returns the generation and the targets to release if it
+    /// was the last one.
+    fn arrive(&self, index: usize) -> io::Result<Option<(u64, Vec<MessageTarget>)>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.targets[index].is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "ring arrived at the barrier before registering",
+            ));
+        }
+
+        state.arrived[index] = true;
+        trace!("ring {index} arrived at generation {}", state.generation);
+        if !state.arrived.iter().all(|&arrived| arrived) {
+            return Ok(None);
+        }
+
+        let generation = state.generation;
+        if let Some(leader) = &self.shared.leader {
+            leader(generation);
+        }
+        state.generation += 1;
+        state.arrived.fill(false);
+        debug!("barrier generation {generation} complete");
+        Ok(Some((
+            generation,
+            state.targets.iter().flatten().copied().collect(),
+        )))
+    }
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to arrive at the barrier.
+#[derive(Debug, Clone)]
+pub struct BarrierHandle {
+    pending: Rc<Cell<bool>>,
+    waiting: Rc<Cell<bool>>,
+}
+
+impl BarrierHandle {
+    /// Arrives at the current generation during the next housekeeping, does nothing while the
+    /// ring is still waiting for the previous release.
+    pub fn arrive(&self) {
+        if !self.waiting.get() {
+            self.pending.set(true);
+        }
+    }
+
+    /// The ring arrived and was not released yet.
+    pub fn is_waiting(&self) -> bool {
+        self.waiting.get() || self.pending.get()
+    }
+}
+
+/// The part of a [`PoolBarrier`] living on one ring, created with [`PoolBarrier::op`].
+pub struct BarrierOp {
+    barrier: PoolBarrier,
+    index: usize,
+    handle: BarrierHandle,
+    on_release: Box<dyn FnMut(u64) -> OpControlFlow>,
+}
+
+impl Debug for BarrierOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BarrierOp")
+            .field("index", &self.index)
+            .field("handle", &self.handle)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BarrierOp {
+    pub fn handle(&self) -> BarrierHandle {
+        self.handle.clone()
+    }
+}
+
+impl RingOperation for BarrierOp {
+    /// The released generation
+    type RingData = u64;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        Ok(())
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let res = completion_entry.result();
+        if res < 0 {
+            // the ring is gone, a pool shutting down does not wait for it
+            let e = io::Error::from_raw_os_error(-res);
+            debug!("unable to release a ring from generation {ring_data}: {e}");
+            return (ControlFlow::Warn(e.into()), None);
+        }
+        (ControlFlow::Continue, None)
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        if !self.handle.pending.replace(false) {
+            return ControlFlow::Continue;
+        }
+
+        let release = match self.barrier.arrive(self.index) {
+            Ok(release) => release,
+            Err(e) => return ControlFlow::Warn(e.into()),
+        };
+        self.handle.waiting.set(true);
+
+        let Some((generation, targets)) = release else {
+            return ControlFlow::Continue;
+        };
+        let payload = generation & RingMessage::MAX_PAYLOAD;
+        let entries = targets
+            .iter()
+            .map(|&target| OutgoingMessage::data(target, payload, 0).build())
+            .collect();
+        let data = vec![generation; targets.len()].into_boxed_slice();
+        match submitter.push_slice(entries, data) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e.into()),
+        }
+    }
+
+    fn on_message<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        message: RingMessage,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        trace!("ring {} released from {message:?}", self.index);
+        self.handle.waiting.set(false);
+        (self.on_release)(message.payload())
+    }
+}
@@ -0,0 +1,326 @@
+//! A registered file table shared by all rings of a pool.
+//!
+//! io_uring has no way to share one registered file table between rings, so every ring keeps a
+//! mirror: a sparse table of the same size, updated by its [`FileMirrorOp`] whenever a slot of
+//! the [`SharedFileTable`] changes. A fixed file index therefore refers to the same file on
+//! every ring, fds can be passed between rings as plain slot numbers.
+//!
+//! Mirrors are updated asynchronously, a ring may learn about a slot (e.g. through a message)
+//! before its mirror applied the update. Check [`FileMirrorHandle::is_installed`] before using a
+//! slot that was installed by another ring.
+
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::FilesUpdate;
+use tracing::{debug, trace};
+
+use crate::ops::{Error, OpControlFlow, RemoteOp, RingHandle};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// A change of one slot, `fd` is `None` if the slot was cleared.
+#[derive(Debug, Clone)]
+pub struct FileUpdate {
+    pub slot: u32,
+    pub fd: Option<Arc<OwnedFd>>,
+}
+
+#[derive(Debug)]
+struct Slots {
+    fds: Vec<Option<Arc<OwnedFd>>>,
+    free: Vec<u32>,
+    mirrors: Vec<RingHandle<FileUpdate>>,
+}
+
+/// The file table all mirrors follow, cheap to clone and [`Send`].
+///
+/// The table owns the installed fds, a removed fd is closed once every mirror dropped it.
+#[derive(Debug, Clone)]
+pub struct SharedFileTable {
+    slots: Arc<Mutex<Slots>>,
+    size: u32,
+}
+
+impl SharedFileTable {
+    pub fn new(size: u32) -> Self {
+        Self {
+            slots: Arc::new(Mutex::new(Slots {
+                fds: vec![None; size as usize],
+                free: (0..size).rev().collect(),
+                mirrors: Vec::new(),
+            })),
+            size,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Number of installed fds.
+    pub fn len(&self) -> usize {
+        let slots = self.slots.lock().unwrap();
+        self.size as usize - slots.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Registers a sparse file table on the ring `submitter` belongs to and creates the
+    /// operation keeping it in sync. Slots installed so far are applied during its setup.
+    ///
+    /// Has to be called on the ring thread, before the ring is built.
+    pub fn mirror(&self, submitter: &io_uring::Submitter) -> io::Result<FileMirrorOp> {
+        submitter.register_files_sparse(self.size)?;
+
+        let pending: Rc<RefCell<Vec<FileUpdate>>> = Default::default();
+        let queue = pending.clone();
+        let remote = RemoteOp::new(move |update| {
+            queue.borrow_mut().push(update);
+            ControlFlow::Continue
+        })?;
+
+        let mut slots = self.slots.lock().unwrap();
+        pending
+            .borrow_mut()
+            .extend(slots.fds.iter().enumerate().filter_map(|(slot, fd)| {
+                Some(FileUpdate {
+                    slot: slot as u32,
+                    fd: Some(fd.clone()?),
+                })
+            }));
+        slots.mirrors.push(remote.handle());
+        debug!("{} mirrors of the shared file table", slots.mirrors.len());
+
+        Ok(FileMirrorOp {
+            remote,
+            pending,
+            handle: FileMirrorHandle {
+                installed: Rc::new(RefCell::new(vec![false; self.size as usize])),
+            },
+            latest: vec![0; self.size as usize],
+            seq: 0,
+        })
+    }
+
+    /// Installs `fd` into a free slot of every mirror.
+    pub fn install(&self, fd: OwnedFd) -> io::Result<u32> {
+        let mut slots = self.slots.lock().unwrap();
+        let Some(slot) = slots.free.pop() else {
+            return Err(io::Error::from_raw_os_error(libc::ENFILE));
+        };
+
+        let fd = Arc::new(fd);
+        slots.fds[slot as usize] = Some(fd.clone());
+        Self::broadcast(&mut slots, FileUpdate { slot, fd: Some(fd) });
+        Ok(slot)
+    }
+
+    /// Clears `slot` on every mirror, returns `false` if it was empty.
+    pub fn remove(&self, slot: u32) -> bool {
+        let mut slots = self.slots.lock().unwrap();
+        if slots
+            .fds
+            .get_mut(slot as usize)
+            .and_then(Option::take)
+            .is_none()
+        {
+            return false;
+        }
+
+        slots.free.push(slot);
+        Self::broadcast(&mut slots, FileUpdate { slot, fd: None });
+        true
+    }
+
+    /// The fd installed in `slot`.
+    pub fn get(&self, slot: u32) -> Option<Arc<OwnedFd>> {
+        self.slots.lock().unwrap().fds.get(slot as usize)?.clone()
+    }
+
+    fn broadcast(slots: &mut Slots, update: FileUpdate) {
+        trace!("broadcast {update:?}");
+        // mirrors of rings that are gone fail and are forgotten
+        slots
+            .mirrors
+            .retain(|mirror| mirror.send(update.clone()).is_ok());
+    }
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to check the state of the
+/// ring's mirror.
+#[derive(Debug, Clone)]
+pub struct FileMirrorHandle {
+    installed: Rc<RefCell<Vec<bool>>>,
+}
+
+impl FileMirrorHandle {
+    /// Whether the fd currently installed in `slot` of the shared table is usable on this ring.
+    pub fn is_installed(&self, slot: u32) -> bool {
+        self.installed
+            .borrow()
+            .get(slot as usize)
+            .copied()
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Debug)]
+pub struct MirrorUpdate {
+    slot: u32,
+    seq: u64,
+    fd: Box<RawFd>,
+    // keeps the fd open until the kernel took its own reference
+    _owner: Option<Arc<OwnedFd>>,
+}
+
+#[derive(Debug)]
+pub enum FileMirrorData {
+    Wake(()),
+    Update(MirrorUpdate),
+}
+
+/// Keeps the registered file table of one ring in sync with a [`SharedFileTable`], created with
+/// [`SharedFileTable::mirror`].
+pub struct FileMirrorOp {
+    remote: RemoteOp<FileUpdate>,
+    pending: Rc<RefCell<Vec<FileUpdate>>>,
+    handle: FileMirrorHandle,
+    // sequence number of the last update submitted per slot
+    latest: Vec<u64>,
+    seq: u64,
+}
+
+impl Debug for FileMirrorOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileMirrorOp")
+            .field("remote", &self.remote)
+            .field("pending", &self.pending.borrow().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl FileMirrorOp {
+    pub fn handle(&self) -> FileMirrorHandle {
+        self.handle.clone()
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, FileMirrorData)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<FileMirrorData, W>,
+    ) -> Result<(), Error> {
+        let updates = std::mem::take(&mut *self.pending.borrow_mut());
+        if updates.is_empty() {
+            return Ok(());
+        }
+
+        let mut entries = Vec::with_capacity(updates.len());
+        let mut data = Vec::with_capacity(updates.len());
+        for update in updates {
+            self.seq += 1;
+            self.latest[update.slot as usize] = self.seq;
+            if update.fd.is_none() {
+                self.handle.installed.borrow_mut()[update.slot as usize] = false;
+            }
+
+            let fd = Box::new(update.fd.as_ref().map_or(-1, |fd| fd.as_raw_fd()));
+            entries.push(
+                FilesUpdate::new(fd.as_ref(), 1)
+                    .offset(update.slot as i32)
+                    .build(),
+            );
+            data.push(FileMirrorData::Update(MirrorUpdate {
+                slot: update.slot,
+                seq: self.seq,
+                fd,
+                _owner: update.fd,
+            }));
+        }
+
+        submitter.push_slice(entries.into_boxed_slice(), data.into_boxed_slice())?;
+        Ok(())
+    }
+}
+
+impl RingOperation for FileMirrorOp {
+    type RingData = FileMirrorData;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.remote
+            .setup(submitter.map_data(FileMirrorData::Wake))?;
+        self.flush(&mut submitter)
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        match ring_data {
+            FileMirrorData::Wake(data) => {
+                let (flow, data) = self.remote.on_completion(
+                    completion_entry,
+                    data,
+                    submitter.map_data(FileMirrorData::Wake),
+                );
+                (flow, data.map(FileMirrorData::Wake))
+            }
+            FileMirrorData::Update(update) => {
+                let res = completion_entry.result();
+                if res < 0 {
+                    let e = io::Error::from_raw_os_error(-res);
+                    return (ControlFlow::Warn(e.into()), None);
+                }
+
+                trace!("mirrored slot {}: {}", update.slot, update.fd);
+                // only the last update submitted for a slot decides its state
+                if self.latest[update.slot as usize] == update.seq {
+                    self.handle.installed.borrow_mut()[update.slot as usize] = *update.fd >= 0;
+                }
+                (ControlFlow::Continue, None)
+            }
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        _completion_entry: Entry,
+        _ring_data: Self::RingData,
+        _submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        Ok(())
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match self.flush(&mut submitter) {
+            Ok(()) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Error(e),
+        }
+    }
+
+    fn on_message<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        message: crate::message::RingMessage,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        self.remote
+            .on_message(message, submitter.map_data(FileMirrorData::Wake))
+    }
+}
@@ -0,0 +1,411 @@
+//! Moving established connections between the rings of a pool.
+//!
+//! The operation owning the connections implements [`Migratable`] and is wrapped in a
+//! [`MigratingOp`], which is added to the ring in its place. Asking its [`MigrationHandle`] to
+//! migrate a connection calls [`Migratable::export`] on the owning operation, parks the
+//! exported state in a [`MigrationTable`] shared by all rings and sends a message with the
+//! parcel's token to the target ring, where [`Migratable::import`] takes it over.
+//!
+//! Connections on fixed files are passed with `IORING_OP_MSG_RING` into a slot the kernel
+//! allocates in the receiving ring's file table (which therefore needs free sparse slots), the
+//! sender's slot is closed once the message was delivered. Regular fds travel with the parcel.
+//! If a message can not be delivered the connection is imported back into the sending ring.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::OwnedFd;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Close;
+use io_uring::squeue::Flags;
+use io_uring::types::Fixed;
+use tracing::{debug, trace, warn};
+
+use crate::message::{MessageTarget, OutgoingMessage, RingMessage};
+use crate::ops::{Error, OpControlFlow};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// `value` of the data messages announcing a parcel, other messages go to the wrapped operation
+const MIGRATION: i32 = i32::from_be_bytes(*b"MIGR");
+
+/// The fd of a migrating connection.
+#[derive(Debug)]
+pub enum Connection {
+    Fd(OwnedFd),
+    /// A slot of the ring's fixed file table
+    Fixed(u32),
+}
+
+/// Hooks of an operation whose connections can move to another ring.
+pub trait Migratable: RingOperation<ControlFlowWarn = Error, ControlFlowError = Error> {
+    /// Identifies a connection of this operation, e.g. its fd or an index into a slab
+    type ConnectionId: Debug;
+    /// Everything the receiving ring needs to continue the connection, e.g. parser state and
+    /// buffered bytes
+    type State: Send + 'static;
+
+    /// Detaches connection `id` and returns its fd and state, `None` if it can not migrate
+    /// right now. The operation must not have requests in flight on an exported connection.
+    fn export(&mut self, id: Self::ConnectionId) -> Option<(Connection, Self::State)>;
+
+    /// Takes over a connection exported by another ring (or handed back by this ring's
+    /// [`MigratingOp`] after a failed migration) and submits its next requests.
+    fn import<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        connection: Connection,
+        state: Self::State,
+        submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow;
+}
+
+struct Parcel<S> {
+    state: S,
+    fd: Option<OwnedFd>,
+}
+
+/// Parcels in transit between rings, shared by the [`MigratingOp`]s of all rings. Cheap to
+/// clone and [`Send`].
+pub struct MigrationTable<S> {
+    parcels: Arc<Mutex<HashMap<u64, Parcel<S>>>>,
+    next: Arc<AtomicU64>,
+}
+
+impl<S> Clone for MigrationTable<S> {
+    fn clone(&self) -> Self {
+        Self {
+            parcels: self.parcels.clone(),
+            next: self.next.clone(),
+        }
+    }
+}
+
+impl<S> Debug for MigrationTable<S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MigrationTable")
+            .field("in_transit", &self.len())
+            .finish()
+    }
+}
+
+impl<S> Default for MigrationTable<S> {
+    fn default() -> Self {
+        Self {
+            parcels: Default::default(),
+            next: Arc::new(AtomicU64::new(1)),
+        }
+    }
+}
+
+impl<S> MigrationTable<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of connections in transit.
+    pub fn len(&self) -> usize {
+        self.parcels.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn park(&self, parcel: Parcel<S>) -> u64 {
+        let token = self.next.fetch_add(1, Ordering::Relaxed) & RingMessage::MAX_PAYLOAD;
+        self.parcels.lock().unwrap().insert(token, parcel);
+        token
+    }
+
+    fn take(&self, token: u64) -> Option<Parcel<S>> {
+        self.parcels.lock().unwrap().remove(&token)
+    }
+}
+
+/// Cloneable handle other operations (on the same ring thread) use to request migrations.
+pub struct MigrationHandle<I> {
+    queue: Rc<RefCell<VecDeque<(I, MessageTarget)>>>,
+}
+
+impl<I> Clone for MigrationHandle<I> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<I> Debug for MigrationHandle<I> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MigrationHandle")
+            .field("queued", &self.queue.borrow().len())
+            .finish()
+    }
+}
+
+impl<I> MigrationHandle<I> {
+    /// Moves connection `id` to the [`MigratingOp`] addressed by `target` (on another ring),
+    /// the connection is exported during the next housekeeping.
+    pub fn migrate(&self, id: I, target: MessageTarget) {
+        self.queue.borrow_mut().push_back((id, target));
+    }
+}
+
+#[derive(Debug)]
+pub enum MigratingData<D> {
+    Inner(D),
+    Sent {
+        token: u64,
+        fixed: Option<u32>,
+    },
+    /// The sender's fixed slot, closed after the message was delivered
+    Closed,
+}
+
+/// Wraps the operation owning the connections, see the [module docs](self).
+pub struct MigratingOp<O: Migratable> {
+    inner: O,
+    table: MigrationTable<O::State>,
+    handle: MigrationHandle<O::ConnectionId>,
+    migrated: u64,
+    imported: u64,
+}
+
+impl<O: Migratable> Debug for MigratingOp<O> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MigratingOp")
+            .field("inner", &self.inner)
+            .field("table", &self.table)
+            .field("migrated", &self.migrated)
+            .field("imported", &self.imported)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<O: Migratable> MigratingOp<O> {
+    pub fn new(inner: O, table: MigrationTable<O::State>) -> Self {
+        Self {
+            inner,
+            table,
+            handle: MigrationHandle {
+                queue: Default::default(),
+            },
+            migrated: 0,
+            imported: 0,
+        }
+    }
+
+    pub fn handle(&self) -> MigrationHandle<O::ConnectionId> {
+        self.handle.clone()
+    }
+
+    pub fn inner(&self) -> &O {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut O {
+        &mut self.inner
+    }
+
+    /// Connections that left this ring.
+    pub fn migrated(&self) -> u64 {
+        self.migrated
+    }
+
+    /// Connections that arrived from other rings.
+    pub fn imported(&self) -> u64 {
+        self.imported
+    }
+
+    fn flush<W: Fn(&mut io_uring::squeue::Entry, MigratingData<O::RingData>)>(
+        &mut self,
+        submitter: &mut SubmissionQueueSubmitter<MigratingData<O::RingData>, W>,
+    ) -> OpControlFlow {
+        loop {
+            let Some((id, target)) = self.handle.queue.borrow_mut().pop_front() else {
+                return ControlFlow::Continue;
+            };
+            let Some((connection, state)) = self.inner.export(id) else {
+                trace!("connection did not export, not migrating it");
+                continue;
+            };
+
+            let result = match connection {
+                Connection::Fd(fd) => {
+                    let token = self.table.park(Parcel {
+                        state,
+                        fd: Some(fd),
+                    });
+                    let message = OutgoingMessage::data(target, token, MIGRATION).build();
+                    submitter.push(message, MigratingData::Sent { token, fixed: None })
+                }
+                Connection::Fixed(slot) => {
+                    let token = self.table.park(Parcel { state, fd: None });
+                    let message = OutgoingMessage::fd(target, token, slot, None)
+                        .build()
+                        .flags(Flags::IO_LINK);
+                    let close = Close::new(Fixed(slot)).build();
+                    submitter.push_multiple(
+                        [message, close],
+                        [
+                            MigratingData::Sent {
+                                token,
+                                fixed: Some(slot),
+                            },
+                            MigratingData::Closed,
+                        ],
+                    )
+                }
+            };
+
+            if let Err(e) = result {
+                return ControlFlow::Error(e.into());
+            }
+        }
+    }
+
+    fn import<W: Fn(&mut io_uring::squeue::Entry, MigratingData<O::RingData>)>(
+        &mut self,
+        connection: Connection,
+        state: O::State,
+        mut submitter: SubmissionQueueSubmitter<MigratingData<O::RingData>, W>,
+    ) -> OpControlFlow {
+        self.inner
+            .import(connection, state, submitter.map_data(MigratingData::Inner))
+    }
+}
+
+impl<O: Migratable> RingOperation for MigratingOp<O> {
+    type RingData = MigratingData<O::RingData>;
+    type SetupError = O::SetupError;
+    type TeardownError = O::TeardownError;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.inner.setup(submitter.map_data(MigratingData::Inner))
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        match ring_data {
+            MigratingData::Inner(data) => {
+                let (flow, data) = self.inner.on_completion(
+                    completion_entry,
+                    data,
+                    submitter.map_data(MigratingData::Inner),
+                );
+                (flow, data.map(MigratingData::Inner))
+            }
+            MigratingData::Sent { token, fixed } => {
+                let res = completion_entry.result();
+                if res >= 0 {
+                    self.migrated += 1;
+                    return (ControlFlow::Continue, None);
+                }
+
+                // the receiving ring never saw the message, the connection stays here
+                let e = io::Error::from_raw_os_error(-res);
+                warn!("unable to migrate connection: {e}");
+                let Some(parcel) = self.table.take(token) else {
+                    return (ControlFlow::Warn(e.into()), None);
+                };
+                let connection = match (fixed, parcel.fd) {
+                    (Some(slot), _) => Connection::Fixed(slot),
+                    (None, Some(fd)) => Connection::Fd(fd),
+                    (None, None) => unreachable!("parcel without fd"),
+                };
+                (self.import(connection, parcel.state, submitter), None)
+            }
+            MigratingData::Closed => {
+                let res = completion_entry.result();
+                match res {
+                    0.. => (ControlFlow::Continue, None),
+                    // the message failed, the slot is still in use
+                    res if res == -libc::ECANCELED => (ControlFlow::Continue, None),
+                    res => (
+                        ControlFlow::Warn(io::Error::from_raw_os_error(-res).into()),
+                        None,
+                    ),
+                }
+            }
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        match ring_data {
+            MigratingData::Inner(data) => self.inner.on_teardown_completion(
+                completion_entry,
+                data,
+                submitter.map_data(MigratingData::Inner),
+            ),
+            MigratingData::Sent { token, .. } => {
+                if completion_entry.result() < 0 {
+                    // nobody imports it anymore, closes a regular fd
+                    self.table.take(token);
+                }
+                Ok(())
+            }
+            MigratingData::Closed => Ok(()),
+        }
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        if let flow @ (ControlFlow::Exit | ControlFlow::Error(_)) = self.flush(&mut submitter) {
+            return flow;
+        }
+        self.inner
+            .housekeeping(submitter.map_data(MigratingData::Inner))
+    }
+
+    fn on_message<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        message: RingMessage,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        let parcel = match message {
+            RingMessage::Data { value, .. } if value != MIGRATION => None,
+            message => self.table.take(message.payload()),
+        };
+        let Some(parcel) = parcel else {
+            // not a migration, e.g. a message for the wrapped operation
+            return self
+                .inner
+                .on_message(message, submitter.map_data(MigratingData::Inner));
+        };
+
+        let connection = match (message, parcel.fd) {
+            (RingMessage::Fd { slot, .. }, _) => Connection::Fixed(slot),
+            (RingMessage::Data { .. }, Some(fd)) => Connection::Fd(fd),
+            (RingMessage::Data { .. }, None) => {
+                return ControlFlow::Warn(
+                    io::Error::new(io::ErrorKind::InvalidData, "migrated fixed file is missing")
+                        .into(),
+                )
+            }
+        };
+
+        debug!("imported {connection:?}");
+        self.imported += 1;
+        self.import(connection, parcel.state, submitter)
+    }
+}
@@ -0,0 +1,897 @@
+//! Running several rings, one per thread.
+
+use std::any::Any;
+use std::fmt::{Debug, Display, Formatter};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, error, warn};
+
+use crate::ops::{OpControlFlow, RemoteOp, RingHandle};
+use crate::pool::affinity::{AffinityPolicy, Placement};
+use crate::pool::balance::{LoadBoard, LoadReporter, RingCounters};
+use crate::pool::scale::{Autoscaler, ScaleAction};
+use crate::pool::supervisor::{PanicPolicy, RestartPolicy, Supervisor, SupervisorEvent};
+use crate::ControlFlow;
+
+pub mod acceptor;
+pub mod affinity;
+pub mod balance;
+pub mod barrier;
+pub mod files;
+pub mod migrate;
+pub mod reuseport;
+pub mod scale;
+pub mod spill;
+pub mod supervisor;
+
+/// What a ring factory gets to know about the ring it builds.
+#[derive(Clone)]
+pub struct RingContext {
+    /// `0..threads`, rings added later continue the sequence
+    pub index: usize,
+    /// The CPU the ring thread is pinned to
+    pub cpu: Option<usize>,
+    /// CPUs the io-wq workers of the ring should run on, see
+    /// [`RingContext::register_iowq_affinity`]
+    pub iowq_cpus: Vec<usize>,
+    shared: Arc<PoolShared>,
+}
+
+impl Debug for RingContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingContext")
+            .field("index", &self.index)
+            .field("cpu", &self.cpu)
+            .field("iowq_cpus", &self.iowq_cpus)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RingContext {
+    /// Restricts the io-wq workers of the ring `submitter` belongs to to
+    /// [`RingContext::iowq_cpus`], does nothing if the pool does not pin its rings.
+    pub fn register_iowq_affinity(&self, submitter: &io_uring::Submitter) -> io::Result<()> {
+        if self.iowq_cpus.is_empty() {
+            return Ok(());
+        }
+        submitter.register_iowq_aff(&affinity::cpu_set(&self.iowq_cpus))
+    }
+
+    /// Reporter for the pool's [`LoadBoard`], pass it to `Ring::report_load` to include the ring
+    /// in [`RingPool::stats`].
+    pub fn load_reporter(&self) -> LoadReporter {
+        self.shared.board.reporter(self.index)
+    }
+
+    /// Creates the operation receiving [`RingPool::shutdown`] and [`RingPool::retire_ring`]
+    /// signals for this ring.
+    ///
+    /// `on_drain` is called with [`DrainSignal::Graceful`], it should stop taking new work and
+    /// exit the ring once the work in flight is done (or right away if there is none).
+    /// [`DrainSignal::Force`] exits the ring without asking `on_drain`, its teardown cancels
+    /// everything still in flight.
+    pub fn drain_op(
+        &self,
+        mut on_drain: impl FnMut(DrainSignal) -> OpControlFlow + 'static,
+    ) -> io::Result<RemoteOp<DrainSignal>> {
+        let op = RemoteOp::new(move |signal| match signal {
+            DrainSignal::Graceful => on_drain(signal),
+            DrainSignal::Force => ControlFlow::Exit,
+        })?;
+
+        let mut rings = self.shared.rings.lock().unwrap();
+        let slot = &mut rings[self.index];
+        if let Some(signal) = slot.requested {
+            let _ = op.handle().send(signal);
+        }
+        slot.drain = Some(op.handle());
+        Ok(op)
+    }
+}
+
+/// Sent to every ring by [`RingPool::shutdown`], or to a single one by
+/// [`RingPool::retire_ring`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DrainSignal {
+    /// Finish the work in flight, then exit.
+    Graceful,
+    /// The grace period is over, exit now.
+    Force,
+}
+
+#[derive(Debug, Default)]
+struct RingSlot {
+    drain: Option<RingHandle<DrainSignal>>,
+    requested: Option<DrainSignal>,
+}
+
+#[derive(Debug)]
+struct PoolShared {
+    rings: Mutex<Vec<RingSlot>>,
+    board: LoadBoard,
+    // set once a panic aborted the pool, see `PanicPolicy::Abort`
+    aborted: AtomicBool,
+}
+
+impl PoolShared {
+    fn add_slot(&self) {
+        let mut rings = self.rings.lock().unwrap();
+        // a ring spawned after the pool was aborted exits right away
+        let requested = self
+            .aborted
+            .load(Ordering::Acquire)
+            .then_some(DrainSignal::Force);
+        rings.push(RingSlot {
+            drain: None,
+            requested,
+        });
+    }
+
+    fn signal(&self, index: usize, signal: DrainSignal) {
+        let mut rings = self.rings.lock().unwrap();
+        let slot = &mut rings[index];
+        slot.requested = Some(signal);
+        if let Some(drain) = &slot.drain {
+            // a ring that is gone does not need to drain
+            let _ = drain.send(signal);
+        }
+    }
+
+    fn broadcast(&self, signal: DrainSignal) {
+        let len = self.rings.lock().unwrap().len();
+        for index in 0..len {
+            self.signal(index, signal);
+        }
+    }
+
+    fn abort(&self) {
+        self.aborted.store(true, Ordering::Release);
+        self.broadcast(DrainSignal::Force);
+    }
+
+    fn requested(&self, index: usize) -> Option<DrainSignal> {
+        self.rings.lock().unwrap()[index].requested
+    }
+}
+
+/// Reports the ring thread as finished, even if it panicked.
+struct FinishGuard {
+    index: usize,
+    finished: Sender<usize>,
+}
+
+impl Drop for FinishGuard {
+    fn drop(&mut self) {
+        let _ = self.finished.send(self.index);
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RingThreadError<E> {
+    #[error("unable to pin ring thread: {0}")]
+    Affinity(io::Error),
+
+    #[error("ring failed: {0:?}")]
+    Ring(E),
+
+    #[error("ring thread panicked: {0}")]
+    Panic(PanicPayload),
+}
+
+/// What a ring thread panicked with.
+pub struct PanicPayload(Box<dyn Any + Send>);
+
+impl PanicPayload {
+    /// The panic message, if the payload is a string.
+    pub fn message(&self) -> Option<&str> {
+        match self.0.downcast_ref::<String>() {
+            Some(message) => Some(message),
+            None => self.0.downcast_ref::<&'static str>().copied(),
+        }
+    }
+
+    /// The payload itself, e.g. to continue the panic with [`std::panic::resume_unwind`].
+    pub fn into_inner(self) -> Box<dyn Any + Send> {
+        self.0
+    }
+}
+
+impl Debug for PanicPayload {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PanicPayload")
+            .field(&self.message())
+            .finish()
+    }
+}
+
+impl Display for PanicPayload {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message().unwrap_or("<non-string panic payload>"))
+    }
+}
+
+/// How a ring that finished ran, see [`RingPool::join`].
+#[derive(Debug, Clone)]
+pub struct RingReport<T> {
+    pub index: usize,
+    /// Time from the start of the ring thread until it finished, including restarts
+    pub duration: Duration,
+    /// As reported through [`RingContext::load_reporter`], all zero if the ring did not report
+    pub counters: RingCounters,
+    /// What the factory returned, e.g. state recovered from the ring's operations
+    pub state: T,
+}
+
+/// A ring that failed, see [`RingPool::join`].
+#[derive(Debug, thiserror::Error)]
+#[error("ring {index} failed after {duration:?}: {error}")]
+pub struct RingError<E> {
+    pub index: usize,
+    /// Time from the start of the ring thread until it finished, including restarts
+    pub duration: Duration,
+    /// As reported through [`RingContext::load_reporter`], all zero if the ring did not report
+    pub counters: RingCounters,
+    pub error: RingThreadError<E>,
+}
+
+/// Errors of all rings that failed.
+#[derive(Debug)]
+pub struct PoolError<E>(pub Vec<RingError<E>>);
+
+impl<E> PoolError<E> {
+    /// Splits the results of [`RingPool::join`] or [`RingPool::shutdown`] into the reports of
+    /// all rings or the errors of those that failed.
+    pub fn check<T>(
+        results: Vec<Result<RingReport<T>, RingError<E>>>,
+    ) -> Result<Vec<RingReport<T>>, Self> {
+        let (reports, errors): (Vec<_>, Vec<_>) = results.into_iter().partition(Result::is_ok);
+        match errors.is_empty() {
+            true => Ok(reports.into_iter().flatten().collect()),
+            false => Err(Self(errors.into_iter().filter_map(Result::err).collect())),
+        }
+    }
+}
+
+impl<E: Debug> Display for PoolError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ring(s) failed", self.0.len())?;
+        for e in &self.0 {
+            write!(f, "; {e}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: Debug> std::error::Error for PoolError<E> {}
+
+#[derive(Debug, Clone, Default)]
+pub struct RingPoolBuilder {
+    threads: Option<usize>,
+    affinity: AffinityPolicy,
+    board: Option<LoadBoard>,
+    name: Option<String>,
+    supervisor: Supervisor,
+}
+
+impl RingPoolBuilder {
+    /// Number of rings, defaults to the number of CPUs the process may run on.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Pins ring `i` to the `i`-th CPU the process may run on (wrapping around), short for
+    /// [`AffinityPolicy::Allowed`] without skipping SMT siblings.
+    pub fn pin(mut self, pin: bool) -> Self {
+        self.affinity = match pin {
+            true => AffinityPolicy::Allowed { skip_smt: false },
+            false => AffinityPolicy::Unpinned,
+        };
+        self
+    }
+
+    pub fn affinity(mut self, affinity: AffinityPolicy) -> Self {
+        self.affinity = affinity;
+        self
+    }
+
+    /// Rebuilds rings that failed or panicked by calling the factory again, with exponential
+    /// backoff between attempts. Rings are not restarted while the pool shuts down.
+    pub fn restart(mut self, policy: RestartPolicy) -> Self {
+        self.supervisor.policy = Some(policy);
+        self
+    }
+
+    /// What happens when a ring panics, defaults to [`PanicPolicy::Restart`].
+    pub fn on_panic(mut self, policy: PanicPolicy) -> Self {
+        self.supervisor.panic = policy;
+        self
+    }
+
+    /// Called on the ring thread for every failure and restart of a ring.
+    pub fn observe(mut self, observer: impl Fn(&SupervisorEvent) + Send + Sync + 'static) -> Self {
+        self.supervisor.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// The board the rings report their load and counters to, e.g. one that is shared with
+    /// their [`Rebalancer`](balance::Rebalancer)s. By default the pool creates its own.
+    pub fn load_board(mut self, board: LoadBoard) -> Self {
+        self.board = Some(board);
+        self
+    }
+
+    /// Thread name prefix, threads are named `{name}-{index}`.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Spawns the ring threads. Every thread calls `factory` to build its ring (and the ring's
+    /// operations) and run it, so neither the ring nor the operations have to be `Send`.
+    ///
+    /// What `factory` returns once the ring finished ends up in the ring's [`RingReport`].
+    pub fn spawn<E, T, F>(self, factory: F) -> io::Result<RingPool<E, T>>
+    where
+        E: Debug + Send + 'static,
+        T: Send + 'static,
+        F: Fn(RingContext) -> Result<T, E> + Send + Sync + 'static,
+    {
+        let threads = match self.threads {
+            Some(threads) => threads,
+            None => allowed_cpus()?.len(),
+        }
+        .max(1);
+        let placements = self.affinity.plan(threads)?;
+        let (finished_tx, finished) = mpsc::channel();
+
+        let mut pool = RingPool {
+            handles: Vec::with_capacity(threads),
+            shared: Arc::new(PoolShared {
+                rings: Default::default(),
+                board: self.board.unwrap_or_else(|| LoadBoard::new(threads)),
+                aborted: AtomicBool::new(false),
+            }),
+            finished,
+            spawner: Spawner {
+                name: self.name.unwrap_or_else(|| "rummelplatz".to_string()),
+                affinity: self.affinity,
+                supervisor: self.supervisor,
+                factory: Arc::new(factory),
+                finished: finished_tx,
+            },
+        };
+        for placement in placements {
+            pool.spawn_ring(placement)?;
+        }
+        Ok(pool)
+    }
+
+    /// Like [`RingPoolBuilder::spawn`], but the operations of every ring are built by `build`,
+    /// which may be `FnMut`: it is called on the ring thread (for added and restarted rings as
+    /// well) one ring at a time, so it can hand out unique resources such as listeners, buffer
+    /// pools or per-core arenas without them having to be `Clone` or `Send`. `run` then builds
+    /// the ring with the operations and runs it.
+    pub fn spawn_with<E, T, O, B, F>(self, build: B, run: F) -> io::Result<RingPool<E, T>>
+    where
+        E: Debug + Send + 'static,
+        T: Send + 'static,
+        B: FnMut(&RingContext) -> O + Send + 'static,
+        F: Fn(RingContext, O) -> Result<T, E> + Send + Sync + 'static,
+    {
+        let build = Mutex::new(build);
+        self.spawn(move |context| {
+            // a ring that panicked in `build` does not stop the others from building theirs
+            let operations = build.lock().unwrap_or_else(PoisonError::into_inner)(&context);
+            run(context, operations)
+        })
+    }
+}
+
+type Factory<E, T> = Arc<dyn Fn(RingContext) -> Result<T, E> + Send + Sync>;
+
+/// Everything needed to spawn another ring into a running pool.
+struct Spawner<E, T> {
+    name: String,
+    affinity: AffinityPolicy,
+    supervisor: Supervisor,
+    factory: Factory<E, T>,
+    finished: Sender<usize>,
+}
+
+/// Also returns how long the thread ran
+type RingJoinHandle<E, T> = JoinHandle<(Duration, Result<T, RingThreadError<E>>)>;
+
+/// A set of rings running on their own threads.
+///
+/// ```no_run
+/// # use rummelplatz::pool::{PoolError, RingPool};
+/// let pool = RingPool::builder()
+///     .pin(true)
+///     .spawn(|context| {
+///         // build the ring and its operations for `context.index`, then `ring.run()`
+///         Ok::<(), std::io::Error>(())
+///     })?;
+/// for report in PoolError::check(pool.join())? {
+///     println!("ring {} ran for {:?}", report.index, report.duration);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct RingPool<E, T = ()> {
+    // retired rings leave a `None` behind, ring indices are never reused
+    handles: Vec<Option<RingJoinHandle<E, T>>>,
+    shared: Arc<PoolShared>,
+    finished: Receiver<usize>,
+    spawner: Spawner<E, T>,
+}
+
+impl<E, T> Debug for RingPool<E, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingPool")
+            .field("rings", &self.len())
+            .field("spawned", &self.handles.len())
+            .finish()
+    }
+}
+
+impl RingPool<()> {
+    pub fn builder() -> RingPoolBuilder {
+        RingPoolBuilder::default()
+    }
+}
+
+impl<E: Debug + Send + 'static, T: Send + 'static> RingPool<E, T> {
+    fn spawn_ring(&mut self, placement: Placement) -> io::Result<usize> {
+        let index = self.handles.len();
+        self.shared.add_slot();
+
+        let context = RingContext {
+            index,
+            cpu: placement.cpu,
+            iowq_cpus: placement.iowq_cpus,
+            shared: self.shared.clone(),
+        };
+        let factory = self.spawner.factory.clone();
+        let supervisor = self.spawner.supervisor.clone();
+        let guard = FinishGuard {
+            index,
+            finished: self.spawner.finished.clone(),
+        };
+
+        let handle = std::thread::Builder::new()
+            .name(format!("{}-{index}", self.spawner.name))
+            .spawn(move || {
+                let _guard = guard;
+                let started = Instant::now();
+                if let Some(cpu) = context.cpu {
+                    if let Err(e) = pin_current_thread(cpu) {
+                        return (started.elapsed(), Err(RingThreadError::Affinity(e)));
+                    }
+                }
+                debug!("ring {index} starting on cpu {:?}", context.cpu);
+                let result = supervisor.run(context, factory.as_ref());
+                (started.elapsed(), result)
+            })?;
+        self.handles.push(Some(handle));
+        Ok(index)
+    }
+
+    /// Spawns one more ring with the pool's factory, placed as if the pool had been built with
+    /// one more thread. Returns the index of the new ring.
+    pub fn add_ring(&mut self) -> io::Result<usize> {
+        let placement = self
+            .spawner
+            .affinity
+            .plan(self.handles.len() + 1)?
+            .pop()
+            .unwrap_or_default();
+        let index = self.spawn_ring(placement)?;
+        debug!("added ring {index}");
+        Ok(index)
+    }
+}
+
+impl<E, T> RingPool<E, T> {
+    /// Number of rings that have not been retired.
+    pub fn len(&self) -> usize {
+        self.handles.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Indices of the rings that have not been retired.
+    pub fn rings(&self) -> Vec<usize> {
+        self.handles
+            .iter()
+            .enumerate()
+            .filter_map(|(index, handle)| handle.as_ref().map(|_| index))
+            .collect()
+    }
+
+    /// The board the rings report their load to.
+    pub fn load_board(&self) -> &LoadBoard {
+        &self.shared.board
+    }
+
+    /// Counters of every ring that was not retired, as reported through
+    /// [`RingContext::load_reporter`].
+    pub fn stats(&self) -> PoolStats {
+        let board = &self.shared.board;
+        let rings: Vec<_> = self
+            .rings()
+            .into_iter()
+            .map(|index| match index < board.len() {
+                true => (index, board.counters(index)),
+                false => (index, RingCounters::default()),
+            })
+            .collect();
+
+        PoolStats {
+            total: RingCounters::sum(rings.iter().map(|(_, counters)| counters)),
+            rings,
+        }
+    }
+
+    /// Drains ring `index` like [`RingPool::shutdown`] and removes it from the pool, its index
+    /// is not reused. Returns `None` if there is no such ring.
+    pub fn retire_ring(
+        &mut self,
+        index: usize,
+        grace: Duration,
+    ) -> Option<Result<RingReport<T>, RingError<E>>> {
+        let handle = self.handles.get_mut(index)?.take()?;
+        debug!("retiring ring {index}");
+        self.shared.signal(index, DrainSignal::Graceful);
+
+        let deadline = Instant::now() + grace;
+        while !handle.is_finished() {
+            if Instant::now() >= deadline {
+                warn!("ring {index} did not drain in {grace:?}, forcing it to exit");
+                self.shared.signal(index, DrainSignal::Force);
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        Some(join_ring(index, handle, &self.shared.board))
+    }
+
+    /// Samples `board` with `scaler` and adds or retires a ring accordingly. A retired ring is
+    /// unregistered from `board` first, so no new work is migrated to it while it drains.
+    ///
+    /// The result of a retired ring is only logged, use [`Autoscaler::decide`] with
+    /// [`RingPool::add_ring`] and [`RingPool::retire_ring`] to handle it yourself.
+    pub fn autoscale(
+        &mut self,
+        scaler: &mut Autoscaler,
+        board: &LoadBoard,
+        grace: Duration,
+    ) -> io::Result<ScaleAction>
+    where
+        E: Debug + Send + 'static,
+        T: Send + 'static,
+    {
+        let action = scaler.decide(board, &self.rings());
+        match action {
+            ScaleAction::Hold => {}
+            ScaleAction::Grow => {
+                self.add_ring()?;
+            }
+            ScaleAction::Shrink(index) => {
+                board.unregister(index);
+                if let Some(Err(e)) = self.retire_ring(index, grace) {
+                    error!("retired {e}");
+                }
+            }
+        }
+        Ok(action)
+    }
+
+    /// Asks every ring to drain (see [`RingContext::drain_op`]) and waits up to `grace` for
+    /// them to finish. Rings still running afterwards are forced to exit.
+    ///
+    /// Returns the result of every ring that was not retired, like [`RingPool::join`]. Rings
+    /// without a drain operation can not be signalled, `shutdown` blocks until they exit on
+    /// their own.
+    pub fn shutdown(self, grace: Duration) -> Vec<Result<RingReport<T>, RingError<E>>> {
+        let mut running = self.rings();
+        debug!("draining {} rings", running.len());
+        self.shared.broadcast(DrainSignal::Graceful);
+
+        let deadline = Instant::now() + grace;
+        while !running.is_empty() {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.finished.recv_timeout(timeout) {
+                Ok(index) => running.retain(|&running| running != index),
+                Err(RecvTimeoutError::Timeout) => {
+                    warn!(
+                        "{} ring(s) did not drain in {grace:?}, forcing them to exit",
+                        running.len()
+                    );
+                    self.shared.broadcast(DrainSignal::Force);
+                    break;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        self.join()
+    }
+
+    /// Waits for all rings that were not retired to finish and returns a report of every ring,
+    /// in order of their indices. [`PoolError::check`] turns the results into a single one.
+    pub fn join(self) -> Vec<Result<RingReport<T>, RingError<E>>> {
+        let board = &self.shared.board;
+        self.handles
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, handle)| Some(join_ring(index, handle?, board)))
+            .inspect(|result| {
+                if let Err(e) = result {
+                    error!("ring {} failed", e.index);
+                }
+            })
+            .collect()
+    }
+}
+
+/// A snapshot of the counters of all rings of a pool, see [`RingPool::stats`].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct PoolStats {
+    pub total: RingCounters,
+    /// Per ring, with its index
+    pub rings: Vec<(usize, RingCounters)>,
+}
+
+impl PoolStats {
+    /// Load ([`RingCounters::in_flight`] + [`RingCounters::cq_backlog`]) of the busiest ring
+    /// relative to the average, `1.0` for a perfectly balanced pool.
+    pub fn imbalance(&self) -> f64 {
+        let load = |counters: &RingCounters| (counters.in_flight + counters.cq_backlog) as f64;
+        let max = self
+            .rings
+            .iter()
+            .map(|(_, counters)| load(counters))
+            .fold(0.0, f64::max);
+        let average = load(&self.total) / self.rings.len().max(1) as f64;
+        match average > 0.0 {
+            true => max / average,
+            false => 1.0,
+        }
+    }
+}
+
+impl Display for PoolStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>5} {:>12} {:>12} {:>8} {:>9} {:>10}",
+            "ring", "submitted", "completed", "spills", "in flight", "cq backlog"
+        )?;
+        let row = |f: &mut Formatter<'_>, ring: &dyn Display, counters: &RingCounters| {
+            writeln!(
+                f,
+                "{ring:>5} {:>12} {:>12} {:>8} {:>9} {:>10}",
+                counters.submitted,
+                counters.completed,
+                counters.backlog_spills,
+                counters.in_flight,
+                counters.cq_backlog
+            )
+        };
+        for (index, counters) in &self.rings {
+            row(f, index, counters)?;
+        }
+        row(f, &"total", &self.total)?;
+        write!(f, "imbalance {:.2}", self.imbalance())
+    }
+}
+
+fn join_ring<E, T>(
+    index: usize,
+    handle: RingJoinHandle<E, T>,
+    board: &LoadBoard,
+) -> Result<RingReport<T>, RingError<E>> {
+    let (duration, result) = handle.join().unwrap_or_else(|panic| {
+        (
+            Duration::ZERO,
+            Err(RingThreadError::Panic(PanicPayload(panic))),
+        )
+    });
+    let counters = match index < board.len() {
+        true => board.counters(index),
+        false => RingCounters::default(),
+    };
+
+    match result {
+        Ok(state) => Ok(RingReport {
+            index,
+            duration,
+            counters,
+            state,
+        }),
+        Err(error) => Err(RingError {
+            index,
+            duration,
+            counters,
+            error,
+        }),
+    }
+}
+
+/// The CPUs the calling thread may run on.
+pub fn allowed_cpus() -> io::Result<Vec<usize>> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    if unsafe { libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok((0..libc::CPU_SETSIZE as usize)
+        .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+        .collect())
+}
+
+pub fn pin_current_thread(cpu: usize) -> io::Result<()> {
+    let set = affinity::cpu_set(&[cpu]);
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+// the ring is generated inside the crate, its unused parts are not exempt from lints
+#[allow(dead_code, unused_imports)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    crate::ring! { drain_ring -> crate::ops::Error, drain: crate::ops::RemoteOp<crate::pool::DrainSignal> }
+
+    /// Runs a ring until it is drained, `on_drain` answers [`DrainSignal::Graceful`]. Returns
+    /// the ring's index.
+    pub(super) fn run_drain_ring(
+        context: RingContext,
+        on_drain: impl FnMut(DrainSignal) -> OpControlFlow + 'static,
+    ) -> Result<usize, String> {
+        let index = context.index;
+        let mut ring = drain_ring::Ring::builder()
+            .drain(context.drain_op(on_drain).map_err(|e| e.to_string())?)
+            .build()
+            .map_err(|e| e.to_string())?;
+        ring.run().map_err(|e| format!("{e:?}"))?;
+        Ok(index)
+    }
+
+    fn states(results: Vec<Result<RingReport<usize>, RingError<String>>>) -> Vec<usize> {
+        PoolError::check(results)
+            .unwrap()
+            .into_iter()
+            .map(|report| report.state)
+            .collect()
+    }
+
+    #[test]
+    fn spawned_rings_shut_down() {
+        let pool = RingPool::builder()
+            .threads(2)
+            .spawn(|context| run_drain_ring(context, |_| ControlFlow::Exit))
+            .unwrap();
+        assert_eq!(pool.len(), 2);
+        assert_eq!(pool.rings(), [0, 1]);
+        assert_eq!(states(pool.shutdown(Duration::from_secs(10))), [0, 1]);
+    }
+
+    #[test]
+    fn shutdown_without_grace_forces_the_rings_to_exit() {
+        let pool = RingPool::builder()
+            .threads(2)
+            .spawn(|context| run_drain_ring(context, |_| ControlFlow::Continue))
+            .unwrap();
+        assert_eq!(states(pool.shutdown(Duration::ZERO)), [0, 1]);
+    }
+
+    #[test]
+    fn graceful_drain_ends_before_the_grace() {
+        let asked = Arc::new(AtomicUsize::new(0));
+        let pool = RingPool::builder()
+            .threads(2)
+            .spawn({
+                let asked = asked.clone();
+                move |context| {
+                    let asked = asked.clone();
+                    run_drain_ring(context, move |signal| {
+                        assert_eq!(signal, DrainSignal::Graceful);
+                        asked.fetch_add(1, Ordering::Relaxed);
+                        ControlFlow::Exit
+                    })
+                }
+            })
+            .unwrap();
+
+        let started = Instant::now();
+        assert_eq!(states(pool.shutdown(Duration::from_secs(10))), [0, 1]);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(asked.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn stragglers_are_forced_after_the_grace() {
+        let asked = Arc::new(AtomicUsize::new(0));
+        let pool = RingPool::builder()
+            .threads(2)
+            .spawn({
+                let asked = asked.clone();
+                move |context| {
+                    let asked = asked.clone();
+                    // ring 1 keeps working after it was asked to drain
+                    run_drain_ring(context.clone(), move |_| {
+                        asked.fetch_add(1, Ordering::Relaxed);
+                        match context.index {
+                            0 => ControlFlow::Exit,
+                            _ => ControlFlow::Continue,
+                        }
+                    })
+                }
+            })
+            .unwrap();
+
+        let grace = Duration::from_millis(50);
+        let started = Instant::now();
+        assert_eq!(states(pool.shutdown(grace)), [0, 1]);
+        assert!(started.elapsed() >= grace);
+        // `Force` does not ask the ring again
+        assert_eq!(asked.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn retired_rings_drain_and_leave_the_pool() {
+        let mut pool = RingPool::builder()
+            .threads(2)
+            .spawn(|context| {
+                run_drain_ring(context.clone(), move |_| match context.index {
+                    0 => ControlFlow::Continue,
+                    _ => ControlFlow::Exit,
+                })
+            })
+            .unwrap();
+
+        let report = pool.retire_ring(0, Duration::from_millis(10)).unwrap();
+        assert_eq!(report.unwrap().state, 0);
+        assert!(pool.retire_ring(0, Duration::ZERO).is_none());
+        assert_eq!(pool.rings(), [1]);
+        assert_eq!(states(pool.shutdown(Duration::from_secs(10))), [1]);
+    }
+
+    #[test]
+    fn join_waits_for_rings_exiting_on_their_own() {
+        let pool = RingPool::builder()
+            .threads(2)
+            .spawn(|context| Ok::<_, String>(context.index * 10))
+            .unwrap();
+        assert_eq!(states(pool.join()), [0, 10]);
+    }
+
+    #[test]
+    fn failed_rings_are_reported() {
+        let pool = RingPool::builder()
+            .threads(2)
+            .spawn(|context| match context.index {
+                0 => Ok(0),
+                _ => Err("broken".to_string()),
+            })
+            .unwrap();
+        let errors = PoolError::check(pool.join()).unwrap_err().0;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].index, 1);
+        assert!(matches!(&errors[0].error, RingThreadError::Ring(e) if e == "broken"));
+    }
+}
@@ -0,0 +1,343 @@
+//! One `SO_REUSEPORT` listener per ring, the kernel distributes incoming connections across
+//! them without any cross-thread handoff.
+
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::mem::size_of;
+use std::net::SocketAddr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::{Arc, Mutex};
+
+use tracing::{debug, warn};
+
+use crate::ops::{AcceptOp, OpControlFlow, SockAddr};
+
+/// `BPF_A`, missing in libc
+const BPF_RET_A: u16 = 0x10;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ReuseportOptions {
+    /// `listen(2)` backlog of every listener
+    pub backlog: i32,
+    /// Attaches a classic BPF program to the group that picks the listener by the CPU the
+    /// connection arrived on (`cpu % shards`), so a connection is accepted by the ring pinned
+    /// to that CPU. Only effective when ring `i` is pinned to CPU `i`.
+    pub cpu_steering: bool,
+}
+
+impl Default for ReuseportOptions {
+    fn default() -> Self {
+        Self {
+            backlog: libc::SOMAXCONN,
+            cpu_steering: false,
+        }
+    }
+}
+
+/// `shards` listeners bound to the same address, listener `i` belongs to ring `i`.
+///
+/// Create it before spawning the pool and move it into the factory (behind an `Arc`), every ring
+/// then accepts on its own listener:
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use rummelplatz::pool::{RingPool, reuseport::ReuseportGroup};
+/// # use rummelplatz::ControlFlow;
+/// let group = Arc::new(ReuseportGroup::bind("0.0.0.0:8080".parse()?, 4, Default::default())?);
+/// let pool = RingPool::builder().threads(4).pin(true).spawn(move |context| {
+///     let (listener, accept) = group
+///         .accept_op(context.index, |connection| ControlFlow::Continue)
+///         .expect("no listener for this ring");
+///     // build the ring with `accept` and run it while `listener` is alive
+///     Ok::<(), std::io::Error>(())
+/// })?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// When the pool scales, [`ReuseportGroup::add`] a listener for every new ring and
+/// [`ReuseportGroup::remove`] the listener of a retired one. Connections still queued on a removed
+/// listener are reset once its ring dropped it.
+///
+/// Sockets inherited from a service manager are served with [`ReuseportGroup::from_listener`].
+pub struct ReuseportGroup {
+    addr: SocketAddr,
+    options: ReuseportOptions,
+    // the listener every ring accepts on, if the group was created from one without `SO_REUSEPORT`
+    shared: Option<Arc<OwnedFd>>,
+    // `None` for removed listeners, the index of a listener is the index of its ring
+    listeners: Mutex<Vec<Option<Arc<OwnedFd>>>>,
+}
+
+impl Debug for ReuseportGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReuseportGroup")
+            .field("addr", &self.addr)
+            .field("options", &self.options)
+            .field("shared", &self.shared)
+            .field("listeners", &self.listeners)
+            .finish()
+    }
+}
+
+impl ReuseportGroup {
+    /// Binds `shards` listeners to `addr`. If `addr` has port `0` all listeners share the port
+    /// the kernel picked for the first one, see [`ReuseportGroup::local_addr`].
+    pub fn bind(addr: SocketAddr, shards: usize, options: ReuseportOptions) -> io::Result<Self> {
+        assert!(shards > 0, "a reuseport group needs at least one listener");
+
+        let mut addr = addr;
+        let mut listeners = Vec::with_capacity(shards);
+        for _ in 0..shards {
+            let listener = listen(&addr, options.backlog)?;
+            if addr.port() == 0 {
+                addr = local_addr(&listener)?;
+            }
+            listeners.push(listener);
+        }
+
+        if options.cpu_steering {
+            attach_cpu_steering(&listeners[0], shards as u32)?;
+        }
+
+        debug!("bound {shards} reuseport listeners to {addr}");
+        Ok(Self {
+            addr,
+            options,
+            shared: None,
+            listeners: Mutex::new(listeners.into_iter().map(Arc::new).map(Some).collect()),
+        })
+    }
+
+    /// Serves an already listening TCP socket on `shards` rings, e.g. one inherited from systemd
+    /// ([`ListenFds`](crate::systemd::ListenFds)). If the socket has `SO_REUSEPORT` set
+    /// (`ReusePort=yes` in the socket unit) the other listeners are bound next to it, which
+    /// requires the process to run as the user that bound it. Otherwise all rings accept on the
+    /// inherited socket, [`ReuseportOptions::cpu_steering`] is ignored then.
+    pub fn from_listener(
+        listener: OwnedFd,
+        shards: usize,
+        options: ReuseportOptions,
+    ) -> io::Result<Self> {
+        assert!(shards > 0, "a reuseport group needs at least one listener");
+
+        if get_option(&listener, libc::SO_TYPE)? != libc::SOCK_STREAM
+            || get_option(&listener, libc::SO_ACCEPTCONN)? == 0
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a listening stream socket",
+            ));
+        }
+        let addr = local_addr(&listener)?;
+        let listener = Arc::new(listener);
+
+        if get_option(&listener, libc::SO_REUSEPORT)? == 0 {
+            if options.cpu_steering {
+                warn!("{addr} was not bound with SO_REUSEPORT, cpu steering is disabled");
+            }
+            debug!("sharing the listener on {addr} between {shards} rings");
+            return Ok(Self {
+                addr,
+                options,
+                shared: Some(listener.clone()),
+                listeners: Mutex::new(vec![Some(listener); shards]),
+            });
+        }
+
+        let mut listeners = vec![listener];
+        for _ in 1..shards {
+            listeners.push(Arc::new(listen(&addr, options.backlog)?));
+        }
+        if options.cpu_steering {
+            attach_cpu_steering(&listeners[0], shards as u32)?;
+        }
+
+        debug!("bound {} reuseport listeners next to {addr}", shards - 1);
+        Ok(Self {
+            addr,
+            options,
+            shared: None,
+            listeners: Mutex::new(listeners.into_iter().map(Some).collect()),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Number of listeners that were not removed.
+    pub fn len(&self) -> usize {
+        self.listeners.lock().unwrap().iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Binds another listener to the group, returns its index. Indices of removed listeners are
+    /// not reused, so the index matches the one [`RingPool::add_ring`](crate::pool::RingPool::add_ring)
+    /// returns as long as both grow in lockstep.
+    pub fn add(&self) -> io::Result<usize> {
+        let listener = match &self.shared {
+            Some(shared) => shared.clone(),
+            None => Arc::new(listen(&self.addr, self.options.backlog)?),
+        };
+        let mut listeners = self.listeners.lock().unwrap();
+        listeners.push(Some(listener));
+        self.steer(&listeners)?;
+
+        debug!("added reuseport listener {}", listeners.len() - 1);
+        Ok(listeners.len() - 1)
+    }
+
+    /// Removes the listener of ring `index` from the group, returns `false` if there is none.
+    /// The socket is closed (and leaves the group) once the ring dropped its reference as well.
+    pub fn remove(&self, index: usize) -> bool {
+        let mut listeners = self.listeners.lock().unwrap();
+        let Some(listener) = listeners.get_mut(index).and_then(Option::take) else {
+            return false;
+        };
+        drop(listener);
+
+        debug!("removed reuseport listener {index}");
+        if let Err(e) = self.steer(&listeners) {
+            warn!("failed to update cpu steering: {e}");
+        }
+        true
+    }
+
+    /// The listener of ring `index`, `None` if it was removed.
+    pub fn listener(&self, index: usize) -> Option<Arc<OwnedFd>> {
+        self.listeners.lock().unwrap().get(index)?.clone()
+    }
+
+    /// Creates an [`AcceptOp`] on the listener of ring `index`. The returned listener has to
+    /// outlive the ring. A ring rebuilt after a failure gets the same listener again.
+    pub fn accept_op(
+        &self,
+        index: usize,
+        on_accept: impl FnMut(OwnedFd) -> OpControlFlow + 'static,
+    ) -> Option<(Arc<OwnedFd>, AcceptOp)> {
+        let listener = self.listener(index)?;
+        let accept = AcceptOp::new(listener.as_raw_fd(), on_accept);
+        Some((listener, accept))
+    }
+
+    /// Re-attaches the steering program for the current number of listeners. The kernel fills
+    /// the place of a removed socket with the last one of the group, CPU steering is only exact
+    /// again once the rings are pinned accordingly.
+    fn steer(&self, listeners: &[Option<Arc<OwnedFd>>]) -> io::Result<()> {
+        if !self.options.cpu_steering || self.shared.is_some() {
+            return Ok(());
+        }
+        let shards = listeners.iter().flatten().count() as u32;
+        match listeners.iter().flatten().next() {
+            Some(listener) => attach_cpu_steering(listener, shards),
+            None => Ok(()),
+        }
+    }
+}
+
+fn listen(addr: &SocketAddr, backlog: i32) -> io::Result<OwnedFd> {
+    let addr = SockAddr::new(addr);
+    let fd = unsafe { libc::socket(addr.family(), libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    set_option(&fd, libc::SO_REUSEADDR, 1)?;
+    set_option(&fd, libc::SO_REUSEPORT, 1)?;
+    if unsafe { libc::bind(fd.as_raw_fd(), addr.as_ptr(), addr.len()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::listen(fd.as_raw_fd(), backlog) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+fn local_addr(fd: &OwnedFd) -> io::Result<SocketAddr> {
+    let mut addr = SockAddr::empty();
+    if unsafe { libc::getsockname(fd.as_raw_fd(), addr.as_mut_ptr(), addr.len_mut()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    addr.to_std()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unexpected address family"))
+}
+
+fn get_option(fd: &OwnedFd, option: i32) -> io::Result<i32> {
+    let mut value = 0i32;
+    let mut len = size_of::<i32>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            &mut value as *mut i32 as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+fn set_option(fd: &OwnedFd, option: i32, value: i32) -> io::Result<()> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            &value as *const i32 as *const libc::c_void,
+            size_of::<i32>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// `A = cpu; A %= shards; return A`, the returned value selects the listener by index.
+fn attach_cpu_steering(fd: &OwnedFd, shards: u32) -> io::Result<()> {
+    let mut filter = [
+        libc::sock_filter {
+            code: (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16,
+            jt: 0,
+            jf: 0,
+            k: (libc::SKF_AD_OFF + libc::SKF_AD_CPU) as u32,
+        },
+        libc::sock_filter {
+            code: (libc::BPF_ALU | libc::BPF_MOD | libc::BPF_K) as u16,
+            jt: 0,
+            jf: 0,
+            k: shards,
+        },
+        libc::sock_filter {
+            code: libc::BPF_RET as u16 | BPF_RET_A,
+            jt: 0,
+            jf: 0,
+            k: 0,
+        },
+    ];
+    let program = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+
+    let res = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ATTACH_REUSEPORT_CBPF,
+            &program as *const libc::sock_fprog as *const libc::c_void,
+            size_of::<libc::sock_fprog>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,206 @@
+//! Growing and shrinking a pool with its load.
+//!
+//! An [`Autoscaler`] watches the [`LoadBoard`] of a pool and asks for another ring once the
+//! average load stayed above [`ScalePolicy::grow_above`] for [`ScalePolicy::sustain`], or for
+//! one less once it stayed below [`ScalePolicy::shrink_below`] as long.
+//! [`RingPool::autoscale`](crate::pool::RingPool::autoscale) applies its decisions.
+//!
+//! Resources bound to a ring index move with the pool: a new ring builds its own listener (see
+//! [`ReuseportGroup::add`](crate::pool::reuseport::ReuseportGroup::add)) and mirror of a
+//! [`SharedFileTable`](crate::pool::files::SharedFileTable), which replays every installed slot.
+//! A retired ring is drained first, its connections finish (or are migrated by its drain
+//! handler) before the ring exits.
+
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use crate::pool::balance::{LoadBoard, RingLoad};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ScalePolicy {
+    /// The pool never shrinks below this many rings
+    pub min: usize,
+    /// The pool never grows beyond this many rings
+    pub max: usize,
+    /// Average [`RingLoad::total`] per ring above which the pool grows
+    pub grow_above: usize,
+    /// Average [`RingLoad::total`] per ring below which the pool shrinks
+    pub shrink_below: usize,
+    /// How long the load has to stay above or below the thresholds
+    pub sustain: Duration,
+}
+
+impl Default for ScalePolicy {
+    fn default() -> Self {
+        Self {
+            min: 1,
+            max: 64,
+            grow_above: 256,
+            shrink_below: 16,
+            sustain: Duration::from_secs(10),
+        }
+    }
+}
+
+/// What the pool should do, see [`Autoscaler::decide`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScaleAction {
+    Hold,
+    /// Spawn another ring.
+    Grow,
+    /// Retire ring `index`, the least loaded one.
+    Shrink(usize),
+}
+
+/// Turns load samples into [`ScaleAction`]s, sample it periodically (e.g. every second).
+#[derive(Debug, Clone)]
+pub struct Autoscaler {
+    policy: ScalePolicy,
+    // since when the load is above `grow_above` / below `shrink_below`
+    above: Option<Instant>,
+    below: Option<Instant>,
+}
+
+impl Autoscaler {
+    pub fn new(policy: ScalePolicy) -> Self {
+        assert!(
+            policy.min > 0 && policy.min <= policy.max,
+            "invalid scale limits {}..={}",
+            policy.min,
+            policy.max
+        );
+        Self {
+            policy,
+            above: None,
+            below: None,
+        }
+    }
+
+    pub fn policy(&self) -> &ScalePolicy {
+        &self.policy
+    }
+
+    /// Samples the load of the live `rings` and decides whether the pool should change size.
+    /// After a `Grow` or `Shrink` the load has to be sustained again before the next one.
+    pub fn decide(&mut self, board: &LoadBoard, rings: &[usize]) -> ScaleAction {
+        let loads: Vec<(usize, RingLoad)> = rings
+            .iter()
+            .filter(|&&index| index < board.len())
+            .map(|&index| (index, board.load(index)))
+            .collect();
+        if loads.is_empty() {
+            return ScaleAction::Hold;
+        }
+
+        let average = loads.iter().map(|(_, load)| load.total()).sum::<usize>() / loads.len();
+        let now = Instant::now();
+        let sustained = |since: &mut Option<Instant>, condition: bool| {
+            if !condition {
+                *since = None;
+                return false;
+            }
+            now.duration_since(*since.get_or_insert(now)) >= self.policy.sustain
+        };
+
+        if sustained(
+            &mut self.above,
+            average > self.policy.grow_above && rings.len() < self.policy.max,
+        ) {
+            debug!(
+                "average load {average} over {}, growing",
+                self.policy.grow_above
+            );
+            self.above = None;
+            return ScaleAction::Grow;
+        }
+
+        if sustained(
+            &mut self.below,
+            average < self.policy.shrink_below && rings.len() > self.policy.min,
+        ) {
+            self.below = None;
+            let (index, _) = loads
+                .iter()
+                .min_by_key(|(_, load)| load.total())
+                .copied()
+                .expect("loads is not empty");
+            debug!(
+                "average load {average} under {}, retiring ring {index}",
+                self.policy.shrink_below
+            );
+            return ScaleAction::Shrink(index);
+        }
+
+        ScaleAction::Hold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: ScalePolicy = ScalePolicy {
+        min: 1,
+        max: 3,
+        grow_above: 10,
+        shrink_below: 2,
+        sustain: Duration::ZERO,
+    };
+
+    fn board(in_flight: &[usize]) -> LoadBoard {
+        let board = LoadBoard::new(in_flight.len());
+        for (index, &n) in in_flight.iter().enumerate() {
+            board.reporter(index).set_in_flight(n);
+        }
+        board
+    }
+
+    #[test]
+    fn decides_by_the_average_load() {
+        let cases: &[(&[usize], &[usize], ScaleAction)] = &[
+            // in flight per ring, live rings, decision
+            (&[5, 5], &[0, 1], ScaleAction::Hold),
+            (&[20, 20], &[0, 1], ScaleAction::Grow),
+            (&[20, 0], &[0, 1], ScaleAction::Hold),
+            (&[20, 20, 20], &[0, 1, 2], ScaleAction::Hold),
+            (&[0, 1], &[0, 1], ScaleAction::Shrink(0)),
+            (&[3, 0], &[0, 1], ScaleAction::Shrink(1)),
+            (&[0, 50, 1], &[0, 2], ScaleAction::Shrink(0)),
+            (&[0], &[0], ScaleAction::Hold),
+            (&[20, 20], &[0, 5], ScaleAction::Grow),
+            (&[20], &[], ScaleAction::Hold),
+        ];
+        for &(in_flight, rings, action) in cases {
+            let mut scaler = Autoscaler::new(POLICY);
+            assert_eq!(
+                scaler.decide(&board(in_flight), rings),
+                action,
+                "{in_flight:?} on rings {rings:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn load_has_to_be_sustained_again_after_a_decision() {
+        let sustain = Duration::from_millis(20);
+        let mut scaler = Autoscaler::new(ScalePolicy { sustain, ..POLICY });
+        let busy = board(&[20, 20]);
+
+        assert_eq!(scaler.decide(&busy, &[0, 1]), ScaleAction::Hold);
+        std::thread::sleep(sustain);
+        assert_eq!(scaler.decide(&busy, &[0, 1]), ScaleAction::Grow);
+        assert_eq!(scaler.decide(&busy, &[0, 1]), ScaleAction::Hold);
+
+        // a dip restarts the clock
+        std::thread::sleep(sustain);
+        assert_eq!(scaler.decide(&board(&[5, 5]), &[0, 1]), ScaleAction::Hold);
+        assert_eq!(scaler.decide(&busy, &[0, 1]), ScaleAction::Hold);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid scale limits")]
+    fn limits_are_checked() {
+        Autoscaler::new(ScalePolicy { min: 0, ..POLICY });
+    }
+}
@@ -0,0 +1,383 @@
+//! Forwarding submissions a full ring can not take to a sibling ring.
+//!
+//! A ring whose submission queue and backlog are full can hand requests that are not bound to
+//! its thread to another ring of the pool instead of failing them: reads and writes of plain
+//! buffers on regular fds or on fixed files of a
+//! [`SharedFileTable`](crate::pool::files::SharedFileTable), whose slots refer to the same file
+//! on every ring. Requests using registered or provided buffers, fixed files of the ring's own
+//! table and multishot requests have to stay on their ring.
+//!
+//! Every ring has a [`SpillOp`]. [`SpillHandle::push_or_spill`] pushes a request locally while
+//! there is room and otherwise sends it to the next sibling in round-robin order, which submits
+//! it and returns the result to the [`SpillOp`] of the originating ring with a message. Buffers
+//! of a spilled request have to stay valid until its result arrived, requests still queued
+//! when a sibling exits are dropped without a result.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use io_uring::cqueue::Entry;
+use io_uring::squeue::PushError;
+use tracing::{debug, trace, warn};
+
+use crate::message::{MessageTarget, OutgoingMessage, RingMessage};
+use crate::ops::{Error, OpControlFlow, RemoteOp, RingHandle};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+/// Set in the payload of messages carrying a result, wakeups of the [`RemoteOp`] have payload 0
+const RESULT: u64 = 1;
+
+/// Largest token of a spilled request, the lowest payload bit tells results from wakeups.
+pub const MAX_TOKEN: u64 = RingMessage::MAX_PAYLOAD >> 1;
+
+struct Spilled {
+    entry: io_uring::squeue::Entry,
+    origin: usize,
+    token: u64,
+}
+
+struct Member {
+    handle: RingHandle<Spilled>,
+    target: Option<MessageTarget>,
+}
+
+/// The [`SpillOp`]s of all rings, cheap to clone and [`Send`].
+#[derive(Clone, Default)]
+pub struct SpillGroup {
+    members: Arc<Mutex<Vec<Option<Member>>>>,
+    next: Arc<AtomicUsize>,
+}
+
+impl Debug for SpillGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let members = self.members.lock().unwrap();
+        let targets: Vec<_> = members
+            .iter()
+            .map(|member| member.as_ref().and_then(|member| member.target))
+            .collect();
+        f.debug_struct("SpillGroup")
+            .field("targets", &targets)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SpillGroup {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the operation of ring `index`. `on_result` is called on that ring with the
+    /// token and the result of every request it spilled.
+    pub fn op(
+        &self,
+        index: usize,
+        on_result: impl FnMut(u64, i32) -> OpControlFlow + 'static,
+    ) -> io::Result<SpillOp> {
+        let queue: Rc<RefCell<VecDeque<Spilled>>> = Default::default();
+        let remote = RemoteOp::new({
+            let queue = queue.clone();
+            move |spilled| {
+                queue.borrow_mut().push_back(spilled);
+                ControlFlow::Continue
+            }
+        })?;
+
+        let mut members = self.members.lock().unwrap();
+        if members.len() <= index {
+            members.resize_with(index + 1, || None);
+        }
+        members[index] = Some(Member {
+            handle: remote.handle(),
+            target: None,
+        });
+
+        Ok(SpillOp {
+            handle: SpillHandle {
+                group: self.clone(),
+                index,
+            },
+            remote,
+            queue,
+            executed: 0,
+            on_result: Box::new(on_result),
+        })
+    }
+
+    /// Sets the address of ring `index`'s [`SpillOp`], see `Ring::message_target`. A ring
+    /// takes requests of its siblings once it is registered.
+    pub fn register(&self, index: usize, target: MessageTarget) {
+        let mut members = self.members.lock().unwrap();
+        let member = members
+            .get_mut(index)
+            .and_then(Option::as_mut)
+            .expect("ring has no spill operation");
+        member.handle.connect(target);
+        member.target = Some(target);
+    }
+
+    /// Stops spilling to ring `index`, e.g. before it is retired.
+    pub fn unregister(&self, index: usize) {
+        if let Some(member) = self.members.lock().unwrap().get_mut(index) {
+            *member = None;
+        }
+    }
+
+    fn target(&self, index: usize) -> Option<MessageTarget> {
+        let members = self.members.lock().unwrap();
+        members.get(index)?.as_ref()?.target
+    }
+
+    /// Hands `spilled` to the next registered sibling of its origin, gives it back if there is
+    /// none.
+    fn forward(&self, mut spilled: Spilled) -> Result<usize, Spilled> {
+        let members = self.members.lock().unwrap();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        for offset in 0..members.len() {
+            let index = (start + offset) % members.len();
+            let Some(member) = &members[index] else {
+                continue;
+            };
+            if index == spilled.origin || member.target.is_none() {
+                continue;
+            }
+
+            match member.handle.send(spilled) {
+                Ok(()) => return Ok(index),
+                // the ring is gone
+                Err(e) => spilled = e.0,
+            }
+        }
+        Err(spilled)
+    }
+}
+
+/// Where [`SpillHandle::push_or_spill`] put a request.
+#[derive(Debug)]
+pub enum Pushed<D> {
+    /// On this ring, the data went into the submitter.
+    Local,
+    /// To ring `ring`, the result arrives at this ring's `on_result` with the token.
+    Spilled { ring: usize, data: D },
+}
+
+/// Cloneable handle the operations of a ring use to spill requests, see the
+/// [module docs](self).
+#[derive(Debug, Clone)]
+pub struct SpillHandle {
+    group: SpillGroup,
+    index: usize,
+}
+
+impl SpillHandle {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Pushes `entry` on this ring if it fits into the submission queue or backlog, otherwise
+    /// sends it to a sibling and returns `data`. Fails like
+    /// [`SubmissionQueueSubmitter::push`] if no sibling takes it either.
+    ///
+    /// # Panics
+    /// If `token` exceeds [`MAX_TOKEN`].
+    pub fn push_or_spill<D, W: Fn(&mut io_uring::squeue::Entry, D)>(
+        &self,
+        submitter: &mut SubmissionQueueSubmitter<D, W>,
+        entry: io_uring::squeue::Entry,
+        data: D,
+        token: u64,
+    ) -> Result<Pushed<D>, PushError> {
+        if submitter.has_capacity(1) {
+            submitter.push(entry, data)?;
+            return Ok(Pushed::Local);
+        }
+
+        match self.spill(entry, token) {
+            Ok(ring) => Ok(Pushed::Spilled { ring, data }),
+            Err(entry) => submitter.push(entry, data).map(|()| Pushed::Local),
+        }
+    }
+
+    /// Sends `entry` to a sibling right away, returns its index or the entry if no sibling is
+    /// registered. The `user_data` of `entry` is replaced by the sibling.
+    ///
+    /// # Panics
+    /// If `token` exceeds [`MAX_TOKEN`].
+    pub fn spill(
+        &self,
+        entry: io_uring::squeue::Entry,
+        token: u64,
+    ) -> Result<usize, io_uring::squeue::Entry> {
+        assert!(token <= MAX_TOKEN, "spill token {token} is too large");
+        let spilled = Spilled {
+            entry,
+            origin: self.index,
+            token,
+        };
+        match self.group.forward(spilled) {
+            Ok(ring) => {
+                trace!("ring {} spilled request {token} to ring {ring}", self.index);
+                Ok(ring)
+            }
+            Err(spilled) => Err(spilled.entry),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SpillData {
+    Remote(()),
+    /// A request of ring `origin`
+    Executed {
+        origin: usize,
+        token: u64,
+    },
+    /// The result of a request sent back to ring `origin`
+    Returned {
+        origin: usize,
+        token: u64,
+    },
+}
+
+/// Submits the requests siblings spilled to this ring and receives the results of the requests
+/// this ring spilled, see the [module docs](self).
+pub struct SpillOp {
+    handle: SpillHandle,
+    remote: RemoteOp<Spilled>,
+    // spilled to this ring, submitted during housekeeping
+    queue: Rc<RefCell<VecDeque<Spilled>>>,
+    executed: u64,
+    on_result: Box<dyn FnMut(u64, i32) -> OpControlFlow>,
+}
+
+impl Debug for SpillOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SpillOp")
+            .field("index", &self.handle.index)
+            .field("queued", &self.queue.borrow().len())
+            .field("executed", &self.executed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SpillOp {
+    pub fn handle(&self) -> SpillHandle {
+        self.handle.clone()
+    }
+
+    /// Number of requests of siblings this ring submitted.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+}
+
+impl RingOperation for SpillOp {
+    type RingData = SpillData;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.remote.setup(submitter.map_data(SpillData::Remote))
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        match ring_data {
+            SpillData::Remote(data) => {
+                let (flow, data) = self.remote.on_completion(
+                    completion_entry,
+                    data,
+                    submitter.map_data(SpillData::Remote),
+                );
+                (flow, data.map(SpillData::Remote))
+            }
+            SpillData::Executed { origin, token } => {
+                self.executed += 1;
+                let Some(target) = self.handle.group.target(origin) else {
+                    debug!("ring {origin} left, dropping the result of request {token}");
+                    return (ControlFlow::Continue, None);
+                };
+
+                let payload = token << 1 | RESULT;
+                let message = OutgoingMessage::data(target, payload, completion_entry.result());
+                match submitter.push(message.build(), SpillData::Returned { origin, token }) {
+                    Ok(()) => (ControlFlow::Continue, None),
+                    Err(e) => (ControlFlow::Error(e.into()), None),
+                }
+            }
+            SpillData::Returned { origin, token } => {
+                let res = completion_entry.result();
+                if res < 0 {
+                    let e = io::Error::from_raw_os_error(-res);
+                    warn!("unable to return the result of request {token} to ring {origin}: {e}");
+                    return (ControlFlow::Warn(e.into()), None);
+                }
+                (ControlFlow::Continue, None)
+            }
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        match ring_data {
+            SpillData::Remote(data) => self.remote.on_teardown_completion(
+                completion_entry,
+                data,
+                submitter.map_data(SpillData::Remote),
+            ),
+            SpillData::Executed { .. } | SpillData::Returned { .. } => Ok(()),
+        }
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        // room for the request and its result, the rest waits for the next iteration
+        while submitter.has_capacity(2) {
+            let Some(spilled) = self.queue.borrow_mut().pop_front() else {
+                break;
+            };
+            let data = SpillData::Executed {
+                origin: spilled.origin,
+                token: spilled.token,
+            };
+            if let Err(e) = submitter.push(spilled.entry, data) {
+                return ControlFlow::Error(e.into());
+            }
+        }
+        ControlFlow::Continue
+    }
+
+    fn on_message<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        message: RingMessage,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        match message {
+            RingMessage::Data { payload, value } if payload & RESULT != 0 => {
+                (self.on_result)(payload >> 1, value)
+            }
+            message => self
+                .remote
+                .on_message(message, submitter.map_data(SpillData::Remote)),
+        }
+    }
+}
@@ -0,0 +1,364 @@
+//! Restarting rings that failed or panicked.
+//!
+//! Panics of a ring thread are caught and handled according to the pool's [`PanicPolicy`],
+//! the payload ends up in the ring's result (see [`RingThreadError::Panic`]).
+
+use std::fmt::{Debug, Formatter};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::{error, warn};
+
+use crate::pool::{PanicPayload, RingContext, RingThreadError};
+
+/// When and how often a failed ring is rebuilt.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RestartPolicy {
+    /// Restarts per ring before the supervisor gives up, `None` for no limit.
+    pub max_restarts: Option<u32>,
+    /// Delay before the first restart, doubled for every following one
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// A ring that ran at least this long before failing starts over with `initial_backoff`
+    pub reset_after: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: Some(8),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            reset_after: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RestartPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(
+                1u32.checked_shl(attempt.saturating_sub(1))
+                    .unwrap_or(u32::MAX),
+            )
+            .min(self.max_backoff)
+    }
+}
+
+/// What the pool does when one of its ring threads panics.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum PanicPolicy {
+    /// Force every ring of the pool to exit (see
+    /// [`RingContext::drain_op`](crate::pool::RingContext::drain_op)), as if the panic
+    /// unwound through the whole pool.
+    Abort,
+    /// Keep the other rings running, the panicked ring stays down even if the pool has a
+    /// [`RestartPolicy`].
+    Isolate,
+    /// Handle the panic like a failure: the ring is rebuilt according to the pool's
+    /// [`RestartPolicy`], or stays down while the others keep running if there is none.
+    #[default]
+    Restart,
+}
+
+/// Emitted by the supervisor of every ring, see [`RingPoolBuilder::observe`](crate::pool::RingPoolBuilder::observe).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SupervisorEvent {
+    /// Ring `index` failed (or panicked) with `error`.
+    Failed { index: usize, error: String },
+    /// Ring `index` is rebuilt after `backoff`, `attempt` counts from 1.
+    Restarting {
+        index: usize,
+        attempt: u32,
+        backoff: Duration,
+    },
+    /// Ring `index` failed `restarts` times in a row and stays down.
+    GaveUp { index: usize, restarts: u32 },
+}
+
+pub(crate) type Observer = Arc<dyn Fn(&SupervisorEvent) + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub(crate) struct Supervisor {
+    pub(crate) policy: Option<RestartPolicy>,
+    pub(crate) panic: PanicPolicy,
+    pub(crate) observer: Option<Observer>,
+}
+
+impl Debug for Supervisor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Supervisor")
+            .field("policy", &self.policy)
+            .field("panic", &self.panic)
+            .field("observed", &self.observer.is_some())
+            .finish()
+    }
+}
+
+impl Supervisor {
+    fn emit(&self, event: SupervisorEvent) {
+        if let Some(observer) = &self.observer {
+            observer(&event);
+        }
+    }
+
+    /// Runs `factory` until it succeeds, the policy gives up or the pool shuts down.
+    pub(crate) fn run<E: Debug, T, F: Fn(RingContext) -> Result<T, E> + ?Sized>(
+        &self,
+        context: RingContext,
+        factory: &F,
+    ) -> Result<T, RingThreadError<E>> {
+        let index = context.index;
+        let mut attempt = 0;
+        loop {
+            let started = std::time::Instant::now();
+            let (e, panicked) = match catch_unwind(AssertUnwindSafe(|| factory(context.clone()))) {
+                Ok(Ok(state)) => return Ok(state),
+                Ok(Err(e)) => (RingThreadError::Ring(e), false),
+                Err(panic) => (RingThreadError::Panic(PanicPayload(panic)), true),
+            };
+            error!("ring {index} failed: {e}");
+            self.emit(SupervisorEvent::Failed {
+                index,
+                error: e.to_string(),
+            });
+
+            match self.panic {
+                PanicPolicy::Abort if panicked => {
+                    error!("ring {index} panicked, stopping the pool");
+                    context.shared.abort();
+                    return Err(e);
+                }
+                PanicPolicy::Isolate if panicked => return Err(e),
+                _ => {}
+            }
+
+            let Some(policy) = self.policy else {
+                return Err(e);
+            };
+            if context.shared.requested(index).is_some() {
+                // shutting down, the ring is not needed anymore
+                return Err(e);
+            }
+
+            if started.elapsed() >= policy.reset_after {
+                attempt = 0;
+            }
+            if policy.max_restarts.is_some_and(|max| attempt >= max) {
+                self.emit(SupervisorEvent::GaveUp {
+                    index,
+                    restarts: attempt,
+                });
+                return Err(e);
+            }
+
+            attempt += 1;
+            let backoff = policy.backoff(attempt);
+            warn!("restarting ring {index} in {backoff:?} (attempt {attempt})");
+            self.emit(SupervisorEvent::Restarting {
+                index,
+                attempt,
+                backoff,
+            });
+            std::thread::sleep(backoff);
+            if context.shared.requested(index).is_some() {
+                return Err(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::pool::tests::run_drain_ring;
+    use crate::pool::{PoolError, RingError, RingPool, RingPoolBuilder, RingReport};
+    use crate::ControlFlow;
+
+    const POLICY: RestartPolicy = RestartPolicy {
+        max_restarts: Some(2),
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(10),
+        reset_after: Duration::from_secs(60),
+    };
+
+    /// A pool of one ring observed into the returned events.
+    fn observed() -> (RingPoolBuilder, Arc<Mutex<Vec<SupervisorEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let builder = RingPool::builder().threads(1).restart(POLICY).observe({
+            let events = events.clone();
+            move |event| events.lock().unwrap().push(event.clone())
+        });
+        (builder, events)
+    }
+
+    fn failed() -> SupervisorEvent {
+        SupervisorEvent::Failed {
+            index: 0,
+            error: r#"ring failed: "broken""#.to_string(),
+        }
+    }
+
+    fn restarting(attempt: u32, backoff: u64) -> SupervisorEvent {
+        SupervisorEvent::Restarting {
+            index: 0,
+            attempt,
+            backoff: Duration::from_millis(backoff),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        let backoffs: Vec<_> = [1, 2, 3, 4, 5, 100]
+            .map(|attempt| POLICY.backoff(attempt).as_millis())
+            .into();
+        assert_eq!(backoffs, [1, 2, 4, 8, 10, 10]);
+    }
+
+    #[test]
+    fn failed_rings_are_restarted() {
+        let (builder, events) = observed();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let pool = builder
+            .spawn({
+                let attempts = attempts.clone();
+                move |_| match attempts.fetch_add(1, Ordering::Relaxed) {
+                    0 | 1 => Err("broken"),
+                    attempt => Ok(attempt),
+                }
+            })
+            .unwrap();
+
+        let reports = PoolError::check(pool.join()).unwrap();
+        assert_eq!(reports[0].state, 2);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [failed(), restarting(1, 1), failed(), restarting(2, 2)]
+        );
+    }
+
+    #[test]
+    fn supervisor_gives_up_after_max_restarts() {
+        let (builder, events) = observed();
+        let pool = builder.spawn(|_| Err::<(), _>("broken")).unwrap();
+
+        let errors = PoolError::check(pool.join()).unwrap_err().0;
+        assert!(matches!(errors[0].error, RingThreadError::Ring("broken")));
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                failed(),
+                restarting(1, 1),
+                failed(),
+                restarting(2, 2),
+                failed(),
+                SupervisorEvent::GaveUp {
+                    index: 0,
+                    restarts: 2
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rings_failing_during_shutdown_are_not_restarted() {
+        let (builder, events) = observed();
+        let pool = builder
+            .spawn(|context| {
+                run_drain_ring(context, |_| ControlFlow::Exit)
+                    .and(Err::<(), _>("broken".to_string()))
+            })
+            .unwrap();
+
+        let errors = PoolError::check(pool.shutdown(Duration::from_secs(10)))
+            .unwrap_err()
+            .0;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(*events.lock().unwrap(), [failed()]);
+    }
+
+    /// Ring 0 panics the first time it is built, ring 1 runs until it is drained. Also returns
+    /// how often ring 0 was built and how often ring 1 was asked to drain.
+    fn panicking_pool(
+        policy: PanicPolicy,
+    ) -> (RingPool<String, usize>, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+        let built = Arc::new(AtomicUsize::new(0));
+        let asked = Arc::new(AtomicUsize::new(0));
+        let pool = RingPool::builder()
+            .threads(2)
+            .restart(POLICY)
+            .on_panic(policy)
+            .spawn({
+                let built = built.clone();
+                let asked = asked.clone();
+                move |context| match context.index {
+                    0 => match built.fetch_add(1, Ordering::Relaxed) {
+                        0 => panic!("ring 0 panicked"),
+                        _ => Ok(0),
+                    },
+                    _ => {
+                        let asked = asked.clone();
+                        run_drain_ring(context, move |_| {
+                            asked.fetch_add(1, Ordering::Relaxed);
+                            ControlFlow::Exit
+                        })
+                    }
+                }
+            })
+            .unwrap();
+        (pool, built, asked)
+    }
+
+    fn panicked(result: &Result<RingReport<usize>, RingError<String>>) -> bool {
+        matches!(
+            result,
+            Err(RingError { error: RingThreadError::Panic(payload), .. })
+                if payload.message() == Some("ring 0 panicked")
+        )
+    }
+
+    fn wait_for_ring_0(pool: &RingPool<String, usize>) {
+        let ring = pool.handles[0].as_ref().unwrap();
+        while !ring.is_finished() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn abort_forces_every_ring_to_exit() {
+        let (pool, built, asked) = panicking_pool(PanicPolicy::Abort);
+
+        let results = pool.join();
+        assert!(panicked(&results[0]));
+        assert_eq!(results[1].as_ref().unwrap().state, 1);
+        assert_eq!(built.load(Ordering::Relaxed), 1);
+        assert_eq!(asked.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn isolate_keeps_the_other_rings_running() {
+        let (pool, built, asked) = panicking_pool(PanicPolicy::Isolate);
+        wait_for_ring_0(&pool);
+
+        let results = pool.shutdown(Duration::from_secs(10));
+        assert!(panicked(&results[0]));
+        assert_eq!(results[1].as_ref().unwrap().state, 1);
+        assert_eq!(built.load(Ordering::Relaxed), 1);
+        assert_eq!(asked.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn restart_rebuilds_the_panicked_ring() {
+        let (pool, built, asked) = panicking_pool(PanicPolicy::Restart);
+        wait_for_ring_0(&pool);
+
+        let reports = PoolError::check(pool.shutdown(Duration::from_secs(10))).unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(built.load(Ordering::Relaxed), 2);
+        assert_eq!(asked.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,269 @@
+//! Prometheus metrics for rings, registered with a [`prometheus::Registry`].
+//!
+//! A [`PrometheusExporter`] owns the metric vectors, every ring feeds them with the observer
+//! returned by [`PrometheusExporter::stats_observer`] (its [`RingStats`] after every iteration)
+//! and the completions of a [`Timeline`] ([`PrometheusExporter::observe_timeline`]):
+//!
+//! ```no_run
+//! # rummelplatz::ring! { my_ring, tick: rummelplatz::ops::TickOp }
+//! # fn example(mut ring: my_ring::Ring, index: usize) -> prometheus::Result<()> {
+//! use rummelplatz::prometheus::PrometheusExporter;
+//! use rummelplatz::timeline::Timeline;
+//!
+//! let exporter = PrometheusExporter::new(prometheus::default_registry())?;
+//! // on the thread of every ring
+//! ring.observe_stats(exporter.stats_observer(index.to_string()));
+//! let timeline = Timeline::new(0);
+//! exporter.observe_timeline(index.to_string(), &timeline);
+//! ring.record_timeline(timeline);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! With the default [`PrometheusOptions`] this exports (labels in braces):
+//!
+//! - `rummelplatz_submissions_total{ring}`, `rummelplatz_completions_total{ring}`,
+//!   `rummelplatz_syscalls_total{ring}`, `rummelplatz_backlog_spills_total{ring}`,
+//!   `rummelplatz_iterations_total{ring}` and `rummelplatz_blocked_seconds_total{ring}`
+//! - `rummelplatz_queue_entries{ring,queue}`, `rummelplatz_queue_high_water{ring,queue}` and
+//!   `rummelplatz_queue_capacity{ring,queue}`, the fill level of the `sq` and `cq` of a ring
+//! - `rummelplatz_in_flight{ring}` and `rummelplatz_in_flight_high_water{ring}`, the requests
+//!   of a ring in flight
+//! - `rummelplatz_request_duration_seconds{ring,operation}`, a histogram of the time from
+//!   pushing a request to its last completion
+//! - `rummelplatz_request_errors_total{ring,operation}`, completions with an error
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+
+use prometheus::{
+    CounterVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, Result,
+};
+
+use crate::stats::RingStats;
+use crate::timeline::{Completion, Timeline};
+
+/// Names and labels of the metrics of a [`PrometheusExporter`].
+#[derive(Debug, Clone)]
+pub struct PrometheusOptions {
+    /// Prefix of every metric name, `rummelplatz` by default
+    pub namespace: String,
+    /// Name of the label holding the ring, `ring` by default
+    pub ring_label: String,
+    /// Name of the label holding the operation, `operation` by default
+    pub operation_label: String,
+    /// Name of the label telling the submission (`sq`) and completion queue (`cq`) apart,
+    /// `queue` by default
+    pub queue_label: String,
+    /// Labels with the same value on every metric, e.g. the service or pool
+    pub const_labels: HashMap<String, String>,
+    /// Bucket boundaries of the request durations in seconds, 1µs to 10s by default
+    pub buckets: Vec<f64>,
+}
+
+impl Default for PrometheusOptions {
+    fn default() -> Self {
+        Self {
+            namespace: "rummelplatz".to_string(),
+            ring_label: "ring".to_string(),
+            operation_label: "operation".to_string(),
+            queue_label: "queue".to_string(),
+            const_labels: HashMap::new(),
+            buckets: vec![
+                0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01,
+                0.05, 0.1, 0.5, 1.0, 5.0, 10.0,
+            ],
+        }
+    }
+}
+
+/// The metrics of rings in a registry, cheap to clone.
+#[derive(Clone)]
+pub struct PrometheusExporter {
+    submissions: IntCounterVec,
+    completions: IntCounterVec,
+    syscalls: IntCounterVec,
+    backlog_spills: IntCounterVec,
+    iterations: IntCounterVec,
+    blocked: CounterVec,
+    queue_entries: IntGaugeVec,
+    queue_high_water: IntGaugeVec,
+    queue_capacity: IntGaugeVec,
+    in_flight: IntGaugeVec,
+    in_flight_high_water: IntGaugeVec,
+    durations: HistogramVec,
+    errors: IntCounterVec,
+}
+
+impl Debug for PrometheusExporter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PrometheusExporter").finish_non_exhaustive()
+    }
+}
+
+impl PrometheusExporter {
+    /// Registers the metrics with the default [`PrometheusOptions`].
+    pub fn new(registry: &Registry) -> Result<Self> {
+        Self::with_options(registry, PrometheusOptions::default())
+    }
+
+    /// Registers the metrics, fails if metrics with the same names are registered already.
+    pub fn with_options(registry: &Registry, options: PrometheusOptions) -> Result<Self> {
+        let opts = |name: &str, help: &str| {
+            Opts::new(name, help)
+                .namespace(options.namespace.clone())
+                .const_labels(options.const_labels.clone())
+        };
+        let ring = [options.ring_label.as_str()];
+        let operation = [
+            options.ring_label.as_str(),
+            options.operation_label.as_str(),
+        ];
+        let queue = [options.ring_label.as_str(), options.queue_label.as_str()];
+
+        let exporter = Self {
+            submissions: IntCounterVec::new(
+                opts("submissions_total", "SQEs submitted to the kernel"),
+                &ring,
+            )?,
+            completions: IntCounterVec::new(
+                opts("completions_total", "CQEs handled by the ring"),
+                &ring,
+            )?,
+            syscalls: IntCounterVec::new(opts("syscalls_total", "Calls to io_uring_enter"), &ring)?,
+            backlog_spills: IntCounterVec::new(
+                opts(
+                    "backlog_spills_total",
+                    "Batches of SQEs that waited in the backlog for a full submission queue",
+                ),
+                &ring,
+            )?,
+            iterations: IntCounterVec::new(
+                opts("iterations_total", "Iterations of the run loop"),
+                &ring,
+            )?,
+            blocked: CounterVec::new(
+                opts(
+                    "blocked_seconds_total",
+                    "Time spent in io_uring_enter waiting for completions",
+                ),
+                &ring,
+            )?,
+            queue_entries: IntGaugeVec::new(
+                opts(
+                    "queue_entries",
+                    "Entries in the queue when the ring last submitted (sq) or synced it (cq)",
+                ),
+                &queue,
+            )?,
+            queue_high_water: IntGaugeVec::new(
+                opts("queue_high_water", "Most entries the queue held so far"),
+                &queue,
+            )?,
+            queue_capacity: IntGaugeVec::new(opts("queue_capacity", "Size of the queue"), &queue)?,
+            in_flight: IntGaugeVec::new(
+                opts("in_flight", "Requests pushed and not completed yet"),
+                &ring,
+            )?,
+            in_flight_high_water: IntGaugeVec::new(
+                opts(
+                    "in_flight_high_water",
+                    "Most requests in flight at once so far",
+                ),
+                &ring,
+            )?,
+            durations: HistogramVec::new(
+                HistogramOpts::from(opts(
+                    "request_duration_seconds",
+                    "Time from pushing a request to the submission queue to its last completion",
+                ))
+                .buckets(options.buckets.clone()),
+                &operation,
+            )?,
+            errors: IntCounterVec::new(
+                opts("request_errors_total", "Completions with an error"),
+                &operation,
+            )?,
+        };
+
+        registry.register(Box::new(exporter.submissions.clone()))?;
+        registry.register(Box::new(exporter.completions.clone()))?;
+        registry.register(Box::new(exporter.syscalls.clone()))?;
+        registry.register(Box::new(exporter.backlog_spills.clone()))?;
+        registry.register(Box::new(exporter.iterations.clone()))?;
+        registry.register(Box::new(exporter.blocked.clone()))?;
+        registry.register(Box::new(exporter.queue_entries.clone()))?;
+        registry.register(Box::new(exporter.queue_high_water.clone()))?;
+        registry.register(Box::new(exporter.queue_capacity.clone()))?;
+        registry.register(Box::new(exporter.in_flight.clone()))?;
+        registry.register(Box::new(exporter.in_flight_high_water.clone()))?;
+        registry.register(Box::new(exporter.durations.clone()))?;
+        registry.register(Box::new(exporter.errors.clone()))?;
+        Ok(exporter)
+    }
+
+    /// An observer for `Ring::observe_stats` adding the counters of the ring labeled `ring` and
+    /// setting its queue and in-flight gauges. A ring rebuilt with the same label continues its counters.
+    pub fn stats_observer(&self, ring: impl Into<String>) -> impl FnMut(&RingStats) + 'static {
+        let ring = ring.into();
+        let labels = [ring.as_str()];
+        let submissions = self.submissions.with_label_values(&labels);
+        let completions = self.completions.with_label_values(&labels);
+        let syscalls = self.syscalls.with_label_values(&labels);
+        let backlog_spills = self.backlog_spills.with_label_values(&labels);
+        let iterations = self.iterations.with_label_values(&labels);
+        let blocked = self.blocked.with_label_values(&labels);
+        let in_flight = self.in_flight.with_label_values(&labels);
+        let in_flight_high_water = self.in_flight_high_water.with_label_values(&labels);
+        let queue = |queue: &str| {
+            let labels = [ring.as_str(), queue];
+            [
+                self.queue_entries.with_label_values(&labels),
+                self.queue_high_water.with_label_values(&labels),
+                self.queue_capacity.with_label_values(&labels),
+            ]
+        };
+        let (sq, cq) = (queue("sq"), queue("cq"));
+
+        let mut previous = RingStats::default();
+        move |stats| {
+            let delta = stats.since(&previous);
+            previous = *stats;
+
+            submissions.inc_by(delta.submitted);
+            completions.inc_by(delta.completed);
+            syscalls.inc_by(delta.syscalls);
+            backlog_spills.inc_by(delta.backlog_spills);
+            iterations.inc_by(delta.iterations);
+            blocked.inc_by(delta.blocked.as_secs_f64());
+            for (gauges, fill) in [(&sq, stats.sq), (&cq, stats.cq)] {
+                gauges[0].set(fill.len as i64);
+                gauges[1].set(fill.high_water as i64);
+                gauges[2].set(fill.capacity as i64);
+            }
+            in_flight.set(stats.in_flight.current as i64);
+            in_flight_high_water.set(stats.in_flight.high_water as i64);
+        }
+    }
+
+    /// Records the durations and errors of the completions on `timeline` labeled `ring`, see
+    /// [`Timeline::on_completion`]. Durations are recorded for the last completion of a request
+    /// only, a timeline of capacity `0` records nothing else.
+    pub fn observe_timeline(&self, ring: impl Into<String>, timeline: &Timeline) {
+        let ring = ring.into();
+        let durations = self.durations.clone();
+        let errors = self.errors.clone();
+
+        timeline.on_completion(move |completion: &Completion| {
+            let labels = [ring.as_str(), completion.operation];
+            if completion.result < 0 {
+                errors.with_label_values(&labels).inc();
+            }
+            if !completion.more {
+                durations
+                    .with_label_values(&labels)
+                    .observe(completion.elapsed.as_secs_f64());
+            }
+        });
+    }
+}
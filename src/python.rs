@@ -0,0 +1,402 @@
+//! Python bindings of the high-level pieces, a `rummelplatz` extension module built with pyo3.
+//!
+//! Build it with maturin (`maturin build --features python`) or
+//! `cargo rustc --release --features python --crate-type cdylib` and copy
+//! `librummelplatz.so` to `rummelplatz.so` on the Python path.
+//!
+//! ```python
+//! import rummelplatz
+//!
+//! # batched file I/O on a temporary ring, without holding the GIL
+//! contents = rummelplatz.read_files(["a.txt", "b.txt"])
+//! rummelplatz.write_files({"copy.txt": contents[0]})
+//!
+//! # a pool of rings on dedicated threads, callbacks run on the ring threads
+//! pool = rummelplatz.RingPool(rings=2)
+//! pool.read("a.txt", lambda data, error: print(error or len(data)))
+//! pool.shutdown(1.0)
+//!
+//! # a thread-per-core TCP server, the handler returns what to send back
+//! server = rummelplatz.Server("127.0.0.1:8080", lambda connection, data: data)
+//! server.shutdown(5.0)
+//! ```
+//!
+//! Callbacks and handlers run on the ring threads and hold the GIL while they do, the rings
+//! wait for them.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Wake, Waker};
+use std::time::Duration;
+
+use io_uring::opcode::{Read, Write};
+use io_uring::types::Fd;
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::blocking::block_on_ring;
+use crate::ops::{Error, RecvEvent};
+use crate::pool::reuseport::ReuseportGroup;
+use crate::runtime::Runtime;
+use crate::tokio_bridge::{Bridge, BridgeHandle};
+use crate::ControlFlow;
+
+/// Largest read or write of a single request
+const CHUNK: usize = 1 << 20;
+
+fn ring_size(entries: u32) -> PyResult<NonZeroU32> {
+    NonZeroU32::new(entries).ok_or_else(|| PyValueError::new_err("ring_size must not be 0"))
+}
+
+fn py_error(e: Error) -> PyErr {
+    match e {
+        Error::Io(e) => e.into(),
+        Error::Push(e) => PyOSError::new_err(e.to_string()),
+    }
+}
+
+fn read_all(paths: &[PathBuf]) -> Result<Vec<Vec<u8>>, Error> {
+    let files = paths
+        .iter()
+        .map(File::open)
+        .collect::<io::Result<Vec<_>>>()?;
+    let sizes = files
+        .iter()
+        .map(|file| Ok(file.metadata()?.len() as usize))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut contents: Vec<Vec<u8>> = sizes.iter().map(|&size| Vec::with_capacity(size)).collect();
+
+    // regular files are read in one round, the next ones pick up short reads and files
+    // growing in the meantime
+    let mut pending: Vec<usize> = (0..files.len()).collect();
+    while !pending.is_empty() {
+        let results = block_on_ring(NonZeroU32::new(64).unwrap(), |mut submitter| {
+            for index in pending.drain(..) {
+                let mut content = std::mem::take(&mut contents[index]);
+                if content.len() == content.capacity() {
+                    content.reserve(content.len().clamp(4096, CHUNK));
+                }
+                let len = (content.capacity() - content.len()).min(CHUNK);
+                let buf = unsafe { content.as_mut_ptr().add(content.len()) };
+                let read = Read::new(Fd(files[index].as_raw_fd()), buf, len as u32)
+                    .offset(content.len() as u64)
+                    .build();
+                submitter.push(read, (index, content))?;
+            }
+            Ok(())
+        })?;
+
+        for ((index, mut content), cqe) in results {
+            if cqe.result() < 0 {
+                return Err(io::Error::from_raw_os_error(-cqe.result()).into());
+            }
+            unsafe { content.set_len(content.len() + cqe.result() as usize) };
+            if cqe.result() > 0 && content.len() != sizes[index] {
+                pending.push(index);
+            }
+            contents[index] = content;
+        }
+    }
+    Ok(contents)
+}
+
+fn write_all(files: &[(PathBuf, Vec<u8>)]) -> Result<(), Error> {
+    let handles = files
+        .iter()
+        .map(|(path, _)| File::create(path))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut pending: Vec<(usize, usize)> = (0..files.len()).map(|index| (index, 0)).collect();
+    while !pending.is_empty() {
+        let results = block_on_ring(NonZeroU32::new(64).unwrap(), |mut submitter| {
+            for (index, written) in pending.drain(..) {
+                let data = &files[index].1[written..];
+                let len = data.len().min(CHUNK);
+                let write = Write::new(Fd(handles[index].as_raw_fd()), data.as_ptr(), len as u32)
+                    .offset(written as u64)
+                    .build();
+                submitter.push(write, (index, written))?;
+            }
+            Ok(())
+        })?;
+
+        for ((index, written), cqe) in results {
+            if cqe.result() < 0 {
+                return Err(io::Error::from_raw_os_error(-cqe.result()).into());
+            }
+            let written = written + cqe.result() as usize;
+            if written < files[index].1.len() {
+                pending.push((index, written));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads the files at `paths` with batched requests on a temporary ring.
+#[pyfunction]
+fn read_files(py: Python<'_>, paths: Vec<PathBuf>) -> PyResult<Vec<Py<PyBytes>>> {
+    let contents = py.detach(|| read_all(&paths)).map_err(py_error)?;
+    Ok(contents
+        .iter()
+        .map(|content| PyBytes::new(py, content).unbind())
+        .collect())
+}
+
+/// Creates (or truncates) the files of the `path -> bytes` dict and writes their contents
+/// with batched requests on a temporary ring.
+#[pyfunction]
+fn write_files(py: Python<'_>, files: HashMap<PathBuf, Vec<u8>>) -> PyResult<()> {
+    let files: Vec<_> = files.into_iter().collect();
+    py.detach(|| write_all(&files)).map_err(py_error)
+}
+
+/// A future polled by whichever thread wakes it, the ring threads of the pool.
+struct Task {
+    future: Mutex<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+}
+
+impl Task {
+    fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(Box::pin(future))),
+        });
+        task.poll();
+    }
+
+    fn poll(self: &Arc<Self>) {
+        let waker = Waker::from(self.clone());
+        let mut future = self.future.lock().unwrap();
+        if let Some(pending) = future.as_mut() {
+            if pending
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_ready()
+            {
+                *future = None;
+            }
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.poll();
+    }
+}
+
+async fn read_file(handle: BridgeHandle, file: File) -> io::Result<Vec<u8>> {
+    let mut content = Vec::<u8>::with_capacity(file.metadata()?.len() as usize);
+    loop {
+        if content.len() == content.capacity() {
+            content.reserve(CHUNK);
+        }
+        let len = (content.capacity() - content.len()).min(CHUNK);
+        let buf = unsafe { content.as_mut_ptr().add(content.len()) };
+        let read = Read::new(Fd(file.as_raw_fd()), buf, len as u32)
+            .offset(content.len() as u64)
+            .build();
+        // the task owns the buffer until the request completed or the pool is gone
+        let res = unsafe { handle.submit(read) }.await?.result();
+        if res < 0 {
+            return Err(io::Error::from_raw_os_error(-res));
+        }
+        if res == 0 {
+            return Ok(content);
+        }
+        unsafe { content.set_len(content.len() + res as usize) };
+    }
+}
+
+async fn write_file(handle: BridgeHandle, file: File, data: Vec<u8>) -> io::Result<usize> {
+    let mut written = 0;
+    while written < data.len() {
+        let len = (data.len() - written).min(CHUNK);
+        let write = Write::new(Fd(file.as_raw_fd()), data[written..].as_ptr(), len as u32)
+            .offset(written as u64)
+            .build();
+        // the task owns the data until the request completed or the pool is gone
+        let res = unsafe { handle.submit(write) }.await?.result();
+        if res < 0 {
+            return Err(io::Error::from_raw_os_error(-res));
+        }
+        written += res as usize;
+    }
+    Ok(written)
+}
+
+/// Calls `callback(result, None)` or `callback(None, error)` and prints what it raises.
+fn complete<T: for<'py> IntoPyObject<'py>>(callback: Py<PyAny>, result: io::Result<T>) {
+    Python::attach(|py| {
+        let called = match result {
+            Ok(value) => callback.call1(py, (value, py.None())),
+            Err(e) => callback.call1(py, (py.None(), PyErr::from(e))),
+        };
+        if let Err(e) = called {
+            e.print(py);
+        }
+    })
+}
+
+/// Rings on dedicated threads running file requests, their callbacks are called on the ring
+/// threads.
+#[pyclass(name = "RingPool")]
+struct PyRingPool {
+    // taken on shutdown, a `Mutex` as Python objects may be shared between threads
+    bridge: Mutex<Option<Bridge>>,
+    handle: BridgeHandle,
+}
+
+#[pymethods]
+impl PyRingPool {
+    /// Starts `rings` rings (one per allowed CPU by default).
+    #[new]
+    #[pyo3(signature = (rings = None, ring_size = 256))]
+    fn new(rings: Option<usize>, ring_size: u32) -> PyResult<Self> {
+        let rings = match rings {
+            Some(rings) => rings,
+            None => crate::pool::allowed_cpus()?.len(),
+        };
+        let bridge = Bridge::spawn(rings.max(1), self::ring_size(ring_size)?)?;
+        Ok(Self {
+            handle: bridge.handle(),
+            bridge: Mutex::new(Some(bridge)),
+        })
+    }
+
+    /// Reads the file at `path`, then calls `callback(data, None)` or `callback(None, error)`.
+    fn read(&self, path: PathBuf, callback: Py<PyAny>) -> PyResult<()> {
+        let file = File::open(path)?;
+        let handle = self.handle.clone();
+        Task::spawn(async move { complete(callback, read_file(handle, file).await) });
+        Ok(())
+    }
+
+    /// Creates (or truncates) the file at `path` and writes `data`, then calls
+    /// `callback(written, None)` or `callback(None, error)`.
+    fn write(&self, path: PathBuf, data: Vec<u8>, callback: Py<PyAny>) -> PyResult<()> {
+        let file = File::create(path)?;
+        let handle = self.handle.clone();
+        Task::spawn(async move { complete(callback, write_file(handle, file, data).await) });
+        Ok(())
+    }
+
+    /// Waits up to `grace` seconds for the requests in flight, the callbacks of requests still
+    /// running afterwards get an error.
+    #[pyo3(signature = (grace = 5.0))]
+    fn shutdown(&self, py: Python<'_>, grace: f64) {
+        // released before waiting, which needs the GIL released as well
+        let bridge = self.bridge.lock().unwrap().take();
+        if let Some(bridge) = bridge {
+            py.detach(|| bridge.shutdown(Duration::from_secs_f64(grace)));
+        }
+    }
+}
+
+/// A thread-per-core TCP server, `handler(connection, data)` is called on the ring threads for
+/// everything received.
+#[pyclass(name = "Server")]
+struct PyServer {
+    runtime: Mutex<Option<Runtime>>,
+    address: SocketAddr,
+}
+
+#[pymethods]
+impl PyServer {
+    /// Listens on `address`. The handler returns `bytes` to send back, `None` to send nothing
+    /// or `False` to close the connection (which it also does if the handler raises), and is
+    /// called with `data = None` once the peer closed the connection.
+    #[new]
+    #[pyo3(signature = (address, handler, cores = None, ring_size = 256))]
+    fn new(
+        py: Python<'_>,
+        address: &str,
+        handler: Py<PyAny>,
+        cores: Option<usize>,
+        ring_size: u32,
+    ) -> PyResult<Self> {
+        let address: SocketAddr = address
+            .parse()
+            .map_err(|e| PyValueError::new_err(format!("invalid address {address}: {e}")))?;
+        let mut builder = Runtime::builder().ring_size(self::ring_size(ring_size)?);
+        if let Some(cores) = cores {
+            builder = builder.cores(cores);
+        }
+
+        let handler = Arc::new(handler);
+        let runtime = py.detach(|| {
+            builder.serve(
+                |shards| ReuseportGroup::bind(address, shards, Default::default()),
+                move |context| {
+                    let handler = handler.clone();
+                    let connection = context.fd;
+                    move |event, out: &crate::ops::SendHandle| {
+                        Python::attach(|py| {
+                            let data = match event {
+                                RecvEvent::Data(data) => Some(PyBytes::new(py, data)),
+                                RecvEvent::Closed => None,
+                            };
+                            match handler.call1(py, (connection, data)) {
+                                Ok(reply) if reply.is_none(py) => ControlFlow::Continue,
+                                Ok(reply) => {
+                                    if let Ok(bytes) = reply.extract::<Vec<u8>>(py) {
+                                        out.send(bytes);
+                                        ControlFlow::Continue
+                                    } else if reply.extract::<bool>(py).is_ok_and(|keep| !keep) {
+                                        ControlFlow::Exit
+                                    } else {
+                                        ControlFlow::Continue
+                                    }
+                                }
+                                Err(e) => {
+                                    e.print(py);
+                                    ControlFlow::Exit
+                                }
+                            }
+                        })
+                    }
+                },
+            )
+        })?;
+
+        Ok(Self {
+            address: runtime.local_addr(),
+            runtime: Mutex::new(Some(runtime)),
+        })
+    }
+
+    /// The address the server listens on, e.g. to find the port bound for port 0.
+    #[getter]
+    fn local_addr(&self) -> String {
+        self.address.to_string()
+    }
+
+    /// Stops accepting, waits up to `grace` seconds for open connections to close and closes
+    /// the rest.
+    #[pyo3(signature = (grace = 5.0))]
+    fn shutdown(&self, py: Python<'_>, grace: f64) {
+        // released before waiting, which needs the GIL released as well
+        let runtime = self.runtime.lock().unwrap().take();
+        if let Some(runtime) = runtime {
+            py.detach(|| runtime.shutdown(Duration::from_secs_f64(grace)));
+        }
+    }
+}
+
+#[pymodule]
+fn rummelplatz(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(read_files, module)?)?;
+    module.add_function(wrap_pyfunction!(write_files, module)?)?;
+    module.add_class::<PyRingPool>()?;
+    module.add_class::<PyServer>()?;
+    Ok(())
+}
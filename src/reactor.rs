@@ -0,0 +1,299 @@
+//! An I/O driver for user-written executors.
+//!
+//! A [`Reactor`] owns a ring and plays the part `mio::Poll` plays for tokio: futures register
+//! their requests and get a [`Token`], poll the reactor for the token's completions (which
+//! stores the task's waker) and the executor calls [`Reactor::turn`] whenever it runs out of
+//! ready tasks. `turn` submits, waits for completions and wakes the tasks they belong to.
+//!
+//! ```no_run
+//! # use rummelplatz::reactor::Reactor;
+//! # use rummelplatz::io_uring::opcode::Nop;
+//! let reactor = Reactor::new(64)?;
+//! let completion = reactor.completion(unsafe { reactor.register(Nop::new().build())? });
+//! // spawn a task awaiting `completion`, then in the executor loop:
+//! reactor.turn(None)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! The reactor is not `Send`, it belongs to the executor thread. Wakers are called from
+//! [`Reactor::turn`], so they may also be `!Send` in spirit, but have to be valid
+//! [`Waker`]s.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::AsyncCancel;
+use io_uring::types::{SubmitArgs, Timespec};
+use io_uring::IoUring;
+use tracing::trace;
+
+/// Identifies a registered request, see [`Reactor::register`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Token(pub usize);
+
+impl Token {
+    // never zero and with the lowest bit clear, which is reserved for messages
+    #[inline]
+    fn user_data(self) -> u64 {
+        (self.0 as u64 + 1) << 1
+    }
+
+    #[inline]
+    fn from_user_data(user_data: u64) -> Self {
+        Self((user_data >> 1) as usize - 1)
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    completions: VecDeque<Entry>,
+    waker: Option<Waker>,
+    /// The kernel posted the last completion of the request
+    finished: bool,
+    /// Nobody polls the token anymore, it is freed with the last completion
+    deregistered: bool,
+}
+
+struct Inner {
+    ring: IoUring,
+    slots: Vec<Option<Slot>>,
+    free: Vec<usize>,
+}
+
+impl Inner {
+    fn push(&mut self, entry: &io_uring::squeue::Entry) -> io::Result<()> {
+        if unsafe { self.ring.submission().push(entry) }.is_ok() {
+            return Ok(());
+        }
+
+        // make room by handing the queued entries to the kernel
+        self.ring.submit()?;
+        unsafe { self.ring.submission().push(entry) }
+            .map_err(|_| io::Error::new(io::ErrorKind::WouldBlock, "submission queue is full"))
+    }
+
+    /// Pushes an entry no token owns, e.g. a cancellation, [`Reactor::turn`] drops its completion.
+    fn push_unmanaged(&mut self, entry: io_uring::squeue::Entry) -> io::Result<()> {
+        self.push(&entry.user_data(0))
+    }
+
+    fn free(&mut self, token: Token) {
+        self.slots[token.0] = None;
+        self.free.push(token.0);
+    }
+}
+
+/// Drives a ring for an executor, see the [module docs](self). Cheap to clone, all clones
+/// share the ring.
+#[derive(Clone)]
+pub struct Reactor {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl Debug for Reactor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let inner = self.inner.borrow();
+        f.debug_struct("Reactor")
+            .field("ring_fd", &inner.ring.as_raw_fd())
+            .field("registered", &(inner.slots.len() - inner.free.len()))
+            .finish()
+    }
+}
+
+impl Reactor {
+    /// Creates a ring with `entries` submission queue entries.
+    pub fn new(entries: u32) -> io::Result<Self> {
+        Ok(Self::with_ring(IoUring::new(entries)?))
+    }
+
+    pub fn with_ring(ring: IoUring) -> Self {
+        Self {
+            inner: Rc::new(RefCell::new(Inner {
+                ring,
+                slots: Vec::new(),
+                free: Vec::new(),
+            })),
+        }
+    }
+
+    /// Queues `entry` (submitted by the next [`Reactor::turn`]) and returns the token its
+    /// completions are polled with. Multishot requests are supported, their completions are
+    /// queued per token.
+    ///
+    /// # Safety
+    /// Everything `entry` points to has to stay valid until the request posted its last
+    /// completion, even if the token is deregistered before.
+    pub unsafe fn register(&self, entry: io_uring::squeue::Entry) -> io::Result<Token> {
+        let mut inner = self.inner.borrow_mut();
+        let token = Token(match inner.free.pop() {
+            Some(index) => index,
+            None => {
+                inner.slots.push(None);
+                inner.slots.len() - 1
+            }
+        });
+        inner.slots[token.0] = Some(Slot::default());
+
+        if let Err(e) = inner.push(&entry.user_data(token.user_data())) {
+            inner.free(token);
+            return Err(e);
+        }
+        trace!("registered {token:?}");
+        Ok(token)
+    }
+
+    /// Takes the next completion of `token`, or stores the waker of `cx` to be woken once
+    /// there is one. Returns `None` once the request posted its last completion and all of
+    /// them were taken, the token is freed then.
+    ///
+    /// # Panics
+    /// If `token` is not registered.
+    pub fn poll_completion(&self, token: Token, cx: &mut Context<'_>) -> Poll<Option<Entry>> {
+        let mut inner = self.inner.borrow_mut();
+        let slot = inner.slots[token.0]
+            .as_mut()
+            .expect("token is not registered");
+
+        if let Some(entry) = slot.completions.pop_front() {
+            return Poll::Ready(Some(entry));
+        }
+        if slot.finished {
+            inner.free(token);
+            return Poll::Ready(None);
+        }
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// A future resolving with the first completion of `token`, for one-shot requests. The
+    /// token is deregistered when the future is dropped.
+    pub fn completion(&self, token: Token) -> ReactorCompletion {
+        ReactorCompletion {
+            reactor: self.clone(),
+            token: Some(token),
+        }
+    }
+
+    /// Gives up on `token`: a request still in flight is canceled and the token is freed once
+    /// it posted its last completion.
+    pub fn deregister(&self, token: Token) -> io::Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        let Some(slot) = inner.slots.get_mut(token.0).and_then(Option::as_mut) else {
+            return Ok(());
+        };
+
+        if slot.finished {
+            inner.free(token);
+            return Ok(());
+        }
+        slot.deregistered = true;
+        slot.waker = None;
+        slot.completions.clear();
+        inner.push_unmanaged(AsyncCancel::new(token.user_data()).build())
+    }
+
+    /// Submits the queued requests, waits up to `timeout` (forever for `None`) for at least
+    /// one completion and wakes the tasks of all completions that arrived. Returns the number
+    /// of completions.
+    ///
+    /// Call it with `Some(Duration::ZERO)` to only submit and collect what is already there.
+    pub fn turn(&self, timeout: Option<Duration>) -> io::Result<usize> {
+        let mut inner = self.inner.borrow_mut();
+        let submitted = match timeout {
+            None => inner.ring.submit_and_wait(1),
+            Some(Duration::ZERO) => inner.ring.submit(),
+            Some(timeout) => {
+                let timespec = Timespec::from(timeout);
+                let args = SubmitArgs::new().timespec(&timespec);
+                inner.ring.submitter().submit_with_args(1, &args)
+            }
+        };
+        match submitted {
+            Ok(_) => {}
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ETIME | libc::EINTR)) => {}
+            Err(e) => return Err(e),
+        }
+
+        let completions: Vec<Entry> = inner.ring.completion().collect();
+        let mut wakers = Vec::new();
+        for entry in &completions {
+            if entry.user_data() == 0 {
+                // a cancellation
+                continue;
+            }
+
+            let token = Token::from_user_data(entry.user_data());
+            let finished = !io_uring::cqueue::more(entry.flags());
+            let Some(slot) = inner.slots.get_mut(token.0).and_then(Option::as_mut) else {
+                trace!("dropped completion of unknown {token:?}");
+                continue;
+            };
+
+            slot.finished = finished;
+            if slot.deregistered {
+                if finished {
+                    inner.free(token);
+                }
+                continue;
+            }
+            slot.completions.push_back(entry.clone());
+            wakers.extend(slot.waker.take());
+        }
+        // a woken task may poll the reactor right away
+        drop(inner);
+
+        trace!("{} completions", completions.len());
+        wakers.into_iter().for_each(Waker::wake);
+        Ok(completions.len())
+    }
+}
+
+impl AsRawFd for Reactor {
+    /// The ring fd, e.g. to nest the reactor in another event loop.
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.borrow().ring.as_raw_fd()
+    }
+}
+
+/// The first completion of a one-shot request, see [`Reactor::completion`].
+#[derive(Debug)]
+pub struct ReactorCompletion {
+    reactor: Reactor,
+    // taken once resolved
+    token: Option<Token>,
+}
+
+impl Future for ReactorCompletion {
+    type Output = Entry;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let token = self.token.expect("completion polled after it resolved");
+        match self.reactor.poll_completion(token, cx) {
+            Poll::Ready(Some(entry)) => {
+                self.token = None;
+                // frees the token, the request is done
+                let _ = self.reactor.deregister(token);
+                Poll::Ready(entry)
+            }
+            Poll::Ready(None) => panic!("request of {token:?} finished without a completion"),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for ReactorCompletion {
+    fn drop(&mut self) {
+        if let Some(token) = self.token {
+            let _ = self.reactor.deregister(token);
+        }
+    }
+}
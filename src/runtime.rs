@@ -0,0 +1,510 @@
+//! A thread-per-core server in one call.
+//!
+//! [`Runtime`] puts the pieces of [`pool`](crate::pool) together: one pinned ring per core, a
+//! [`ReuseportGroup`] so every ring accepts its own connections, an optional
+//! [`RegisteredBufferPool`] per ring and graceful shutdown. Connections are handled by plain
+//! closures, the runtime drives their `Recv`/`Send` requests.
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use rummelplatz::ControlFlow;
+//! # use rummelplatz::ops::RecvEvent;
+//! # use rummelplatz::pool::reuseport::ReuseportGroup;
+//! # use rummelplatz::runtime::Runtime;
+//! let runtime = Runtime::builder().cores(4).serve(
+//!     |shards| ReuseportGroup::bind("0.0.0.0:8080".parse().unwrap(), shards, Default::default()),
+//!     |_connection| {
+//!         |event, out: &rummelplatz::ops::SendHandle| {
+//!             if let RecvEvent::Data(data) = event {
+//!                 out.send(data.to_vec());
+//!             }
+//!             ControlFlow::Continue
+//!         }
+//!     },
+//! )?;
+//! runtime.shutdown(Duration::from_secs(5));
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Reach for [`RingPool`] directly once a server needs operations of its own.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use io_uring::cqueue::Entry;
+use tracing::{debug, warn};
+
+use crate::ops::{
+    AcceptOp, Error, OpControlFlow, RecvEvent, RecvOp, RegisteredBufferPool, SendBuf, SendHandle,
+    SendOp,
+};
+use crate::pool::affinity::AffinityPolicy;
+use crate::pool::balance::LoadReporter;
+use crate::pool::reuseport::ReuseportGroup;
+use crate::pool::{PoolError, PoolStats, RingContext, RingError, RingPool, RingReport};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+// expanded inside the crate, lints on the generated code are not suppressed like for users
+#[allow(dead_code, unused_imports)]
+mod ring {
+    crate::ring! {
+        server_ring,
+        drain: crate::ops::RemoteOp<crate::pool::DrainSignal>,
+        server: crate::runtime::ServerOp
+    }
+}
+
+/// What a connection handler gets to know about its connection.
+#[derive(Debug, Clone)]
+pub struct ConnectionContext {
+    /// Index of the ring handling the connection
+    pub ring: usize,
+    pub fd: RawFd,
+    /// The ring's buffers, see [`RuntimeBuilder::buffers`]
+    pub buffers: Option<RegisteredBufferPool>,
+}
+
+type Handler = Box<dyn FnMut(RecvEvent<'_>, &SendHandle) -> OpControlFlow>;
+type HandlerFactory = Arc<dyn Fn(&ConnectionContext) -> Handler + Send + Sync>;
+
+#[derive(Debug)]
+pub struct RuntimeBuilder {
+    cores: Option<usize>,
+    affinity: AffinityPolicy,
+    ring_size: NonZeroU32,
+    buffers: Option<(u16, usize)>,
+    name: String,
+}
+
+impl Default for RuntimeBuilder {
+    fn default() -> Self {
+        Self {
+            cores: None,
+            affinity: AffinityPolicy::Allowed { skip_smt: false },
+            ring_size: NonZeroU32::new(256).unwrap(),
+            buffers: None,
+            name: "rummelplatz".to_string(),
+        }
+    }
+}
+
+impl RuntimeBuilder {
+    /// Number of rings, defaults to the number of CPUs the process may run on.
+    pub fn cores(mut self, cores: usize) -> Self {
+        self.cores = Some(cores);
+        self
+    }
+
+    /// Ring `i` is pinned to the `i`-th allowed CPU by default.
+    pub fn affinity(mut self, affinity: AffinityPolicy) -> Self {
+        self.affinity = affinity;
+        self
+    }
+
+    /// Submission queue entries per ring, defaults to 256.
+    pub fn ring_size(mut self, ring_size: NonZeroU32) -> Self {
+        self.ring_size = ring_size;
+        self
+    }
+
+    /// Registers `count` buffers of `size` bytes with every ring, handed to the connection
+    /// handlers through [`ConnectionContext::buffers`].
+    pub fn buffers(mut self, count: u16, size: usize) -> Self {
+        self.buffers = Some((count, size));
+        self
+    }
+
+    /// Thread name prefix, threads are named `{name}-{index}`.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Binds the listeners with `listener` (called once with the number of rings) and starts
+    /// serving. Every accepted connection gets a handler from `connection`, called on the ring
+    /// thread with everything received and a handle to send replies. A handler exiting or
+    /// failing closes its connection.
+    pub fn serve<L, C, H>(self, listener: L, connection: C) -> io::Result<Runtime>
+    where
+        L: FnOnce(usize) -> io::Result<ReuseportGroup>,
+        C: Fn(&ConnectionContext) -> H + Send + Sync + 'static,
+        H: FnMut(RecvEvent<'_>, &SendHandle) -> OpControlFlow + 'static,
+    {
+        let cores = match self.cores {
+            Some(cores) => cores,
+            None => crate::pool::allowed_cpus()?.len(),
+        }
+        .max(1);
+        let group = Arc::new(listener(cores)?);
+        let factory: HandlerFactory = Arc::new(move |context| Box::new(connection(context)));
+
+        let ring_size = self.ring_size;
+        let buffers = self.buffers;
+        let listeners = group.clone();
+        let pool = RingPool::builder()
+            .threads(cores)
+            .affinity(self.affinity)
+            .name(self.name)
+            .spawn(move |context| {
+                let Some(listener) = listeners.listener(context.index) else {
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "no listener for this ring",
+                    )));
+                };
+                run_ring(&context, listener, buffers, ring_size, factory.clone())
+            })?;
+
+        Ok(Runtime { pool, group })
+    }
+}
+
+fn run_ring(
+    context: &RingContext,
+    listener: Arc<OwnedFd>,
+    buffers: Option<(u16, usize)>,
+    ring_size: NonZeroU32,
+    factory: HandlerFactory,
+) -> Result<(), Error> {
+    let raw = ring::server_ring::Ring::new_raw_ring(ring_size)?;
+    context.register_iowq_affinity(&raw.submitter())?;
+    let buffers = match buffers {
+        Some((count, size)) => {
+            let pool = RegisteredBufferPool::new(count, size);
+            // the server op keeps a clone for as long as the ring exists
+            unsafe { pool.register(&raw.submitter())? };
+            Some(pool)
+        }
+        None => None,
+    };
+
+    let draining = Rc::new(Cell::new(false));
+    let drain = {
+        let draining = draining.clone();
+        context.drain_op(move |_| {
+            draining.set(true);
+            ControlFlow::Continue
+        })?
+    };
+    let server = ServerOp::new(
+        context.index,
+        listener,
+        buffers,
+        factory,
+        draining,
+        context.load_reporter(),
+    );
+
+    let mut ring = ring::server_ring::Ring::new(raw, None, drain, server);
+    ring.report_load(context.load_reporter());
+    ring.run::<Error, Error, Error>().map_err(|e| {
+        use ring::server_ring::RingError;
+        match e {
+            RingError::Setup(e) | RingError::Completion(e) | RingError::Teardown(e) => e,
+            RingError::Api(e) => e.into(),
+            RingError::Push(e) => e.into(),
+            e @ RingError::Dynamic { .. } => io::Error::other(e.to_string()).into(),
+        }
+    })
+}
+
+/// A running thread-per-core server, see the [module docs](self).
+#[derive(Debug)]
+pub struct Runtime {
+    pool: RingPool<Error>,
+    group: Arc<ReuseportGroup>,
+}
+
+impl Runtime {
+    pub fn builder() -> RuntimeBuilder {
+        RuntimeBuilder::default()
+    }
+
+    /// The address the listeners are bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.group.local_addr()
+    }
+
+    pub fn cores(&self) -> usize {
+        self.pool.len()
+    }
+
+    /// Connections (in flight) and counters of every ring.
+    pub fn stats(&self) -> PoolStats {
+        self.pool.stats()
+    }
+
+    /// Stops accepting, waits up to `grace` for open connections to close and closes the rest.
+    pub fn shutdown(self, grace: Duration) -> Vec<Result<RingReport<()>, RingError<Error>>> {
+        self.pool.shutdown(grace)
+    }
+
+    /// Waits until every ring exited, which only happens if they fail.
+    pub fn join(self) -> Result<(), PoolError<Error>> {
+        PoolError::check(self.pool.join()).map(drop)
+    }
+}
+
+#[doc(hidden)]
+#[derive(Debug)]
+pub enum ServerData {
+    Accept(()),
+    Recv(u64, Box<[u8]>),
+    Send(u64, SendBuf),
+}
+
+struct Connection {
+    fd: OwnedFd,
+    recv: RecvOp,
+    send: SendOp,
+}
+
+/// Accepts connections on the ring's listener and drives their handlers.
+#[doc(hidden)]
+pub struct ServerOp {
+    ring: usize,
+    // keeps the listener open while the ring runs
+    _listener: Arc<OwnedFd>,
+    accept: AcceptOp,
+    accepted: Rc<RefCell<Vec<OwnedFd>>>,
+    closed: Rc<RefCell<Vec<u64>>>,
+    connections: HashMap<u64, Connection>,
+    next_id: u64,
+    buffers: Option<RegisteredBufferPool>,
+    factory: HandlerFactory,
+    draining: Rc<Cell<bool>>,
+    load: LoadReporter,
+}
+
+impl Debug for ServerOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerOp")
+            .field("ring", &self.ring)
+            .field("accept", &self.accept)
+            .field("connections", &self.connections.len())
+            .field("draining", &self.draining.get())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ServerOp {
+    fn new(
+        ring: usize,
+        listener: Arc<OwnedFd>,
+        buffers: Option<RegisteredBufferPool>,
+        factory: HandlerFactory,
+        draining: Rc<Cell<bool>>,
+        load: LoadReporter,
+    ) -> Self {
+        let accepted: Rc<RefCell<Vec<OwnedFd>>> = Default::default();
+        let queue = accepted.clone();
+
+        Self {
+            ring,
+            accept: AcceptOp::new(listener.as_raw_fd(), move |fd| {
+                queue.borrow_mut().push(fd);
+                ControlFlow::Continue
+            }),
+            _listener: listener,
+            accepted,
+            closed: Default::default(),
+            connections: Default::default(),
+            next_id: 0,
+            buffers,
+            factory,
+            draining,
+            load,
+        }
+    }
+
+    fn open<W: Fn(&mut io_uring::squeue::Entry, ServerData)>(
+        &mut self,
+        fd: OwnedFd,
+        submitter: &mut SubmissionQueueSubmitter<ServerData, W>,
+    ) -> Result<(), Error> {
+        if self.draining.get() {
+            // dropping it closes the connection
+            return Ok(());
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        debug!("ring {} opened connection {id}", self.ring);
+
+        let mut handler = (self.factory)(&ConnectionContext {
+            ring: self.ring,
+            fd: fd.as_raw_fd(),
+            buffers: self.buffers.clone(),
+        });
+        let send = SendOp::new(fd.as_raw_fd(), |result| match result {
+            Ok(_) => ControlFlow::Continue,
+            Err(e) => ControlFlow::Warn(e.into()),
+        });
+        let handle = send.handle();
+        let closed = self.closed.clone();
+        let raw_fd = fd.as_raw_fd();
+        let mut recv = RecvOp::new(fd.as_raw_fd(), move |event| {
+            match handler(event, &handle) {
+                ControlFlow::Continue => {}
+                ControlFlow::Warn(e) => warn!("connection {id}: {e}"),
+                ControlFlow::Exit | ControlFlow::Error(_) => {
+                    // the pending `Recv` completes and reports the connection as closed
+                    unsafe { libc::shutdown(raw_fd, libc::SHUT_RD) };
+                }
+            }
+            if matches!(event, RecvEvent::Closed) {
+                closed.borrow_mut().push(id);
+            }
+            ControlFlow::Continue
+        });
+
+        recv.setup(submitter.map_data(move |d| ServerData::Recv(id, d)))?;
+        self.connections.insert(id, Connection { fd, recv, send });
+        self.load.set_in_flight(self.connections.len());
+        Ok(())
+    }
+
+    fn abort(&mut self, id: u64) {
+        if let Some(connection) = self.connections.get(&id) {
+            unsafe { libc::shutdown(connection.fd.as_raw_fd(), libc::SHUT_RDWR) };
+        }
+    }
+}
+
+impl RingOperation for ServerOp {
+    type RingData = ServerData;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.accept.setup(submitter.map_data(ServerData::Accept))
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        match ring_data {
+            ServerData::Accept(data) => {
+                let (flow, data) = self.accept.on_completion(
+                    completion_entry,
+                    data,
+                    submitter.map_data(ServerData::Accept),
+                );
+
+                let accepted = std::mem::take(&mut *self.accepted.borrow_mut());
+                for fd in accepted {
+                    if let Err(e) = self.open(fd, &mut submitter) {
+                        return (ControlFlow::Error(e), data.map(ServerData::Accept));
+                    }
+                }
+                (flow, data.map(ServerData::Accept))
+            }
+            ServerData::Recv(id, data) => {
+                let Some(connection) = self.connections.get_mut(&id) else {
+                    return (ControlFlow::Continue, None);
+                };
+
+                let (flow, data) = connection.recv.on_completion(
+                    completion_entry,
+                    data,
+                    submitter.map_data(move |d| ServerData::Recv(id, d)),
+                );
+                let flow = match flow {
+                    ControlFlow::Error(e) => {
+                        self.closed.borrow_mut().push(id);
+                        ControlFlow::Warn(e)
+                    }
+                    flow => flow,
+                };
+                (flow, data.map(|d| ServerData::Recv(id, d)))
+            }
+            ServerData::Send(id, data) => {
+                let Some(connection) = self.connections.get_mut(&id) else {
+                    return (ControlFlow::Continue, None);
+                };
+
+                let (flow, data) = connection.send.on_completion(
+                    completion_entry,
+                    data,
+                    submitter.map_data(move |d| ServerData::Send(id, d)),
+                );
+                let flow = match flow {
+                    ControlFlow::Warn(e) | ControlFlow::Error(e) => {
+                        self.abort(id);
+                        ControlFlow::Warn(e)
+                    }
+                    flow => flow,
+                };
+                (flow, data.map(|d| ServerData::Send(id, d)))
+            }
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        match ring_data {
+            ServerData::Accept(data) => self.accept.on_teardown_completion(
+                completion_entry,
+                data,
+                submitter.map_data(ServerData::Accept),
+            ),
+            ServerData::Recv(..) | ServerData::Send(..) => Ok(()),
+        }
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        let closed = std::mem::take(&mut *self.closed.borrow_mut());
+        for id in closed {
+            let idle = self
+                .connections
+                .get(&id)
+                .is_some_and(|connection| connection.send.is_idle());
+            if idle {
+                debug!("ring {} closed connection {id}", self.ring);
+                self.connections.remove(&id);
+            } else {
+                // wait for the pending replies
+                self.closed.borrow_mut().push(id);
+            }
+        }
+        self.load.set_in_flight(self.connections.len());
+
+        if self.draining.get() && self.connections.is_empty() {
+            debug!("ring {} drained", self.ring);
+            return ControlFlow::Exit;
+        }
+
+        for (&id, connection) in self.connections.iter_mut() {
+            let flow = connection
+                .send
+                .housekeeping(submitter.map_data(move |d| ServerData::Send(id, d)));
+            if let ControlFlow::Error(e) = flow {
+                return ControlFlow::Error(e);
+            }
+        }
+        ControlFlow::Continue
+    }
+}
@@ -0,0 +1,185 @@
+//! Point-in-time reports of the state of a ring, for diagnosing rings that got stuck.
+//!
+//! `Ring::debug_snapshot` builds a [`RingSnapshot`] on the ring thread, e.g. from the
+//! housekeeping of an operation that noticed a stall. Other threads ask through a
+//! [`SnapshotHandle`] (`Ring::snapshot_handle`): the ring is woken with a message and answers
+//! at the end of its next iteration, so the report reflects a ring that is still turning. A
+//! ring that does not answer at all is blocked outside of `io_uring_enter`, e.g. in an
+//! operation callback.
+//!
+//! The requests in flight are only listed once the ring tracks them (`Ring::track_in_flight`
+//! or `Ring::record_latencies`), which costs a timestamp and a map entry per request:
+//!
+//! ```no_run
+//! # rummelplatz::ring! { my_ring, tick: rummelplatz::ops::TickOp }
+//! # fn example(mut ring: my_ring::Ring) {
+//! ring.track_in_flight();
+//! let handle = ring.snapshot_handle();
+//! std::thread::spawn(move || loop {
+//!     std::thread::sleep(std::time::Duration::from_secs(60));
+//!     match handle.snapshot(std::time::Duration::from_secs(5)) {
+//!         Ok(snapshot) => eprintln!("{snapshot}"),
+//!         Err(e) => eprintln!("ring did not answer: {e}"),
+//!     }
+//! });
+//! # }
+//! ```
+
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::message::{MessageTarget, OutgoingMessage};
+use crate::stats::{InFlightGauge, QueueFill, RingStats};
+
+/// The state of a ring at the end of a loop iteration (or whenever `Ring::debug_snapshot`
+/// was called).
+#[derive(Debug, Clone)]
+pub struct RingSnapshot {
+    /// Name given to `ring!`
+    pub ring: &'static str,
+    pub stats: RingStats,
+    /// Entries pushed to the submission queue and not consumed by the kernel yet
+    pub sq: QueueFill,
+    /// Completions posted by the kernel and not handled yet
+    pub cq: QueueFill,
+    /// Requests in flight of every operation, in the order of `ring!`
+    pub operations: Vec<(&'static str, InFlightGauge)>,
+    /// Requests in flight, oldest first. `None` unless the ring tracks them.
+    pub in_flight: Option<Vec<InFlightRequest>>,
+    /// Batches of SQEs waiting in the backlog for room in the submission queue, as their
+    /// `Debug` output.
+    pub backlog: Vec<String>,
+}
+
+/// A request the kernel did not complete yet, or a multishot request still producing
+/// completions.
+#[derive(Debug, Clone)]
+pub struct InFlightRequest {
+    /// Name of the operation in `ring!`
+    pub operation: &'static str,
+    /// Opcode of the SQE, e.g. `io_uring::opcode::Read::CODE`
+    pub opcode: u8,
+    pub user_data: u64,
+    /// `Debug` output of the data the operation attached to the request, or its JSON once the ring
+    /// `serialize_data`s (feature `serde`)
+    pub data: String,
+    /// Time since it was pushed to the submission queue
+    pub age: Duration,
+}
+
+impl Display for RingSnapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "ring {}: {}", self.ring, self.stats)?;
+        writeln!(f, "  sq {}, cq {}", self.sq, self.cq)?;
+        for (operation, gauge) in &self.operations {
+            writeln!(f, "  {operation}: {gauge}")?;
+        }
+        match &self.in_flight {
+            Some(requests) => {
+                writeln!(f, "  {} requests in flight", requests.len())?;
+                for request in requests {
+                    writeln!(
+                        f,
+                        "    {:?} {} (opcode {}) {:#x}: {}",
+                        request.age,
+                        request.operation,
+                        request.opcode,
+                        request.user_data,
+                        request.data
+                    )?;
+                }
+            }
+            None => writeln!(f, "  requests in flight not tracked")?,
+        }
+        writeln!(f, "  {} batches in the backlog", self.backlog.len())?;
+        for batch in &self.backlog {
+            writeln!(f, "    {batch}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Snapshot requests waiting for the ring, shared with its [`SnapshotHandle`]s.
+#[doc(hidden)]
+#[derive(Debug, Default)]
+pub struct SnapshotRequests {
+    requested: AtomicBool,
+    pending: Mutex<Vec<Sender<RingSnapshot>>>,
+}
+
+impl SnapshotRequests {
+    /// Whether a handle asked for a snapshot, cheap enough to check every iteration.
+    #[inline]
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::Acquire)
+    }
+
+    /// Answers all pending requests with `snapshot`, called by the ring.
+    pub fn answer(&self, snapshot: impl FnOnce() -> RingSnapshot) {
+        let pending = {
+            let mut pending = self.pending.lock().unwrap();
+            self.requested.store(false, Ordering::Release);
+            std::mem::take(&mut *pending)
+        };
+        if pending.is_empty() {
+            return;
+        }
+
+        let snapshot = snapshot();
+        for sender in pending {
+            // the handle stopped waiting
+            let _ = sender.send(snapshot.clone());
+        }
+    }
+}
+
+/// Asks a ring for [`RingSnapshot`]s from any thread, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct SnapshotHandle {
+    ring_fd: RawFd,
+    requests: Arc<SnapshotRequests>,
+}
+
+impl SnapshotHandle {
+    #[doc(hidden)]
+    pub fn new(ring_fd: RawFd, requests: Arc<SnapshotRequests>) -> Self {
+        Self { ring_fd, requests }
+    }
+
+    /// Asks the ring for a snapshot without waiting for it, e.g. from the housekeeping of
+    /// another ring. The ring answers at the end of its next iteration, the receiver
+    /// disconnects if it is dropped first.
+    pub fn request(&self) -> io::Result<Receiver<RingSnapshot>> {
+        let (sender, receiver) = mpsc::channel();
+        {
+            let mut pending = self.requests.pending.lock().unwrap();
+            pending.push(sender);
+            self.requests.requested.store(true, Ordering::Release);
+        }
+
+        OutgoingMessage::data(MessageTarget::wake(self.ring_fd), 0, 0).send_blocking()?;
+        Ok(receiver)
+    }
+
+    /// Asks the ring for a snapshot and waits up to `timeout` for it. Fails with
+    /// [`io::ErrorKind::TimedOut`] if the ring does not answer in time, which means it is
+    /// blocked outside of `io_uring_enter`.
+    pub fn snapshot(&self, timeout: Duration) -> io::Result<RingSnapshot> {
+        match self.request()?.recv_timeout(timeout) {
+            Ok(snapshot) => Ok(snapshot),
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "ring did not answer the snapshot request",
+            )),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "ring was dropped before answering the snapshot request",
+            )),
+        }
+    }
+}
@@ -0,0 +1,867 @@
+//! Counters maintained by the run loop of every ring.
+//!
+//! `Ring::stats` returns them at any time and `Ring::observe_stats` hands them to a callback
+//! after every loop iteration, e.g. to publish them to a metrics system without waiting for the
+//! ring to return:
+//!
+//! ```no_run
+//! # rummelplatz::ring! { my_ring, tick: rummelplatz::ops::TickOp }
+//! # fn example(mut ring: my_ring::Ring) {
+//! ring.observe_stats(|stats| {
+//!     if stats.iterations % 10_000 == 0 {
+//!         println!("{stats}");
+//!     }
+//! });
+//! # }
+//! ```
+//!
+//! With `Ring::record_latencies` a ring also timestamps every request when it is pushed to the
+//! submission queue and records the time to its last completion in a [`LatencyHistogram`] per
+//! operation, see [`Latencies`]. `Ring::detect_slow_completions` hands every request that
+//! took longer than a threshold to an observer as a [`SlowCompletion`]:
+//!
+//! ```no_run
+//! # rummelplatz::ring! { my_ring, tick: rummelplatz::ops::TickOp }
+//! # fn example(mut ring: my_ring::Ring) {
+//! ring.detect_slow_completions(std::time::Duration::from_millis(100), |slow| {
+//!     tracing::warn!("slow completion: {slow}");
+//! });
+//! # }
+//! ```
+//!
+//! `Ring::io_wq_stats` reads the limits and the number of the io-wq workers the kernel runs
+//! blocking requests on, see [`IoWqStats`].
+//!
+//! `Ring::count_errors` counts the completions that failed by opcode and errno in
+//! [`CompletionErrors`], so e.g. a flood of `ECONNRESET` or of `EINVAL` from a misbuilt SQE shows
+//! up without trace logging.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::io;
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Cumulative counters of a single ring, they start at zero when the ring is created.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct RingStats {
+    /// SQEs submitted to the kernel
+    pub submitted: u64,
+    /// CQEs handled, including the ones of teardown
+    pub completed: u64,
+    /// Calls to `io_uring_enter`
+    pub syscalls: u64,
+    /// Batches of SQEs that did not fit into the submission queue and waited in the backlog
+    pub backlog_spills: u64,
+    /// Batches of SQEs still waiting in the backlog after the last iteration
+    pub backlog: usize,
+    /// Iterations of the run loop (or calls of `run_step`)
+    pub iterations: u64,
+    /// Time spent in `io_uring_enter` waiting for completions
+    pub blocked: Duration,
+    /// Entries in the submission queue when the ring last submitted
+    pub sq: QueueFill,
+    /// Completions in the completion queue when the ring last synced it
+    pub cq: QueueFill,
+    /// Requests of all operations pushed and not completed yet, see
+    /// `Ring::in_flight_by_operation` for the ones of each operation. Counted from
+    /// `Ring::count_in_flight` on, 0 until then
+    pub in_flight: InFlightGauge,
+}
+
+impl RingStats {
+    /// Counters accumulated since `earlier`, e.g. a previous snapshot of the same ring. The
+    /// backlog, queue fill levels and requests in flight are the current ones.
+    pub fn since(&self, earlier: &RingStats) -> RingStats {
+        RingStats {
+            submitted: self.submitted.saturating_sub(earlier.submitted),
+            completed: self.completed.saturating_sub(earlier.completed),
+            syscalls: self.syscalls.saturating_sub(earlier.syscalls),
+            backlog_spills: self.backlog_spills.saturating_sub(earlier.backlog_spills),
+            backlog: self.backlog,
+            iterations: self.iterations.saturating_sub(earlier.iterations),
+            blocked: self.blocked.saturating_sub(earlier.blocked),
+            sq: self.sq,
+            cq: self.cq,
+            in_flight: self.in_flight,
+        }
+    }
+
+    /// Sum of the stats of several rings. The queue fill levels and requests in flight add up
+    /// as well, the summed high-water marks are an upper bound of what all rings held at once.
+    pub fn sum<'a>(stats: impl IntoIterator<Item = &'a RingStats>) -> Self {
+        let queue = |total: QueueFill, fill: QueueFill| QueueFill {
+            len: total.len + fill.len,
+            high_water: total.high_water + fill.high_water,
+            capacity: total.capacity + fill.capacity,
+        };
+        stats
+            .into_iter()
+            .fold(Self::default(), |total, stats| Self {
+                submitted: total.submitted + stats.submitted,
+                completed: total.completed + stats.completed,
+                syscalls: total.syscalls + stats.syscalls,
+                backlog_spills: total.backlog_spills + stats.backlog_spills,
+                backlog: total.backlog + stats.backlog,
+                iterations: total.iterations + stats.iterations,
+                blocked: total.blocked + stats.blocked,
+                sq: queue(total.sq, stats.sq),
+                cq: queue(total.cq, stats.cq),
+                in_flight: InFlightGauge {
+                    current: total.in_flight.current + stats.in_flight.current,
+                    high_water: total.in_flight.high_water + stats.in_flight.high_water,
+                },
+            })
+    }
+}
+
+impl Display for RingStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} submitted, {} completed, {} syscalls, {} backlog spills ({} waiting), {} iterations, {:?} blocked, sq {}, cq {}, {}",
+            self.submitted,
+            self.completed,
+            self.syscalls,
+            self.backlog_spills,
+            self.backlog,
+            self.iterations,
+            self.blocked,
+            self.sq,
+            self.cq,
+            self.in_flight
+        )
+    }
+}
+
+/// Fill level of a submission or completion queue, to size rings by what they actually use.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct QueueFill {
+    /// Entries in the queue
+    pub len: usize,
+    /// Most entries the queue held since the ring was created
+    pub high_water: usize,
+    pub capacity: usize,
+}
+
+impl QueueFill {
+    #[doc(hidden)]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            len: 0,
+            high_water: 0,
+            capacity,
+        }
+    }
+
+    /// Sets the current fill level and raises the high-water mark, called by the ring.
+    #[doc(hidden)]
+    #[inline]
+    pub fn set(&mut self, len: usize) {
+        self.len = len;
+        self.high_water = self.high_water.max(len);
+    }
+
+    /// The fill level with `len` as the current one.
+    pub fn with_len(mut self, len: usize) -> Self {
+        self.set(len);
+        self
+    }
+}
+
+impl Display for QueueFill {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} (high water {})",
+            self.len, self.capacity, self.high_water
+        )
+    }
+}
+
+/// Requests pushed (into the submission queue or the backlog) whose data was not dropped yet,
+/// that is all requests in flight and multishot requests still producing completions.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct InFlightGauge {
+    pub current: usize,
+    /// Most requests in flight at once since the ring was created
+    pub high_water: usize,
+}
+
+impl Display for InFlightGauge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} in flight (high water {})",
+            self.current, self.high_water
+        )
+    }
+}
+
+/// The [`InFlightGauge`] of every operation of a ring and of all of them, maintained by the
+/// ring once `Ring::count_in_flight` turned them on. Shared with the `Handle`s of the ring, which
+/// count the data they post as pushed.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct OperationGauges {
+    // until it is set, pushes and completions only load it
+    counting: AtomicBool,
+    operations: &'static [&'static str],
+    gauges: Box<[AtomicGauge]>,
+    total: AtomicGauge,
+}
+
+#[derive(Debug, Default)]
+struct AtomicGauge {
+    current: AtomicUsize,
+    high_water: AtomicUsize,
+}
+
+impl AtomicGauge {
+    #[inline]
+    fn get(&self) -> InFlightGauge {
+        InFlightGauge {
+            current: self.current.load(Ordering::Relaxed),
+            high_water: self.high_water.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl OperationGauges {
+    pub fn new(operations: &'static [&'static str]) -> Self {
+        Self {
+            counting: AtomicBool::new(false),
+            operations,
+            gauges: operations.iter().map(|_| Default::default()).collect(),
+            total: Default::default(),
+        }
+    }
+
+    /// Counts the requests pushed from now on.
+    pub fn count(&self) {
+        self.counting.store(true, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn increment(gauge: &AtomicGauge) {
+        let current = gauge.current.fetch_add(1, Ordering::Relaxed) + 1;
+        gauge.high_water.fetch_max(current, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn decrement(gauge: &AtomicGauge) {
+        let _ = gauge
+            .current
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                current.checked_sub(1)
+            });
+    }
+
+    /// A request of operation `index` was pushed, or data for it posted.
+    #[inline]
+    pub fn pushed(&self, index: usize) {
+        if !self.counting.load(Ordering::Relaxed) {
+            return;
+        }
+        Self::increment(&self.gauges[index]);
+        Self::increment(&self.total);
+    }
+
+    /// The data of a request of operation `index` was dropped.
+    #[inline]
+    pub fn dropped(&self, index: usize) {
+        if !self.counting.load(Ordering::Relaxed) {
+            return;
+        }
+        Self::decrement(&self.gauges[index]);
+        Self::decrement(&self.total);
+    }
+
+    pub fn total(&self) -> InFlightGauge {
+        self.total.get()
+    }
+
+    /// The gauges by operation name, in the order of `ring!`.
+    pub fn gauges(&self) -> Vec<(&'static str, InFlightGauge)> {
+        self.operations
+            .iter()
+            .zip(self.gauges.iter())
+            .map(|(&operation, gauge)| (operation, gauge.get()))
+            .collect()
+    }
+}
+
+/// Sums up backlog spills for at most one warning per interval, used by the ring.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct SpillWarnings {
+    interval: Duration,
+    // start of the window the pending spills accumulated in
+    since: Instant,
+    warned: bool,
+    pending: u64,
+}
+
+impl SpillWarnings {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            since: Instant::now(),
+            warned: false,
+            pending: 0,
+        }
+    }
+
+    /// Adds `spills`, returns the spills to warn about with the time they accumulated over once
+    /// `interval` passed since the last warning. The first spill is reported right away.
+    pub fn add(&mut self, spills: usize) -> Option<(u64, Duration)> {
+        self.pending += spills as u64;
+        if self.pending == 0 {
+            return None;
+        }
+
+        let now = Instant::now();
+        if self.warned && now - self.since < self.interval {
+            return None;
+        }
+        let window = now - self.since;
+        self.since = now;
+        self.warned = true;
+        Some((std::mem::take(&mut self.pending), window))
+    }
+}
+
+/// Sub-buckets per power of two, values are kept with a relative error below `1 / SUB_BUCKETS`
+const SUB_BUCKET_BITS: u32 = 5;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = ((64 - SUB_BUCKET_BITS + 1) as u64 * SUB_BUCKETS) as usize;
+
+/// A histogram of durations with logarithmic buckets like HdrHistogram: every power of two of
+/// nanoseconds is split into 32 linear sub-buckets, so percentiles are exact to about 3% from
+/// a nanosecond up to centuries, in constant memory.
+#[derive(Clone)]
+pub struct LatencyHistogram {
+    counts: Box<[u64]>,
+    count: u64,
+    sum: u128,
+    min: u64,
+    max: u64,
+}
+
+impl Debug for LatencyHistogram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LatencyHistogram")
+            .field("count", &self.count)
+            .field("min", &self.min())
+            .field("p50", &self.percentile(50.0))
+            .field("p99", &self.percentile(99.0))
+            .field("max", &self.max())
+            .finish()
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; BUCKETS].into_boxed_slice(),
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    fn index(nanos: u64) -> usize {
+        if nanos < SUB_BUCKETS {
+            return nanos as usize;
+        }
+        let shift = 63 - nanos.leading_zeros() - SUB_BUCKET_BITS;
+        ((shift as u64 + 1) * SUB_BUCKETS + (nanos >> shift) - SUB_BUCKETS) as usize
+    }
+
+    /// Highest value (in nanoseconds) that lands in bucket `index`.
+    fn upper_bound(index: usize) -> u64 {
+        let index = index as u64;
+        if index < SUB_BUCKETS {
+            return index;
+        }
+        let shift = index / SUB_BUCKETS - 1;
+        let low = (SUB_BUCKETS + index % SUB_BUCKETS) << shift;
+        low + ((1 << shift) - 1)
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+        self.counts[Self::index(nanos)] += 1;
+        self.count += 1;
+        self.sum += nanos as u128;
+        self.min = self.min.min(nanos);
+        self.max = self.max.max(nanos);
+    }
+
+    /// Number of recorded durations.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Sum of all recorded durations.
+    pub fn sum(&self) -> Duration {
+        Duration::from_nanos(self.sum.min(u64::MAX as u128) as u64)
+    }
+
+    pub fn min(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            _ => Duration::from_nanos(self.min),
+        }
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos((self.sum / count as u128) as u64),
+        }
+    }
+
+    /// The duration `percentile` (`0.0..=100.0`) of all recorded ones are at most, rounded up
+    /// to the end of its bucket. `Duration::ZERO` if nothing was recorded.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0) * self.count as f64).ceil() as u64;
+        let rank = rank.max(1);
+        let mut seen = 0;
+        for (index, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let nanos = Self::upper_bound(index).clamp(self.min, self.max);
+                return Duration::from_nanos(nanos);
+            }
+        }
+        self.max()
+    }
+
+    /// Non-empty buckets in ascending order, as the highest duration of the bucket with the
+    /// number of durations recorded in it.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(index, &count)| (Duration::from_nanos(Self::upper_bound(index)), count))
+    }
+
+    /// Adds the durations recorded in `other`, e.g. to combine the histograms of several rings.
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl Display for LatencyHistogram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} requests, p50 {:?}, p90 {:?}, p99 {:?}, p99.9 {:?}, max {:?}",
+            self.count,
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            self.percentile(99.9),
+            self.max()
+        )
+    }
+}
+
+/// Submission timestamps and opcodes of the requests of a ring that are in flight, kept while
+/// latencies are recorded or in-flight requests tracked, used by the ring.
+#[doc(hidden)]
+#[derive(Debug, Default)]
+pub struct InFlight {
+    submitted: RefCell<HashMap<u64, (Instant, u8)>>,
+}
+
+impl InFlight {
+    pub fn submitted(&self, user_data: u64, opcode: u8) {
+        self.submitted
+            .borrow_mut()
+            .insert(user_data, (Instant::now(), opcode));
+    }
+
+    /// Opcode of the request with `user_data` and the time since it was submitted, `None` if
+    /// it was submitted before tracking started.
+    pub fn elapsed(&self, user_data: u64) -> Option<(u8, Duration)> {
+        self.submitted
+            .borrow()
+            .get(&user_data)
+            .map(|(submitted, opcode)| (*opcode, submitted.elapsed()))
+    }
+
+    /// Forgets the request with `user_data` once its data was dropped.
+    pub fn remove(&self, user_data: u64) {
+        self.submitted.borrow_mut().remove(&user_data);
+    }
+
+    pub fn len(&self) -> usize {
+        self.submitted.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.submitted.borrow().is_empty()
+    }
+
+    /// The user data and opcode of all requests in flight with the time since their
+    /// submission, oldest first.
+    pub fn ages(&self) -> Vec<(u64, u8, Duration)> {
+        let now = Instant::now();
+        let mut ages: Vec<_> = self
+            .submitted
+            .borrow()
+            .iter()
+            .map(|(&user_data, &(submitted, opcode))| (user_data, opcode, now - submitted))
+            .collect();
+        ages.sort_unstable_by_key(|&(_, _, age)| std::cmp::Reverse(age));
+        ages
+    }
+
+    pub fn clear(&self) {
+        self.submitted.borrow_mut().clear();
+    }
+}
+
+/// Submit-to-complete latencies of the requests of a ring, one [`LatencyHistogram`] per
+/// operation. Multishot requests are recorded once, at their last completion; completions
+/// handled during teardown are not recorded.
+#[derive(Default)]
+pub struct Latencies {
+    histograms: RefCell<Vec<(&'static str, LatencyHistogram)>>,
+}
+
+impl Debug for Latencies {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.histograms()).finish()
+    }
+}
+
+impl Latencies {
+    /// Records the latency of a completed request of `operation`, called by the ring.
+    #[doc(hidden)]
+    pub fn record(&self, operation: &'static str, elapsed: Duration) {
+        let mut histograms = self.histograms.borrow_mut();
+        match histograms.iter_mut().find(|(name, _)| *name == operation) {
+            Some((_, histogram)) => histogram.record(elapsed),
+            None => {
+                let mut histogram = LatencyHistogram::new();
+                histogram.record(elapsed);
+                histograms.push((operation, histogram));
+            }
+        }
+    }
+
+    /// A copy of the histogram of `operation` (as named in `ring!`), `None` before its first
+    /// request completed.
+    pub fn histogram(&self, operation: &str) -> Option<LatencyHistogram> {
+        self.histograms
+            .borrow()
+            .iter()
+            .find(|(name, _)| *name == operation)
+            .map(|(_, histogram)| histogram.clone())
+    }
+
+    /// Copies of the histograms of all operations that completed requests.
+    pub fn histograms(&self) -> Vec<(&'static str, LatencyHistogram)> {
+        self.histograms.borrow().clone()
+    }
+
+    /// Forgets the recorded latencies, requests in flight are still recorded once they complete.
+    pub fn clear(&self) {
+        self.histograms.borrow_mut().clear();
+    }
+}
+
+/// Completions of one opcode that failed with one errno, see [`CompletionErrors`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ErrorCount {
+    /// Opcode of the SQE, `None` for requests pushed before the ring tracked them
+    pub opcode: Option<u8>,
+    /// The negated `res` of the completions, e.g. `libc::ECONNRESET`
+    pub errno: i32,
+    pub count: u64,
+}
+
+impl Display for ErrorCount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.opcode {
+            Some(opcode) => write!(f, "opcode {opcode}")?,
+            None => write!(f, "opcode ?")?,
+        }
+        write!(
+            f,
+            ": {} x {}",
+            self.count,
+            std::io::Error::from_raw_os_error(self.errno)
+        )
+    }
+}
+
+/// Completions with a negative `res` counted by opcode and errno, see
+/// `Ring::count_errors`. Every completion of a multishot request counts; completions handled
+/// during teardown (mostly `ECANCELED`) do not.
+#[derive(Default)]
+pub struct CompletionErrors {
+    counts: RefCell<HashMap<(Option<u8>, i32), u64>>,
+}
+
+impl Debug for CompletionErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.counts()).finish()
+    }
+}
+
+impl Display for CompletionErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let counts = self.counts();
+        if counts.is_empty() {
+            return write!(f, "no errors");
+        }
+        for (i, count) in counts.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{count}")?;
+        }
+        Ok(())
+    }
+}
+
+impl CompletionErrors {
+    /// Counts a completion of `opcode` that failed with `errno`, called by the ring.
+    #[doc(hidden)]
+    #[inline]
+    pub fn record(&self, opcode: Option<u8>, errno: i32) {
+        *self.counts.borrow_mut().entry((opcode, errno)).or_default() += 1;
+    }
+
+    /// Completions of `opcode` that failed with `errno`.
+    pub fn count(&self, opcode: u8, errno: i32) -> u64 {
+        self.counts
+            .borrow()
+            .get(&(Some(opcode), errno))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Failed completions of all opcodes and errnos.
+    pub fn total(&self) -> u64 {
+        self.counts.borrow().values().sum()
+    }
+
+    /// All counts, the most frequent first.
+    pub fn counts(&self) -> Vec<ErrorCount> {
+        let mut counts: Vec<_> = self
+            .counts
+            .borrow()
+            .iter()
+            .map(|(&(opcode, errno), &count)| ErrorCount {
+                opcode,
+                errno,
+                count,
+            })
+            .collect();
+        counts.sort_unstable_by_key(|count| {
+            (std::cmp::Reverse(count.count), count.opcode, count.errno)
+        });
+        counts
+    }
+
+    /// Resets all counts, e.g. after publishing them.
+    pub fn clear(&self) {
+        self.counts.borrow_mut().clear();
+    }
+}
+
+/// A request that took longer than the threshold of `Ring::detect_slow_completions`.
+#[derive(Debug, Copy, Clone)]
+pub struct SlowCompletion {
+    /// Name of the operation in `ring!`
+    pub operation: &'static str,
+    /// Opcode of the SQE, e.g. `io_uring::opcode::Read::CODE`
+    pub opcode: u8,
+    pub user_data: u64,
+    pub result: i32,
+    /// Time from pushing the request to the submission queue to its last completion
+    pub latency: Duration,
+}
+
+impl Display for SlowCompletion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (opcode {}) took {:?} to complete with {}",
+            self.operation, self.opcode, self.latency, self.result
+        )
+    }
+}
+
+/// Threshold and observer of `Ring::detect_slow_completions`, used by the ring.
+#[doc(hidden)]
+pub struct SlowCompletions {
+    threshold: Duration,
+    observer: Box<dyn FnMut(&SlowCompletion)>,
+}
+
+impl Debug for SlowCompletions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SlowCompletions")
+            .field("threshold", &self.threshold)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SlowCompletions {
+    pub fn new(threshold: Duration, observer: Box<dyn FnMut(&SlowCompletion)>) -> Self {
+        Self {
+            threshold,
+            observer,
+        }
+    }
+
+    #[inline]
+    pub fn check(
+        &mut self,
+        operation: &'static str,
+        opcode: u8,
+        user_data: u64,
+        result: i32,
+        latency: Duration,
+    ) {
+        if latency > self.threshold {
+            (self.observer)(&SlowCompletion {
+                operation,
+                opcode,
+                user_data,
+                result,
+                latency,
+            });
+        }
+    }
+}
+
+/// The io-wq workers executing the requests of a ring that can not complete inline (e.g. reads
+/// of regular files or requests with `IOSQE_ASYNC`), see `Ring::io_wq_stats`.
+///
+/// io-wq belongs to the thread submitting the requests (the SQPOLL thread for SQPOLL rings),
+/// rings on the same thread share it.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct IoWqStats {
+    /// Limit of workers for bounded requests (regular files, block devices), by default
+    /// `min(sq entries, 4 * CPUs)`
+    pub bounded_max: u32,
+    /// Limit of workers for unbounded requests (sockets, pipes, ...), by default
+    /// `RLIMIT_NPROC`
+    pub unbounded_max: u32,
+    /// Workers of both kinds alive right now, the kernel names bounded and unbounded workers
+    /// alike (`iou-wrk-<tid>`), so there is no count per kind
+    pub combined_workers: usize,
+}
+
+impl Display for IoWqStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} io-wq workers of both kinds (max {} bounded, {} unbounded)",
+            self.combined_workers, self.bounded_max, self.unbounded_max
+        )
+    }
+}
+
+impl IoWqStats {
+    /// Sets the limits of the io-wq of the ring to `bounded` and `unbounded` workers, a limit
+    /// of 0 is left unchanged, and reads the stats with the new limits. Called by the ring.
+    #[doc(hidden)]
+    pub fn read(
+        submitter: &io_uring::Submitter<'_>,
+        ring_fd: RawFd,
+        sqpoll: bool,
+        bounded: u32,
+        unbounded: u32,
+    ) -> io::Result<Self> {
+        let mut max = [bounded, unbounded];
+        // returns the limits before the call
+        submitter.register_iowq_max_workers(&mut max)?;
+        let [bounded_max, unbounded_max] = max;
+        let bounded_max = if bounded == 0 { bounded_max } else { bounded };
+        let unbounded_max = if unbounded == 0 {
+            unbounded_max
+        } else {
+            unbounded
+        };
+
+        let owner = if sqpoll {
+            sq_thread(ring_fd)?
+        } else {
+            // SAFETY: no preconditions
+            unsafe { libc::gettid() }
+        };
+        Ok(Self {
+            bounded_max,
+            unbounded_max,
+            combined_workers: io_wq_workers(owner)?,
+        })
+    }
+}
+
+/// Thread id of the SQPOLL thread of a ring, from its fdinfo.
+fn sq_thread(ring_fd: RawFd) -> io::Result<libc::pid_t> {
+    let fdinfo = std::fs::read_to_string(format!("/proc/self/fdinfo/{ring_fd}"))?;
+    fdinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("SqThread:"))
+        .and_then(|tid| tid.trim().parse().ok())
+        .filter(|&tid| tid > 0)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "ring has no SQPOLL thread"))
+}
+
+/// Counts the io-wq workers of thread `owner`, threads of the process named `iou-wrk-<owner>`.
+fn io_wq_workers(owner: libc::pid_t) -> io::Result<usize> {
+    let name = format!("iou-wrk-{owner}");
+    // thread names are truncated to 15 bytes
+    let name = &name.as_bytes()[..name.len().min(15)];
+    let mut workers = 0;
+    for task in std::fs::read_dir("/proc/self/task")? {
+        let Ok(comm) = std::fs::read(task?.path().join("comm")) else {
+            // the thread exited
+            continue;
+        };
+        if comm.strip_suffix(b"\n").unwrap_or(&comm) == name {
+            workers += 1;
+        }
+    }
+    Ok(workers)
+}
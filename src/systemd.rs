@@ -0,0 +1,187 @@
+//! systemd socket activation and readiness notification.
+//!
+//! Socket-activated services get their listeners from systemd instead of binding them:
+//! [`ListenFds::from_env`] takes the inherited fds (`LISTEN_FDS`, named by `LISTEN_FDNAMES`),
+//! which go straight into an [`AcceptOp`](crate::ops::AcceptOp) or, sharded over the rings of
+//! a pool, into [`ReuseportGroup::from_listener`](crate::pool::reuseport::ReuseportGroup::from_listener).
+//! Once the service accepts, [`notify_ready`] tells systemd (`Type=notify`):
+//!
+//! ```no_run
+//! # use rummelplatz::ControlFlow;
+//! # use rummelplatz::ops::RecvEvent;
+//! # use rummelplatz::pool::reuseport::ReuseportGroup;
+//! # use rummelplatz::runtime::Runtime;
+//! use rummelplatz::systemd::{self, ListenFds};
+//!
+//! let mut fds = ListenFds::from_env()?.expect("not socket activated");
+//! let listener = fds.take("http").expect("no socket named http");
+//!
+//! let runtime = Runtime::builder().serve(
+//!     |shards| ReuseportGroup::from_listener(listener, shards, Default::default()),
+//!     |_connection| {
+//!         |event, out: &rummelplatz::ops::SendHandle| {
+//!             if let RecvEvent::Data(data) = event {
+//!                 out.send(data.to_vec());
+//!             }
+//!             ControlFlow::Continue
+//!         }
+//!     },
+//! )?;
+//! systemd::notify_ready()?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::env;
+use std::ffi::OsStr;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::Path;
+
+use tracing::debug;
+
+/// The first fd passed by systemd, `SD_LISTEN_FDS_START`
+const LISTEN_FDS_START: RawFd = 3;
+
+/// A socket inherited from systemd.
+pub struct ListenFd {
+    pub fd: OwnedFd,
+    /// `FileDescriptorName=` of the socket unit, the unit name by default
+    pub name: Option<String>,
+}
+
+impl Debug for ListenFd {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ListenFd")
+            .field("fd", &self.fd.as_raw_fd())
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+/// The sockets systemd passed to this process.
+#[derive(Debug)]
+pub struct ListenFds {
+    fds: Vec<ListenFd>,
+}
+
+impl ListenFds {
+    /// Takes the fds systemd passed to this process, `None` if it was not socket activated
+    /// (or the variables are meant for another process). The variables are removed from the
+    /// environment, so the fds are only taken once and child processes do not see them. Call
+    /// it early, before threads are spawned that might read the environment.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let Ok(pid) = env::var("LISTEN_PID") else {
+            return Ok(None);
+        };
+        let pid: u32 = pid
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid LISTEN_PID"))?;
+        if pid != std::process::id() {
+            debug!("LISTEN_PID {pid} is another process");
+            return Ok(None);
+        }
+
+        let count: RawFd = env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|count| count.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid LISTEN_FDS"))?;
+        let names = env::var("LISTEN_FDNAMES").ok();
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+
+        let names: Vec<&str> = match &names {
+            Some(names) => names.split(':').collect(),
+            None => Vec::new(),
+        };
+        let mut fds = Vec::with_capacity(count.max(0) as usize);
+        for (index, fd) in (LISTEN_FDS_START..LISTEN_FDS_START + count).enumerate() {
+            // inherited fds are not close-on-exec
+            if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            fds.push(ListenFd {
+                fd: unsafe { OwnedFd::from_raw_fd(fd) },
+                name: names
+                    .get(index)
+                    .filter(|_| names.len() == count as usize)
+                    .map(|name| name.to_string()),
+            });
+        }
+
+        debug!("inherited {} sockets from systemd", fds.len());
+        Ok(Some(Self { fds }))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+
+    /// Takes the first socket named `name`.
+    pub fn take(&mut self, name: &str) -> Option<OwnedFd> {
+        let index = self
+            .fds
+            .iter()
+            .position(|fd| fd.name.as_deref() == Some(name))?;
+        Some(self.fds.remove(index).fd)
+    }
+
+    /// Takes all sockets named `name`, e.g. of a socket unit with several `ListenStream=`.
+    pub fn take_all(&mut self, name: &str) -> Vec<OwnedFd> {
+        let (named, rest) = std::mem::take(&mut self.fds)
+            .into_iter()
+            .partition(|fd| fd.name.as_deref() == Some(name));
+        self.fds = rest;
+        named.into_iter().map(|fd| fd.fd).collect()
+    }
+
+    /// The remaining sockets in the order systemd passed them.
+    pub fn into_vec(self) -> Vec<ListenFd> {
+        self.fds
+    }
+}
+
+/// Sends `state` (newline separated `KEY=value` pairs, see `sd_notify(3)`) to systemd. Returns
+/// `false` without doing anything if the service is not supervised (`NOTIFY_SOCKET` is unset).
+pub fn notify(state: &str) -> io::Result<bool> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    let path = path.as_bytes();
+    let address = match path.strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(Path::new(OsStr::from_bytes(path)))?,
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &address)?;
+    Ok(true)
+}
+
+/// Tells systemd the service is up, see [`notify`].
+pub fn notify_ready() -> io::Result<bool> {
+    notify("READY=1")
+}
+
+/// Tells systemd the service is shutting down, see [`notify`].
+pub fn notify_stopping() -> io::Result<bool> {
+    notify("STOPPING=1")
+}
+
+/// Shows `status` in `systemctl status`, see [`notify`].
+pub fn notify_status(status: &str) -> io::Result<bool> {
+    notify(&format!("STATUS={status}"))
+}
+
+/// Keeps the watchdog (`WatchdogSec=`) from restarting the service, see [`notify`].
+pub fn notify_watchdog() -> io::Result<bool> {
+    notify("WATCHDOG=1")
+}
@@ -0,0 +1,305 @@
+//! Recording the requests of rings for the Chrome trace viewer and Perfetto.
+//!
+//! A [`Timeline`] attached to rings with `Ring::record_timeline` timestamps every request when
+//! it is pushed to the submission queue and again for every completion. Exported with
+//! [`Timeline::write_chrome_trace`] and opened in `chrome://tracing` or
+//! [ui.perfetto.dev](https://ui.perfetto.dev), every ring shows up as a process, every
+//! operation as a track and every request as an async slice identified by its user data, so
+//! queueing delays and bursts of completions are visible at a glance:
+//!
+//! ```no_run
+//! # rummelplatz::ring! { my_ring, tick: rummelplatz::ops::TickOp }
+//! # fn example(mut ring: my_ring::Ring) -> std::io::Result<()> {
+//! use rummelplatz::timeline::Timeline;
+//!
+//! let timeline = Timeline::new(100_000);
+//! ring.record_timeline(timeline.clone());
+//! // ... run the ring, then from any thread:
+//! timeline.save("ring.trace.json")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Only requests pushed through a `SubmissionQueueSubmitter` with data are recorded, raw
+//! entries and messages between rings are not.
+//!
+//! Listeners registered with [`Timeline::on_completion`] see every [`Completion`] with the time
+//! since its submission, a timeline of capacity `0` only feeds them.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+struct Pending {
+    ring: &'static str,
+    operation: &'static str,
+    at: Duration,
+}
+
+/// A completion seen by the listeners of a [`Timeline`].
+#[derive(Debug, Copy, Clone)]
+pub struct Completion {
+    /// Name of the ring type
+    pub ring: &'static str,
+    pub operation: &'static str,
+    pub user_data: u64,
+    pub result: i32,
+    /// The request stays in flight, it is multishot
+    pub more: bool,
+    /// Time since the request was pushed to the submission queue
+    pub elapsed: Duration,
+}
+
+type Listener = Box<dyn Fn(&Completion) + Send + Sync>;
+
+#[derive(Copy, Clone)]
+enum Phase {
+    Begin,
+    /// A completion of a multishot request that stays in flight
+    Step {
+        result: i32,
+    },
+    End {
+        result: i32,
+    },
+}
+
+struct Event {
+    ring: &'static str,
+    operation: &'static str,
+    user_data: u64,
+    at: Duration,
+    phase: Phase,
+}
+
+struct Inner {
+    epoch: Instant,
+    capacity: usize,
+    pending: HashMap<u64, Pending>,
+    events: VecDeque<Event>,
+    dropped: u64,
+}
+
+impl Inner {
+    fn record(&mut self, event: Event) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(event);
+    }
+}
+
+/// Cloneable and [`Send`] recorder of submissions and completions, keeping the last `capacity`
+/// events.
+#[derive(Clone)]
+pub struct Timeline {
+    inner: Arc<Mutex<Inner>>,
+    listeners: Arc<RwLock<Vec<Listener>>>,
+}
+
+impl Debug for Timeline {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("Timeline")
+            .field("capacity", &inner.capacity)
+            .field("events", &inner.events.len())
+            .field("in_flight", &inner.pending.len())
+            .field("dropped", &inner.dropped)
+            .field("listeners", &self.listeners.read().unwrap().len())
+            .finish()
+    }
+}
+
+impl Timeline {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                epoch: Instant::now(),
+                capacity,
+                pending: HashMap::new(),
+                events: VecDeque::with_capacity(capacity.min(1 << 16)),
+                dropped: 0,
+            })),
+            listeners: Default::default(),
+        }
+    }
+
+    /// Calls `listener` with every completion of a request recorded on this timeline, on the
+    /// thread of its ring. Completions of requests submitted before the timeline was attached
+    /// are not seen.
+    pub fn on_completion(&self, listener: impl Fn(&Completion) + Send + Sync + 'static) {
+        self.listeners.write().unwrap().push(Box::new(listener));
+    }
+
+    /// Records the submission of the request with `user_data`, called by the ring.
+    #[doc(hidden)]
+    pub fn submitted(&self, ring: &'static str, operation: &'static str, user_data: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        let at = inner.epoch.elapsed();
+        inner.pending.insert(
+            user_data,
+            Pending {
+                ring,
+                operation,
+                at,
+            },
+        );
+        inner.record(Event {
+            ring,
+            operation,
+            user_data,
+            at,
+            phase: Phase::Begin,
+        });
+    }
+
+    /// Records a completion of the request with `user_data`, called by the ring.
+    #[doc(hidden)]
+    pub fn completed(&self, user_data: u64, result: i32, flags: u32) {
+        let mut inner = self.inner.lock().unwrap();
+        let at = inner.epoch.elapsed();
+        let more = io_uring::cqueue::more(flags);
+        let pending = match more {
+            true => inner
+                .pending
+                .get(&user_data)
+                .map(|p| (p.ring, p.operation, p.at)),
+            false => inner
+                .pending
+                .remove(&user_data)
+                .map(|p| (p.ring, p.operation, p.at)),
+        };
+        // submitted before the timeline was attached
+        let Some((ring, operation, submitted)) = pending else {
+            return;
+        };
+        inner.record(Event {
+            ring,
+            operation,
+            user_data,
+            at,
+            phase: match more {
+                true => Phase::Step { result },
+                false => Phase::End { result },
+            },
+        });
+        drop(inner);
+
+        let listeners = self.listeners.read().unwrap();
+        if listeners.is_empty() {
+            return;
+        }
+        let completion = Completion {
+            ring,
+            operation,
+            user_data,
+            result,
+            more,
+            elapsed: at - submitted,
+        };
+        for listener in listeners.iter() {
+            listener(&completion);
+        }
+    }
+
+    /// Number of requests submitted but not completed yet.
+    pub fn in_flight(&self) -> usize {
+        self.inner.lock().unwrap().pending.len()
+    }
+
+    /// Number of events dropped because the timeline was full.
+    pub fn dropped(&self) -> u64 {
+        self.inner.lock().unwrap().dropped
+    }
+
+    /// Forgets all recorded events, requests in flight are still recorded once they complete.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.events.clear();
+        inner.dropped = 0;
+    }
+
+    /// Writes the recorded events in the Chrome trace event format. Requests still in flight
+    /// are open slices, whose submission may have been dropped already if the timeline was full.
+    pub fn write_chrome_trace<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let inner = self.inner.lock().unwrap();
+
+        // processes and threads are numbered in order of appearance
+        let mut pids: Vec<&'static str> = Vec::new();
+        let mut tids: Vec<(&'static str, &'static str)> = Vec::new();
+        let mut first = true;
+        let mut separator = |writer: &mut W| -> io::Result<()> {
+            if !std::mem::take(&mut first) {
+                writer.write_all(b",\n")?;
+            }
+            Ok(())
+        };
+
+        writer.write_all(b"{\"displayTimeUnit\":\"ns\",\"traceEvents\":[\n")?;
+        for event in &inner.events {
+            let pid = match pids.iter().position(|&ring| ring == event.ring) {
+                Some(pid) => pid + 1,
+                None => {
+                    pids.push(event.ring);
+                    separator(&mut writer)?;
+                    write!(
+                        writer,
+                        r#"{{"ph":"M","name":"process_name","pid":{},"args":{{"name":"{}"}}}}"#,
+                        pids.len(),
+                        event.ring
+                    )?;
+                    pids.len()
+                }
+            };
+            let key = (event.ring, event.operation);
+            let tid = match tids.iter().position(|&t| t == key) {
+                Some(tid) => tid + 1,
+                None => {
+                    tids.push(key);
+                    separator(&mut writer)?;
+                    write!(
+                        writer,
+                        r#"{{"ph":"M","name":"thread_name","pid":{pid},"tid":{},"args":{{"name":"{}"}}}}"#,
+                        tids.len(),
+                        event.operation
+                    )?;
+                    tids.len()
+                }
+            };
+
+            let (ph, result) = match event.phase {
+                Phase::Begin => ("b", None),
+                Phase::Step { result } => ("n", Some(result)),
+                Phase::End { result } => ("e", Some(result)),
+            };
+            separator(&mut writer)?;
+            write!(
+                writer,
+                r#"{{"ph":"{ph}","cat":"{}","name":"{}","id":"{:#x}","pid":{pid},"tid":{tid},"ts":{:.3}"#,
+                event.operation,
+                event.operation,
+                event.user_data,
+                event.at.as_nanos() as f64 / 1000.0,
+            )?;
+            match result {
+                Some(result) => write!(writer, r#","args":{{"result":{result}}}}}"#)?,
+                None => writer.write_all(b"}")?,
+            }
+        }
+        writer.write_all(b"\n]}\n")?;
+        writer.flush()
+    }
+
+    /// Writes the Chrome trace to the file at `path`, see [`Timeline::write_chrome_trace`].
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_chrome_trace(BufWriter::new(File::create(path)?))
+    }
+}
@@ -0,0 +1,270 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use io_uring::opcode::{AsyncCancel2, PollAdd};
+use io_uring::types::{CancelBuilder, Fd};
+use tracing::trace;
+
+use crate::ops::RingHandle;
+use crate::tokio_bridge::{stream_to, submit_to, BridgeHandle, BridgeStream, Request, Rings};
+
+/// Completions a readiness poll buffers before it is paused
+const POLL_CAPACITY: usize = 8;
+
+/// Readiness of one direction of a [`RingAsyncFd`].
+struct Readiness {
+    /// `poll(2)` events watched
+    interest: u32,
+    /// The multishot poll, armed on first use
+    poll: Option<BridgeStream>,
+    /// Events reported since the readiness was cleared
+    ready: u32,
+}
+
+impl Readiness {
+    fn new(interest: u32) -> Mutex<Self> {
+        Mutex::new(Self {
+            interest,
+            poll: None,
+            ready: 0,
+        })
+    }
+}
+
+/// Returned by [`RingReadyGuard::try_io`] if the I/O would block, the readiness was cleared.
+#[derive(Debug)]
+pub struct TryIoError(());
+
+/// An fd whose readiness is watched by a ring of a [`Bridge`](super::Bridge) instead of
+/// tokio's epoll reactor, with the interface of `tokio::io::unix::AsyncFd`: wait for
+/// [`readable`](RingAsyncFd::readable)/[`writable`](RingAsyncFd::writable), try the
+/// non-blocking I/O through the returned guard and clear the readiness once it would block.
+///
+/// ```no_run
+/// # use std::io::Read;
+/// # use std::os::unix::net::UnixStream;
+/// # use rummelplatz::tokio_bridge::{BridgeHandle, RingAsyncFd};
+/// # async fn example(handle: &BridgeHandle, stream: UnixStream) -> std::io::Result<()> {
+/// stream.set_nonblocking(true)?;
+/// let fd = RingAsyncFd::new(handle, stream);
+/// let mut buf = [0u8; 1024];
+/// let read = loop {
+///     let mut guard = fd.readable().await?;
+///     match guard.try_io(|fd| fd.get_ref().read(&mut buf)) {
+///         Ok(result) => break result?,
+///         Err(_would_block) => continue,
+///     }
+/// };
+/// println!("read {read} bytes");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Each direction is watched with a multishot `PollAdd` (like [`PollOp`](crate::ops::PollOp)
+/// does on rings of your own) armed on first use and kept in flight, so a ready fd costs no
+/// submission. With the `tokio` feature, fds registered with an `AsyncFd` move over with
+/// [`RingAsyncFd::from_async_fd`], which allows migrating away from epoll one fd at a time.
+///
+/// The fd has to be non-blocking. Dropping it cancels every bridged request on the fd, the
+/// inner value is dropped once the cancellation is through.
+pub struct RingAsyncFd<T: AsRawFd + Send + 'static> {
+    inner: Option<T>,
+    rings: Arc<Rings>,
+    ring: Option<RingHandle<Request>>,
+    read: Mutex<Readiness>,
+    write: Mutex<Readiness>,
+}
+
+impl<T: AsRawFd + Send + 'static> Debug for RingAsyncFd<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingAsyncFd")
+            .field("fd", &self.as_raw_fd())
+            .field("readable", &(self.read.lock().unwrap().ready != 0))
+            .field("writable", &(self.write.lock().unwrap().ready != 0))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: AsRawFd + Send + 'static> RingAsyncFd<T> {
+    /// Watches `inner` with one of the rings of `handle`, both directions on the same ring.
+    pub fn new(handle: &BridgeHandle, inner: T) -> Self {
+        Self {
+            inner: Some(inner),
+            rings: handle.rings.clone(),
+            ring: handle.rings.pick(),
+            read: Readiness::new((libc::POLLIN | libc::POLLRDHUP) as u32),
+            write: Readiness::new(libc::POLLOUT as u32),
+        }
+    }
+
+    /// Takes `fd` over from tokio's reactor, see the [type docs](RingAsyncFd).
+    #[cfg(feature = "tokio")]
+    pub fn from_async_fd(handle: &BridgeHandle, fd: tokio::io::unix::AsyncFd<T>) -> Self {
+        Self::new(handle, fd.into_inner())
+    }
+
+    pub fn get_ref(&self) -> &T {
+        self.inner.as_ref().unwrap()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.as_mut().unwrap()
+    }
+
+    /// Stops watching the fd and returns it. The polls are canceled in the background, the fd
+    /// must stay open until they are through.
+    pub fn into_inner(mut self) -> T {
+        self.cancel(self.as_raw_fd(), None);
+        self.inner.take().unwrap()
+    }
+
+    fn cancel(&mut self, fd: RawFd, keep: Option<T>) {
+        let armed = self.read.get_mut().unwrap().poll.is_some()
+            || self.write.get_mut().unwrap().poll.is_some();
+        if !armed {
+            return;
+        }
+
+        trace!("cancel the readiness polls of {fd}");
+        let cancel = AsyncCancel2::new(CancelBuilder::fd(Fd(fd)).all()).build();
+        let keep = keep.map(|keep| Box::new(keep) as _);
+        drop(submit_to(&self.rings, self.ring.as_ref(), cancel, keep));
+    }
+
+    /// Returns once the fd is readable or the readiness poll failed, or stores the waker of
+    /// `cx` to be woken once it is.
+    pub fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_ready(&self.read, cx)
+    }
+
+    /// Like [`RingAsyncFd::poll_read_ready`], for writing.
+    pub fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_ready(&self.write, cx)
+    }
+
+    /// Waits until the fd is readable.
+    pub async fn readable(&self) -> io::Result<RingReadyGuard<'_, T>> {
+        std::future::poll_fn(|cx| self.poll_read_ready(cx)).await?;
+        Ok(RingReadyGuard {
+            fd: self,
+            readiness: &self.read,
+        })
+    }
+
+    /// Waits until the fd is writable.
+    pub async fn writable(&self) -> io::Result<RingReadyGuard<'_, T>> {
+        std::future::poll_fn(|cx| self.poll_write_ready(cx)).await?;
+        Ok(RingReadyGuard {
+            fd: self,
+            readiness: &self.write,
+        })
+    }
+
+    fn poll_ready(
+        &self,
+        readiness: &Mutex<Readiness>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        let mut readiness = readiness.lock().unwrap();
+        let interest = readiness.interest;
+        loop {
+            let poll = readiness.poll.get_or_insert_with(|| {
+                let entry = PollAdd::new(Fd(self.as_raw_fd()), interest)
+                    .multi(true)
+                    .build();
+                stream_to(&self.rings, self.ring.as_ref(), entry, POLL_CAPACITY)
+            });
+
+            match poll.poll_next(cx) {
+                Poll::Ready(Some(Ok(entry))) => match entry.result() {
+                    events if events >= 0 => readiness.ready |= events as u32,
+                    res if res == -libc::ECANCELED => {}
+                    res => {
+                        readiness.poll = None;
+                        return Poll::Ready(Err(io::Error::from_raw_os_error(-res)));
+                    }
+                },
+                Poll::Ready(Some(Err(e))) => {
+                    readiness.poll = None;
+                    return Poll::Ready(Err(e));
+                }
+                // the kernel ended the multishot poll, re-arm it
+                Poll::Ready(None) => readiness.poll = None,
+                Poll::Pending => break,
+            }
+        }
+
+        match readiness.ready {
+            0 => Poll::Pending,
+            _ => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+impl<T: AsRawFd + Send + 'static> AsRawFd for RingAsyncFd<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.get_ref().as_raw_fd()
+    }
+}
+
+impl<T: AsRawFd + Send + 'static> Drop for RingAsyncFd<T> {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            // the fd stays open until the polls are canceled
+            self.cancel(inner.as_raw_fd(), Some(inner));
+        }
+    }
+}
+
+/// Proof that a [`RingAsyncFd`] was ready, see [`RingAsyncFd::readable`].
+pub struct RingReadyGuard<'a, T: AsRawFd + Send + 'static> {
+    fd: &'a RingAsyncFd<T>,
+    readiness: &'a Mutex<Readiness>,
+}
+
+impl<T: AsRawFd + Send + 'static> Debug for RingReadyGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingReadyGuard")
+            .field("fd", &self.fd.as_raw_fd())
+            .field("ready", &self.ready())
+            .finish()
+    }
+}
+
+impl<'a, T: AsRawFd + Send + 'static> RingReadyGuard<'a, T> {
+    pub fn get_ref(&self) -> &'a RingAsyncFd<T> {
+        self.fd
+    }
+
+    pub fn get_inner(&self) -> &'a T {
+        self.fd.get_ref()
+    }
+
+    /// The `poll(2)` events reported since the readiness was cleared.
+    pub fn ready(&self) -> u32 {
+        self.readiness.lock().unwrap().ready
+    }
+
+    /// Forgets the readiness, the next wait returns once the ring reports the fd ready again.
+    /// Only call it after the I/O returned [`io::ErrorKind::WouldBlock`].
+    pub fn clear_ready(&mut self) {
+        self.readiness.lock().unwrap().ready = 0;
+    }
+
+    /// Runs the non-blocking I/O `f` and clears the readiness if it would block.
+    pub fn try_io<R>(
+        &mut self,
+        f: impl FnOnce(&'a RingAsyncFd<T>) -> io::Result<R>,
+    ) -> Result<io::Result<R>, TryIoError> {
+        match f(self.fd) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.clear_ready();
+                Err(TryIoError(()))
+            }
+            result => Ok(result),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::io;
+use std::net::ToSocketAddrs;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use http::Uri;
+use tracing::debug;
+
+use crate::tokio_bridge::{BridgeHandle, RingTcpStream};
+
+/// A `tower::Service<Uri>` connecting [`RingTcpStream`]s, e.g. the connector of hyper-util's
+/// `client::legacy::Client`: every connection of the HTTP client runs on the rings of a
+/// [`Bridge`](super::Bridge).
+///
+/// ```no_run
+/// # use rummelplatz::tokio_bridge::{BridgeHandle, RingConnector};
+/// # use tower_service::Service;
+/// # async fn connect(handle: BridgeHandle) -> std::io::Result<()> {
+/// let mut connector = RingConnector::new(handle);
+/// // or `Client::builder(executor).build(connector)`
+/// let stream = connector.call("http://127.0.0.1:8080".parse().unwrap()).await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Connections are plain TCP, wrap the connector into a TLS connector for `https`. Host names
+/// are resolved with the blocking resolver of the standard library, pass addresses for
+/// latency sensitive clients. Servers hand accepted connections to hyper's `serve_connection`
+/// as [`RingTcpStream`]s, which implement hyper's I/O traits with the `hyper` feature.
+#[derive(Clone)]
+pub struct RingConnector {
+    handle: BridgeHandle,
+}
+
+impl Debug for RingConnector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingConnector").finish_non_exhaustive()
+    }
+}
+
+impl RingConnector {
+    pub fn new(handle: BridgeHandle) -> Self {
+        Self { handle }
+    }
+}
+
+impl tower_service::Service<Uri> for RingConnector {
+    type Response = RingTcpStream;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<RingTcpStream>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let handle = self.handle.clone();
+        Box::pin(async move {
+            let host = uri
+                .host()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "uri without host"))?;
+            // brackets of IPv6 literals
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            let port = match (uri.port_u16(), uri.scheme_str()) {
+                (Some(port), _) => port,
+                (None, Some("https")) => 443,
+                (None, _) => 80,
+            };
+
+            let mut last_error = None;
+            for addr in (host, port).to_socket_addrs()? {
+                match RingTcpStream::connect_async(&handle, addr).await {
+                    Ok(stream) => return Ok(stream),
+                    Err(e) => {
+                        debug!("unable to connect to {addr}: {e}");
+                        last_error = Some(e);
+                    }
+                }
+            }
+            Err(last_error.unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "host resolved to no address")
+            }))
+        })
+    }
+}
@@ -0,0 +1,1080 @@
+//! Routing I/O of `async` applications (e.g. on tokio) through rings on dedicated threads.
+//!
+//! A [`Bridge`] runs a [`RingPool`] whose rings only execute requests sent to them through a
+//! [`BridgeHandle`]. The handle is `Send + Sync` and [`BridgeHandle::submit`] returns a
+//! `Send` future resolving with the completion entry, woken from the ring thread. Nothing in
+//! here depends on tokio: the futures only use [`std::task::Waker`], so they can be awaited on
+//! any executor.
+//!
+//! ```no_run
+//! # use std::num::NonZeroU32;
+//! # use std::time::Duration;
+//! # use rummelplatz::io_uring::{opcode, types};
+//! # use rummelplatz::tokio_bridge::Bridge;
+//! # async fn read(fd: i32) -> std::io::Result<()> {
+//! let bridge = Bridge::spawn(2, NonZeroU32::new(256).unwrap())?;
+//! let handle = bridge.handle();
+//!
+//! let mut buf = vec![0u8; 4096];
+//! let read = opcode::Read::new(types::Fd(fd), buf.as_mut_ptr(), buf.len() as u32).build();
+//! let completion = unsafe { handle.submit(read) }.await?;
+//! println!("read {} bytes", completion.result());
+//!
+//! bridge.shutdown(Duration::from_secs(1));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Multishot requests (accepts, receives into provided buffers, ...) are submitted with
+//! [`BridgeHandle::submit_multishot`], their completions arrive through a [`BridgeStream`].
+//!
+//! [`RingTcpStream`] wraps a TCP connection into `AsyncRead`/`AsyncWrite` (features `tokio`
+//! and `futures-io`) running its receives and sends on a ring. With the `hyper` feature it
+//! implements hyper's I/O traits and [`RingConnector`] connects HTTP clients through rings.
+//!
+//! [`RingAsyncFd`] watches the readiness of fds on a ring, for code written against tokio's
+//! `AsyncFd`.
+//!
+//! [`BridgeHandle::sleep`] times futures on the rings, with one `Timeout` request per ring for
+//! all sleeps.
+//!
+//! Rings of your own can take bridged requests as well, add a [`BridgeOp`] to them and hand
+//! out its [`BridgeOp::handle`].
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::io;
+use std::num::NonZeroU32;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::AsyncCancel;
+use io_uring::squeue::PushError;
+use tracing::{debug, trace};
+
+use crate::ops::{Error, OpControlFlow, RemoteOp, RingHandle};
+use crate::pool::{RingContext, RingError, RingPool, RingReport};
+use crate::{ControlFlow, RingOperation, SubmissionQueueSubmitter};
+
+mod async_fd;
+#[cfg(feature = "hyper")]
+mod connector;
+mod sleep;
+mod tcp;
+pub use async_fd::{RingAsyncFd, RingReadyGuard, TryIoError};
+#[cfg(feature = "hyper")]
+pub use connector::RingConnector;
+pub use sleep::RingSleep;
+pub use tcp::RingTcpStream;
+
+// expanded inside the crate, lints on the generated code are not suppressed like for users
+#[allow(dead_code, unused_imports)]
+mod ring {
+    crate::ring! {
+        bridge_ring,
+        drain: crate::ops::RemoteOp<crate::pool::DrainSignal>,
+        bridge: crate::tokio_bridge::BridgeOp
+    }
+}
+
+enum State {
+    Waiting(Option<Waker>),
+    Completed(Entry),
+    /// The ring exited before the request completed
+    Dropped,
+    Taken,
+}
+
+/// The state of a request shared with its [`BridgeCompletion`]. Dropping it before the
+/// request completed resolves the future with an error.
+struct Pending {
+    state: Arc<Mutex<State>>,
+    // whatever the request points to, owned by the ring until the request completed
+    _keep: Option<Box<dyn Any + Send>>,
+}
+
+impl Pending {
+    fn resolve(&self, state: State) {
+        let mut current = self.state.lock().unwrap();
+        if let State::Waiting(waker) = &mut *current {
+            let waker = waker.take();
+            *current = state;
+            drop(current);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.resolve(State::Dropped);
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum End {
+    Running,
+    /// The request posted its last completion
+    Finished,
+    /// The ring exited before the request finished
+    Dropped,
+}
+
+struct StreamState {
+    items: VecDeque<Entry>,
+    waker: Option<Waker>,
+    capacity: usize,
+    /// The ring canceled the request because `items` was full, it is re-armed once the stream
+    /// took half of them
+    paused: bool,
+    resume_sent: bool,
+    end: End,
+    /// The stream was dropped, the request is canceled with its next completion
+    closed: bool,
+}
+
+/// The ring side of a [`BridgeStream`].
+struct StreamRequest {
+    entry: io_uring::squeue::Entry,
+    state: Arc<Mutex<StreamState>>,
+}
+
+impl Drop for StreamRequest {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if state.end == End::Running {
+            state.end = End::Dropped;
+            if let Some(waker) = state.waker.take() {
+                drop(state);
+                waker.wake();
+            }
+        }
+    }
+}
+
+enum Request {
+    Once {
+        entry: io_uring::squeue::Entry,
+        pending: Pending,
+    },
+    Stream(StreamRequest),
+    /// A paused stream has room again
+    Resume,
+    Sleep {
+        deadline: Instant,
+        pending: Pending,
+    },
+}
+
+/// The rings taking bridged requests.
+#[derive(Default)]
+struct Rings {
+    handles: Mutex<Vec<Option<RingHandle<Request>>>>,
+    next: AtomicUsize,
+}
+
+impl Rings {
+    fn set(&self, index: usize, handle: Option<RingHandle<Request>>) {
+        let mut handles = self.handles.lock().unwrap();
+        if handles.len() <= index {
+            handles.resize_with(index + 1, || None);
+        }
+        handles[index] = handle;
+    }
+
+    /// Sends `request` to the next ring in round-robin order and returns that ring, gives the
+    /// request back if there is none.
+    fn send(&self, mut request: Request) -> Result<RingHandle<Request>, Request> {
+        let handles = self.handles.lock().unwrap();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        for offset in 0..handles.len() {
+            let Some(handle) = &handles[(start + offset) % handles.len()] else {
+                continue;
+            };
+            match handle.send(request) {
+                Ok(()) => return Ok(handle.clone()),
+                // the ring is gone
+                Err(e) => request = e.0,
+            }
+        }
+        Err(request)
+    }
+
+    /// The next ring in round-robin order, for requests that have to run on the same ring.
+    fn pick(&self) -> Option<RingHandle<Request>> {
+        let handles = self.handles.lock().unwrap();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..handles.len()).find_map(|offset| handles[(start + offset) % handles.len()].clone())
+    }
+}
+
+/// Sends `entry` to `ring` (or the next ring if `None`), `keep` is dropped once the request
+/// completed.
+fn submit_to(
+    rings: &Rings,
+    ring: Option<&RingHandle<Request>>,
+    entry: io_uring::squeue::Entry,
+    keep: Option<Box<dyn Any + Send>>,
+) -> BridgeCompletion {
+    let state = Arc::new(Mutex::new(State::Waiting(None)));
+    let request = Request::Once {
+        entry,
+        pending: Pending {
+            state: state.clone(),
+            _keep: keep,
+        },
+    };
+    let sent = match ring {
+        Some(ring) => ring.send(request).map_err(|e| e.0),
+        None => rings.send(request).map(drop),
+    };
+    if let Err(request) = sent {
+        debug!("no ring takes bridged requests");
+        // resolves the future
+        drop(request);
+    }
+    BridgeCompletion { state }
+}
+
+/// Sends the multishot request `entry` to `ring` (or the next ring if `None`), see
+/// [`BridgeHandle::submit_multishot`].
+fn stream_to(
+    rings: &Rings,
+    ring: Option<&RingHandle<Request>>,
+    entry: io_uring::squeue::Entry,
+    capacity: usize,
+) -> BridgeStream {
+    let state = Arc::new(Mutex::new(StreamState {
+        items: VecDeque::new(),
+        waker: None,
+        capacity: capacity.max(1),
+        paused: false,
+        resume_sent: false,
+        end: End::Running,
+        closed: false,
+    }));
+    let request = Request::Stream(StreamRequest {
+        entry,
+        state: state.clone(),
+    });
+    let sent = match ring {
+        Some(ring) => ring.send(request).map(|()| ring.clone()).map_err(|e| e.0),
+        None => rings.send(request),
+    };
+    let ring = match sent {
+        Ok(ring) => Some(ring),
+        Err(request) => {
+            debug!("no ring takes bridged requests");
+            // ends the stream
+            drop(request);
+            None
+        }
+    };
+    BridgeStream { state, ring }
+}
+
+/// Cloneable, `Send` and `Sync` handle submitting requests to the rings of a [`Bridge`] (or a
+/// single [`BridgeOp`]), see the [module docs](self).
+#[derive(Clone)]
+pub struct BridgeHandle {
+    rings: Arc<Rings>,
+}
+
+impl Debug for BridgeHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let handles = self.rings.handles.lock().unwrap();
+        f.debug_struct("BridgeHandle")
+            .field("rings", &handles.iter().flatten().count())
+            .finish()
+    }
+}
+
+impl BridgeHandle {
+    /// Sends `entry` to one of the rings, the returned future resolves with its completion
+    /// entry. Only one-shot requests are supported, later completions of a multishot request
+    /// are dropped.
+    ///
+    /// The future fails with [`io::ErrorKind::BrokenPipe`] if no ring takes the request or the
+    /// ring exits before the request completed.
+    ///
+    /// # Safety
+    /// Everything `entry` points to has to stay valid until the request completed, even if
+    /// the future is dropped before.
+    pub unsafe fn submit(&self, entry: io_uring::squeue::Entry) -> BridgeCompletion {
+        submit_to(&self.rings, None, entry, None)
+    }
+
+    /// Like [`BridgeHandle::submit`], the ring drops `keep` once the request completed.
+    pub(crate) unsafe fn submit_keeping(
+        &self,
+        entry: io_uring::squeue::Entry,
+        keep: Box<dyn Any + Send>,
+    ) -> BridgeCompletion {
+        submit_to(&self.rings, None, entry, Some(keep))
+    }
+
+    /// Sends the multishot request `entry` to one of the rings, its completions are taken
+    /// from the returned stream. The ring buffers up to `capacity` completions: once the
+    /// stream falls behind, the request is canceled and submitted again after the stream took
+    /// half of them. Completions the kernel posted before the cancellation took effect are
+    /// kept, so the buffer may exceed `capacity` slightly.
+    ///
+    /// The stream ends after the last completion of the request (e.g. a multishot receive
+    /// running out of buffers), requests have to be submitted again then. If no ring takes the
+    /// request or its ring exits, the stream yields an [`io::ErrorKind::BrokenPipe`] error
+    /// and ends.
+    ///
+    /// # Safety
+    /// Everything `entry` points to has to stay valid until the request posted its last
+    /// completion, which may be after the stream was dropped: a dropped stream cancels its
+    /// request with the next completion.
+    pub unsafe fn submit_multishot(
+        &self,
+        entry: io_uring::squeue::Entry,
+        capacity: usize,
+    ) -> BridgeStream {
+        stream_to(&self.rings, None, entry, capacity)
+    }
+}
+
+/// The completions of a multishot request submitted through
+/// [`BridgeHandle::submit_multishot`].
+///
+/// With the `futures-core` feature it is a `futures_core::Stream`, e.g. for the combinators of
+/// `futures`/`tokio-stream`.
+pub struct BridgeStream {
+    state: Arc<Mutex<StreamState>>,
+    // resumes the request after it was paused
+    ring: Option<RingHandle<Request>>,
+}
+
+impl Debug for BridgeStream {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("BridgeStream")
+            .field("buffered", &state.items.len())
+            .field("paused", &state.paused)
+            .field("end", &state.end)
+            .finish()
+    }
+}
+
+impl BridgeStream {
+    /// Takes the next completion, or stores the waker of `cx` to be woken once there is one.
+    /// `None` once the request finished and all of its completions were taken.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<Entry>>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.items.pop_front() {
+            let resume =
+                state.paused && !state.resume_sent && state.items.len() <= state.capacity / 2;
+            if resume {
+                state.resume_sent = true;
+            }
+            drop(state);
+
+            if let (true, Some(ring)) = (resume, &self.ring) {
+                trace!("resuming a bridged stream");
+                // a ring that is gone dropped the request, which ends the stream
+                let _ = ring.send(Request::Resume);
+            }
+            return Poll::Ready(Some(Ok(entry)));
+        }
+
+        match state.end {
+            End::Running => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            End::Finished => Poll::Ready(None),
+            End::Dropped => {
+                state.end = End::Finished;
+                Poll::Ready(Some(Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "the ring exited before the request finished",
+                ))))
+            }
+        }
+    }
+
+    /// The next completion, see [`BridgeStream::poll_next`].
+    pub fn next_completion(&mut self) -> impl Future<Output = Option<io::Result<Entry>>> + '_ {
+        std::future::poll_fn(|cx| self.poll_next(cx))
+    }
+}
+
+#[cfg(feature = "futures-core")]
+impl futures_core::Stream for BridgeStream {
+    type Item = io::Result<Entry>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        BridgeStream::poll_next(self.get_mut(), cx)
+    }
+}
+
+impl Drop for BridgeStream {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().closed = true;
+    }
+}
+
+/// Resolves with the completion entry of a request submitted through
+/// [`BridgeHandle::submit`].
+pub struct BridgeCompletion {
+    state: Arc<Mutex<State>>,
+}
+
+impl Debug for BridgeCompletion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let completed = matches!(*self.state.lock().unwrap(), State::Completed(_));
+        f.debug_struct("BridgeCompletion")
+            .field("completed", &completed)
+            .finish()
+    }
+}
+
+impl Future for BridgeCompletion {
+    type Output = io::Result<Entry>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match std::mem::replace(&mut *state, State::Taken) {
+            State::Completed(entry) => Poll::Ready(Ok(entry)),
+            State::Dropped => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the ring exited before the request completed",
+            ))),
+            State::Waiting(_) => {
+                *state = State::Waiting(Some(cx.waker().clone()));
+                Poll::Pending
+            }
+            State::Taken => panic!("completion polled after it resolved"),
+        }
+    }
+}
+
+enum InFlight {
+    Once(Pending),
+    Stream(StreamRequest),
+    /// The timeout for the earliest sleep
+    Timer {
+        deadline: Instant,
+        _timespec: Box<io_uring::types::Timespec>,
+    },
+}
+
+/// The request a [`BridgeCompletion`] or [`BridgeStream`] waits for, `None` for the wakeups
+/// of the ring.
+pub struct BridgeData(Option<InFlight>);
+
+impl Debug for BridgeData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(InFlight::Once(_)) => f.write_str("BridgeData::Request"),
+            Some(InFlight::Stream(_)) => f.write_str("BridgeData::Stream"),
+            Some(InFlight::Timer { .. }) => f.write_str("BridgeData::Timer"),
+            None => f.write_str("BridgeData::Remote"),
+        }
+    }
+}
+
+/// Executes the requests of [`BridgeHandle`]s on its ring and wakes their futures.
+pub struct BridgeOp {
+    rings: Arc<Rings>,
+    index: usize,
+    remote: RemoteOp<Request>,
+    // received, submitted during housekeeping
+    queue: Rc<RefCell<VecDeque<Request>>>,
+    in_flight: usize,
+    // streams canceled because they fell behind
+    paused: Vec<StreamRequest>,
+    timers: sleep::Timers,
+    draining: Rc<Cell<bool>>,
+}
+
+impl Debug for BridgeOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BridgeOp")
+            .field("index", &self.index)
+            .field("queued", &self.queue.borrow().len())
+            .field("in_flight", &self.in_flight)
+            .field("paused", &self.paused.len())
+            .field("sleeps", &self.timers.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl BridgeOp {
+    /// An operation taking the requests of its own [`BridgeHandle`].
+    pub fn new() -> io::Result<Self> {
+        Self::with_rings(Default::default(), 0)
+    }
+
+    /// Becomes ring `index` of `rings`, replacing a ring with the same index.
+    fn with_rings(rings: Arc<Rings>, index: usize) -> io::Result<Self> {
+        let queue: Rc<RefCell<VecDeque<Request>>> = Default::default();
+        let remote = RemoteOp::new({
+            let queue = queue.clone();
+            move |request| {
+                queue.borrow_mut().push_back(request);
+                ControlFlow::Continue
+            }
+        })?;
+        rings.set(index, Some(remote.handle()));
+
+        Ok(Self {
+            rings,
+            index,
+            remote,
+            queue,
+            in_flight: 0,
+            paused: Vec::new(),
+            timers: Default::default(),
+            draining: Default::default(),
+        })
+    }
+
+    pub fn handle(&self) -> BridgeHandle {
+        BridgeHandle {
+            rings: self.rings.clone(),
+        }
+    }
+
+    /// Stops taking new requests and exits the ring once the ones received are done.
+    pub fn drain(&self) {
+        self.rings.set(self.index, None);
+        self.draining.set(true);
+    }
+
+    /// Requests submitted and not completed yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+}
+
+impl BridgeOp {
+    /// Hands a completion to its stream and pauses the stream if it fell behind. Returns
+    /// whether the request posts more completions, a request that finished without ending the
+    /// stream was paused.
+    fn on_stream_completion<W: Fn(&mut io_uring::squeue::Entry, BridgeData)>(
+        &mut self,
+        stream: &StreamRequest,
+        completion_entry: Entry,
+        submitter: &mut SubmissionQueueSubmitter<BridgeData, W>,
+    ) -> Result<bool, PushError> {
+        let more = io_uring::cqueue::more(completion_entry.flags());
+        let user_data = completion_entry.user_data();
+        let mut state = stream.state.lock().unwrap();
+
+        let cancel = if state.closed {
+            more
+        } else if state.paused && !more && completion_entry.result() == -libc::ECANCELED {
+            // canceled by the pause below, the stream goes on
+            return Ok(false);
+        } else {
+            state.items.push_back(completion_entry);
+            if !more {
+                state.end = End::Finished;
+            }
+            let full = more && !state.paused && state.items.len() >= state.capacity;
+            if full {
+                trace!("ring {} pauses a bridged stream", self.index);
+                state.paused = true;
+                state.resume_sent = false;
+            }
+            if let Some(waker) = state.waker.take() {
+                drop(state);
+                waker.wake();
+            }
+            full
+        };
+
+        if cancel {
+            let cancel = AsyncCancel::new(user_data).build();
+            submitter.push_unmanaged(cancel)?;
+        }
+        Ok(more)
+    }
+}
+
+impl RingOperation for BridgeOp {
+    type RingData = BridgeData;
+    type SetupError = Error;
+    type TeardownError = Error;
+    type ControlFlowWarn = Error;
+    type ControlFlowError = Error;
+
+    fn setup<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::SetupError> {
+        self.remote.setup(submitter.map_data(|()| BridgeData(None)))
+    }
+
+    fn on_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> (ControlFlow<Error, Error>, Option<Self::RingData>) {
+        let more = io_uring::cqueue::more(completion_entry.flags());
+        match ring_data.0 {
+            None => {
+                let (flow, data) = self.remote.on_completion(
+                    completion_entry,
+                    (),
+                    submitter.map_data(|()| BridgeData(None)),
+                );
+                (flow, data.map(|()| BridgeData(None)))
+            }
+            Some(InFlight::Once(pending)) => {
+                pending.resolve(State::Completed(completion_entry));
+                if more {
+                    return (
+                        ControlFlow::Continue,
+                        Some(BridgeData(Some(InFlight::Once(pending)))),
+                    );
+                }
+                self.in_flight -= 1;
+                (ControlFlow::Continue, None)
+            }
+            Some(InFlight::Stream(stream)) => {
+                match self.on_stream_completion(&stream, completion_entry, &mut submitter) {
+                    Err(e) => (ControlFlow::Error(e.into()), None),
+                    Ok(true) => (
+                        ControlFlow::Continue,
+                        Some(BridgeData(Some(InFlight::Stream(stream)))),
+                    ),
+                    Ok(false) => {
+                        self.in_flight -= 1;
+                        let paused = {
+                            let state = stream.state.lock().unwrap();
+                            state.end == End::Running && !state.closed
+                        };
+                        if paused {
+                            self.paused.push(stream);
+                        }
+                        (ControlFlow::Continue, None)
+                    }
+                }
+            }
+            Some(InFlight::Timer { deadline, .. }) => {
+                self.timers.expire(deadline, completion_entry);
+                if let Some((entry, timer)) = self.timers.arm() {
+                    if let Err(e) = submitter.push(entry, BridgeData(Some(timer))) {
+                        return (ControlFlow::Error(e.into()), None);
+                    }
+                }
+                (ControlFlow::Continue, None)
+            }
+        }
+    }
+
+    fn on_teardown_completion<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        completion_entry: Entry,
+        ring_data: Self::RingData,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> Result<(), Self::TeardownError> {
+        match ring_data.0 {
+            None => self.remote.on_teardown_completion(
+                completion_entry,
+                (),
+                submitter.map_data(|()| BridgeData(None)),
+            ),
+            // dropping the request fails its future
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn housekeeping<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        while submitter.has_capacity(1) {
+            let Some(request) = self.queue.borrow_mut().pop_front() else {
+                break;
+            };
+            trace!("ring {} submits a bridged request", self.index);
+            let (entry, data) = match request {
+                Request::Once { entry, pending } => (entry, InFlight::Once(pending)),
+                Request::Stream(stream) => (stream.entry.clone(), InFlight::Stream(stream)),
+                // the paused streams are checked below
+                Request::Resume => continue,
+                // armed below
+                Request::Sleep { deadline, pending } => {
+                    self.timers.insert(deadline, pending);
+                    continue;
+                }
+            };
+            if let Err(e) = submitter.push(entry, BridgeData(Some(data))) {
+                return ControlFlow::Error(e.into());
+            }
+            self.in_flight += 1;
+        }
+
+        if submitter.has_capacity(1) {
+            if let Some((entry, timer)) = self.timers.arm() {
+                if let Err(e) = submitter.push(entry, BridgeData(Some(timer))) {
+                    return ControlFlow::Error(e.into());
+                }
+            }
+        }
+
+        let mut index = 0;
+        while index < self.paused.len() && submitter.has_capacity(1) {
+            let resume = {
+                let state = self.paused[index].state.lock().unwrap();
+                if state.closed {
+                    None
+                } else {
+                    Some(state.items.len() <= state.capacity / 2)
+                }
+            };
+            match resume {
+                Some(false) => index += 1,
+                None => drop(self.paused.swap_remove(index)),
+                Some(true) => {
+                    let stream = self.paused.swap_remove(index);
+                    trace!("ring {} resumes a bridged stream", self.index);
+                    stream.state.lock().unwrap().paused = false;
+                    let entry = stream.entry.clone();
+                    let data = BridgeData(Some(InFlight::Stream(stream)));
+                    if let Err(e) = submitter.push(entry, data) {
+                        return ControlFlow::Error(e.into());
+                    }
+                    self.in_flight += 1;
+                }
+            }
+        }
+
+        match self.draining.get() && self.in_flight == 0 && self.queue.borrow().is_empty() {
+            true => ControlFlow::Exit,
+            false => ControlFlow::Continue,
+        }
+    }
+
+    fn on_message<W: Fn(&mut io_uring::squeue::Entry, Self::RingData)>(
+        &mut self,
+        message: crate::message::RingMessage,
+        mut submitter: SubmissionQueueSubmitter<Self::RingData, W>,
+    ) -> OpControlFlow {
+        self.remote
+            .on_message(message, submitter.map_data(|()| BridgeData(None)))
+    }
+}
+
+/// Rings on dedicated threads executing the requests of [`BridgeHandle`]s, see the
+/// [module docs](self).
+#[derive(Debug)]
+pub struct Bridge {
+    pool: RingPool<Error>,
+    handle: BridgeHandle,
+}
+
+impl Bridge {
+    /// Spawns `rings` rings with `ring_size` submission queue entries each and waits until
+    /// all of them take requests.
+    pub fn spawn(rings: usize, ring_size: NonZeroU32) -> io::Result<Self> {
+        let shared: Arc<Rings> = Default::default();
+        let (ready_tx, ready) = mpsc::channel();
+        let ready_tx = Mutex::new(ready_tx);
+
+        let pool = RingPool::builder()
+            .threads(rings.max(1))
+            .name("rummelplatz-bridge")
+            .spawn({
+                let shared = shared.clone();
+                move |context| {
+                    let started = build_ring(&context, &shared, ring_size);
+                    let ring = match started {
+                        Ok(ring) => ring,
+                        Err(e) => {
+                            let _ = ready_tx.lock().unwrap().send(Err(e.kind()));
+                            return Err(e.into());
+                        }
+                    };
+                    // only the first start of every ring is waited for
+                    let _ = ready_tx.lock().unwrap().send(Ok(()));
+                    run_ring(ring)
+                }
+            })?;
+
+        for _ in 0..pool.len() {
+            let started = ready.recv().unwrap_or(Err(io::ErrorKind::BrokenPipe));
+            if let Err(kind) = started {
+                let e = io::Error::new(kind, "unable to start the bridge rings");
+                for result in pool.shutdown(Duration::ZERO) {
+                    if let Err(e) = result {
+                        debug!("{e}");
+                    }
+                }
+                return Err(e);
+            }
+        }
+
+        Ok(Self {
+            pool,
+            handle: BridgeHandle { rings: shared },
+        })
+    }
+
+    pub fn handle(&self) -> BridgeHandle {
+        self.handle.clone()
+    }
+
+    pub fn rings(&self) -> usize {
+        self.pool.len()
+    }
+
+    /// Stops taking new requests and waits up to `grace` for the requests in flight, the
+    /// futures of requests still running afterwards fail.
+    pub fn shutdown(self, grace: Duration) -> Vec<Result<RingReport<()>, RingError<Error>>> {
+        self.pool.shutdown(grace)
+    }
+}
+
+fn build_ring(
+    context: &RingContext,
+    rings: &Arc<Rings>,
+    ring_size: NonZeroU32,
+) -> io::Result<ring::bridge_ring::Ring> {
+    let raw = ring::bridge_ring::Ring::new_raw_ring(ring_size)?;
+    context.register_iowq_affinity(&raw.submitter())?;
+
+    let bridge = BridgeOp::with_rings(rings.clone(), context.index)?;
+    let drain = {
+        let rings = rings.clone();
+        let index = context.index;
+        let draining = bridge.draining.clone();
+        context.drain_op(move |_| {
+            rings.set(index, None);
+            draining.set(true);
+            ControlFlow::Continue
+        })?
+    };
+
+    let handle = bridge.remote.handle();
+    let mut ring = ring::bridge_ring::Ring::new(raw, None, drain, bridge);
+    handle.connect(ring.message_target(ring::bridge_ring::Operation::bridge));
+    ring.report_load(context.load_reporter());
+    Ok(ring)
+}
+
+fn run_ring(mut ring: ring::bridge_ring::Ring) -> Result<(), Error> {
+    ring.run::<Error, Error, Error>().map_err(|e| {
+        use ring::bridge_ring::RingError;
+        match e {
+            RingError::Setup(e) | RingError::Completion(e) | RingError::Teardown(e) => e,
+            RingError::Api(e) => e.into(),
+            RingError::Push(e) => e.into(),
+            e @ RingError::Dynamic { .. } => io::Error::other(e.to_string()).into(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    use io_uring::opcode::{Nop, PollAdd, Read};
+    use io_uring::types::Fd;
+
+    use super::*;
+    use crate::pool::DrainSignal;
+    use crate::RingStep;
+
+    pub(super) fn bridge_ring() -> (ring::bridge_ring::Ring, RingHandle<DrainSignal>) {
+        let drain = RemoteOp::new(|_| ControlFlow::Exit).unwrap();
+        let exit = drain.handle();
+        let ring = ring::bridge_ring::Ring::builder()
+            .drain(drain)
+            .bridge(BridgeOp::new().unwrap())
+            .build()
+            .unwrap();
+        (ring, exit)
+    }
+
+    /// Steps `ring` until `done` holds, the completions of the kernel may take a few steps.
+    pub(super) fn step_until(
+        ring: &mut ring::bridge_ring::Ring,
+        mut done: impl FnMut(&ring::bridge_ring::Ring) -> bool,
+    ) {
+        for _ in 0..1000 {
+            if done(ring) {
+                return;
+            }
+            let step = ring.run_step::<Error, Error, Error>().unwrap();
+            assert!(matches!(step, RingStep::Running { .. }), "the ring exited");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        panic!("the ring did not get there");
+    }
+
+    pub(super) fn run_to_exit(ring: &mut ring::bridge_ring::Ring) {
+        for _ in 0..1000 {
+            if let RingStep::Finished = ring.run_step::<Error, Error, Error>().unwrap() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        panic!("the ring did not exit");
+    }
+
+    /// Polls `future` on this thread until it is ready, for requests on the rings of a
+    /// [`Bridge`].
+    pub(super) fn block_on<F: Future>(future: F) -> F::Output {
+        struct Unpark(std::thread::Thread);
+
+        impl std::task::Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut future = std::pin::pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut Context::from_waker(&waker)) {
+                return output;
+            }
+            std::thread::park();
+        }
+    }
+
+    pub(super) fn poll<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+        Pin::new(future).poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    fn next(stream: &mut BridgeStream) -> Poll<Option<io::Result<Entry>>> {
+        stream.poll_next(&mut Context::from_waker(Waker::noop()))
+    }
+
+    fn eventfd() -> OwnedFd {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        assert!(fd >= 0, "{}", io::Error::last_os_error());
+        unsafe { OwnedFd::from_raw_fd(fd) }
+    }
+
+    fn signal(fd: &OwnedFd) {
+        let value = 1u64;
+        let res = unsafe {
+            libc::write(
+                fd.as_raw_fd(),
+                &value as *const u64 as *const libc::c_void,
+                size_of::<u64>(),
+            )
+        };
+        assert_eq!(res, size_of::<u64>() as isize);
+    }
+
+    #[test]
+    fn nop_resolves_its_future_and_drain_exits() {
+        let (mut ring, _exit) = bridge_ring();
+        let handle = ring.operations().bridge.handle();
+
+        let mut completion = unsafe { handle.submit(Nop::new().build()) };
+        assert!(poll(&mut completion).is_pending());
+        step_until(&mut ring, |_| {
+            matches!(*completion.state.lock().unwrap(), State::Completed(_))
+        });
+        let Poll::Ready(Ok(entry)) = poll(&mut completion) else {
+            panic!("the nop did not complete");
+        };
+        assert_eq!(entry.result(), 0);
+        assert_eq!(ring.operations().bridge.in_flight(), 0);
+
+        ring.operations().bridge.drain();
+        run_to_exit(&mut ring);
+        // the drained ring takes no requests anymore
+        let mut completion = unsafe { handle.submit(Nop::new().build()) };
+        let Poll::Ready(Err(e)) = poll(&mut completion) else {
+            panic!("a drained ring took a request");
+        };
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn multishot_poll_pauses_and_resumes() {
+        let (mut ring, _exit) = bridge_ring();
+        let handle = ring.operations().bridge.handle();
+        let fd = eventfd();
+
+        let poll_add = PollAdd::new(Fd(fd.as_raw_fd()), libc::POLLIN as u32)
+            .multi(true)
+            .build();
+        let mut stream = unsafe { handle.submit_multishot(poll_add, 2) };
+        step_until(&mut ring, |ring| ring.operations().bridge.in_flight() == 1);
+
+        signal(&fd);
+        step_until(&mut ring, |_| stream.state.lock().unwrap().items.len() == 1);
+        signal(&fd);
+        // the second completion fills the buffer, the poll is canceled and its final
+        // -ECANCELED completion does not end the stream
+        step_until(&mut ring, |ring| ring.operations().bridge.paused.len() == 1);
+        {
+            let state = stream.state.lock().unwrap();
+            assert_eq!(state.items.len(), 2);
+            assert!(state.paused);
+            assert_eq!(state.end, End::Running);
+        }
+        assert_eq!(ring.operations().bridge.in_flight(), 0);
+
+        // taking half of the buffer re-arms the poll, the eventfd is still readable
+        assert!(matches!(next(&mut stream), Poll::Ready(Some(Ok(_)))));
+        step_until(&mut ring, |_| stream.state.lock().unwrap().items.len() == 2);
+        assert!(matches!(next(&mut stream), Poll::Ready(Some(Ok(_)))));
+        assert!(matches!(next(&mut stream), Poll::Ready(Some(Ok(_)))));
+        assert!(next(&mut stream).is_pending());
+
+        // a dropped stream is canceled (or forgotten while paused), then the ring drains
+        drop(stream);
+        signal(&fd);
+        ring.operations().bridge.drain();
+        run_to_exit(&mut ring);
+    }
+
+    #[test]
+    fn ring_exit_fails_the_requests_in_flight() {
+        let (mut ring, exit) = bridge_ring();
+        let handle = ring.operations().bridge.handle();
+        let fd = eventfd();
+        let mut value = Box::new(0u64);
+
+        // nothing signals the eventfd, the read and the poll stay in flight
+        let read = Read::new(
+            Fd(fd.as_raw_fd()),
+            value.as_mut() as *mut u64 as *mut u8,
+            size_of::<u64>() as u32,
+        )
+        .build();
+        let mut completion = unsafe { handle.submit(read) };
+        let poll_add = PollAdd::new(Fd(fd.as_raw_fd()), libc::POLLIN as u32)
+            .multi(true)
+            .build();
+        let mut stream = unsafe { handle.submit_multishot(poll_add, 2) };
+        step_until(&mut ring, |ring| ring.operations().bridge.in_flight() == 2);
+
+        exit.send(DrainSignal::Force).unwrap();
+        run_to_exit(&mut ring);
+
+        let Poll::Ready(Err(e)) = poll(&mut completion) else {
+            panic!("the read did not fail");
+        };
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+        let Poll::Ready(Some(Err(e))) = next(&mut stream) else {
+            panic!("the stream did not fail");
+        };
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+        assert!(matches!(next(&mut stream), Poll::Ready(None)));
+    }
+}
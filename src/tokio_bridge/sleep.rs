@@ -0,0 +1,197 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use io_uring::cqueue::Entry;
+use io_uring::opcode::Timeout;
+use io_uring::types::Timespec;
+use tracing::trace;
+
+use super::{BridgeCompletion, BridgeHandle, InFlight, Pending, Request, State};
+
+/// The sleeps of one ring, ordered by deadline. Only the earliest deadline has a `Timeout`
+/// request in flight, however many sleeps are waiting.
+#[derive(Default)]
+pub(super) struct Timers {
+    sleeps: BTreeMap<(Instant, u64), Pending>,
+    next_id: u64,
+    /// Deadlines of the timeouts in flight, a sleep earlier than all of them arms another one
+    armed: BTreeSet<Instant>,
+}
+
+impl Timers {
+    pub(super) fn len(&self) -> usize {
+        self.sleeps.len()
+    }
+
+    pub(super) fn insert(&mut self, deadline: Instant, pending: Pending) {
+        self.next_id += 1;
+        self.sleeps.insert((deadline, self.next_id), pending);
+    }
+
+    /// The timeout for the earliest sleep, unless one that fires before is in flight already.
+    pub(super) fn arm(&mut self) -> Option<(io_uring::squeue::Entry, InFlight)> {
+        let &(deadline, _) = self.sleeps.keys().next()?;
+        if self.armed.first().is_some_and(|&armed| armed <= deadline) {
+            return None;
+        }
+        self.armed.insert(deadline);
+
+        // the kernel reads the timespec on submission, it lives with the request
+        let timespec = Box::new(Timespec::from(
+            deadline.saturating_duration_since(Instant::now()),
+        ));
+        let entry = Timeout::new(&*timespec).build();
+        Some((
+            entry,
+            InFlight::Timer {
+                deadline,
+                _timespec: timespec,
+            },
+        ))
+    }
+
+    /// Wakes all sleeps that are due, after the timeout armed for `deadline` fired.
+    pub(super) fn expire(&mut self, deadline: Instant, completion_entry: Entry) {
+        // the later timeouts stay in flight and serve the sleeps left
+        self.armed.remove(&deadline);
+
+        let now = Instant::now();
+        let later = self.sleeps.split_off(&(now, u64::MAX));
+        let due = std::mem::replace(&mut self.sleeps, later);
+        trace!("{} sleeps are due", due.len());
+        for pending in due.into_values() {
+            pending.resolve(State::Completed(completion_entry.clone()));
+        }
+    }
+}
+
+/// Resolves once its deadline passed, see [`BridgeHandle::sleep`].
+pub struct RingSleep {
+    deadline: Instant,
+    completion: BridgeCompletion,
+}
+
+impl Debug for RingSleep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingSleep")
+            .field("deadline", &self.deadline)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RingSleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl Future for RingSleep {
+    type Output = io::Result<()>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.completion).poll(cx).map_ok(drop)
+    }
+}
+
+impl BridgeHandle {
+    /// A future resolving once `duration` passed, see [`BridgeHandle::sleep_until`].
+    pub fn sleep(&self, duration: std::time::Duration) -> RingSleep {
+        self.sleep_until(Instant::now() + duration)
+    }
+
+    /// A future resolving once `deadline` passed, timed by one of the rings. All sleeps on a
+    /// ring share a single `Timeout` request for the earliest deadline, so thousands of
+    /// connection timeouts cost one request instead of one each. A dropped sleep stays on its
+    /// ring until the deadline and is discarded then.
+    ///
+    /// Like [`BridgeHandle::submit`] the future fails with [`io::ErrorKind::BrokenPipe`] if no
+    /// ring takes the sleep or the ring exits before the deadline.
+    pub fn sleep_until(&self, deadline: Instant) -> RingSleep {
+        let state = Arc::new(Mutex::new(State::Waiting(None)));
+        let request = Request::Sleep {
+            deadline,
+            pending: Pending {
+                state: state.clone(),
+                _keep: None,
+            },
+        };
+        if let Err(request) = self.rings.send(request) {
+            trace!("no ring takes sleeps");
+            // resolves the future
+            drop(request);
+        }
+        RingSleep {
+            deadline,
+            completion: BridgeCompletion { state },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::tokio_bridge::tests::{bridge_ring, poll, run_to_exit, step_until};
+
+    #[test]
+    fn sleeps_with_mixed_deadlines_all_wake() {
+        let (mut ring, _exit) = bridge_ring();
+        let handle = ring.operations().bridge.handle();
+        let armed = |ring: &crate::tokio_bridge::ring::bridge_ring::Ring| {
+            ring.operations().bridge.timers.armed.len()
+        };
+
+        let mut late = handle.sleep(Duration::from_millis(60));
+        step_until(&mut ring, |ring| armed(ring) == 1);
+        // an earlier sleep needs a timeout of its own
+        let mut early = handle.sleep(Duration::from_millis(10));
+        step_until(&mut ring, |ring| armed(ring) == 2);
+
+        step_until(&mut ring, |_| poll(&mut early).is_ready());
+        assert!(poll(&mut late).is_pending());
+        // the late timeout is still in flight, nothing is armed again
+        assert_eq!(armed(&ring), 1);
+
+        step_until(&mut ring, |_| poll(&mut late).is_ready());
+        assert!(Instant::now() >= late.deadline());
+        assert_eq!(armed(&ring), 0);
+        assert_eq!(ring.operations().bridge.timers.len(), 0);
+
+        ring.operations().bridge.drain();
+        run_to_exit(&mut ring);
+    }
+
+    #[test]
+    fn one_timeout_serves_many_sleeps() {
+        let (mut ring, _exit) = bridge_ring();
+        let handle = ring.operations().bridge.handle();
+
+        let mut sleeps: Vec<_> = (0..100)
+            .map(|i| handle.sleep(Duration::from_millis(20 + i % 3)))
+            .collect();
+        step_until(&mut ring, |ring| {
+            ring.operations().bridge.timers.len() == 100
+        });
+        assert_eq!(ring.operations().bridge.timers.armed.len(), 1);
+
+        step_until(&mut ring, |_| {
+            sleeps
+                .iter()
+                .all(|sleep| matches!(*sleep.completion.state.lock().unwrap(), State::Completed(_)))
+        });
+        assert!(sleeps.iter_mut().all(|sleep| poll(sleep).is_ready()));
+        assert!(sleeps
+            .iter()
+            .all(|sleep| Instant::now() >= sleep.deadline()));
+
+        ring.operations().bridge.drain();
+        run_to_exit(&mut ring);
+    }
+}
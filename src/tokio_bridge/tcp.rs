@@ -0,0 +1,574 @@
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll, Wake, Waker};
+
+use io_uring::opcode::{AsyncCancel2, Connect, Recv, Send};
+use io_uring::types::{CancelBuilder, Fd};
+
+use crate::ops::{RingHandle, SockAddr};
+use crate::tokio_bridge::{submit_to, BridgeCompletion, BridgeHandle, Request, Rings};
+
+/// Largest buffer of a single `Recv`/`Send`
+const MAX_CHUNK: usize = 64 * 1024;
+
+/// A buffer the kernel writes to (or reads from) while a request is in flight, shared between
+/// the stream and the ring until the request completed.
+struct Chunk {
+    ptr: *mut [u8],
+}
+
+// only accessed by the kernel while a request is in flight and by the stream otherwise
+unsafe impl std::marker::Send for Chunk {}
+unsafe impl Sync for Chunk {}
+
+impl Chunk {
+    fn new(len: usize) -> Arc<Self> {
+        let ptr = Box::into_raw(vec![0; len].into_boxed_slice());
+        Arc::new(Self { ptr })
+    }
+
+    fn ptr(&self) -> *mut u8 {
+        self.ptr.cast()
+    }
+
+    fn len(&self) -> usize {
+        self.ptr.len()
+    }
+
+    /// # Safety
+    /// No request using the chunk may be in flight.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn get(&self) -> &mut [u8] {
+        &mut *self.ptr
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(self.ptr) });
+    }
+}
+
+struct ReadState {
+    chunk: Arc<Chunk>,
+    /// Received bytes not handed out yet
+    start: usize,
+    end: usize,
+    in_flight: Option<BridgeCompletion>,
+}
+
+struct WriteState {
+    chunk: Arc<Chunk>,
+    /// Bytes of the chunk sent so far and in total
+    sent: usize,
+    len: usize,
+    completion: BridgeCompletion,
+}
+
+/// A TCP stream whose receives and sends run on a ring of a [`Bridge`](super::Bridge),
+/// implementing `AsyncRead`/`AsyncWrite` of tokio (feature `tokio`) and of `futures-io`
+/// (feature `futures-io`), so protocol libraries built on them run unchanged.
+///
+/// All requests of a stream go to the same ring, one receive and one send at a time. Writes
+/// are buffered: a write returns once its data was copied and submitted, its result surfaces
+/// with the next write or flush, which wait for the send before. Dropping the stream lets the
+/// send in flight finish and cancels the receive, the socket is closed afterwards. Errors of
+/// that last send are lost, flush before dropping to see them.
+pub struct RingTcpStream {
+    // taken on drop, closed once the last send and the cancellation completed
+    fd: Option<OwnedFd>,
+    rings: Arc<Rings>,
+    ring: RingHandle<Request>,
+    read: ReadState,
+    write: Option<WriteState>,
+}
+
+impl Debug for RingTcpStream {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingTcpStream")
+            .field("fd", &self.fd)
+            .field("buffered", &(self.read.end - self.read.start))
+            .field("receiving", &self.read.in_flight.is_some())
+            .field("sending", &self.write.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl RingTcpStream {
+    /// Moves `stream` onto one of the rings of `handle`.
+    pub fn new(handle: &BridgeHandle, stream: TcpStream) -> io::Result<Self> {
+        let ring = handle.rings.pick().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "no ring takes bridged requests")
+        })?;
+        Ok(Self {
+            fd: Some(stream.into()),
+            rings: handle.rings.clone(),
+            ring,
+            read: ReadState {
+                chunk: Chunk::new(0),
+                start: 0,
+                end: 0,
+                in_flight: None,
+            },
+            write: None,
+        })
+    }
+
+    /// Connects to `addr` (blocking) and moves the stream onto one of the rings of `handle`.
+    pub fn connect(handle: &BridgeHandle, addr: SocketAddr) -> io::Result<Self> {
+        Self::new(handle, TcpStream::connect(addr)?)
+    }
+
+    /// Connects to `addr` with a `Connect` request on the ring the stream is moved onto.
+    pub fn connect_async(
+        handle: &BridgeHandle,
+        addr: SocketAddr,
+    ) -> impl Future<Output = io::Result<Self>> + std::marker::Send + 'static {
+        let handle = handle.clone();
+        async move {
+            let address = Box::new(SockAddr::new(&addr));
+            let fd = unsafe {
+                libc::socket(address.family(), libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0)
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let stream = Self::new(
+                &handle,
+                TcpStream::from(unsafe { OwnedFd::from_raw_fd(fd) }),
+            )?;
+
+            let connect = Connect::new(Fd(fd), address.as_ptr(), address.len()).build();
+            let keep: Box<dyn std::any::Any + std::marker::Send> = address;
+            let res = submit_to(&stream.rings, Some(&stream.ring), connect, Some(keep))
+                .await?
+                .result();
+            if res < 0 {
+                return Err(io::Error::from_raw_os_error(-res));
+            }
+            Ok(stream)
+        }
+    }
+
+    fn fd(&self) -> RawFd {
+        self.fd.as_ref().expect("stream is open").as_raw_fd()
+    }
+
+    fn submit(&self, entry: io_uring::squeue::Entry, chunk: &Arc<Chunk>) -> BridgeCompletion {
+        let keep: Box<dyn std::any::Any + std::marker::Send> = Box::new(chunk.clone());
+        submit_to(&self.rings, Some(&self.ring), entry, Some(keep))
+    }
+
+    /// Reads received bytes into `buf`, receiving up to `buf.len()` (at most 64 KiB) more if
+    /// there are none. `Ok(0)` at the end of the stream.
+    pub fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let received = ready!(self.poll_received(cx, buf.len()))?;
+        let len = buf.len().min(received.len());
+        buf[..len].copy_from_slice(&received[..len]);
+        self.read.start += len;
+        Poll::Ready(Ok(len))
+    }
+
+    /// The received bytes not read yet, receiving up to `want` (at most 64 KiB) if there are
+    /// none. Empty at the end of the stream (or for `want == 0`), [`Self::consume`] marks them
+    /// read.
+    fn poll_received(&mut self, cx: &mut Context<'_>, want: usize) -> Poll<io::Result<&[u8]>> {
+        if want == 0 {
+            return Poll::Ready(Ok(&[]));
+        }
+
+        if self.read.start == self.read.end {
+            if self.read.in_flight.is_none() {
+                let len = want.min(MAX_CHUNK);
+                // the ring may still hold the chunk of the previous receive for a moment
+                if Arc::strong_count(&self.read.chunk) > 1 || self.read.chunk.len() < len {
+                    self.read.chunk = Chunk::new(len);
+                }
+                let recv = Recv::new(Fd(self.fd()), self.read.chunk.ptr(), len as u32).build();
+                self.read.in_flight = Some(self.submit(recv, &self.read.chunk));
+            }
+
+            let completion = self.read.in_flight.as_mut().unwrap();
+            let entry = ready!(Pin::new(completion).poll(cx));
+            self.read.in_flight = None;
+            let res = entry?.result();
+            if res < 0 {
+                return Poll::Ready(Err(io::Error::from_raw_os_error(-res)));
+            }
+            self.read.start = 0;
+            self.read.end = res as usize;
+        }
+
+        let received = unsafe { &self.read.chunk.get()[self.read.start..self.read.end] };
+        Poll::Ready(Ok(received))
+    }
+
+    #[cfg(any(feature = "tokio", feature = "hyper"))]
+    fn consume(&mut self, len: usize) {
+        self.read.start += len;
+    }
+
+    /// Waits for the send in flight, submitting what is left of it after partial sends.
+    fn poll_sent(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            let Some(write) = &mut self.write else {
+                return Poll::Ready(Ok(()));
+            };
+            let entry = ready!(Pin::new(&mut write.completion).poll(cx));
+            let res = match entry {
+                Ok(entry) => entry.result(),
+                Err(e) => {
+                    self.write = None;
+                    return Poll::Ready(Err(e));
+                }
+            };
+            if res < 0 {
+                self.write = None;
+                return Poll::Ready(Err(io::Error::from_raw_os_error(-res)));
+            }
+
+            write.sent += res as usize;
+            if write.sent >= write.len {
+                self.write = None;
+                continue;
+            }
+            let ptr = unsafe { write.chunk.ptr().add(write.sent) };
+            let left = (write.len - write.sent) as u32;
+            let chunk = write.chunk.clone();
+            let send = Send::new(Fd(self.fd()), ptr, left).build();
+            let completion = self.submit(send, &chunk);
+            self.write.as_mut().unwrap().completion = completion;
+        }
+    }
+
+    /// Waits for the previous send, then copies up to 64 KiB of `buf` and sends them. They count
+    /// as written before they were sent, [`Self::poll_flush`] waits for that.
+    pub fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        ready!(self.poll_sent(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let len = buf.len().min(MAX_CHUNK);
+        let chunk = Chunk::new(len);
+        unsafe { chunk.get() }.copy_from_slice(&buf[..len]);
+        let send = Send::new(Fd(self.fd()), chunk.ptr(), len as u32).build();
+        let completion = self.submit(send, &chunk);
+        self.write = Some(WriteState {
+            chunk,
+            sent: 0,
+            len,
+            completion,
+        });
+        Poll::Ready(Ok(len))
+    }
+
+    /// Waits until everything written was sent.
+    pub fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_sent(cx)
+    }
+
+    /// Flushes, then shuts the sending side of the socket down.
+    pub fn poll_shutdown(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_sent(cx))?;
+        let stream = TcpStream::from(self.fd.take().expect("stream is open"));
+        let shutdown = stream.shutdown(Shutdown::Write);
+        self.fd = Some(stream.into());
+        Poll::Ready(shutdown)
+    }
+}
+
+impl AsRawFd for RingTcpStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd()
+    }
+}
+
+/// A dropped stream whose send is still in flight, dropped for good once the send finished.
+struct Linger(Mutex<Option<RingTcpStream>>);
+
+impl Wake for Linger {
+    fn wake(self: Arc<Self>) {
+        let waker = Waker::from(self.clone());
+        let mut slot = self.0.lock().unwrap();
+        let Some(mut stream) = slot.take() else {
+            return;
+        };
+        // sends what is left after partial sends, woken again by each of them
+        if stream
+            .poll_sent(&mut Context::from_waker(&waker))
+            .is_pending()
+        {
+            *slot = Some(stream);
+        }
+    }
+}
+
+impl Drop for RingTcpStream {
+    fn drop(&mut self) {
+        if self.write.is_some() && self.fd.is_some() {
+            // written data is sent, the stream is dropped again once its send finished
+            let stream = RingTcpStream {
+                fd: self.fd.take(),
+                rings: self.rings.clone(),
+                ring: self.ring.clone(),
+                read: ReadState {
+                    chunk: self.read.chunk.clone(),
+                    start: 0,
+                    end: 0,
+                    in_flight: self.read.in_flight.take(),
+                },
+                write: self.write.take(),
+            };
+            Arc::new(Linger(Mutex::new(Some(stream)))).wake();
+            return;
+        }
+
+        let Some(fd) = self.fd.take() else {
+            return;
+        };
+        if self.read.in_flight.is_none() {
+            return;
+        }
+
+        // cancel by fd on the ring running the requests, the fd has to stay open until then
+        let cancel = AsyncCancel2::new(CancelBuilder::fd(Fd(fd.as_raw_fd())).all()).build();
+        drop(submit_to(
+            &self.rings,
+            Some(&self.ring),
+            cancel,
+            Some(Box::new(fd)),
+        ));
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncRead for RingTcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let received = ready!(this.poll_received(cx, buf.remaining()))?;
+        let len = buf.remaining().min(received.len());
+        buf.put_slice(&received[..len]);
+        this.consume(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncWrite for RingTcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncRead for RingTcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_read(cx, buf)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl futures_io::AsyncWrite for RingTcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "hyper")]
+impl hyper::rt::Read for RingTcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        mut buf: hyper::rt::ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let received = ready!(this.poll_received(cx, buf.remaining()))?;
+        let len = buf.remaining().min(received.len());
+        buf.put_slice(&received[..len]);
+        this.consume(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "hyper")]
+impl hyper::rt::Write for RingTcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "hyper")]
+impl hyper_util::client::legacy::connect::Connection for RingTcpStream {
+    fn connected(&self) -> hyper_util::client::legacy::connect::Connected {
+        hyper_util::client::legacy::connect::Connected::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::num::NonZeroU32;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::tokio_bridge::tests::block_on;
+    use crate::tokio_bridge::Bridge;
+
+    fn connected(bridge: &Bridge) -> (RingTcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = RingTcpStream::connect(&bridge.handle(), listener.local_addr().unwrap());
+        let (peer, _) = listener.accept().unwrap();
+        (stream.unwrap(), peer)
+    }
+
+    fn write_all(stream: &mut RingTcpStream, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            let written = block_on(std::future::poll_fn(|cx| stream.poll_write(cx, buf)));
+            buf = &buf[written.unwrap()..];
+        }
+    }
+
+    /// Sends `data` without blocking until the buffers of the socket are full, returns how
+    /// much was sent.
+    fn fill(fd: RawFd, data: &[u8]) -> usize {
+        let mut sent = 0;
+        loop {
+            let res = unsafe {
+                libc::send(
+                    fd,
+                    data[sent..].as_ptr() as *const libc::c_void,
+                    data.len() - sent,
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if res < 0 {
+                assert_eq!(io::Error::last_os_error().kind(), io::ErrorKind::WouldBlock);
+                return sent;
+            }
+            sent += res as usize;
+            assert!(sent < data.len(), "the socket buffers never filled up");
+        }
+    }
+
+    #[test]
+    fn reads_and_writes_over_loopback() {
+        let bridge = Bridge::spawn(1, NonZeroU32::new(8).unwrap()).unwrap();
+        let (mut stream, mut peer) = connected(&bridge);
+
+        write_all(&mut stream, b"ping");
+        block_on(std::future::poll_fn(|cx| stream.poll_flush(cx))).unwrap();
+        let mut buf = [0; 4];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        peer.write_all(b"pong").unwrap();
+        let mut buf = [0; 16];
+        let read = block_on(std::future::poll_fn(|cx| stream.poll_read(cx, &mut buf)));
+        assert_eq!(&buf[..read.unwrap()], b"pong");
+
+        drop(peer);
+        let read = block_on(std::future::poll_fn(|cx| stream.poll_read(cx, &mut buf)));
+        assert_eq!(read.unwrap(), 0);
+
+        drop(stream);
+        bridge.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn partial_sends_are_completed_and_survive_a_drop() {
+        let bridge = Bridge::spawn(1, NonZeroU32::new(8).unwrap()).unwrap();
+        let (mut stream, mut peer) = connected(&bridge);
+
+        // smaller than a chunk, the kernel never takes a whole chunk at once
+        let sndbuf: libc::c_int = 16 << 10;
+        let res = unsafe {
+            libc::setsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_SNDBUF,
+                &sndbuf as *const libc::c_int as *const libc::c_void,
+                size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(res, 0, "{}", io::Error::last_os_error());
+        let data: Vec<u8> = (0..8 << 20).map(|i| (i % 251) as u8).collect();
+        let filled = fill(stream.as_raw_fd(), &data);
+        let data = &data[..filled + 3 * MAX_CHUNK];
+        write_all(&mut stream, &data[filled..filled + MAX_CHUNK]);
+        // the peer makes room for less than the buffered data and the chunk
+        let mut received = vec![0; MAX_CHUNK];
+        peer.read_exact(&mut received).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut cx = Context::from_waker(Waker::noop());
+        while stream.write.as_ref().is_some_and(|write| write.sent == 0) {
+            assert!(stream.poll_flush(&mut cx).is_pending());
+            assert!(Instant::now() < deadline, "nothing was sent");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let write = stream.write.as_ref().expect("the chunk was sent at once");
+        assert!(write.sent < write.len);
+
+        let reader = std::thread::spawn(move || {
+            peer.read_to_end(&mut received).unwrap();
+            received
+        });
+        write_all(&mut stream, &data[filled + MAX_CHUNK..]);
+        // the last chunk is still in flight, dropping the stream does not cancel it
+        assert!(stream.write.is_some());
+        drop(stream);
+
+        assert!(reader.join().unwrap() == data, "data was lost");
+        bridge.shutdown(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,120 @@
+//! Buffers owned by requests.
+
+/// A buffer requests read from, its memory stays in place while the buffer is moved.
+///
+/// # Safety
+/// `stable_ptr` has to point to `bytes_total` bytes, the first `bytes_init` initialized, that
+/// stay valid and in place as long as the buffer lives.
+pub unsafe trait IoBuf: Send + Unpin + 'static {
+    fn stable_ptr(&self) -> *const u8;
+
+    /// Bytes written to the buffer, the ones a write sends.
+    fn bytes_init(&self) -> usize;
+
+    /// Capacity of the buffer.
+    fn bytes_total(&self) -> usize;
+}
+
+/// A buffer requests write to.
+///
+/// # Safety
+/// See [`IoBuf`], `stable_mut_ptr` points to the same memory as `stable_ptr`.
+pub unsafe trait IoBufMut: IoBuf {
+    fn stable_mut_ptr(&mut self) -> *mut u8;
+
+    /// Marks the first `pos` bytes as initialized, after a request wrote them.
+    ///
+    /// # Safety
+    /// The first `pos` bytes have to be initialized.
+    unsafe fn set_init(&mut self, pos: usize);
+}
+
+unsafe impl IoBuf for Vec<u8> {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.capacity()
+    }
+}
+
+unsafe impl IoBufMut for Vec<u8> {
+    fn stable_mut_ptr(&mut self) -> *mut u8 {
+        self.as_mut_ptr()
+    }
+
+    unsafe fn set_init(&mut self, pos: usize) {
+        if self.len() < pos {
+            self.set_len(pos);
+        }
+    }
+}
+
+unsafe impl IoBuf for Box<[u8]> {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.len()
+    }
+}
+
+unsafe impl IoBufMut for Box<[u8]> {
+    fn stable_mut_ptr(&mut self) -> *mut u8 {
+        self.as_mut_ptr()
+    }
+
+    unsafe fn set_init(&mut self, _pos: usize) {}
+}
+
+unsafe impl IoBuf for &'static [u8] {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.len()
+    }
+}
+
+unsafe impl IoBuf for &'static str {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.len()
+    }
+}
+
+unsafe impl IoBuf for String {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.capacity()
+    }
+}
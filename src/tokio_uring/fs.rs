@@ -0,0 +1,288 @@
+//! Files with owned-buffer reads and writes at offsets.
+
+use std::ffi::CString;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use io_uring::opcode::{Close, Fsync, OpenAt, Read, Write};
+use io_uring::types::{Fd, FsyncFlags};
+
+use super::buf::{IoBuf, IoBufMut};
+use super::{submit, BufResult};
+
+/// Options to open a [`File`] with, like [`std::fs::OpenOptions`].
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+    mode: u32,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self {
+            read: false,
+            write: false,
+            append: false,
+            truncate: false,
+            create: false,
+            create_new: false,
+            mode: 0o666,
+        }
+    }
+
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    /// Permissions of created files, before the umask, `0o666` by default.
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    fn flags(&self) -> io::Result<i32> {
+        let access = match (self.read, self.write || self.append) {
+            (true, false) => libc::O_RDONLY,
+            (false, true) => libc::O_WRONLY,
+            (true, true) => libc::O_RDWR,
+            (false, false) => return Err(io::ErrorKind::InvalidInput.into()),
+        };
+        let mut flags = access | libc::O_CLOEXEC;
+        if self.append {
+            flags |= libc::O_APPEND;
+        }
+        if self.truncate {
+            flags |= libc::O_TRUNC;
+        }
+        if self.create_new {
+            flags |= libc::O_CREAT | libc::O_EXCL;
+        } else if self.create {
+            flags |= libc::O_CREAT;
+        }
+        Ok(flags)
+    }
+
+    pub async fn open(&self, path: impl AsRef<Path>) -> io::Result<File> {
+        let flags = self.flags()?;
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())?;
+        let open = OpenAt::new(Fd(libc::AT_FDCWD), path.as_ptr())
+            .flags(flags)
+            .mode(self.mode)
+            .build();
+        let fd = submit(open, path).await.0?;
+        Ok(File {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+}
+
+/// An open file, its reads and writes run on a ring.
+pub struct File {
+    fd: OwnedFd,
+}
+
+impl Debug for File {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("File").field("fd", &self.fd).finish()
+    }
+}
+
+impl File {
+    /// Opens the file at `path` for reading.
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        OpenOptions::new().read(true).open(path).await
+    }
+
+    /// Creates (or truncates) the file at `path` for writing.
+    pub async fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .await
+    }
+
+    pub fn from_std(file: std::fs::File) -> Self {
+        Self { fd: file.into() }
+    }
+
+    /// Reads into `buf` (from its start, up to its capacity) at `pos`, `Ok(0)` at the end of
+    /// the file.
+    pub async fn read_at<T: IoBufMut>(&self, mut buf: T, pos: u64) -> BufResult<usize, T> {
+        let len = buf.bytes_total().min(u32::MAX as usize) as u32;
+        let read = Read::new(Fd(self.fd.as_raw_fd()), buf.stable_mut_ptr(), len)
+            .offset(pos)
+            .build();
+        let (res, mut buf) = submit(read, buf).await;
+        let res = res.map(|n| n as usize);
+        if let Ok(n) = res {
+            unsafe { buf.set_init(n) };
+        }
+        (res, buf)
+    }
+
+    /// Reads until `buf` is full, fails with [`io::ErrorKind::UnexpectedEof`] if the file ends
+    /// before.
+    pub async fn read_exact_at<T: IoBufMut>(&self, mut buf: T, pos: u64) -> BufResult<(), T> {
+        let total = buf.bytes_total();
+        let mut done = 0;
+        while done < total {
+            let len = (total - done).min(u32::MAX as usize) as u32;
+            let ptr = unsafe { buf.stable_mut_ptr().add(done) };
+            let read = Read::new(Fd(self.fd.as_raw_fd()), ptr, len)
+                .offset(pos + done as u64)
+                .build();
+            let res;
+            (res, buf) = submit(read, buf).await;
+            match res {
+                Ok(0) => return (Err(io::ErrorKind::UnexpectedEof.into()), buf),
+                Ok(n) => {
+                    done += n as usize;
+                    unsafe { buf.set_init(done) };
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return (Err(e), buf),
+            }
+        }
+        (Ok(()), buf)
+    }
+
+    /// Writes the initialized bytes of `buf` at `pos`, returns how many were written.
+    pub async fn write_at<T: IoBuf>(&self, buf: T, pos: u64) -> BufResult<usize, T> {
+        let len = buf.bytes_init().min(u32::MAX as usize) as u32;
+        let write = Write::new(Fd(self.fd.as_raw_fd()), buf.stable_ptr(), len)
+            .offset(pos)
+            .build();
+        let (res, buf) = submit(write, buf).await;
+        (res.map(|n| n as usize), buf)
+    }
+
+    /// Writes all initialized bytes of `buf` at `pos`.
+    pub async fn write_all_at<T: IoBuf>(&self, mut buf: T, pos: u64) -> BufResult<(), T> {
+        let total = buf.bytes_init();
+        let mut done = 0;
+        while done < total {
+            let len = (total - done).min(u32::MAX as usize) as u32;
+            let ptr = unsafe { buf.stable_ptr().add(done) };
+            let write = Write::new(Fd(self.fd.as_raw_fd()), ptr, len)
+                .offset(pos + done as u64)
+                .build();
+            let res;
+            (res, buf) = submit(write, buf).await;
+            match res {
+                Ok(0) => return (Err(io::ErrorKind::WriteZero.into()), buf),
+                Ok(n) => done += n as usize,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return (Err(e), buf),
+            }
+        }
+        (Ok(()), buf)
+    }
+
+    /// Flushes data and metadata to the device.
+    pub async fn sync_all(&self) -> io::Result<()> {
+        let fsync = Fsync::new(Fd(self.fd.as_raw_fd())).build();
+        submit(fsync, ()).await.0.map(drop)
+    }
+
+    /// Flushes the data (and the metadata needed to read it) to the device.
+    pub async fn sync_data(&self) -> io::Result<()> {
+        let fsync = Fsync::new(Fd(self.fd.as_raw_fd()))
+            .flags(FsyncFlags::DATASYNC)
+            .build();
+        submit(fsync, ()).await.0.map(drop)
+    }
+
+    /// Closes the file on the ring, reporting errors a plain drop would ignore.
+    pub async fn close(self) -> io::Result<()> {
+        let close = Close::new(Fd(self.fd.into_raw_fd())).build();
+        submit(close, ()).await.0.map(drop)
+    }
+}
+
+impl AsRawFd for File {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_data_reads_back() {
+        let path = std::env::temp_dir().join(format!("rummelplatz-uring-{}", std::process::id()));
+        crate::tokio_uring::start(async {
+            let file = File::create(&path).await.unwrap();
+            let (res, _) = file.write_all_at(&b"hello "[..], 0).await;
+            res.unwrap();
+            let (res, _) = file.write_all_at("world".to_string(), 6).await;
+            res.unwrap();
+            file.sync_all().await.unwrap();
+            file.close().await.unwrap();
+
+            let file = File::open(&path).await.unwrap();
+            let (res, buf) = file.read_exact_at(vec![0; 11].into_boxed_slice(), 0).await;
+            res.unwrap();
+            assert_eq!(&*buf, b"hello world");
+
+            let (res, buf) = file.read_at(Vec::with_capacity(64), 6).await;
+            assert_eq!(res.unwrap(), 5);
+            assert_eq!(buf, b"world");
+
+            let (res, _) = file.read_exact_at(vec![0; 4].into_boxed_slice(), 9).await;
+            assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+            file.close().await.unwrap();
+        });
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_files_fail_to_open() {
+        let path = std::env::temp_dir().join(format!("rummelplatz-missing-{}", std::process::id()));
+        let res = crate::tokio_uring::start(File::open(path));
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,161 @@
+//! The core of the [tokio-uring](https://docs.rs/tokio-uring) API on rings of a
+//! [`Bridge`](crate::tokio_bridge::Bridge), so projects written against it can switch drivers
+//! by changing an import.
+//!
+//! ```no_run
+//! use rummelplatz::tokio_uring::{self, fs::File};
+//!
+//! tokio_uring::start(async {
+//!     let file = File::open("hello.txt").await?;
+//!     let (res, buf) = file.read_at(vec![0; 4096], 0).await;
+//!     println!("read {:?}", &buf[..res?]);
+//!     file.close().await
+//! })
+//! # .unwrap();
+//! ```
+//!
+//! Like tokio-uring, [`start`] runs the future on a current thread tokio runtime whose tasks
+//! ([`spawn`]) stay on that thread. Unlike tokio-uring the requests do not run on that thread:
+//! they are sent to rings on dedicated threads, [`Builder::rings`] of them. Buffers are owned
+//! by the requests ([`BufResult`]) and handed back with their results, a dropped future leaves
+//! its buffer to the ring until the request completed.
+//!
+//! Covered are [`net::TcpListener`], [`net::TcpStream`] and [`fs::File`] with owned-buffer
+//! reads and writes, fixed buffers, slices and the unix/UDP sockets of tokio-uring are not.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::io;
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::tokio_bridge::{Bridge, BridgeHandle};
+
+pub mod buf;
+pub mod fs;
+pub mod net;
+
+/// The result of a request using an owned buffer, with the buffer.
+pub type BufResult<T, B> = (io::Result<T>, B);
+
+thread_local! {
+    static CURRENT: RefCell<Option<BridgeHandle>> = const { RefCell::new(None) };
+}
+
+/// The handle of the runtime on this thread.
+fn current() -> BridgeHandle {
+    CURRENT.with_borrow(|handle| handle.clone()).expect(
+        "must be called from the context of a rummelplatz::tokio_uring runtime, see `start`",
+    )
+}
+
+/// Sends `entry` to a ring of the current runtime, `data` (what `entry` points to) is owned by
+/// the request until it completed and handed back with the result then.
+async fn submit<D: Send + 'static>(
+    entry: io_uring::squeue::Entry,
+    data: D,
+) -> (io::Result<i32>, D) {
+    let slot = Arc::new(Mutex::new(Some(data)));
+    let completion = unsafe { current().submit_keeping(entry, Box::new(slot.clone())) };
+    let result = completion.await.and_then(|entry| match entry.result() {
+        res if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+        res => Ok(res),
+    });
+    // the request completed (or its ring is gone), nothing points to the data anymore
+    let data = slot.lock().unwrap().take().unwrap();
+    (result, data)
+}
+
+/// Configures the runtime of [`start`].
+#[derive(Debug, Clone)]
+pub struct Builder {
+    rings: usize,
+    entries: NonZeroU32,
+}
+
+pub fn builder() -> Builder {
+    Builder {
+        rings: 1,
+        entries: NonZeroU32::new(256).unwrap(),
+    }
+}
+
+impl Builder {
+    /// Submission queue entries of every ring, 256 by default and at least 1.
+    pub fn entries(&mut self, entries: u32) -> &mut Self {
+        self.entries = NonZeroU32::new(entries).unwrap_or(NonZeroU32::MIN);
+        self
+    }
+
+    /// Number of rings executing the requests, 1 by default and at least 1.
+    pub fn rings(&mut self, rings: usize) -> &mut Self {
+        self.rings = rings.max(1);
+        self
+    }
+
+    /// Runs `future` to completion, see [`start`].
+    pub fn start<F: Future>(&self, future: F) -> F::Output {
+        let bridge = Bridge::spawn(self.rings, self.entries).expect("unable to spawn the rings");
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("unable to build the tokio runtime");
+
+        let previous = CURRENT.replace(Some(bridge.handle()));
+        let output = tokio::task::LocalSet::new().block_on(&runtime, future);
+        // tasks still running were dropped with the local set, their requests are canceled below
+        drop(runtime);
+        CURRENT.set(previous);
+
+        for result in bridge.shutdown(Duration::ZERO) {
+            if let Err(e) = result {
+                debug!("{e}");
+            }
+        }
+        output
+    }
+}
+
+/// Starts a runtime with one ring and runs `future` on it to completion, requests of
+/// [`fs`] and [`net`] can only be made inside of it.
+pub fn start<F: Future>(future: F) -> F::Output {
+    builder().start(future)
+}
+
+/// Spawns a task on the runtime of this thread, it does not have to be `Send`.
+pub fn spawn<T: Future + 'static>(task: T) -> tokio::task::JoinHandle<T::Output> {
+    tokio::task::spawn_local(task)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_and_rings_are_at_least_1() {
+        let mut builder = builder();
+        builder.entries(0).rings(0);
+        assert_eq!(builder.entries, NonZeroU32::MIN);
+        assert_eq!(builder.rings, 1);
+
+        builder.start(async {
+            let dir = fs::File::open(std::env::temp_dir()).await.unwrap();
+            dir.close().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn spawned_tasks_run_on_the_runtime() {
+        let output = start(async { spawn(async { 7 }).await.unwrap() });
+        assert_eq!(output, 7);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be called from the context")]
+    fn requests_need_a_runtime() {
+        current();
+    }
+}
@@ -0,0 +1,210 @@
+//! TCP listeners and streams with owned-buffer reads and writes.
+
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::net::{Shutdown, SocketAddr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use io_uring::opcode::{Accept, Connect, Recv, Send};
+use io_uring::types::Fd;
+
+use super::buf::{IoBuf, IoBufMut};
+use super::{submit, BufResult};
+use crate::ops::SockAddr;
+
+/// A listening TCP socket accepting on a ring.
+pub struct TcpListener {
+    inner: std::net::TcpListener,
+}
+
+impl Debug for TcpListener {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TcpListener")
+            .field("addr", &self.inner.local_addr().ok())
+            .finish()
+    }
+}
+
+impl TcpListener {
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        std::net::TcpListener::bind(addr).map(Self::from_std)
+    }
+
+    pub fn from_std(listener: std::net::TcpListener) -> Self {
+        Self { inner: listener }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    /// Accepts the next connection and returns it with the address of the peer.
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        let mut address = Box::new(SockAddr::empty());
+        let accept = Accept::new(
+            Fd(self.inner.as_raw_fd()),
+            address.as_mut_ptr(),
+            address.len_mut(),
+        )
+        .flags(libc::SOCK_CLOEXEC)
+        .build();
+        let (res, address) = submit(accept, address).await;
+        let stream = std::net::TcpStream::from(unsafe { OwnedFd::from_raw_fd(res?) });
+        let peer = match address.to_std() {
+            Some(peer) => peer,
+            None => stream.peer_addr()?,
+        };
+        Ok((TcpStream::from_std(stream), peer))
+    }
+}
+
+impl AsRawFd for TcpListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+/// A TCP connection whose receives and sends run on a ring.
+pub struct TcpStream {
+    inner: std::net::TcpStream,
+}
+
+impl Debug for TcpStream {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TcpStream")
+            .field("peer", &self.inner.peer_addr().ok())
+            .finish()
+    }
+}
+
+impl TcpStream {
+    /// Connects to `addr` on a ring.
+    pub async fn connect(addr: SocketAddr) -> io::Result<Self> {
+        let address = Box::new(SockAddr::new(&addr));
+        let fd =
+            unsafe { libc::socket(address.family(), libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let stream = std::net::TcpStream::from(unsafe { OwnedFd::from_raw_fd(fd) });
+
+        let connect = Connect::new(Fd(fd), address.as_ptr(), address.len()).build();
+        submit(connect, address).await.0?;
+        Ok(Self::from_std(stream))
+    }
+
+    pub fn from_std(stream: std::net::TcpStream) -> Self {
+        Self { inner: stream }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.peer_addr()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.inner.set_nodelay(nodelay)
+    }
+
+    /// Receives into `buf` (from its start, up to its capacity), `Ok(0)` once the peer shut
+    /// the connection down.
+    pub async fn read<T: IoBufMut>(&self, mut buf: T) -> BufResult<usize, T> {
+        let len = buf.bytes_total().min(u32::MAX as usize) as u32;
+        let recv = Recv::new(Fd(self.inner.as_raw_fd()), buf.stable_mut_ptr(), len).build();
+        let (res, mut buf) = submit(recv, buf).await;
+        let res = res.map(|n| n as usize);
+        if let Ok(n) = res {
+            unsafe { buf.set_init(n) };
+        }
+        (res, buf)
+    }
+
+    /// Sends the initialized bytes of `buf`, returns how many were sent.
+    pub async fn write<T: IoBuf>(&self, buf: T) -> BufResult<usize, T> {
+        let len = buf.bytes_init().min(u32::MAX as usize) as u32;
+        let send = Send::new(Fd(self.inner.as_raw_fd()), buf.stable_ptr(), len)
+            .flags(libc::MSG_NOSIGNAL)
+            .build();
+        let (res, buf) = submit(send, buf).await;
+        (res.map(|n| n as usize), buf)
+    }
+
+    /// Sends all initialized bytes of `buf`.
+    pub async fn write_all<T: IoBuf>(&self, mut buf: T) -> BufResult<(), T> {
+        let total = buf.bytes_init();
+        let mut done = 0;
+        while done < total {
+            let len = (total - done).min(u32::MAX as usize) as u32;
+            let ptr = unsafe { buf.stable_ptr().add(done) };
+            let send = Send::new(Fd(self.inner.as_raw_fd()), ptr, len)
+                .flags(libc::MSG_NOSIGNAL)
+                .build();
+            let res;
+            (res, buf) = submit(send, buf).await;
+            match res {
+                Ok(0) => return (Err(io::ErrorKind::WriteZero.into()), buf),
+                Ok(n) => done += n as usize,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return (Err(e), buf),
+            }
+        }
+        (Ok(()), buf)
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.inner.shutdown(how)
+    }
+}
+
+impl AsRawFd for TcpStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokio_uring;
+
+    #[test]
+    fn echoes_over_loopback() {
+        tokio_uring::start(async {
+            let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = tokio_uring::spawn(async move {
+                let (stream, peer) = listener.accept().await.unwrap();
+                assert_eq!(peer, stream.peer_addr().unwrap());
+                let (res, buf) = stream.read(Vec::with_capacity(64)).await;
+                res.unwrap();
+                let (res, _) = stream.write_all(buf).await;
+                res.unwrap();
+                peer
+            });
+
+            let stream = TcpStream::connect(addr).await.unwrap();
+            assert_eq!(stream.peer_addr().unwrap(), addr);
+            let (res, _) = stream.write_all("ping").await;
+            res.unwrap();
+            let (res, buf) = stream.read(Vec::with_capacity(64)).await;
+            assert_eq!(res.unwrap(), 4);
+            assert_eq!(buf, b"ping");
+
+            assert_eq!(server.await.unwrap(), stream.local_addr().unwrap());
+            stream.shutdown(Shutdown::Write).unwrap();
+        });
+    }
+
+    #[test]
+    fn connecting_to_a_closed_port_fails() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let res = tokio_uring::start(TcpStream::connect(addr));
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -0,0 +1,115 @@
+//! Verbosity of the per-completion logging of a ring, adjustable while it runs.
+//!
+//! A ring logs every CQE it handles (`> CQE: ...`, its user data, messages and dropped
+//! completions) at `TRACE` by default, which production subscribers usually filter out. Raising
+//! the level of these events makes them visible for one misbehaving ring without restarting the
+//! process or enabling `TRACE` for everything, `None` turns them off entirely:
+//!
+//! ```no_run
+//! # rummelplatz::ring! { my_ring, tick: rummelplatz::ops::TickOp }
+//! # fn example(ring: my_ring::Ring) {
+//! use tracing::Level;
+//!
+//! let handle = ring.trace_level_handle();
+//! // from any thread, e.g. an admin endpoint
+//! handle.set(Some(Level::INFO));
+//! std::thread::sleep(std::time::Duration::from_secs(10));
+//! handle.set(Some(Level::TRACE));
+//! # }
+//! ```
+//!
+//! The level is read once per loop iteration, a ring blocked in `io_uring_enter` picks it up
+//! with its next completion.
+
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use tracing::Level;
+
+#[doc(hidden)]
+pub const OFF: u8 = 0;
+#[doc(hidden)]
+pub const ERROR: u8 = 1;
+#[doc(hidden)]
+pub const WARN: u8 = 2;
+#[doc(hidden)]
+pub const INFO: u8 = 3;
+#[doc(hidden)]
+pub const DEBUG: u8 = 4;
+#[doc(hidden)]
+pub const TRACE: u8 = 5;
+
+/// Sets the level a ring logs its completions at from any thread, cheap to clone. See
+/// `Ring::trace_level_handle`.
+#[derive(Clone)]
+pub struct TraceLevelHandle {
+    level: Arc<AtomicU8>,
+}
+
+impl Debug for TraceLevelHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TraceLevelHandle")
+            .field(&self.get())
+            .finish()
+    }
+}
+
+impl Default for TraceLevelHandle {
+    fn default() -> Self {
+        Self {
+            level: Arc::new(AtomicU8::new(TRACE)),
+        }
+    }
+}
+
+impl TraceLevelHandle {
+    /// Logs completions at `level` from the next loop iteration on, `None` turns their logging
+    /// off.
+    pub fn set(&self, level: Option<Level>) {
+        let level = match level {
+            None => OFF,
+            Some(Level::ERROR) => ERROR,
+            Some(Level::WARN) => WARN,
+            Some(Level::INFO) => INFO,
+            Some(Level::DEBUG) => DEBUG,
+            Some(Level::TRACE) => TRACE,
+        };
+        self.level.store(level, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Option<Level> {
+        match self.raw() {
+            ERROR => Some(Level::ERROR),
+            WARN => Some(Level::WARN),
+            INFO => Some(Level::INFO),
+            DEBUG => Some(Level::DEBUG),
+            TRACE => Some(Level::TRACE),
+            _ => None,
+        }
+    }
+
+    /// The level as one of the constants of this module, read by the ring once per iteration.
+    #[doc(hidden)]
+    #[inline]
+    pub fn raw(&self) -> u8 {
+        self.level.load(Ordering::Relaxed)
+    }
+}
+
+/// Logs an event at the level `$level` (a constant of [`trace_level`](crate::trace_level)) read
+/// from a [`TraceLevelHandle`], nothing for [`OFF`].
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ring_event {
+    ($level:expr, $($arg:tt)+) => {
+        match $level {
+            $crate::trace_level::ERROR => tracing::error!($($arg)+),
+            $crate::trace_level::WARN => tracing::warn!($($arg)+),
+            $crate::trace_level::INFO => tracing::info!($($arg)+),
+            $crate::trace_level::DEBUG => tracing::debug!($($arg)+),
+            $crate::trace_level::TRACE => tracing::trace!($($arg)+),
+            _ => {}
+        }
+    };
+}
@@ -0,0 +1,155 @@
+//! Static tracepoints (USDT, SystemTap SDT notes) in the loop of every ring, enabled with the
+//! `usdt` feature.
+//!
+//! A probe site is a single `nop` plus the moves of its arguments into registers, it costs next
+//! to nothing while no tracer is attached. bpftrace and perf find the probes in the ELF notes of
+//! the binary:
+//!
+//! ```text
+//! bpftrace -l 'usdt:./server:rummelplatz:*'
+//! bpftrace -e 'usdt:./server:rummelplatz:completion /(int64)arg3 < 0/ {
+//!     @errors[str(arg1), (int64)arg3] = count();
+//! }'
+//! perf buildid-cache --add ./server && perf record -e sdt_rummelplatz:submit -a
+//! ```
+//!
+//! Provider `rummelplatz`, every argument is 8 bytes wide. `ring` and `operation` point to
+//! NUL-terminated names as given to `ring!` (`str(arg0)`), signed values are sign-extended.
+//!
+//! | probe | arguments |
+//! |-------|-----------|
+//! | `submit` | `ring`, SQEs submitted, completions waited for |
+//! | `backlog_spill` | `ring`, batches spilled into the backlog, batches in the backlog |
+//! | `completion` | `ring`, `operation`, user data, `res`, `flags`, before the operation handles it |
+//! | `teardown` | `ring`, requests in flight (0 unless tracked) |
+//! | `teardown_done` | `ring`, `1` if the ring failed |
+//!
+//! Probes are emitted on x86-64 and aarch64, elsewhere the feature has no effect.
+
+/// Fires the static probe `rummelplatz:$name` with up to 5 arguments converted with `as u64`,
+/// see [`usdt`](crate::usdt). Expands to nothing without the `usdt` feature.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __probe {
+    ($name:literal, $a0:expr) => {
+        $crate::__probe_note!($name, "8@{0}", in(reg) ($a0) as u64,)
+    };
+    ($name:literal, $a0:expr, $a1:expr) => {
+        $crate::__probe_note!($name, "8@{0} 8@{1}", in(reg) ($a0) as u64, in(reg) ($a1) as u64,)
+    };
+    ($name:literal, $a0:expr, $a1:expr, $a2:expr) => {
+        $crate::__probe_note!(
+            $name,
+            "8@{0} 8@{1} 8@{2}",
+            in(reg) ($a0) as u64,
+            in(reg) ($a1) as u64,
+            in(reg) ($a2) as u64,
+        )
+    };
+    ($name:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr) => {
+        $crate::__probe_note!(
+            $name,
+            "8@{0} 8@{1} 8@{2} 8@{3}",
+            in(reg) ($a0) as u64,
+            in(reg) ($a1) as u64,
+            in(reg) ($a2) as u64,
+            in(reg) ($a3) as u64,
+        )
+    };
+    ($name:literal, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr) => {
+        $crate::__probe_note!(
+            $name,
+            "8@{0} 8@{1} 8@{2} 8@{3} 8@{4}",
+            in(reg) ($a0) as u64,
+            in(reg) ($a1) as u64,
+            in(reg) ($a2) as u64,
+            in(reg) ($a3) as u64,
+            in(reg) ($a4) as u64,
+        )
+    };
+}
+
+/// A `nop` and its `.note.stapsdt` entry, the layout `sys/sdt.h` emits.
+#[cfg(all(feature = "usdt", target_arch = "x86_64"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __probe_note {
+    ($name:literal, $args:literal, $($operands:tt)*) => {{
+        // probes are also placed inside of `unsafe` blocks
+        #[allow(unused_unsafe)]
+        let () = unsafe {
+            ::core::arch::asm!(
+                "990: nop",
+                ".pushsection .note.stapsdt, \"\", \"note\"",
+                ".balign 4",
+                ".4byte 992f-991f, 994f-993f, 3",
+                "991: .asciz \"stapsdt\"",
+                "992: .balign 4",
+                "993: .8byte 990b",
+                ".8byte _.stapsdt.base",
+                ".8byte 0",
+                ".asciz \"rummelplatz\"",
+                concat!(".asciz \"", $name, "\""),
+                concat!(".asciz \"", $args, "\""),
+                "994: .balign 4",
+                ".popsection",
+                ".ifndef _.stapsdt.base",
+                ".pushsection .stapsdt.base, \"aG\", \"progbits\", .stapsdt.base, comdat",
+                ".weak _.stapsdt.base",
+                ".hidden _.stapsdt.base",
+                "_.stapsdt.base: .space 1",
+                ".size _.stapsdt.base, 1",
+                ".popsection",
+                ".endif",
+                $($operands)*
+                options(att_syntax, readonly, nostack, preserves_flags),
+            )
+        };
+    }};
+}
+
+/// A `nop` and its `.note.stapsdt` entry, the layout `sys/sdt.h` emits.
+#[cfg(all(feature = "usdt", target_arch = "aarch64"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __probe_note {
+    ($name:literal, $args:literal, $($operands:tt)*) => {{
+        // probes are also placed inside of `unsafe` blocks
+        #[allow(unused_unsafe)]
+        let () = unsafe {
+            ::core::arch::asm!(
+                "990: nop",
+                ".pushsection .note.stapsdt, \"\", \"note\"",
+                ".balign 4",
+                ".4byte 992f-991f, 994f-993f, 3",
+                "991: .asciz \"stapsdt\"",
+                "992: .balign 4",
+                "993: .8byte 990b",
+                ".8byte _.stapsdt.base",
+                ".8byte 0",
+                ".asciz \"rummelplatz\"",
+                concat!(".asciz \"", $name, "\""),
+                concat!(".asciz \"", $args, "\""),
+                "994: .balign 4",
+                ".popsection",
+                ".ifndef _.stapsdt.base",
+                ".pushsection .stapsdt.base, \"aG\", \"progbits\", .stapsdt.base, comdat",
+                ".weak _.stapsdt.base",
+                ".hidden _.stapsdt.base",
+                "_.stapsdt.base: .space 1",
+                ".size _.stapsdt.base, 1",
+                ".popsection",
+                ".endif",
+                $($operands)*
+                options(readonly, nostack, preserves_flags),
+            )
+        };
+    }};
+}
+
+#[cfg(not(all(feature = "usdt", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __probe_note {
+    ($($tokens:tt)*) => {};
+}
@@ -0,0 +1,168 @@
+//! The data of a request travelling in the `user_data` of its SQE and CQE.
+//!
+//! Rings box the data of every request and put the address of the box into the 64 bits of
+//! `user_data`, the kernel hands it back unchanged with each completion. A [`UserDataToken`]
+//! owns the box on its way: [`UserDataToken::into_user_data`] moves it into a request,
+//! [`UserDataToken::from_user_data`] takes it back from a completion. The address is exposed
+//! with [`expose_provenance`](pointer::expose_provenance) and turned back into a pointer with
+//! [`with_exposed_provenance_mut`](std::ptr::with_exposed_provenance_mut), so the round trip
+//! through an integer is sound under the strict provenance model.
+//!
+//! Boxes are never null and the data of the rings generated by [`ring!`](crate::ring) is at
+//! least 8 byte aligned, so `user_data` 0 and odd values (see [`message`](crate::message)) never
+//! name a box of a ring.
+
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use std::mem::align_of;
+
+/// Owns the boxed data of a request while it is in flight, see [`user_data`](self).
+///
+/// Dropping a token drops the data, e.g. when the SQE carrying it could not be pushed.
+pub struct UserDataToken<T> {
+    user_data: u64,
+    data: PhantomData<Box<T>>,
+}
+
+impl<T> UserDataToken<T> {
+    #[inline]
+    pub fn new(data: Box<T>) -> Self {
+        let user_data = Box::into_raw(data).expose_provenance() as u64;
+        Self {
+            user_data,
+            data: PhantomData,
+        }
+    }
+
+    /// Takes the data back from the `user_data` of a completion.
+    ///
+    /// # Safety
+    /// `user_data` must come from [`UserDataToken::into_user_data`] with the same `T` and its
+    /// data must not have been taken back yet.
+    #[inline]
+    pub unsafe fn from_user_data(user_data: u64) -> Self {
+        debug_assert!(
+            Self::plausible(user_data),
+            "invalid user data {user_data:#x}"
+        );
+        Self {
+            user_data,
+            data: PhantomData,
+        }
+    }
+
+    /// Borrows the data of a request in flight without taking it back.
+    ///
+    /// # Safety
+    /// Like [`UserDataToken::from_user_data`], and the data must not be taken back while the
+    /// reference lives.
+    #[inline]
+    pub unsafe fn borrow<'a>(user_data: u64) -> &'a T {
+        debug_assert!(
+            Self::plausible(user_data),
+            "invalid user data {user_data:#x}"
+        );
+        &*std::ptr::with_exposed_provenance::<T>(user_data as usize)
+    }
+
+    /// The `user_data` of the request, the token keeps the data.
+    #[inline]
+    pub fn user_data(&self) -> u64 {
+        self.user_data
+    }
+
+    /// Hands the data over to the request, its completion has to take it back with
+    /// [`UserDataToken::from_user_data`] or it leaks.
+    #[inline]
+    pub fn into_user_data(self) -> u64 {
+        let user_data = self.user_data;
+        std::mem::forget(self);
+        user_data
+    }
+
+    #[inline]
+    pub fn into_box(self) -> Box<T> {
+        let pointer = std::ptr::with_exposed_provenance_mut::<T>(self.user_data as usize);
+        std::mem::forget(self);
+        // created by `Box::into_raw` in `new`, owned by this token
+        unsafe { Box::from_raw(pointer) }
+    }
+
+    #[inline]
+    fn plausible(user_data: u64) -> bool {
+        user_data != 0 && user_data.is_multiple_of(align_of::<T>() as u64)
+    }
+}
+
+impl<T> Drop for UserDataToken<T> {
+    fn drop(&mut self) {
+        let pointer = std::ptr::with_exposed_provenance_mut::<T>(self.user_data as usize);
+        drop(unsafe { Box::from_raw(pointer) });
+    }
+}
+
+impl<T> Debug for UserDataToken<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("UserDataToken")
+            .field(&format_args!("{:#x}", self.user_data))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::UserDataToken;
+
+    /// Counts how often it was dropped.
+    struct Counted(Rc<Cell<usize>>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let user_data = UserDataToken::new(Box::new(7u64)).into_user_data();
+        assert_ne!(user_data, 0);
+        assert_eq!(user_data % 2, 0);
+
+        let token = unsafe { UserDataToken::<u64>::from_user_data(user_data) };
+        assert_eq!(token.user_data(), user_data);
+        assert_eq!(*token.into_box(), 7);
+    }
+
+    #[test]
+    fn borrow_keeps_the_data() {
+        let user_data = UserDataToken::new(Box::new(String::from("in flight"))).into_user_data();
+
+        assert_eq!(
+            unsafe { UserDataToken::<String>::borrow(user_data) },
+            "in flight"
+        );
+        let data = unsafe { UserDataToken::<String>::from_user_data(user_data) }.into_box();
+        assert_eq!(*data, "in flight");
+    }
+
+    #[test]
+    fn drop_drops_the_data() {
+        let drops = Rc::new(Cell::new(0));
+
+        drop(UserDataToken::new(Box::new(Counted(drops.clone()))));
+        assert_eq!(drops.get(), 1);
+
+        let user_data = UserDataToken::new(Box::new(Counted(drops.clone()))).into_user_data();
+        assert_eq!(drops.get(), 1);
+        drop(unsafe { UserDataToken::<Counted>::from_user_data(user_data) });
+        assert_eq!(drops.get(), 2);
+
+        let data = UserDataToken::new(Box::new(Counted(drops.clone()))).into_box();
+        assert_eq!(drops.get(), 2);
+        drop(data);
+        assert_eq!(drops.get(), 3);
+    }
+}